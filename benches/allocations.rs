@@ -0,0 +1,48 @@
+//! Counts the heap allocations made building a 1000-node ring, whose nodes each
+//! own a `String` name, and the bytes still allocated once it's built.
+//!
+//! Run with `cargo bench --bench allocations`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use consistent_hashing_ring::hashing_ring::{ConsistentHashing, NodeInfoWithWeight};
+
+const NODES: usize = 1000;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn main() {
+    let nodes: Vec<NodeInfoWithWeight> = (0..NODES)
+        .map(|i| NodeInfoWithWeight::new(format!("10.0.{}.{}:11211", i / 256, i % 256), 1))
+        .collect();
+
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let live_bytes = LIVE_BYTES.load(Ordering::Relaxed);
+    let ring = ConsistentHashing::new(&nodes, None);
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations;
+    let live_bytes = LIVE_BYTES.load(Ordering::Relaxed) - live_bytes;
+
+    let points = ring.dump_ranges().len();
+    println!("points:        {}", points);
+    println!("allocations:   {} ({:.1} per point)", allocations, allocations as f64 / points as f64);
+    println!("bytes held:    {} ({:.1} per point)", live_bytes, live_bytes as f64 / points as f64);
+}
@@ -0,0 +1,46 @@
+//! Compares the two point stores (see `Backend`) on a 1000-node ring (about
+//! 120000 points): node churn, one node joining and one leaving, as under an
+//! autoscaler, and plain lookups, so you can see which one your workload favours.
+//!
+//! Run with `cargo bench --bench backends`.
+
+use std::hint::black_box;
+use criterion::{criterion_group, criterion_main, Criterion};
+use consistent_hashing_ring::hashing_ring::{Backend, ConsistentHashing, NodeInfoWithWeight};
+
+const NODES: usize = 1000;
+
+fn node(i: usize) -> NodeInfoWithWeight {
+    NodeInfoWithWeight::new(format!("10.0.{}.{}:11211", i / 256, i % 256), 1)
+}
+
+fn backends(c: &mut Criterion) {
+    let nodes: Vec<NodeInfoWithWeight> = (0..NODES).map(node).collect();
+    let keys: Vec<String> = (0..4096).map(|i| format!("user:{}", i)).collect();
+
+    let mut group = c.benchmark_group(format!("backends {} nodes", NODES));
+    for backend in [Backend::SortedVec, Backend::BTree] {
+        let mut ring = ConsistentHashing::builder(&nodes).backend(backend).build();
+        //the spare node joins, then leaves again
+        group.bench_function(format!("{:?} join and leave", backend), |b| {
+            let mut i = 0;
+            b.iter(|| {
+                i += 1;
+                let spare = node(NODES + i % 16);
+                ring.add_node(black_box(spare.clone()));
+                ring.remove_node_by_name(&spare.node_name)
+            })
+        });
+        group.bench_function(format!("{:?} lookup", backend), |b| {
+            let mut i = 0;
+            b.iter(|| {
+                i = (i + 1) % keys.len();
+                ring.get_node_ref(black_box(&keys[i])).is_some()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, backends);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+//! Compares `bisect_right_by_key` with `bisect_right_by_key_branchless`, which
+//! lookups use, on the points of a ring of about 50000, searched with the
+//! hashes of keys as a lookup would. The hashes are random, so the comparisons
+//! of the plain search mispredict about half the time.
+//!
+//! Run with `cargo bench --bench bisect`.
+
+use std::hint::black_box;
+use criterion::{criterion_group, criterion_main, Criterion};
+use consistent_hashing_ring::bisect::{bisect_right_by_key, bisect_right_by_key_branchless};
+use consistent_hashing_ring::hashing_ring::{hash_key, ConsistentHashing, NodeInfoWithWeight};
+
+//160 digests of 3 points each per node
+const NODES: usize = 105;
+
+fn bisect(c: &mut Criterion) {
+    let nodes: Vec<NodeInfoWithWeight> = (0..NODES)
+        .map(|i| NodeInfoWithWeight::new(format!("10.0.0.{}:11211", i), 1))
+        .collect();
+    let ring = ConsistentHashing::builder(&nodes).interleave_count(160).build();
+    //each arc ends at a point, so these are the ring's points in order
+    let points: Vec<(u32, usize)> = ring.dump_ranges().iter().enumerate().map(|(i, range)| (range.end_token, i)).collect();
+    let hashes: Vec<u32> = (0..4096).map(|i| hash_key(&format!("user:{}", i))).collect();
+    for hash in hashes.iter() {
+        assert_eq!(bisect_right_by_key_branchless(&points, hash, |point| point.0), bisect_right_by_key(&points, hash, |point| point.0));
+    }
+
+    let mut group = c.benchmark_group(format!("bisect {} points", points.len()));
+    group.bench_function("bisect_right_by_key", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % hashes.len();
+            bisect_right_by_key(&points, black_box(&hashes[i]), |point| point.0)
+        })
+    });
+    group.bench_function("bisect_right_by_key_branchless", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % hashes.len();
+            bisect_right_by_key_branchless(&points, black_box(&hashes[i]), |point| point.0)
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bisect);
+criterion_main!(benches);
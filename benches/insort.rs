@@ -0,0 +1,34 @@
+//! Compares inserting one element into a sorted 100k-element vector with
+//! `insort_right` against pushing it and re-sorting.
+//!
+//! Run with `cargo bench --bench insort`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use consistent_hashing_ring::bisect::insort_right;
+
+const LEN: u32 = 100_000;
+
+fn insort(c: &mut Criterion) {
+    let sorted = || -> Vec<u32> { (0..LEN).map(|i| i * 2).collect() };
+    let next = |round: &mut u32| {
+        *round += 1;
+        (*round * 7919) % (LEN * 2) + 1
+    };
+
+    let mut group = c.benchmark_group(format!("insert into {} sorted", LEN));
+    group.bench_function("insort_right", |b| {
+        let mut round = 0;
+        b.iter_batched_ref(sorted, |v| insort_right(v, next(&mut round)), BatchSize::LargeInput)
+    });
+    group.bench_function("push and sort", |b| {
+        let mut round = 0;
+        b.iter_batched_ref(sorted, |v| {
+            v.push(next(&mut round));
+            v.sort();
+        }, BatchSize::LargeInput)
+    });
+    group.finish();
+}
+
+criterion_group!(benches, insort);
+criterion_main!(benches);
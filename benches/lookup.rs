@@ -0,0 +1,83 @@
+//! Times `get_node_ref` on a 100-node ring (about 12000 points) against
+//! `hash_key` alone, which it hashes the key with, and against the lookup the
+//! ring made before its points and owners were one sorted vector: a search of a
+//! sorted `Vec<u32>` of tokens, then a `HashMap<u32, Arc<T>>` from token to node,
+//! rebuilt here from the ring's ranges.
+//!
+//! Run with `cargo bench --bench lookup`.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::sync::Arc;
+use criterion::{criterion_group, criterion_main, Criterion};
+use consistent_hashing_ring::bisect::bisect_right;
+use consistent_hashing_ring::hashing_ring::{hash_key, ConsistentHashing, NodeInfoWithWeight};
+
+const NODES: usize = 100;
+
+//the ring's lookup with the two structures it used to keep
+struct TwoStructures {
+    sorted_keys: Vec<u32>,
+    hashing_ring: HashMap<u32, Arc<NodeInfoWithWeight>>,
+}
+
+impl TwoStructures {
+    fn new(ring: &ConsistentHashing<NodeInfoWithWeight>) -> TwoStructures {
+        let nodes: HashMap<&str, Arc<NodeInfoWithWeight>> = ring.nodes_in_order().into_iter()
+            .map(|node| (node.node_name.as_str(), Arc::new(node.clone())))
+            .collect();
+        //each arc ends at a point, in token order
+        let ranges = ring.dump_ranges();
+        let hashing_ring: HashMap<u32, Arc<NodeInfoWithWeight>> = ranges.iter()
+            .map(|range| (range.end_token, Arc::clone(&nodes[range.node_name.as_str()])))
+            .collect();
+        TwoStructures { sorted_keys: ranges.iter().map(|range| range.end_token).collect(), hashing_ring }
+    }
+
+    fn get_node_ref(&self, key: &str) -> Option<&NodeInfoWithWeight> {
+        let mut pos = bisect_right(&self.sorted_keys, hash_key(key), None, None);
+        if pos == self.sorted_keys.len() {
+            pos = 0;
+        }
+        self.sorted_keys.get(pos).and_then(|key| self.hashing_ring.get(key)).map(|node| &**node)
+    }
+}
+
+fn lookup(c: &mut Criterion) {
+    let nodes: Vec<NodeInfoWithWeight> = (0..NODES)
+        .map(|i| NodeInfoWithWeight::new(format!("10.0.0.{}:11211", i), 1 + i % 3))
+        .collect();
+    let ring = ConsistentHashing::new(&nodes, None);
+    let before = TwoStructures::new(&ring);
+    let keys: Vec<String> = (0..4096).map(|i| format!("user:{}", i)).collect();
+    for key in keys.iter() {
+        assert_eq!(before.get_node_ref(key).unwrap().node_name, ring.get_node_ref(key).unwrap().node_name);
+    }
+
+    let mut group = c.benchmark_group(format!("lookup {} points", ring.virtual_node_count()));
+    group.bench_function("hash_key", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % keys.len();
+            hash_key(black_box(&keys[i]))
+        })
+    });
+    group.bench_function("get_node_ref", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % keys.len();
+            ring.get_node_ref(black_box(&keys[i])).is_some()
+        })
+    });
+    group.bench_function("sorted keys and HashMap", |b| {
+        let mut i = 0;
+        b.iter(|| {
+            i = (i + 1) % keys.len();
+            before.get_node_ref(black_box(&keys[i])).is_some()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, lookup);
+criterion_main!(benches);
@@ -0,0 +1,38 @@
+//! Builds a 5000-node weighted ring (about 2.4 million points) on rayon thread
+//! pools of 1, 2, 4, ... threads up to the machine's cores, to show how the
+//! `rayon` feature's build scales. Only the digests are taken in parallel; the
+//! sort and the bookkeeping after it stay on one thread, so expect the speedup to
+//! flatten out before the core count does. On a single core every pool size
+//! takes about the same time.
+//!
+//! Run with `cargo bench --bench parallel_build --features rayon`.
+
+use std::hint::black_box;
+use std::thread;
+use criterion::{criterion_group, criterion_main, Criterion};
+use consistent_hashing_ring::hashing_ring::{ConsistentHashing, NodeInfoWithWeight};
+
+const NODES: usize = 5000;
+
+fn parallel_build(c: &mut Criterion) {
+    let nodes: Vec<NodeInfoWithWeight> = (0..NODES)
+        .map(|i| NodeInfoWithWeight::new(format!("10.{}.{}.{}:11211", i / 65536, i / 256 % 256, i % 256), 1 + i % 4))
+        .collect();
+    let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+
+    let mut group = c.benchmark_group(format!("build {} nodes", NODES));
+    //a build takes long enough that a few are plenty
+    group.sample_size(10);
+    let mut threads = 1;
+    while threads <= cores {
+        let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        group.bench_function(format!("{} threads", threads), |b| {
+            pool.install(|| b.iter(|| ConsistentHashing::builder(black_box(&nodes)).interleave_count(160).build()))
+        });
+        threads *= 2;
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parallel_build);
+criterion_main!(benches);
@@ -0,0 +1,113 @@
+#ifndef CONSISTENT_HASHING_RING_H
+#define CONSISTENT_HASHING_RING_H
+
+/* Generated by cbindgen from src/ffi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define RING_OK 0
+
+/**
+ *A pointer argument is null.
+ */
+#define RING_ERR_NULL -1
+
+/**
+ *A string argument isn't valid UTF-8.
+ */
+#define RING_ERR_UTF8 -2
+
+/**
+ *The handle doesn't name a live ring.
+ */
+#define RING_ERR_INVALID_HANDLE -3
+
+/**
+ *No node was found: the node to remove isn't on the ring, or the ring has no
+ *node for the key.
+ */
+#define RING_ERR_NOT_FOUND -4
+
+/**
+ *A node with the same name is already on the ring.
+ */
+#define RING_ERR_EXISTS -5
+
+/**
+ *The output buffer can't hold the result; the size it needs has been written.
+ */
+#define RING_ERR_BUFFER_TOO_SMALL -6
+
+/**
+ *The call panicked. The ring is still usable, but may be missing the change.
+ */
+#define RING_ERR_PANIC -7
+
+/**
+ *Identifies a ring created by `ring_new`. 0 is never a valid handle.
+ */
+typedef uint64_t RingHandle;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ *Creates an empty ring with `interleave_count` digests per node, or the default
+ *of 40 if it's 0, and writes its handle to `out_handle`.
+ *
+ *# Safety
+ *
+ *`out_handle` has to be null or valid for a write.
+ */
+int ring_new(size_t interleave_count, RingHandle *out_handle);
+
+/**
+ *Adds the node `name` with `weight`, like `ConsistentHashing::add_node`.
+ *
+ *# Safety
+ *
+ *`name` has to be null or a NUL-terminated string.
+ */
+int ring_add_node(RingHandle handle, const char *name, size_t weight);
+
+/**
+ *Removes the node `name`, like `ConsistentHashing::remove_node_by_name`.
+ *
+ *# Safety
+ *
+ *`name` has to be null or a NUL-terminated string.
+ */
+int ring_remove_node(RingHandle handle, const char *name);
+
+/**
+ *Writes the name of the node `ConsistentHashing::get_node` picks for `key` to
+ *`out_buf`, NUL-terminated. `*out_len` is the size of `out_buf` in bytes on the
+ *way in and is set to the size the name needs, NUL included, on the way out. If
+ *that's more than the buffer holds nothing is written to it and
+ *`RING_ERR_BUFFER_TOO_SMALL` is returned, so passing a null `out_buf` with a
+ *size of 0 asks for the size.
+ *
+ *# Safety
+ *
+ *`key` has to be null or a NUL-terminated string, `out_len` null or valid for
+ *reads and writes, and `out_buf` valid for `*out_len` bytes of writes unless
+ *`*out_len` is 0.
+ */
+int ring_get_node(RingHandle handle, const char *key, char *out_buf, size_t *out_len);
+
+/**
+ *Frees the ring. The handle is invalid afterwards; calls still running on other
+ *threads finish first.
+ */
+int ring_free(RingHandle handle);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CONSISTENT_HASHING_RING_H */
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use crate::hashing_ring::{hash_bytes64, hash_pair64, RingNode, WithWeightInfo};
+
+
+///Error returned by `AnchorHash` membership changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AnchorError {
+    ///Every bucket of the anchor set is already in use.
+    CapacityExceeded { capacity: usize },
+    ///A node with the same name is already in the working set.
+    DuplicateNode(String),
+}
+
+impl fmt::Display for AnchorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AnchorError::CapacityExceeded { capacity } => write!(f, "anchor capacity of {} nodes exceeded", capacity),
+            AnchorError::DuplicateNode(name) => write!(f, "node {} is already in the working set", name),
+        }
+    }
+}
+
+impl Error for AnchorError {}
+
+
+///AnchorHash (Mendelson et al., "AnchorHash: A Scalable Consistent Hash").
+///
+///The anchor set is a fixed number of buckets chosen at construction; the nodes
+///currently in the cluster (the working set) occupy some of them. Lookups take
+///expected constant time and memory is a few words per anchor bucket, with no
+///virtual points. The price is the fixed capacity: it bounds how far the cluster
+///can grow, and lookups get slower as the working set shrinks far below it (the
+///expected cost grows with `ln(capacity / working_set)`), so size it to a small
+///multiple of the largest expected cluster.
+///
+///Removing a node moves only the keys it owned, and re-adding nodes in the
+///reverse order of their removal restores the previous mapping exactly. Weights
+///are ignored: every working node gets an equal share.
+pub struct AnchorHash<T: RingNode + Hash + Clone + WithWeightInfo> {
+    //A[b]: 0 while b is working, otherwise the working set size right after b was removed
+    removed_at: Vec<usize>,
+    //W: working buckets in positions 0..working
+    working_set: Vec<usize>,
+    //L: position of each bucket in `working_set`
+    locations: Vec<usize>,
+    //K: the bucket that took b's place when b was removed
+    successors: Vec<usize>,
+    //R: removed buckets, the most recently removed last
+    removed: Vec<usize>,
+    working: usize,
+    buckets: Vec<Option<T>>,
+    node_buckets: HashMap<String, usize>,
+}
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> AnchorHash<T> {
+    ///Builds an anchor set of `capacity` buckets with `nodes` as the initial working set.
+    pub fn new(capacity: usize, nodes: &[T]) -> Result<AnchorHash<T>, AnchorError> {
+        if nodes.len() > capacity {
+            return Err(AnchorError::CapacityExceeded { capacity });
+        }
+        let mut anchor = AnchorHash {
+            removed_at: vec![0; capacity],
+            working_set: (0..capacity).collect(),
+            locations: (0..capacity).collect(),
+            successors: (0..capacity).collect(),
+            removed: Vec::new(),
+            working: capacity,
+            buckets: vec![None; capacity],
+            node_buckets: HashMap::new(),
+        };
+        //buckets past the initial nodes start out removed, the highest first, so
+        //that additions hand them out in increasing order
+        for bucket in (nodes.len()..capacity).rev() {
+            anchor.removed.push(bucket);
+            anchor.removed_at[bucket] = bucket;
+        }
+        anchor.working = nodes.len();
+
+        for (bucket, node) in nodes.iter().enumerate() {
+            let name = node.ring_id().to_string();
+            if anchor.node_buckets.insert(name.clone(), bucket).is_some() {
+                return Err(AnchorError::DuplicateNode(name));
+            }
+            anchor.buckets[bucket] = Some(node.clone());
+        }
+        Ok(anchor)
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub fn len(&self) -> usize {
+        self.working
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.working == 0
+    }
+
+    ///Adds a node to the working set, in the most recently freed bucket.
+    pub fn add_node(&mut self, node: T) -> Result<(), AnchorError> {
+        let name = node.ring_id().to_string();
+        if self.node_buckets.contains_key(&name) {
+            return Err(AnchorError::DuplicateNode(name));
+        }
+        let bucket = match self.removed.pop() {
+            Some(bucket) => bucket,
+            None => return Err(AnchorError::CapacityExceeded { capacity: self.capacity() }),
+        };
+
+        self.removed_at[bucket] = 0;
+        self.locations[self.working_set[self.working]] = self.working;
+        self.working_set[self.locations[bucket]] = bucket;
+        self.successors[bucket] = bucket;
+        self.working += 1;
+
+        self.node_buckets.insert(name, bucket);
+        self.buckets[bucket] = Some(node);
+        Ok(())
+    }
+
+    ///Removes a node from the working set, returning whether it was there.
+    pub fn remove_node(&mut self, node: &T) -> bool {
+        let bucket = match self.node_buckets.remove(node.ring_id()) {
+            Some(bucket) => bucket,
+            None => return false,
+        };
+
+        self.removed.push(bucket);
+        self.working -= 1;
+        self.removed_at[bucket] = self.working;
+        let last = self.working_set[self.working];
+        self.working_set[self.locations[bucket]] = last;
+        self.locations[last] = self.locations[bucket];
+        self.successors[bucket] = last;
+
+        self.buckets[bucket] = None;
+        true
+    }
+
+    ///Given a string key the node of its bucket is returned.
+    ///If the working set is empty, `None` is returned.
+    pub fn get_node(&self, key: &str) -> Option<T> {
+        if self.working == 0 {
+            return None;
+        }
+        let mut bucket = (hash_bytes64(key.as_bytes()) % self.capacity() as u64) as usize;
+        while self.removed_at[bucket] > 0 {
+            //rehash among the buckets that were working when `bucket` was removed
+            let mut candidate = (hash_pair64(&bucket.to_string(), key) % self.removed_at[bucket] as u64) as usize;
+            while self.removed_at[candidate] >= self.removed_at[bucket] {
+                candidate = self.successors[candidate];
+            }
+            bucket = candidate;
+        }
+        self.buckets[bucket].clone()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing_ring::NodeInfo;
+
+    const NAMES: [&str; 8] = ["n0", "n1", "n2", "n3", "n4", "n5", "n6", "n7"];
+
+    fn owners(anchor: &AnchorHash<NodeInfo>, keys: &[String]) -> Vec<String> {
+        keys.iter().map(|k| anchor.get_node(k).unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn test_remove_moves_only_its_keys_and_readd_restores() {
+        let nodes: Vec<NodeInfo> = NAMES.iter().map(|&name| NodeInfo::new(name)).collect();
+        let mut anchor = AnchorHash::new(32, &nodes).unwrap();
+        let keys: Vec<String> = (0..2000).map(|i| format!("key-{}", i)).collect();
+        let before = owners(&anchor, &keys);
+
+        assert!(anchor.remove_node(&nodes[5]));
+        assert!(!anchor.remove_node(&nodes[5]));
+        let removed = owners(&anchor, &keys);
+        for (old, new) in before.iter().zip(removed.iter()) {
+            if old == "n5" {
+                assert_ne!(new, "n5");
+            } else {
+                assert_eq!(old, new);
+            }
+        }
+
+        assert!(anchor.remove_node(&nodes[2]));
+        anchor.add_node(nodes[2].clone()).unwrap();
+        anchor.add_node(nodes[5].clone()).unwrap();
+        assert_eq!(owners(&anchor, &keys), before);
+    }
+
+    #[test]
+    fn test_add_moves_keys_only_to_new_node() {
+        let nodes: Vec<NodeInfo> = NAMES.iter().map(|&name| NodeInfo::new(name)).collect();
+        let mut anchor = AnchorHash::new(16, &nodes[..6]).unwrap();
+        let keys: Vec<String> = (0..2000).map(|i| format!("key-{}", i)).collect();
+        let before = owners(&anchor, &keys);
+
+        anchor.add_node(nodes[6].clone()).unwrap();
+        let mut moved = 0;
+        for (old, new) in before.iter().zip(owners(&anchor, &keys).iter()) {
+            if old != new {
+                assert_eq!(new, "n6");
+                moved += 1;
+            }
+        }
+        assert!(moved > 2000 / 7 / 2);
+        assert_eq!(anchor.len(), 7);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let nodes: Vec<NodeInfo> = NAMES.iter().map(|&name| NodeInfo::new(name)).collect();
+        assert_eq!(AnchorHash::new(4, &nodes).err(), Some(AnchorError::CapacityExceeded { capacity: 4 }));
+
+        let mut anchor = AnchorHash::new(4, &nodes[..3]).unwrap();
+        anchor.add_node(nodes[3].clone()).unwrap();
+        assert_eq!(anchor.add_node(nodes[4].clone()), Err(AnchorError::CapacityExceeded { capacity: 4 }));
+        assert_eq!(anchor.add_node(nodes[0].clone()), Err(AnchorError::DuplicateNode("n0".to_string())));
+    }
+
+    #[test]
+    fn test_empty_working_set() {
+        let nodes = vec![NodeInfo::new("n0")];
+        let mut anchor = AnchorHash::new(4, &nodes).unwrap();
+        assert!(anchor.remove_node(&nodes[0]));
+        assert!(anchor.is_empty());
+        assert!(anchor.get_node("my_key").is_none());
+
+        anchor.add_node(nodes[0].clone()).unwrap();
+        assert_eq!(anchor.get_node("my_key").unwrap().node_name, "n0");
+    }
+}
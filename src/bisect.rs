@@ -1,32 +1,112 @@
 
 
-use std::cmp::PartialOrd;
-use std::fmt::Display;
-use std::fmt::Debug;
-
-
-pub fn bisect_right<D>(sorted_key: &Vec<D>, search_key: D, lo: Option<usize>,  hi: Option<usize>) -> usize
-    where D: PartialOrd + Display + Debug {
-    ///Return the index where to insert item x in list a, assuming a is sorted.
-    ///
-    ///The return value i is such that all e in a[:i] have e <= x, and all e in
-    ///a[i:] have e > x.  So if x already appears in the list, a.insert(x) will
-    ///insert just after the rightmost x already there.
-    ///
-    ///Optional args lo (default 0) and hi (default len(a)) bound the
-    ///slice of a to be searched.
-    let mut hi = match hi {
-        Some(num) => num,
-        None => sorted_key.len()
-    };
-
-    let mut lo = match lo {
-        Some(num) => num,
-        None => 0
-    };
+use std::borrow::Borrow;
+use std::cmp::{Ordering, PartialOrd};
+
+
+///Return the index where to insert item x in list a, assuming a is sorted.
+///
+///The return value i is such that all e in a[:i] have e <= x, and all e in
+///a[i:] have e > x.  So if x already appears in the list, a.insert(x) will
+///insert just after the rightmost x already there.
+///
+///Optional args lo (default 0) and hi (default len(a)) bound the
+///slice of a to be searched. Panics unless lo <= hi <= len(a).
+///
+///x can be passed by value or by reference (`&D`), so non-Copy items don't
+///have to be cloned to be searched for.
+///
+///Only a `PartialOrd` is required, so elements incomparable with x (NaN in
+///an `f64` slice, or a NaN x) can be met. Such an element is treated as
+///less than x and the search carries on to its right: the result is still
+///within lo..=hi, and a NaN x always ends up at hi. Prefer `bisect_right_ord`
+///for types with a total order.
+pub fn bisect_right<D, K>(sorted_key: &[D], search_key: K, lo: Option<usize>,  hi: Option<usize>) -> usize
+    where D: PartialOrd, K: Borrow<D> {
+    let search_key = search_key.borrow();
+    let (mut lo, mut hi) = check_bounds(sorted_key.len(), lo, hi);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if sorted_key[mid].partial_cmp(search_key).unwrap_or(Ordering::Less) == Ordering::Greater {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+
+    lo
+}
+
+
+///Return the index where to insert item x in list a, assuming a is sorted.
+///
+///The return value i is such that all e in a[:i] have e < x, and all e in
+///a[i:] have e >= x.  So if x already appears in the list, a.insert(x) will
+///insert just before the leftmost x already there.
+///
+///Optional args lo (default 0) and hi (default len(a)) bound the
+///slice of a to be searched. Panics unless lo <= hi <= len(a).
+///
+///x can be passed by value or by reference (`&D`).
+///
+///Elements incomparable with x are treated as less than x, as in `bisect_right`.
+pub fn bisect_left<D, K>(a: &[D], x: K, lo: Option<usize>, hi: Option<usize>) -> usize
+    where D: PartialOrd, K: Borrow<D> {
+    let x = x.borrow();
+    let (mut lo, mut hi) = check_bounds(a.len(), lo, hi);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if a[mid].partial_cmp(x).unwrap_or(Ordering::Less) == Ordering::Less {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+
+    lo
+}
+
+
+///Fills in the default bounds and rejects ones that don't describe a subslice,
+///so bad arguments fail here rather than as an index panic inside the search.
+fn check_bounds(len: usize, lo: Option<usize>, hi: Option<usize>) -> (usize, usize) {
+    let lo = lo.unwrap_or(0);
+    let hi = hi.unwrap_or(len);
+    assert!(hi <= len, "bisect: hi ({}) is beyond the slice length ({})", hi, len);
+    assert!(lo <= hi, "bisect: lo ({}) is greater than hi ({})", lo, hi);
+    (lo, hi)
+}
+
+
+///`bisect_right` for totally ordered types, where no element can be
+///incomparable with x.
+pub fn bisect_right_ord<D, K>(a: &[D], x: K, lo: Option<usize>, hi: Option<usize>) -> usize
+    where D: Ord, K: Borrow<D> {
+    bisect_right(a, x, lo, hi)
+}
+
+
+///`bisect_left` for totally ordered types, where no element can be
+///incomparable with x.
+pub fn bisect_left_ord<D, K>(a: &[D], x: K, lo: Option<usize>, hi: Option<usize>) -> usize
+    where D: Ord, K: Borrow<D> {
+    bisect_left(a, x, lo, hi)
+}
+
+
+///`bisect_right` with a comparator, named after `slice::binary_search_by`.
+///
+///`f` returns how an element orders relative to the item being searched for,
+///and a must be sorted accordingly (all `Less`, then `Equal`, then `Greater`).
+///The return value is the index of the first element for which `f` returns
+///`Greater`.
+pub fn bisect_right_by<D, F>(a: &[D], mut f: F) -> usize
+    where F: FnMut(&D) -> Ordering {
+    let mut lo = 0;
+    let mut hi = a.len();
     while lo < hi {
-        let mid = (lo + hi) / 2 ;
-        if search_key < sorted_key[mid] {
+        let mid = lo + (hi - lo) / 2;
+        if f(&a[mid]) == Ordering::Greater {
             hi = mid;
         } else {
             lo = mid + 1;
@@ -35,3 +115,397 @@ pub fn bisect_right<D>(sorted_key: &Vec<D>, search_key: D, lo: Option<usize>,  h
 
     lo
 }
+
+
+///`bisect_left` with a comparator: the index of the first element for which
+///`f` doesn't return `Less`. See `bisect_right_by`.
+pub fn bisect_left_by<D, F>(a: &[D], mut f: F) -> usize
+    where F: FnMut(&D) -> Ordering {
+    let mut lo = 0;
+    let mut hi = a.len();
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if f(&a[mid]) == Ordering::Less {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+
+    lo
+}
+
+
+///`bisect_right` on a key extracted from each element, named after
+///`slice::binary_search_by_key`. a must be sorted by that key,
+///e.g. `bisect_right_by_key(&points, &token, |&(token, _)| token)`.
+pub fn bisect_right_by_key<D, K, F>(a: &[D], key: &K, mut f: F) -> usize
+    where K: Ord, F: FnMut(&D) -> K {
+    bisect_right_by(a, |e| f(e).cmp(key))
+}
+
+
+///`bisect_left` on a key extracted from each element. See `bisect_right_by_key`.
+pub fn bisect_left_by_key<D, K, F>(a: &[D], key: &K, mut f: F) -> usize
+    where K: Ord, F: FnMut(&D) -> K {
+    bisect_left_by(a, |e| f(e).cmp(key))
+}
+
+
+///`bisect_right_by_key` with a search whose loop doesn't branch on the
+///comparisons, the ring's hot path being one of these per lookup.
+///
+///Each step halves the range and moves its start with
+///`std::hint::select_unpredictable`, which compiles to a conditional move, so
+///a key a random hash lands on costs no mispredicted branches. The loop runs
+///about log2(len) times whatever `key` is. The result is the same as
+///`bisect_right_by_key`'s; see `benches/bisect.rs` for the difference it makes.
+#[inline]
+pub fn bisect_right_by_key_branchless<D, K, F>(a: &[D], key: &K, mut f: F) -> usize
+    where K: Ord, F: FnMut(&D) -> K {
+    partition_point_branchless(a, |e| f(e) <= *key)
+}
+
+
+///`bisect_left_by_key` with the search of `bisect_right_by_key_branchless`.
+#[inline]
+pub fn bisect_left_by_key_branchless<D, K, F>(a: &[D], key: &K, mut f: F) -> usize
+    where K: Ord, F: FnMut(&D) -> K {
+    partition_point_branchless(a, |e| f(e) < *key)
+}
+
+
+//the index of the first element `pred` is false for, a being partitioned so
+//that it's true for all the elements before that one
+#[inline]
+fn partition_point_branchless<D, F>(a: &[D], mut pred: F) -> usize
+    where F: FnMut(&D) -> bool {
+    if a.is_empty() {
+        return 0;
+    }
+    //the answer is always within base..=base + size
+    let mut base = 0;
+    let mut size = a.len();
+    while size > 1 {
+        let half = size / 2;
+        let mid = base + half;
+        base = std::hint::select_unpredictable(pred(&a[mid]), mid, base);
+        size -= half;
+    }
+    base + pred(&a[base]) as usize
+}
+
+
+///Insert item x in list a, and keep it sorted assuming a is sorted.
+///
+///If x is already in a, insert it to the right of the rightmost x.
+///The position is found with a binary search, so the only linear cost
+///is the single `Vec::insert`.
+pub fn insort_right<D: PartialOrd>(a: &mut Vec<D>, x: D) {
+    let pos = bisect_right(a, &x, None, None);
+    a.insert(pos, x);
+}
+
+
+///Insert item x in list a, and keep it sorted assuming a is sorted.
+///
+///If x is already in a, insert it to the left of the leftmost x.
+pub fn insort_left<D: PartialOrd>(a: &mut Vec<D>, x: D) {
+    let pos = bisect_left(a, &x, None, None);
+    a.insert(pos, x);
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //deterministic sorted vectors with plenty of duplicates
+    fn sorted_samples() -> Vec<Vec<u32>> {
+        let mut state: u32 = 0x9e37_79b9;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        (0..200).map(|len| {
+            let mut v: Vec<u32> = (0..len % 40).map(|_| next() % 20).collect();
+            v.sort();
+            v
+        }).collect()
+    }
+
+    //ordered by `key` only, so equal elements can still be told apart by `id`
+    #[derive(Debug)]
+    struct Tagged {
+        key: u32,
+        id: usize,
+    }
+
+    impl PartialEq for Tagged {
+        fn eq(&self, other: &Tagged) -> bool {
+            self.key == other.key
+        }
+    }
+
+    impl PartialOrd for Tagged {
+        fn partial_cmp(&self, other: &Tagged) -> Option<std::cmp::Ordering> {
+            self.key.partial_cmp(&other.key)
+        }
+    }
+
+    #[test]
+    fn test_insort_matches_sorted_concatenation() {
+        for v in sorted_samples() {
+            let mut extra: Vec<u32> = v.iter().rev().map(|e| e.wrapping_mul(7) % 23).collect();
+            let mut right = v.clone();
+            let mut left = v.clone();
+            for x in &extra {
+                insort_right(&mut right, *x);
+                insort_left(&mut left, *x);
+            }
+            extra.extend(v.iter().cloned());
+            extra.sort();
+            assert_eq!(right, extra);
+            assert_eq!(left, extra);
+        }
+    }
+
+    #[test]
+    fn test_insort_duplicate_placement() {
+        let mut right = vec![Tagged{key: 1, id: 0}, Tagged{key: 2, id: 1}, Tagged{key: 3, id: 2}];
+        insort_right(&mut right, Tagged{key: 2, id: 3});
+        insort_right(&mut right, Tagged{key: 2, id: 4});
+        let ids: Vec<usize> = right.iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![0, 1, 3, 4, 2]);
+
+        let mut left = vec![Tagged{key: 1, id: 0}, Tagged{key: 2, id: 1}, Tagged{key: 3, id: 2}];
+        insort_left(&mut left, Tagged{key: 2, id: 3});
+        insort_left(&mut left, Tagged{key: 2, id: 4});
+        let ids: Vec<usize> = left.iter().map(|t| t.id).collect();
+        assert_eq!(ids, vec![0, 4, 3, 1, 2]);
+    }
+
+    #[test]
+    fn test_bisect_slices_and_arrays() {
+        let v: Vec<u32> = vec![1, 3, 5, 7, 9, 11];
+        assert_eq!(bisect_right(&v[2..5], 7, None, None), 2);
+        assert_eq!(bisect_left(&v[2..5], 7, None, None), 1);
+
+        let array = [10, 20, 30];
+        assert_eq!(bisect_right(&array, 20, None, None), 2);
+        assert_eq!(bisect_left(&array, 20, None, None), 1);
+        assert_eq!(bisect_right(&array[..], 25, None, None), 2);
+    }
+
+    #[test]
+    fn test_bisect_non_display_type() {
+        //Tagged implements neither Display nor Clone
+        let v = vec![Tagged{key: 1, id: 0}, Tagged{key: 2, id: 1}, Tagged{key: 2, id: 2}, Tagged{key: 4, id: 3}];
+        let probe = Tagged{key: 2, id: 99};
+        assert_eq!(bisect_right(&v, &probe, None, None), 3);
+        assert_eq!(bisect_left(&v, &probe, None, None), 1);
+        assert_eq!(bisect_right(&v, probe, None, None), 3);
+
+        let strings = vec!["a".to_string(), "c".to_string()];
+        for (i, probe) in ["a".to_string(), "b".to_string(), "c".to_string()].iter().enumerate() {
+            assert_eq!(bisect_right(&strings, probe, None, None), (i + 2) / 2);
+        }
+    }
+
+    #[test]
+    fn test_bisect_nan_search_key_goes_to_hi() {
+        let v = vec![1.0, 2.0, 3.0];
+        assert_eq!(bisect_right(&v, f64::NAN, None, None), 3);
+        assert_eq!(bisect_left(&v, f64::NAN, None, None), 3);
+        assert_eq!(bisect_right(&v, f64::NAN, Some(0), Some(2)), 2);
+        assert_eq!(bisect_left(&v, f64::NAN, Some(1), Some(1)), 1);
+    }
+
+    #[test]
+    fn test_bisect_nan_element_counts_as_less() {
+        //NaN first
+        let v = vec![f64::NAN, 1.0, 2.0, 3.0];
+        assert_eq!(bisect_right(&v, 2.0, None, None), 3);
+        assert_eq!(bisect_left(&v, 2.0, None, None), 2);
+        assert_eq!(bisect_right(&v, 0.5, None, None), 1);
+        assert_eq!(bisect_left(&v, 0.5, None, None), 1);
+        //NaN in the middle
+        let v = vec![1.0, f64::NAN, 3.0];
+        assert_eq!(bisect_right(&v, 2.0, None, None), 2);
+        assert_eq!(bisect_left(&v, 2.0, None, None), 2);
+        assert_eq!(bisect_right(&v, 0.0, None, None), 2);
+        //NaN last
+        let v = vec![1.0, 2.0, 3.0, f64::NAN];
+        assert_eq!(bisect_right(&v, 2.5, None, None), 2);
+        assert_eq!(bisect_right(&v, 5.0, None, None), 4);
+        assert_eq!(bisect_left(&v, 5.0, None, None), 4);
+        //all NaN
+        let v = vec![f64::NAN; 5];
+        assert_eq!(bisect_right(&v, 1.0, None, None), 5);
+        assert_eq!(bisect_left(&v, 1.0, Some(1), Some(3)), 3);
+    }
+
+    #[test]
+    fn test_insort_nan_goes_last() {
+        let mut v = vec![1.0, 2.0];
+        insort_right(&mut v, 1.5);
+        insort_right(&mut v, f64::NAN);
+        insort_left(&mut v, f64::NAN);
+        assert_eq!(&v[..3], &[1.0, 1.5, 2.0]);
+        assert!(v[3].is_nan() && v[4].is_nan());
+    }
+
+    #[test]
+    fn test_bisect_ord_matches_partial_ord() {
+        for v in sorted_samples() {
+            for x in 0..22u32 {
+                assert_eq!(bisect_right_ord(&v, x, None, None), bisect_right(&v, x, None, None));
+                assert_eq!(bisect_left_ord(&v, x, None, None), bisect_left(&v, x, None, None));
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "beyond the slice length")]
+    fn test_bisect_right_hi_past_end() {
+        bisect_right(&[1, 2, 3], 2, None, Some(4));
+    }
+
+    #[test]
+    #[should_panic(expected = "beyond the slice length")]
+    fn test_bisect_left_hi_past_end() {
+        bisect_left(&[1, 2, 3], 2, Some(1), Some(10));
+    }
+
+    #[test]
+    #[should_panic(expected = "greater than hi")]
+    fn test_bisect_right_lo_past_hi() {
+        bisect_right(&[1, 2, 3], 2, Some(2), Some(1));
+    }
+
+    #[test]
+    #[should_panic(expected = "greater than hi")]
+    fn test_bisect_left_lo_past_end() {
+        bisect_left(&[1, 2, 3], 2, Some(4), None);
+    }
+
+    #[test]
+    fn test_bisect_edge_bounds() {
+        let v = vec![1, 2, 3];
+        assert_eq!(bisect_right(&v, 2, Some(3), Some(3)), 3);
+        assert_eq!(bisect_left(&v, 2, Some(0), Some(0)), 0);
+        assert_eq!(bisect_right(&v, 2, None, Some(3)), 2);
+        let empty: Vec<u32> = Vec::new();
+        assert_eq!(bisect_right(&empty, 2, Some(0), Some(0)), 0);
+    }
+
+    #[test]
+    fn test_bisect_huge_indices() {
+        //a zero-sized element type gives a slice of usize::MAX elements without any
+        //memory, so (lo + hi) would overflow here
+        //SAFETY: a dangling, aligned pointer is valid for any number of zero-sized reads
+        let huge: &[()] = unsafe { std::slice::from_raw_parts(std::ptr::NonNull::dangling().as_ptr(), usize::MAX) };
+        assert_eq!(bisect_right(huge, (), None, None), usize::MAX);
+        assert_eq!(bisect_left(huge, (), None, None), 0);
+        assert_eq!(bisect_left(huge, (), Some(usize::MAX - 3), None), usize::MAX - 3);
+        assert_eq!(bisect_right_by(huge, |_| Ordering::Less), usize::MAX);
+        assert_eq!(bisect_left_by(huge, |_| Ordering::Greater), 0);
+    }
+
+    #[test]
+    fn test_bisect_by_tuple_field() {
+        let points: Vec<(u32, usize)> = vec![(10, 2), (20, 0), (20, 1), (30, 0)];
+        assert_eq!(bisect_right_by_key(&points, &20, |&(token, _)| token), 3);
+        assert_eq!(bisect_left_by_key(&points, &20, |&(token, _)| token), 1);
+        assert_eq!(bisect_right_by_key(&points, &5, |&(token, _)| token), 0);
+        assert_eq!(bisect_right_by_key(&points, &99, |&(token, _)| token), 4);
+        assert_eq!(bisect_right_by(&points, |&(token, _)| token.cmp(&25)), 3);
+        assert_eq!(bisect_left_by(&points, |&(token, _)| token.cmp(&30)), 3);
+    }
+
+    #[test]
+    fn test_bisect_by_struct_field() {
+        let v = vec![Tagged{key: 1, id: 7}, Tagged{key: 2, id: 3}, Tagged{key: 2, id: 1}, Tagged{key: 5, id: 0}];
+        assert_eq!(bisect_right_by_key(&v, &2, |t| t.key), 3);
+        assert_eq!(bisect_left_by_key(&v, &2, |t| t.key), 1);
+        assert_eq!(bisect_left_by_key(&v, &3, |t| t.key), 3);
+    }
+
+    #[test]
+    fn test_bisect_by_reversed_comparator() {
+        let descending = vec![9, 7, 7, 4, 1];
+        assert_eq!(bisect_right_by(&descending, |e: &u32| 7.cmp(e)), 3);
+        assert_eq!(bisect_left_by(&descending, |e: &u32| 7.cmp(e)), 1);
+        assert_eq!(bisect_right_by(&descending, |e: &u32| 10.cmp(e)), 0);
+        assert_eq!(bisect_left_by(&descending, |e: &u32| 0.cmp(e)), 5);
+    }
+
+    #[test]
+    fn test_bisect_by_key_matches_counting() {
+        for v in sorted_samples() {
+            let pairs: Vec<(u32, usize)> = v.iter().enumerate().map(|(i, e)| (*e, i)).collect();
+            for x in 0..22 {
+                assert_eq!(bisect_right_by_key(&pairs, &x, |p| p.0), pairs.iter().filter(|p| p.0 <= x).count());
+                assert_eq!(bisect_left_by_key(&pairs, &x, |p| p.0), pairs.iter().filter(|p| p.0 < x).count());
+                assert_eq!(bisect_right_by_key(&pairs, &x, |p| p.0), bisect_right(&v, x, None, None));
+            }
+        }
+    }
+
+    #[test]
+    fn test_branchless_matches_bisect() {
+        for v in sorted_samples() {
+            let pairs: Vec<(u32, usize)> = v.iter().enumerate().map(|(i, e)| (*e, i)).collect();
+            for x in 0..22 {
+                assert_eq!(bisect_right_by_key_branchless(&pairs, &x, |p| p.0), bisect_right_by_key(&pairs, &x, |p| p.0));
+                assert_eq!(bisect_left_by_key_branchless(&pairs, &x, |p| p.0), bisect_left_by_key(&pairs, &x, |p| p.0));
+            }
+        }
+        let edges = [0, 1, u32::MAX - 1, u32::MAX];
+        for x in edges {
+            assert_eq!(bisect_right_by_key_branchless(&edges, &x, |e| *e), bisect_right(&edges, x, None, None));
+            assert_eq!(bisect_left_by_key_branchless(&edges, &x, |e| *e), bisect_left(&edges, x, None, None));
+        }
+    }
+
+    #[test]
+    fn test_bisect_left_empty() {
+        let empty: Vec<u32> = Vec::new();
+        assert_eq!(bisect_left(&empty, 5, None, None), 0);
+    }
+
+    #[test]
+    fn test_bisect_left_all_equal() {
+        let v = vec![7, 7, 7, 7];
+        assert_eq!(bisect_left(&v, 7, None, None), 0);
+        assert_eq!(bisect_right(&v, 7, None, None), 4);
+        assert_eq!(bisect_left(&v, 6, None, None), 0);
+        assert_eq!(bisect_left(&v, 8, None, None), 4);
+    }
+
+    #[test]
+    fn test_bisect_left_below_and_above() {
+        let v = vec![10, 20, 20, 30];
+        assert_eq!(bisect_left(&v, 0, None, None), 0);
+        assert_eq!(bisect_left(&v, 20, None, None), 1);
+        assert_eq!(bisect_left(&v, 25, None, None), 3);
+        assert_eq!(bisect_left(&v, 99, None, None), 4);
+        assert_eq!(bisect_left(&v, 20, Some(2), None), 2);
+        assert_eq!(bisect_left(&v, 30, None, Some(2)), 2);
+    }
+
+    #[test]
+    fn test_bisect_left_le_bisect_right_and_linear_scan() {
+        for v in sorted_samples() {
+            for x in 0..22 {
+                let left = bisect_left(&v, x, None, None);
+                let right = bisect_right(&v, x, None, None);
+                assert!(left <= right);
+                assert_eq!(left, v.iter().filter(|&&e| e < x).count());
+                assert_eq!(right, v.iter().filter(|&&e| e <= x).count());
+            }
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use serde::Deserialize;
+use toml::Spanned;
+use crate::hashing_ring::{ConsistentHashing, NodeInfoWithWeight};
+
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig<N> {
+    interleave_count: Option<usize>,
+    #[serde(default = "Vec::new")]
+    nodes: Vec<N>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawNode {
+    name: Option<String>,
+    weight: Option<i64>,
+}
+
+impl ConsistentHashing<NodeInfoWithWeight> {
+    ///Builds a ring from a TOML or YAML config listing the nodes, e.g.
+    ///
+    ///```toml
+    ///interleave_count = 80
+    ///nodes = [
+    ///    { name = "10.0.0.1:11211", weight = 2 },
+    ///    { name = "10.0.0.2:11211" },
+    ///]
+    ///```
+    ///
+    ///or the same as `[[nodes]]` tables, or in YAML as a `nodes:` list of mappings.
+    ///`weight` defaults to 1 and `interleave_count` to 40. Text whose first line
+    ///that isn't blank or a comment starts with `[` or has an `=` in it is read as
+    ///TOML, anything else as YAML.
+    ///
+    ///Nodes without a name, weights below 1, a name listed twice and unknown keys
+    ///are errors. For TOML they carry the line of the node's table; YAML errors
+    ///only carry the field path, the YAML parser keeping no positions for values,
+    ///except for unknown keys, which are syntax errors with a line either way.
+    pub fn from_config_str(config: &str) -> Result<ConsistentHashing<NodeInfoWithWeight>, ConfigError> {
+        if looks_like_toml(config) {
+            from_toml(config)
+        } else {
+            from_yaml(config)
+        }
+    }
+
+    ///Reads the config file at `path`, see `from_config_str`. Files ending in
+    ///`.toml`, `.yaml` or `.yml` are read as that format whatever they look like.
+    pub fn from_config_path<P: AsRef<Path>>(path: P) -> Result<ConsistentHashing<NodeInfoWithWeight>, ConfigError> {
+        let path = path.as_ref();
+        let config = fs::read_to_string(path).map_err(ConfigError::Io)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => from_toml(&config),
+            Some("yaml") | Some("yml") => from_yaml(&config),
+            _ => ConsistentHashing::from_config_str(&config),
+        }
+    }
+}
+
+fn looks_like_toml(config: &str) -> bool {
+    config.lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .is_some_and(|line| line.starts_with('[') || line.contains('='))
+}
+
+//1-based line of a byte offset
+fn line_of(config: &str, offset: usize) -> usize {
+    config[..offset.min(config.len())].matches('\n').count() + 1
+}
+
+fn from_toml(config: &str) -> Result<ConsistentHashing<NodeInfoWithWeight>, ConfigError> {
+    let raw: RawConfig<Spanned<RawNode>> = toml::from_str(config).map_err(|err| ConfigError::Syntax {
+        line: err.span().map(|span| line_of(config, span.start)),
+        message: err.message().to_string(),
+    })?;
+    let nodes = raw.nodes.into_iter()
+        .map(|node| (Some(line_of(config, node.span().start)), node.into_inner()))
+        .collect();
+    build(raw.interleave_count, nodes)
+}
+
+fn from_yaml(config: &str) -> Result<ConsistentHashing<NodeInfoWithWeight>, ConfigError> {
+    let raw: RawConfig<RawNode> = serde_yaml::from_str(config).map_err(|err| ConfigError::Syntax {
+        line: err.location().map(|location| location.line()),
+        message: err.to_string(),
+    })?;
+    build(raw.interleave_count, raw.nodes.into_iter().map(|node| (None, node)).collect())
+}
+
+fn build(interleave_count: Option<usize>, raw_nodes: Vec<(Option<usize>, RawNode)>) -> Result<ConsistentHashing<NodeInfoWithWeight>, ConfigError> {
+    if interleave_count == Some(0) {
+        return Err(ConfigError::invalid(None, "interleave_count".to_string(), "must be at least 1".to_string()));
+    }
+    let mut nodes: Vec<NodeInfoWithWeight> = Vec::new();
+    let mut seen: HashMap<String, usize> = HashMap::new();
+    for (index, (line, node)) in raw_nodes.into_iter().enumerate() {
+        let name = match node.name {
+            Some(name) if !name.is_empty() => name,
+            Some(_) => return Err(ConfigError::invalid(line, format!("nodes[{}].name", index), "is empty".to_string())),
+            None => return Err(ConfigError::invalid(line, format!("nodes[{}].name", index), "is missing".to_string())),
+        };
+        let weight = node.weight.unwrap_or(1);
+        if weight < 1 {
+            return Err(ConfigError::invalid(line, format!("nodes[{}].weight", index), format!("must be positive, got {}", weight)));
+        }
+        if let Some(first) = seen.insert(name.clone(), index) {
+            return Err(ConfigError::invalid(line, format!("nodes[{}].name", index), format!("{} is already nodes[{}]", name, first)));
+        }
+        nodes.push(NodeInfoWithWeight::new(name, weight as usize));
+    }
+    Ok(ConsistentHashing::new(&nodes, interleave_count))
+}
+
+
+///Error returned by `ConsistentHashing::from_config_str` and `from_config_path`.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    ///The text isn't valid TOML/YAML or doesn't have the expected shape.
+    Syntax { line: Option<usize>, message: String },
+    ///A value is rejected; `field` is its path, e.g. `nodes[2].weight`. `line` is
+    ///the line of the node's table in TOML and always `None` in YAML.
+    Invalid { line: Option<usize>, field: String, message: String },
+}
+
+impl ConfigError {
+    fn invalid(line: Option<usize>, field: String, message: String) -> ConfigError {
+        ConfigError::Invalid { line, field, message }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "can't read config: {}", err),
+            ConfigError::Syntax { line: Some(line), message } => write!(f, "line {}: {}", line, message),
+            ConfigError::Syntax { line: None, message } => write!(f, "{}", message),
+            ConfigError::Invalid { line: Some(line), field, message } => write!(f, "line {}: {} {}", line, field, message),
+            ConfigError::Invalid { line: None, field, message } => write!(f, "{} {}", field, message),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expected_ring() -> ConsistentHashing<NodeInfoWithWeight> {
+        let nodes = vec![
+            NodeInfoWithWeight::new("10.0.0.1:11211", 2),
+            NodeInfoWithWeight::new("10.0.0.2:11211", 1),
+            NodeInfoWithWeight::new("10.0.0.3:11211", 3),
+        ];
+        ConsistentHashing::new(&nodes, Some(80))
+    }
+
+    fn assert_matches_expected(ring: &ConsistentHashing<NodeInfoWithWeight>) {
+        let expected = expected_ring();
+        assert_eq!(ring.total_weight(), 6);
+        assert_eq!(ring.virtual_node_count(), expected.virtual_node_count());
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            assert_eq!(ring.get_node(&key).unwrap().node_name, expected.get_node(&key).unwrap().node_name);
+        }
+    }
+
+    fn invalid(config: &str) -> (Option<usize>, String, String) {
+        match ConsistentHashing::from_config_str(config) {
+            Err(ConfigError::Invalid { line, field, message }) => (line, field, message),
+            other => panic!("expected a validation error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_toml_inline_tables() {
+        let config = r#"
+# cache tier
+interleave_count = 80
+nodes = [
+    { name = "10.0.0.1:11211", weight = 2 },
+    { name = "10.0.0.2:11211" },
+    { name = "10.0.0.3:11211", weight = 3 },
+]
+"#;
+        assert_matches_expected(&ConsistentHashing::from_config_str(config).unwrap());
+    }
+
+    #[test]
+    fn test_toml_array_of_tables() {
+        let config = r#"interleave_count = 80
+
+[[nodes]]
+name = "10.0.0.1:11211"
+weight = 2
+
+[[nodes]]
+name = "10.0.0.2:11211"
+
+[[nodes]]
+name = "10.0.0.3:11211"
+weight = 3
+"#;
+        assert_matches_expected(&ConsistentHashing::from_config_str(config).unwrap());
+    }
+
+    #[test]
+    fn test_yaml() {
+        let config = "
+# cache tier
+interleave_count: 80
+nodes:
+  - name: 10.0.0.1:11211
+    weight: 2
+  - name: 10.0.0.2:11211
+  - name: 10.0.0.3:11211
+    weight: 3
+";
+        assert_matches_expected(&ConsistentHashing::from_config_str(config).unwrap());
+    }
+
+    #[test]
+    fn test_defaults() {
+        let ring = ConsistentHashing::from_config_str("nodes = [{ name = \"10.0.0.1:11211\" }]").unwrap();
+        assert_eq!(ring.weight_of("10.0.0.1:11211"), Some(1));
+        assert_eq!(ring.virtual_node_count(), 120);
+        assert!(ConsistentHashing::from_config_str("").unwrap().get_node("my_key").is_none());
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let missing = "interleave_count = 80\nnodes = [\n  { name = \"a\" },\n  { weight = 2 },\n]\n";
+        assert_eq!(invalid(missing), (Some(4), "nodes[1].name".to_string(), "is missing".to_string()));
+        assert_eq!(invalid("nodes = [{ name = \"\" }]").2, "is empty");
+
+        let zero = "[[nodes]]\nname = \"a\"\n\n[[nodes]]\nname = \"b\"\nweight = 0\n";
+        let (line, field, message) = invalid(zero);
+        assert_eq!((line.unwrap() >= 4, field.as_str(), message.as_str()), (true, "nodes[1].weight", "must be positive, got 0"));
+        assert_eq!(invalid("nodes:\n  - name: a\n    weight: -3\n"), (None, "nodes[0].weight".to_string(), "must be positive, got -3".to_string()));
+
+        let duplicate = "nodes = [\n  { name = \"a\" },\n  { name = \"b\" },\n  { name = \"a\" },\n]\n";
+        assert_eq!(invalid(duplicate), (Some(4), "nodes[2].name".to_string(), "a is already nodes[0]".to_string()));
+        assert_eq!(invalid("nodes:\n  - name: a\n  - name: a\n").2, "a is already nodes[0]");
+
+        assert_eq!(invalid("interleave_count = 0").1, "interleave_count");
+
+        let err = ConsistentHashing::from_config_str(duplicate).err().unwrap();
+        assert_eq!(err.to_string(), "line 4: nodes[2].name a is already nodes[0]");
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        for config in &["nodes = [{ name = \"a\", wieght = 2 }]", "nodes = [{ name = \"a\"", "nodes:\n  - name: a\n    weight: heavy\n", "nodes: 3"] {
+            match ConsistentHashing::from_config_str(config) {
+                Err(ConfigError::Syntax { .. }) => {}
+                other => panic!("{}: expected a syntax error, got {:?}", config, other.map(|_| ())),
+            }
+        }
+        match ConsistentHashing::from_config_str("interleave_count = 80\nnodes = [{ name = \"a\", wieght = 2 }]") {
+            Err(ConfigError::Syntax { line, message }) => {
+                assert_eq!(line, Some(2));
+                assert!(message.contains("wieght"), "{}", message);
+            }
+            other => panic!("expected a syntax error, got {:?}", other.map(|_| ())),
+        }
+        //unknown keys have a line in YAML too, unlike validation errors
+        match ConsistentHashing::from_config_str("nodes:\n  - name: a\n  - name: b\n    wieght: 2\n") {
+            Err(ConfigError::Syntax { line, message }) => {
+                assert_eq!(line, Some(4));
+                assert!(message.contains("wieght"), "{}", message);
+            }
+            other => panic!("expected a syntax error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_from_config_path() {
+        let dir = std::env::temp_dir().join(format!("consistent-hashing-ring-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let toml_path = dir.join("ring.toml");
+        fs::write(&toml_path, "interleave_count = 80\nnodes = [{ name = \"10.0.0.1:11211\", weight = 2 }, { name = \"10.0.0.2:11211\" }, { name = \"10.0.0.3:11211\", weight = 3 }]\n").unwrap();
+        assert_matches_expected(&ConsistentHashing::from_config_path(&toml_path).unwrap());
+
+        //the extension wins over the content sniffing
+        let yaml_path = dir.join("ring.yml");
+        fs::write(&yaml_path, "{interleave_count: 80, nodes: [{name: \"10.0.0.1:11211\", weight: 2}, {name: \"10.0.0.2:11211\"}, {name: \"10.0.0.3:11211\", weight: 3}]}\n").unwrap();
+        assert_matches_expected(&ConsistentHashing::from_config_path(&yaml_path).unwrap());
+
+        match ConsistentHashing::from_config_path(dir.join("missing.toml")) {
+            Err(err @ ConfigError::Io(_)) => assert!(err.source().is_some()),
+            other => panic!("expected an io error, got {:?}", other.map(|_| ())),
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
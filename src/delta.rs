@@ -0,0 +1,277 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use crate::hashing_ring::{ConsistentHashing, NodeInfoWithWeight, RingNode, WithWeightInfo};
+
+
+///A change of membership between two rings: the nodes added with their weights,
+///the nodes removed and the nodes whose weight changed, each list in name order,
+///together with the `fingerprint` of the ring before and after. A control plane
+///sends these instead of the whole ring; `apply_delta` replays one and checks it
+///arrived where the sender's ring did. With the `serde` feature it's
+///`Serialize`/`Deserialize`.
+///
+///Drains aren't part of a delta. A delta across a drain or undrain still carries
+///the fingerprint of the other side, so `apply_delta` refuses it.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RingDelta {
+    pub added: Vec<(String, usize)>,
+    pub removed: Vec<String>,
+    pub reweighted: Vec<(String, usize)>,
+    pub base_fingerprint: u64,
+    pub fingerprint: u64,
+}
+
+impl RingDelta {
+    ///Whether the delta changes no node. It may still move points, e.g. across a
+    ///drain, in which case its fingerprints differ.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.reweighted.is_empty()
+    }
+}
+
+///Returns the delta that takes `old`'s membership to `new`'s. Applying it to `old`
+///removes, reweights and adds nodes in that order, each in name order; whether
+///that ends on `new`'s fingerprint depends on how `new` came about. It always does
+///when `new` was derived from `old` the same way, e.g. under `track_delta`, and
+///for rings built with `points_per_weight`, where a node's points don't depend on
+///the other nodes.
+pub fn diff<T: RingNode + std::hash::Hash + Clone + WithWeightInfo>(old: &ConsistentHashing<T>, new: &ConsistentHashing<T>) -> RingDelta {
+    membership_delta(&membership(old), &membership(new), old.fingerprint(), new.fingerprint())
+}
+
+fn membership<T: RingNode + std::hash::Hash + Clone + WithWeightInfo>(ring: &ConsistentHashing<T>) -> BTreeMap<String, usize> {
+    ring.nodes_in_order().into_iter().map(|node| (node.ring_id().to_string(), node.get_weight())).collect()
+}
+
+fn membership_delta(old: &BTreeMap<String, usize>, new: &BTreeMap<String, usize>, base_fingerprint: u64, fingerprint: u64) -> RingDelta {
+    let mut delta = RingDelta { base_fingerprint, fingerprint, ..RingDelta::default() };
+    for (name, weight) in new {
+        match old.get(name) {
+            None => delta.added.push((name.clone(), *weight)),
+            Some(old_weight) if old_weight != weight => delta.reweighted.push((name.clone(), *weight)),
+            Some(_) => {}
+        }
+    }
+    delta.removed = old.keys().filter(|name| !new.contains_key(*name)).cloned().collect();
+    delta
+}
+
+///Error returned by `ConsistentHashing::apply_delta`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DeltaError {
+    ///The ring isn't the one the delta was taken from; it's left alone.
+    BaseMismatch { expected: u64, actual: u64 },
+    ///The delta removes or reweights a node the ring doesn't have; it's left alone.
+    UnknownNode { name: String },
+    ///The delta adds a node the ring already has; it's left alone.
+    DuplicateNode { name: String },
+    ///Applying the delta would have left the ring elsewhere than the sender's, e.g.
+    ///because the two were built with different settings. The ring is put back on
+    ///the delta's base and has to be resynchronized in full.
+    ResultMismatch { expected: u64, actual: u64 },
+}
+
+impl fmt::Display for DeltaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeltaError::BaseMismatch { expected, actual } => write!(f, "delta was taken from ring {:016x}, not {:016x}", expected, actual),
+            DeltaError::UnknownNode { name } => write!(f, "delta changes node {}, which is not on the ring", name),
+            DeltaError::DuplicateNode { name } => write!(f, "delta adds node {}, which is already on the ring", name),
+            DeltaError::ResultMismatch { expected, actual } => write!(f, "delta should have led to ring {:016x}, got {:016x}", expected, actual),
+        }
+    }
+}
+
+impl Error for DeltaError {}
+
+impl ConsistentHashing<NodeInfoWithWeight> {
+    ///Runs `mutate` on the ring and returns the delta it made, for broadcasting the
+    ///change with the same calls the ring itself went through.
+    pub fn track_delta<F: FnOnce(&mut Self)>(&mut self, mutate: F) -> RingDelta {
+        let before = membership(self);
+        let base_fingerprint = self.fingerprint();
+        mutate(self);
+        membership_delta(&before, &membership(self), base_fingerprint, self.fingerprint())
+    }
+
+    ///Applies a delta from `diff` or `track_delta`. Fails without touching the ring
+    ///if the ring's fingerprint isn't the delta's base or the delta doesn't fit its
+    ///membership, and with `DeltaError::ResultMismatch` if the ring wouldn't end on
+    ///the delta's fingerprint, in which case the ring is put back as it was.
+    pub fn apply_delta(&mut self, delta: &RingDelta) -> Result<(), DeltaError> {
+        let actual = self.fingerprint();
+        if actual != delta.base_fingerprint {
+            return Err(DeltaError::BaseMismatch { expected: delta.base_fingerprint, actual });
+        }
+        if let Some(name) = delta.removed.iter().chain(delta.reweighted.iter().map(|(name, _)| name)).find(|name| !self.contains_node(name)) {
+            return Err(DeltaError::UnknownNode { name: name.clone() });
+        }
+        if let Some((name, _)) = delta.added.iter().find(|(name, _)| self.contains_node(name) && !delta.removed.contains(name)) {
+            return Err(DeltaError::DuplicateNode { name: name.clone() });
+        }
+
+        let saved = self.save_state();
+        for name in &delta.removed {
+            self.remove_node_by_name(name);
+        }
+        for (name, weight) in &delta.reweighted {
+            self.update_weight_by_name(name, *weight);
+        }
+        for (name, weight) in &delta.added {
+            self.add_node(NodeInfoWithWeight::new(name.clone(), *weight));
+        }
+
+        let actual = self.fingerprint();
+        if actual != delta.fingerprint {
+            self.restore_state(saved);
+            return Err(DeltaError::ResultMismatch { expected: delta.fingerprint, actual });
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nodes() -> Vec<NodeInfoWithWeight> {
+        vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+        ]
+    }
+
+    fn assert_same_ring(a: &ConsistentHashing<NodeInfoWithWeight>, b: &ConsistentHashing<NodeInfoWithWeight>) {
+        assert_eq!(a.fingerprint(), b.fingerprint());
+        assert_eq!(a.dump_ranges(), b.dump_ranges());
+        for i in 0..500 {
+            let key = format!("key-{}", i);
+            assert_eq!(a.get_node(&key).unwrap().node_name, b.get_node(&key).unwrap().node_name);
+        }
+    }
+
+    fn mutate(ring: &mut ConsistentHashing<NodeInfoWithWeight>) {
+        ring.remove_node_by_name("192.168.0.101:11212");
+        ring.update_weight_by_name("192.168.0.102:11212", 3);
+        ring.add_node(NodeInfoWithWeight::new("192.168.0.104:11212", 2));
+        ring.add_node(NodeInfoWithWeight::new("192.168.0.105:11212", 1));
+    }
+
+    #[test]
+    fn test_track_and_apply() {
+        let mut sender = ConsistentHashing::new(&nodes(), None);
+        let mut receiver = ConsistentHashing::new(&nodes(), None);
+        let delta = sender.track_delta(mutate);
+        assert_eq!(delta.added, vec![("192.168.0.104:11212".to_string(), 2), ("192.168.0.105:11212".to_string(), 1)]);
+        assert_eq!(delta.removed, vec!["192.168.0.101:11212".to_string()]);
+        assert_eq!(delta.reweighted, vec![("192.168.0.102:11212".to_string(), 3)]);
+        assert_eq!(delta.base_fingerprint, receiver.fingerprint());
+
+        receiver.apply_delta(&delta).unwrap();
+        assert_same_ring(&receiver, &sender);
+
+        //applied twice, the second time is against the wrong base
+        let fingerprint = receiver.fingerprint();
+        assert_eq!(receiver.apply_delta(&delta), Err(DeltaError::BaseMismatch { expected: delta.base_fingerprint, actual: fingerprint }));
+        assert_eq!(receiver.fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = ConsistentHashing::builder(&nodes()).points_per_weight(40).build();
+        let mut new_nodes = nodes();
+        new_nodes.remove(0);
+        new_nodes[0].weight = 3;
+        new_nodes.push(NodeInfoWithWeight::new("192.168.0.104:11212", 2));
+        let new = ConsistentHashing::builder(&new_nodes).points_per_weight(40).build();
+
+        let delta = diff(&old, &new);
+        assert_eq!(delta.added, vec![("192.168.0.104:11212".to_string(), 2)]);
+        assert_eq!(delta.removed, vec!["192.168.0.101:11212".to_string()]);
+        assert_eq!(delta.reweighted, vec![("192.168.0.102:11212".to_string(), 3)]);
+        let mut applied = ConsistentHashing::builder(&nodes()).points_per_weight(40).build();
+        applied.apply_delta(&delta).unwrap();
+        assert_same_ring(&applied, &new);
+
+        let unchanged = diff(&old, &old);
+        assert!(unchanged.is_empty());
+        assert_eq!(unchanged.base_fingerprint, unchanged.fingerprint);
+    }
+
+    #[test]
+    fn test_wrong_base_fails() {
+        let mut sender = ConsistentHashing::new(&nodes(), None);
+        let delta = sender.track_delta(mutate);
+
+        //a receiver that missed an earlier change, or runs other settings
+        let mut behind = ConsistentHashing::new(&nodes()[..2].to_vec(), None);
+        let mut other_settings = ConsistentHashing::new(&nodes(), Some(80));
+        for ring in [&mut behind, &mut other_settings] {
+            let fingerprint = ring.fingerprint();
+            match ring.apply_delta(&delta) {
+                Err(DeltaError::BaseMismatch { expected, actual }) => {
+                    assert_eq!(expected, delta.base_fingerprint);
+                    assert_eq!(actual, fingerprint);
+                }
+                other => panic!("{:?}", other),
+            }
+            assert_eq!(ring.fingerprint(), fingerprint);
+        }
+    }
+
+    #[test]
+    fn test_inconsistent_delta_fails() {
+        let ring = ConsistentHashing::new(&nodes(), None);
+        let base = RingDelta { base_fingerprint: ring.fingerprint(), fingerprint: ring.fingerprint(), ..RingDelta::default() };
+
+        let mut unknown = ConsistentHashing::new(&nodes(), None);
+        let delta = RingDelta { removed: vec!["192.168.0.199:11212".to_string()], ..base.clone() };
+        assert_eq!(unknown.apply_delta(&delta), Err(DeltaError::UnknownNode { name: "192.168.0.199:11212".to_string() }));
+        let delta = RingDelta { added: vec![("192.168.0.101:11212".to_string(), 1)], ..base.clone() };
+        assert_eq!(unknown.apply_delta(&delta), Err(DeltaError::DuplicateNode { name: "192.168.0.101:11212".to_string() }));
+        assert_eq!(unknown.fingerprint(), ring.fingerprint());
+
+        //a delta whose target the ring can't reach
+        let delta = RingDelta { added: vec![("192.168.0.104:11212".to_string(), 1)], ..base };
+        let mut diverged = ConsistentHashing::new(&nodes(), None);
+        diverged.set_load("192.168.0.101:11212", 3);
+        match diverged.apply_delta(&delta) {
+            Err(DeltaError::ResultMismatch { expected, .. }) => assert_eq!(expected, ring.fingerprint()),
+            other => panic!("{:?}", other),
+        }
+        assert!(!diverged.contains_node("192.168.0.104:11212"));
+        assert_eq!(diverged.fingerprint(), ring.fingerprint());
+        assert_eq!(diverged.dump_ranges(), ring.dump_ranges());
+        assert_eq!(diverged.load_of("192.168.0.101:11212"), 3);
+    }
+
+    #[test]
+    fn test_drain_is_not_carried() {
+        let mut sender = ConsistentHashing::new(&nodes(), None);
+        let delta = sender.track_delta(|ring| ring.drain_node("192.168.0.101:11212"));
+        assert!(delta.is_empty());
+        assert_ne!(delta.base_fingerprint, delta.fingerprint);
+        let mut receiver = ConsistentHashing::new(&nodes(), None);
+        assert!(matches!(receiver.apply_delta(&delta), Err(DeltaError::ResultMismatch { .. })));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() {
+        let mut sender = ConsistentHashing::new(&nodes(), None);
+        let delta = sender.track_delta(mutate);
+
+        let json: RingDelta = serde_json::from_str(&serde_json::to_string(&delta).unwrap()).unwrap();
+        let binary: RingDelta = bincode::deserialize(&bincode::serialize(&delta).unwrap()).unwrap();
+        assert_eq!(json, delta);
+        assert_eq!(binary, delta);
+
+        let mut receiver = ConsistentHashing::new(&nodes(), None);
+        receiver.apply_delta(&json).unwrap();
+        assert_same_ring(&receiver, &sender);
+    }
+}
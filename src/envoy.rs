@@ -0,0 +1,434 @@
+use std::hash::Hash;
+use crate::hashing_ring::{RingNode, WithWeightInfo};
+
+
+///Envoy's default `minimum_ring_size`.
+const DEFAULT_MINIMUM_RING_SIZE: u64 = 1024;
+
+///Envoy's default `maximum_ring_size`, also the largest it accepts.
+const DEFAULT_MAXIMUM_RING_SIZE: u64 = 8 * 1024 * 1024;
+
+///`MurmurHash::STD_HASH_SEED`, libstdc++'s `std::hash` seed.
+const STD_HASH_SEED: u64 = 0xc70f6907;
+
+
+///The `hash_function` of an Envoy `RingHashLbConfig`, used for the ring entries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EnvoyHashFunction {
+    ///`XX_HASH`, xxHash64 with seed 0. The default.
+    XxHash,
+    ///`MURMUR_HASH_2`, the 64 bit MurmurHash2 variant of libstdc++'s `std::hash`.
+    MurmurHash2,
+}
+
+///Builder for an `EnvoyRing`, laid out like the ring of Envoy's `ring_hash` load
+///balancer for the same hosts and `RingHashLbConfig`, so both pick the same host
+///for a request hash.
+///
+///A host is identified by `ring_id`, which has to be the string Envoy hashes: the
+///address as Envoy prints it (`"10.0.0.1:8080"`, `"[::1]:8080"`), or the hostname
+///with `use_hostname_for_hashing`. Hosts are taken in the order given, like the
+///hosts of Envoy's priority set. Only a single locality is modelled.
+pub struct EnvoyRingHashCompat<T: RingNode + Hash + Clone + WithWeightInfo> {
+    hosts: Vec<T>,
+    minimum_ring_size: u64,
+    maximum_ring_size: u64,
+    hash_function: EnvoyHashFunction,
+}
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> EnvoyRingHashCompat<T> {
+    pub fn new(hosts: &[T]) -> EnvoyRingHashCompat<T> {
+        EnvoyRingHashCompat {
+            hosts: hosts.to_vec(),
+            minimum_ring_size: DEFAULT_MINIMUM_RING_SIZE,
+            maximum_ring_size: DEFAULT_MAXIMUM_RING_SIZE,
+            hash_function: EnvoyHashFunction::XxHash,
+        }
+    }
+
+    ///`minimum_ring_size`: enough entries are made for the lightest host to get
+    ///a whole number of them and the ring to have at least this many. Defaults to
+    ///1024.
+    pub fn minimum_ring_size(mut self, minimum_ring_size: u64) -> Self {
+        self.minimum_ring_size = minimum_ring_size;
+        self
+    }
+
+    ///`maximum_ring_size`, the cap on the ring's entries. Defaults to 8M.
+    pub fn maximum_ring_size(mut self, maximum_ring_size: u64) -> Self {
+        self.maximum_ring_size = maximum_ring_size;
+        self
+    }
+
+    ///`hash_function`, how ring entries are hashed. Defaults to `XxHash`.
+    pub fn hash_function(mut self, hash_function: EnvoyHashFunction) -> Self {
+        self.hash_function = hash_function;
+        self
+    }
+
+    ///Builds the ring the way `RingHashLoadBalancer::Ring` does. Hosts have their
+    ///weight normalized against the total weight; a weight of 0 stands for a host
+    ///without an explicit `load_balancing_weight` and counts as Envoy's default of 1.
+    ///
+    ///Panics if the minimum ring size is above the maximum, which Envoy rejects as
+    ///well.
+    pub fn build(self) -> EnvoyRing<T> {
+        assert!(self.minimum_ring_size <= self.maximum_ring_size, "minimum ring size must not exceed the maximum ring size");
+        let mut ring = EnvoyRing { entries: Vec::new(), hashes_per_host: (0, 0) };
+        if self.hosts.is_empty() {
+            return ring;
+        }
+
+        let weights: Vec<u64> = self.hosts.iter().map(|host| host.get_weight().max(1) as u64).collect();
+        let sum: u64 = weights.iter().sum();
+        let normalized: Vec<f64> = weights.iter().map(|weight| *weight as f64 * 1.0 / sum as f64).collect();
+        let min_normalized_weight = normalized.iter().fold(1.0f64, |min, weight| min.min(*weight));
+
+        let scale = ((min_normalized_weight * self.minimum_ring_size as f64).ceil() / min_normalized_weight)
+            .min(self.maximum_ring_size as f64);
+
+        //running sums keep the number of entries per host stable when scale * weight
+        //isn't a whole number
+        let mut current_hashes = 0.0f64;
+        let mut target_hashes = 0.0f64;
+        let mut min_hashes_per_host = scale.ceil() as usize;
+        let mut max_hashes_per_host = 0;
+        for (host, weight) in self.hosts.iter().zip(normalized.iter()) {
+            target_hashes += scale * weight;
+            let mut i = 0;
+            while current_hashes < target_hashes {
+                let hash_key = format!("{}_{}", host.ring_id(), i);
+                let hash = match self.hash_function {
+                    EnvoyHashFunction::XxHash => xx_hash64(hash_key.as_bytes(), 0),
+                    EnvoyHashFunction::MurmurHash2 => murmur_hash2(hash_key.as_bytes(), STD_HASH_SEED),
+                };
+                ring.entries.push((hash, host.clone()));
+                i += 1;
+                current_hashes += 1.0;
+            }
+            min_hashes_per_host = min_hashes_per_host.min(i);
+            max_hashes_per_host = max_hashes_per_host.max(i);
+        }
+        ring.entries.sort_by_key(|&(hash, _)| hash);
+        ring.hashes_per_host = (min_hashes_per_host, max_hashes_per_host);
+        ring
+    }
+}
+
+
+///A ring built by `EnvoyRingHashCompat`.
+pub struct EnvoyRing<T: RingNode + Hash + Clone + WithWeightInfo> {
+    //sorted by hash
+    entries: Vec<(u64, T)>,
+    hashes_per_host: (usize, usize),
+}
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> EnvoyRing<T> {
+    ///Number of entries on the ring, Envoy's `size` stat.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    ///The fewest and the most entries any host got, Envoy's `min_hashes_per_host`
+    ///and `max_hashes_per_host` stats.
+    pub fn hashes_per_host(&self) -> (usize, usize) {
+        self.hashes_per_host
+    }
+
+    ///Lists the ring in order as `(hash, ring_id)` pairs.
+    pub fn entries(&self) -> Vec<(u64, &str)> {
+        self.entries.iter().map(|(hash, host)| (*hash, host.ring_id())).collect()
+    }
+
+    ///Given a string key the host is returned that Envoy picks for a hash policy on
+    ///a header with that value, which hashes it with xxHash64. If the ring is empty,
+    ///`None` is returned.
+    pub fn get_node(&self, key: &str) -> Option<T> {
+        self.get_node_for_hash(xx_hash64(key.as_bytes(), 0))
+    }
+
+    ///The host `Ring::chooseHost` picks for the request hash `hash`, for hash
+    ///policies (cookies, source IP, filter state) whose hash is worked out
+    ///elsewhere.
+    pub fn get_node_for_hash(&self, hash: u64) -> Option<T> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        Some(self.entries[self.pos_for_hash(hash)].1.clone())
+    }
+
+    //chooseHost's port of ketama_get_server, signed bounds and all
+    fn pos_for_hash(&self, hash: u64) -> usize {
+        let size = self.entries.len() as i64;
+        let mut lowp: i64 = 0;
+        let mut highp: i64 = size;
+        loop {
+            let midp = (lowp + highp) / 2;
+            if midp == size {
+                return 0;
+            }
+            let midval = self.entries[midp as usize].0;
+            let midval1 = if midp == 0 { 0 } else { self.entries[midp as usize - 1].0 };
+            if hash <= midval && hash > midval1 {
+                return midp as usize;
+            }
+            if midval < hash {
+                lowp = midp + 1;
+            } else {
+                highp = midp - 1;
+            }
+            if lowp > highp {
+                return 0;
+            }
+        }
+    }
+}
+
+
+const PRIME64_1: u64 = 0x9e3779b185ebca87;
+const PRIME64_2: u64 = 0xc2b2ae3d27d4eb4f;
+const PRIME64_3: u64 = 0x165667b19e3779f9;
+const PRIME64_4: u64 = 0x85ebca77c2b2ae63;
+const PRIME64_5: u64 = 0x27d4eb2f165667c5;
+
+fn read_u64(bytes: &[u8]) -> u64 {
+    let mut word = [0u8; 8];
+    word.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(word)
+}
+
+fn xx_round(acc: u64, input: u64) -> u64 {
+    acc.wrapping_add(input.wrapping_mul(PRIME64_2)).rotate_left(31).wrapping_mul(PRIME64_1)
+}
+
+fn xx_merge_round(acc: u64, value: u64) -> u64 {
+    (acc ^ xx_round(0, value)).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4)
+}
+
+///XXH64, what Envoy's `HashUtil::xxHash64` computes.
+fn xx_hash64(input: &[u8], seed: u64) -> u64 {
+    let mut rest = input;
+    let mut hash = if input.len() >= 32 {
+        let mut v = [
+            seed.wrapping_add(PRIME64_1).wrapping_add(PRIME64_2),
+            seed.wrapping_add(PRIME64_2),
+            seed,
+            seed.wrapping_sub(PRIME64_1),
+        ];
+        while rest.len() >= 32 {
+            for (lane, acc) in v.iter_mut().enumerate() {
+                *acc = xx_round(*acc, read_u64(&rest[lane * 8..]));
+            }
+            rest = &rest[32..];
+        }
+        let mut hash = v[0].rotate_left(1)
+            .wrapping_add(v[1].rotate_left(7))
+            .wrapping_add(v[2].rotate_left(12))
+            .wrapping_add(v[3].rotate_left(18));
+        for acc in v.iter() {
+            hash = xx_merge_round(hash, *acc);
+        }
+        hash
+    } else {
+        seed.wrapping_add(PRIME64_5)
+    };
+    hash = hash.wrapping_add(input.len() as u64);
+
+    while rest.len() >= 8 {
+        hash ^= xx_round(0, read_u64(rest));
+        hash = hash.rotate_left(27).wrapping_mul(PRIME64_1).wrapping_add(PRIME64_4);
+        rest = &rest[8..];
+    }
+    if rest.len() >= 4 {
+        let word = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]) as u64;
+        hash ^= word.wrapping_mul(PRIME64_1);
+        hash = hash.rotate_left(23).wrapping_mul(PRIME64_2).wrapping_add(PRIME64_3);
+        rest = &rest[4..];
+    }
+    for byte in rest {
+        hash ^= (*byte as u64).wrapping_mul(PRIME64_5);
+        hash = hash.rotate_left(11).wrapping_mul(PRIME64_1);
+    }
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(PRIME64_2);
+    hash ^= hash >> 29;
+    hash = hash.wrapping_mul(PRIME64_3);
+    hash ^ (hash >> 32)
+}
+
+fn shift_mix(value: u64) -> u64 {
+    value ^ (value >> 47)
+}
+
+///Envoy's `MurmurHash::murmurHash2`, a copy of libstdc++'s 64 bit `_Hash_bytes`.
+fn murmur_hash2(input: &[u8], seed: u64) -> u64 {
+    const MUL: u64 = 0xc6a4a7935bd1e995;
+    let aligned = input.len() & !0x7;
+    let mut hash = seed ^ (input.len() as u64).wrapping_mul(MUL);
+    for chunk in input[..aligned].chunks(8) {
+        let data = shift_mix(read_u64(chunk).wrapping_mul(MUL)).wrapping_mul(MUL);
+        hash ^= data;
+        hash = hash.wrapping_mul(MUL);
+    }
+    if input.len() & 0x7 != 0 {
+        //the tail's bytes little endian
+        let data = input[aligned..].iter().rev().fold(0u64, |data, byte| (data << 8) + *byte as u64);
+        hash ^= data;
+        hash = hash.wrapping_mul(MUL);
+    }
+    hash = shift_mix(hash).wrapping_mul(MUL);
+    shift_mix(hash)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::hashing_ring::{NodeInfo, NodeInfoWithWeight};
+
+    fn hosts(ports: std::ops::Range<u16>) -> Vec<NodeInfo> {
+        ports.map(|port| NodeInfo::new(format!("127.0.0.1:{}", port))).collect()
+    }
+
+    #[test]
+    fn test_hash_functions() {
+        assert_eq!(xx_hash64(b"", 0), 0xef46db3751d8e999);
+        assert_eq!(xx_hash64(b"a", 0), 0xd24ec4f1a98c6e5b);
+        assert_eq!(xx_hash64(b"abc", 0), 0x44bc2cf5ad770999);
+        assert_eq!(xx_hash64(b"The quick brown fox jumps over the lazy dog", 0), 0x0b242d361fda71bc);
+        assert_ne!(murmur_hash2(b"127.0.0.1:80_0", STD_HASH_SEED), murmur_hash2(b"127.0.0.1:80_1", STD_HASH_SEED));
+    }
+
+    //the rings below are the ones spelled out in Envoy's ring_hash_lb_test.cc
+    #[test]
+    fn test_basic() {
+        let hosts = hosts(90..96);
+        let ring = EnvoyRingHashCompat::new(&hosts).minimum_ring_size(12).build();
+        assert_eq!(ring.len(), 12);
+        assert_eq!(ring.hashes_per_host(), (2, 2));
+        assert_eq!(ring.entries(), vec![
+            (833437586790550860, "127.0.0.1:94"),
+            (928266305478181108, "127.0.0.1:92"),
+            (1033482794131418490, "127.0.0.1:90"),
+            (3551244743356806947, "127.0.0.1:95"),
+            (3851675632748031481, "127.0.0.1:93"),
+            (5583722120771150861, "127.0.0.1:91"),
+            (6311230543546372928, "127.0.0.1:91"),
+            (7700377290971790572, "127.0.0.1:93"),
+            (13144177310400110813, "127.0.0.1:95"),
+            (13444792449719432967, "127.0.0.1:92"),
+            (15516499411664133160, "127.0.0.1:94"),
+            (16117243373044804889, "127.0.0.1:90"),
+        ]);
+        assert_eq!(ring.get_node_for_hash(0).unwrap().node_name, hosts[4].node_name);
+        assert_eq!(ring.get_node_for_hash(u64::MAX).unwrap().node_name, hosts[4].node_name);
+        assert_eq!(ring.get_node_for_hash(3551244743356806947).unwrap().node_name, hosts[5].node_name);
+        assert_eq!(ring.get_node_for_hash(3551244743356806948).unwrap().node_name, hosts[3].node_name);
+    }
+
+    #[test]
+    fn test_basic_with_murmur2() {
+        let hosts = hosts(80..86);
+        let ring = EnvoyRingHashCompat::new(&hosts)
+            .minimum_ring_size(12)
+            .hash_function(EnvoyHashFunction::MurmurHash2)
+            .build();
+        assert_eq!(ring.len(), 12);
+        assert_eq!(ring.entries()[0], (1358027074129602068, "127.0.0.1:85"));
+        assert_eq!(ring.get_node_for_hash(10).unwrap().node_name, hosts[5].node_name);
+        assert_eq!(ring.get_node_for_hash(u64::MAX).unwrap().node_name, hosts[5].node_name);
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for (_, host) in ring.entries() {
+            *counts.entry(host).or_insert(0) += 1;
+        }
+        assert!(counts.values().all(|count| *count == 2));
+    }
+
+    #[test]
+    fn test_uneven_hosts() {
+        let ring = EnvoyRingHashCompat::new(&hosts(80..82)).minimum_ring_size(3).build();
+        assert_eq!(ring.entries(), vec![
+            (5454692015285649509, "127.0.0.1:80"),
+            (7859399908942313493, "127.0.0.1:81"),
+            (13838424394637650569, "127.0.0.1:80"),
+            (16064866803292627174, "127.0.0.1:81"),
+        ]);
+        assert_eq!(ring.get_node_for_hash(0).unwrap().node_name, "127.0.0.1:80");
+    }
+
+    #[test]
+    fn test_host_weighted_tiny_ring() {
+        let hosts = vec![
+            NodeInfoWithWeight::new("127.0.0.1:90", 1),
+            NodeInfoWithWeight::new("127.0.0.1:91", 2),
+            NodeInfoWithWeight::new("127.0.0.1:92", 3),
+        ];
+        let ring = EnvoyRingHashCompat::new(&hosts).minimum_ring_size(6).maximum_ring_size(6).build();
+        assert_eq!(ring.len(), 6);
+        assert_eq!(ring.hashes_per_host(), (1, 3));
+        let expected = [
+            (928266305478181108, 2),
+            (4443673547860492590, 2),
+            (5583722120771150861, 1),
+            (6311230543546372928, 1),
+            (13444792449719432967, 2),
+            (16117243373044804889, 0),
+        ];
+        for (hash, host) in expected.iter() {
+            assert_eq!(ring.get_node_for_hash(*hash).unwrap().node_name, hosts[*host].node_name, "{}", hash);
+        }
+    }
+
+    #[test]
+    fn test_weights() {
+        //an unset weight is a weight of 1
+        let unset = vec![NodeInfoWithWeight::new("127.0.0.1:90", 0), NodeInfoWithWeight::new("127.0.0.1:91", 0)];
+        let one = vec![NodeInfoWithWeight::new("127.0.0.1:90", 1), NodeInfoWithWeight::new("127.0.0.1:91", 1)];
+        assert_eq!(EnvoyRingHashCompat::new(&unset).build().entries(), EnvoyRingHashCompat::new(&one).build().entries());
+
+        //the lightest host gets a whole number of entries, the others follow
+        let hosts = vec![NodeInfoWithWeight::new("127.0.0.1:90", 1), NodeInfoWithWeight::new("127.0.0.1:91", 3)];
+        let ring = EnvoyRingHashCompat::new(&hosts).minimum_ring_size(10).build();
+        assert_eq!(ring.len(), 12);
+        assert_eq!(ring.hashes_per_host(), (3, 9));
+
+        //the maximum wins over the minimum
+        let capped = EnvoyRingHashCompat::new(&hosts).minimum_ring_size(10).maximum_ring_size(10).build();
+        assert_eq!(capped.len(), 10);
+    }
+
+    #[test]
+    fn test_get_node() {
+        let hosts = hosts(90..96);
+        let ring = EnvoyRingHashCompat::new(&hosts).build();
+        assert_eq!(ring.len(), 1026);
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            let hash = xx_hash64(key.as_bytes(), 0);
+            let first_at_or_after = ring.entries().iter()
+                .find(|(entry, _)| *entry >= hash)
+                .map(|(_, host)| host.to_string())
+                .unwrap_or_else(|| ring.entries()[0].1.to_string());
+            assert_eq!(ring.get_node(&key).unwrap().node_name, first_at_or_after);
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        let ring: EnvoyRing<NodeInfo> = EnvoyRingHashCompat::new(&[]).build();
+        assert!(ring.is_empty());
+        assert!(ring.get_node("my_key").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "minimum ring size must not exceed the maximum ring size")]
+    fn test_minimum_above_maximum() {
+        EnvoyRingHashCompat::new(&hosts(90..92)).minimum_ring_size(20).maximum_ring_size(10).build();
+    }
+}
@@ -0,0 +1,331 @@
+//!C bindings for `ConsistentHashing<NodeInfoWithWeight>`, so C and C++ callers
+//!share placement with Rust services instead of porting it. Build the shared
+//!library with
+//!
+//!```text
+//!cargo rustc --release --features ffi --crate-type cdylib
+//!```
+//!
+//!and include `include/consistent_hashing_ring.h`, which cbindgen generates from
+//!this module (`cbindgen --config cbindgen.toml --output include/consistent_hashing_ring.h`).
+//!
+//!Rings are referred to by handles rather than pointers, so a handle that was
+//!never issued or has already been freed is reported as `RING_ERR_INVALID_HANDLE`
+//!instead of being dereferenced. Every function returns one of the `RING_*` codes
+//!and never unwinds into the caller; a panic inside comes back as
+//!`RING_ERR_PANIC`. Handles may be used from several threads at once.
+use std::collections::BTreeMap;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use crate::hashing_ring::{ConsistentHashing, NodeInfoWithWeight};
+
+
+///Identifies a ring created by `ring_new`. 0 is never a valid handle.
+pub type RingHandle = u64;
+
+pub const RING_OK: c_int = 0;
+///A pointer argument is null.
+pub const RING_ERR_NULL: c_int = -1;
+///A string argument isn't valid UTF-8.
+pub const RING_ERR_UTF8: c_int = -2;
+///The handle doesn't name a live ring.
+pub const RING_ERR_INVALID_HANDLE: c_int = -3;
+///No node was found: the node to remove isn't on the ring, or the ring has no
+///node for the key.
+pub const RING_ERR_NOT_FOUND: c_int = -4;
+///A node with the same name is already on the ring.
+pub const RING_ERR_EXISTS: c_int = -5;
+///The output buffer can't hold the result; the size it needs has been written.
+pub const RING_ERR_BUFFER_TOO_SMALL: c_int = -6;
+///The call panicked. The ring is still usable, but may be missing the change.
+pub const RING_ERR_PANIC: c_int = -7;
+
+type Ring = ConsistentHashing<NodeInfoWithWeight>;
+
+static RINGS: Mutex<BTreeMap<RingHandle, Arc<RwLock<Ring>>>> = Mutex::new(BTreeMap::new());
+static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+fn guarded<F: FnOnce() -> c_int>(f: F) -> c_int {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(RING_ERR_PANIC)
+}
+
+fn ring_of(handle: RingHandle) -> Result<Arc<RwLock<Ring>>, c_int> {
+    let rings = RINGS.lock().unwrap_or_else(PoisonError::into_inner);
+    rings.get(&handle).cloned().ok_or(RING_ERR_INVALID_HANDLE)
+}
+
+///Borrows a NUL-terminated UTF-8 string from the caller.
+///
+///# Safety
+///
+///`ptr` has to be null or point to a NUL-terminated string that outlives the call.
+unsafe fn str_arg<'a>(ptr: *const c_char) -> Result<&'a str, c_int> {
+    if ptr.is_null() {
+        return Err(RING_ERR_NULL);
+    }
+    CStr::from_ptr(ptr).to_str().map_err(|_| RING_ERR_UTF8)
+}
+
+///Creates an empty ring with `interleave_count` digests per node, or the default
+///of 40 if it's 0, and writes its handle to `out_handle`.
+///
+///# Safety
+///
+///`out_handle` has to be null or valid for a write.
+#[no_mangle]
+pub unsafe extern "C" fn ring_new(interleave_count: usize, out_handle: *mut RingHandle) -> c_int {
+    guarded(|| {
+        if out_handle.is_null() {
+            return RING_ERR_NULL;
+        }
+        let interleave_count = if interleave_count == 0 { None } else { Some(interleave_count) };
+        let ring = ConsistentHashing::new(&Vec::new(), interleave_count);
+        let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+        RINGS.lock().unwrap_or_else(PoisonError::into_inner).insert(handle, Arc::new(RwLock::new(ring)));
+        *out_handle = handle;
+        RING_OK
+    })
+}
+
+///Adds the node `name` with `weight`, like `ConsistentHashing::add_node`.
+///
+///# Safety
+///
+///`name` has to be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ring_add_node(handle: RingHandle, name: *const c_char, weight: usize) -> c_int {
+    guarded(|| {
+        let name = match str_arg(name) {
+            Ok(name) => name,
+            Err(code) => return code,
+        };
+        let ring = match ring_of(handle) {
+            Ok(ring) => ring,
+            Err(code) => return code,
+        };
+        let mut ring = ring.write().unwrap_or_else(PoisonError::into_inner);
+        match ring.add_node(NodeInfoWithWeight::new(name, weight)) {
+            Some(_) => RING_OK,
+            None => RING_ERR_EXISTS,
+        }
+    })
+}
+
+///Removes the node `name`, like `ConsistentHashing::remove_node_by_name`.
+///
+///# Safety
+///
+///`name` has to be null or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn ring_remove_node(handle: RingHandle, name: *const c_char) -> c_int {
+    guarded(|| {
+        let name = match str_arg(name) {
+            Ok(name) => name,
+            Err(code) => return code,
+        };
+        let ring = match ring_of(handle) {
+            Ok(ring) => ring,
+            Err(code) => return code,
+        };
+        let mut ring = ring.write().unwrap_or_else(PoisonError::into_inner);
+        if !ring.contains_node(name) {
+            return RING_ERR_NOT_FOUND;
+        }
+        ring.remove_node_by_name(name);
+        RING_OK
+    })
+}
+
+///Writes the name of the node `ConsistentHashing::get_node` picks for `key` to
+///`out_buf`, NUL-terminated. `*out_len` is the size of `out_buf` in bytes on the
+///way in and is set to the size the name needs, NUL included, on the way out. If
+///that's more than the buffer holds nothing is written to it and
+///`RING_ERR_BUFFER_TOO_SMALL` is returned, so passing a null `out_buf` with a
+///size of 0 asks for the size.
+///
+///# Safety
+///
+///`key` has to be null or a NUL-terminated string, `out_len` null or valid for
+///reads and writes, and `out_buf` valid for `*out_len` bytes of writes unless
+///`*out_len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn ring_get_node(handle: RingHandle, key: *const c_char, out_buf: *mut c_char, out_len: *mut usize) -> c_int {
+    guarded(|| {
+        let key = match str_arg(key) {
+            Ok(key) => key,
+            Err(code) => return code,
+        };
+        if out_len.is_null() || (out_buf.is_null() && *out_len != 0) {
+            return RING_ERR_NULL;
+        }
+        let ring = match ring_of(handle) {
+            Ok(ring) => ring,
+            Err(code) => return code,
+        };
+        let mut name = String::new();
+        if !ring.read().unwrap_or_else(PoisonError::into_inner).node_name_into(key, &mut name) {
+            return RING_ERR_NOT_FOUND;
+        }
+        let needed = name.len() + 1;
+        let capacity = *out_len;
+        *out_len = needed;
+        if needed > capacity {
+            return RING_ERR_BUFFER_TOO_SMALL;
+        }
+        let out = std::slice::from_raw_parts_mut(out_buf as *mut u8, needed);
+        out[..name.len()].copy_from_slice(name.as_bytes());
+        out[name.len()] = 0;
+        RING_OK
+    })
+}
+
+///Frees the ring. The handle is invalid afterwards; calls still running on other
+///threads finish first.
+#[no_mangle]
+pub extern "C" fn ring_free(handle: RingHandle) -> c_int {
+    guarded(|| {
+        match RINGS.lock().unwrap_or_else(PoisonError::into_inner).remove(&handle) {
+            Some(_) => RING_OK,
+            None => RING_ERR_INVALID_HANDLE,
+        }
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    fn new_ring() -> RingHandle {
+        let mut handle: RingHandle = 0;
+        assert_eq!(unsafe { ring_new(40, &mut handle) }, RING_OK);
+        assert_ne!(handle, 0);
+        handle
+    }
+
+    fn get_node(handle: RingHandle, key: &str) -> Result<String, c_int> {
+        let key = CString::new(key).unwrap();
+        let mut buf = [0 as c_char; 64];
+        let mut len = buf.len();
+        match unsafe { ring_get_node(handle, key.as_ptr(), buf.as_mut_ptr(), &mut len) } {
+            RING_OK => Ok(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap().to_string()),
+            code => Err(code),
+        }
+    }
+
+    #[test]
+    fn test_matches_ring() {
+        let handle = new_ring();
+        let nodes = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+        ];
+        for node in &nodes {
+            let name = CString::new(node.node_name.clone()).unwrap();
+            assert_eq!(unsafe { ring_add_node(handle, name.as_ptr(), node.weight) }, RING_OK);
+        }
+        let mut consistent_hasing_ring = ConsistentHashing::new(&Vec::new(), Some(40));
+        for node in &nodes {
+            consistent_hasing_ring.add_node(node.clone());
+        }
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(get_node(handle, &key).unwrap(), consistent_hasing_ring.get_node(&key).unwrap().node_name);
+        }
+
+        let name = CString::new("192.168.0.102:11212").unwrap();
+        assert_eq!(unsafe { ring_add_node(handle, name.as_ptr(), 1) }, RING_ERR_EXISTS);
+        assert_eq!(unsafe { ring_remove_node(handle, name.as_ptr()) }, RING_OK);
+        assert_eq!(unsafe { ring_remove_node(handle, name.as_ptr()) }, RING_ERR_NOT_FOUND);
+        consistent_hasing_ring.remove_node_by_name("192.168.0.102:11212");
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(get_node(handle, &key).unwrap(), consistent_hasing_ring.get_node(&key).unwrap().node_name);
+        }
+        assert_eq!(ring_free(handle), RING_OK);
+    }
+
+    #[test]
+    fn test_buffer_too_small() {
+        let handle = new_ring();
+        let name = CString::new("192.168.0.101:11212").unwrap();
+        assert_eq!(unsafe { ring_add_node(handle, name.as_ptr(), 1) }, RING_OK);
+        let key = CString::new("my_key").unwrap();
+
+        //asking for the size
+        let mut len = 0;
+        assert_eq!(unsafe { ring_get_node(handle, key.as_ptr(), std::ptr::null_mut(), &mut len) }, RING_ERR_BUFFER_TOO_SMALL);
+        assert_eq!(len, 20);
+
+        //one byte short of the NUL, left untouched
+        let mut buf = [1 as c_char; 19];
+        let mut len = buf.len();
+        assert_eq!(unsafe { ring_get_node(handle, key.as_ptr(), buf.as_mut_ptr(), &mut len) }, RING_ERR_BUFFER_TOO_SMALL);
+        assert_eq!(len, 20);
+        assert!(buf.iter().all(|byte| *byte == 1));
+
+        let mut buf = [1 as c_char; 20];
+        let mut len = buf.len();
+        assert_eq!(unsafe { ring_get_node(handle, key.as_ptr(), buf.as_mut_ptr(), &mut len) }, RING_OK);
+        assert_eq!(unsafe { CStr::from_ptr(buf.as_ptr()) }.to_str().unwrap(), "192.168.0.101:11212");
+        ring_free(handle);
+    }
+
+    #[test]
+    fn test_invalid_handle() {
+        let handle = new_ring();
+        assert_eq!(ring_free(handle), RING_OK);
+        let name = CString::new("192.168.0.101:11212").unwrap();
+        for stale in [0, handle, u64::MAX] {
+            assert_eq!(unsafe { ring_add_node(stale, name.as_ptr(), 1) }, RING_ERR_INVALID_HANDLE);
+            assert_eq!(unsafe { ring_remove_node(stale, name.as_ptr()) }, RING_ERR_INVALID_HANDLE);
+            assert_eq!(get_node(stale, "my_key"), Err(RING_ERR_INVALID_HANDLE));
+            assert_eq!(ring_free(stale), RING_ERR_INVALID_HANDLE);
+        }
+    }
+
+    #[test]
+    fn test_null_and_utf8() {
+        let handle = new_ring();
+        let null = std::ptr::null();
+        assert_eq!(unsafe { ring_new(40, std::ptr::null_mut()) }, RING_ERR_NULL);
+        assert_eq!(unsafe { ring_add_node(handle, null, 1) }, RING_ERR_NULL);
+        assert_eq!(unsafe { ring_remove_node(handle, null) }, RING_ERR_NULL);
+        let mut len = 64;
+        assert_eq!(unsafe { ring_get_node(handle, null, std::ptr::null_mut(), &mut len) }, RING_ERR_NULL);
+        let key = CString::new("my_key").unwrap();
+        assert_eq!(unsafe { ring_get_node(handle, key.as_ptr(), std::ptr::null_mut(), &mut len) }, RING_ERR_NULL);
+        let mut buf = [0 as c_char; 64];
+        assert_eq!(unsafe { ring_get_node(handle, key.as_ptr(), buf.as_mut_ptr(), std::ptr::null_mut()) }, RING_ERR_NULL);
+
+        let invalid = CString::new(vec![0xc3, 0x28]).unwrap();
+        assert_eq!(unsafe { ring_add_node(handle, invalid.as_ptr(), 1) }, RING_ERR_UTF8);
+        assert_eq!(unsafe { ring_remove_node(handle, invalid.as_ptr()) }, RING_ERR_UTF8);
+        assert_eq!(get_node(handle, "my_key"), Err(RING_ERR_NOT_FOUND));
+        ring_free(handle);
+    }
+
+    #[test]
+    fn test_panic_is_caught() {
+        assert_eq!(guarded(|| panic!("boom")), RING_ERR_PANIC);
+    }
+
+    #[test]
+    fn test_header_declares_every_function() {
+        let header = include_str!("../include/consistent_hashing_ring.h");
+        for declaration in [
+            "int ring_new(size_t interleave_count, RingHandle *out_handle);",
+            "int ring_add_node(RingHandle handle, const char *name, size_t weight);",
+            "int ring_remove_node(RingHandle handle, const char *name);",
+            "int ring_get_node(RingHandle handle, const char *key, char *out_buf, size_t *out_len);",
+            "int ring_free(RingHandle handle);",
+        ] {
+            assert!(header.contains(declaration), "{}", declaration);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use crate::bisect::bisect_left_ord;
+use crate::hashing_ring::{RingNode, WithWeightInfo};
+use crate::nginx::crc32;
+
+
+///Hashes the bytes of a point label or key, like groupcache's `consistenthash.Hash`.
+type GroupcacheHash = Box<dyn Fn(&[u8]) -> u32 + Send + Sync>;
+
+///A ring built exactly like `consistenthash.Map` from `github.com/golang/groupcache`,
+///so a Rust peer picks the same owner for a key as the Go peers in the pool:
+///
+///* `Add` gives every peer `replicas` points, point `i` being the hash of
+///  `strconv.Itoa(i) + peer`, and sorts all points,
+///* the hash is `crc32.ChecksumIEEE` unless another one is passed to `with_hash`,
+///  the counterpart of `New(replicas, fn)` with a non-nil `fn`,
+///* when two points collide the peer added last owns both copies, which stay in
+///  the sorted keys, as in the `hashMap` map and `keys` slice,
+///* `Get` hashes the key and takes the first point at or after it, wrapping around.
+///
+///Weights play no part. Peers are identified by `ring_id`, which has to be the
+///string the Go side passes to `Add`; for `HTTPPool` that's the peer's base URL,
+///e.g. `"http://10.0.0.1:8080"`, and the pool uses 50 replicas. Barring
+///collisions, the order peers are added in doesn't matter.
+pub struct GroupcacheMap<T: RingNode + Hash + Clone + WithWeightInfo> {
+    hash: GroupcacheHash,
+    replicas: usize,
+    keys: Vec<u32>,
+    hash_map: HashMap<u32, T>,
+}
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> GroupcacheMap<T> {
+    ///`consistenthash.New(replicas, nil)`, hashing with CRC-32.
+    pub fn new(replicas: usize) -> GroupcacheMap<T> {
+        GroupcacheMap::with_hash(replicas, crc32)
+    }
+
+    ///`consistenthash.New(replicas, fn)`, hashing with `hash` instead of CRC-32.
+    pub fn with_hash<H>(replicas: usize, hash: H) -> GroupcacheMap<T>
+        where H: Fn(&[u8]) -> u32 + Send + Sync + 'static {
+        GroupcacheMap {
+            hash: Box::new(hash),
+            replicas,
+            keys: Vec::new(),
+            hash_map: HashMap::new(),
+        }
+    }
+
+    ///`Add(peers...)`. Adding a peer that's already there places its points again,
+    ///as the Go code does.
+    pub fn add(&mut self, peers: &[T]) {
+        for peer in peers {
+            for i in 0..self.replicas {
+                let hash = (self.hash)(format!("{}{}", i, peer.ring_id()).as_bytes());
+                self.keys.push(hash);
+                self.hash_map.insert(hash, peer.clone());
+            }
+        }
+        self.keys.sort();
+    }
+
+    ///Number of points, duplicates included, i.e. `len(keys)`.
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    ///Lists the sorted keys in order as `(point, ring_id)` pairs.
+    pub fn points(&self) -> Vec<(u32, &str)> {
+        self.keys.iter().map(|key| (*key, self.hash_map[key].ring_id())).collect()
+    }
+
+    ///Given a string key the peer `Get` returns is returned. If the map is empty,
+    ///`None` is returned where Go returns `""`.
+    pub fn get_node(&self, key: &str) -> Option<T> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        let hash = (self.hash)(key.as_bytes());
+        let idx = bisect_left_ord(&self.keys, hash, None, None) % self.keys.len();
+        Some(self.hash_map[&self.keys[idx]].clone())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing_ring::NodeInfo;
+    use crate::test_support::{assert_keys, checksum};
+
+    fn names(names: &[&str]) -> Vec<NodeInfo> {
+        names.iter().map(|name| NodeInfo::new(*name)).collect()
+    }
+
+    //consistenthash_test.go's TestHashing: with the hash parsing the label as a
+    //number, "6", "4" and "2" get points 2, 4, 6, 12, 14, 16, 22, 24 and 26
+    #[test]
+    fn test_custom_hash() {
+        let mut map = GroupcacheMap::with_hash(3, |key: &[u8]| std::str::from_utf8(key).unwrap().parse().unwrap());
+        map.add(&names(&["6", "4", "2"]));
+        assert_eq!(map.points().iter().map(|(point, _)| *point).collect::<Vec<u32>>(), vec![2, 4, 6, 12, 14, 16, 22, 24, 26]);
+        for (key, peer) in [("2", "2"), ("11", "2"), ("23", "4"), ("27", "2")] {
+            assert_eq!(map.get_node(key).unwrap().node_name, peer, "{}", key);
+        }
+        //8, 18 and 28
+        map.add(&names(&["8"]));
+        for (key, peer) in [("2", "2"), ("11", "2"), ("23", "4"), ("27", "8")] {
+            assert_eq!(map.get_node(key).unwrap().node_name, peer, "{}", key);
+        }
+    }
+
+    //consistenthash_test.go's TestConsistency
+    #[test]
+    fn test_consistency() {
+        let mut map1 = GroupcacheMap::new(1);
+        let mut map2 = GroupcacheMap::new(1);
+        map1.add(&names(&["Bill", "Bob", "Bonny"]));
+        map2.add(&names(&["Bob", "Bonny", "Bill"]));
+        assert_eq!(map1.get_node("Ben").unwrap().node_name, map2.get_node("Ben").unwrap().node_name);
+
+        //adding peers leaves these keys where they were
+        map2.add(&names(&["Becky", "Ben", "Bobby"]));
+        for key in ["Ben", "Bob", "Bonny"] {
+            assert_eq!(map1.get_node(key).unwrap().node_name, map2.get_node(key).unwrap().node_name, "{}", key);
+        }
+    }
+
+    //vectors from a port of consistenthash.go with CRC-32 and HTTPPool's 50
+    //replicas; each digit is the index of the peer `user:{i}` goes to
+    #[test]
+    fn test_crc32_vectors() {
+        let peers = names(&["http://10.0.0.1:8080", "http://10.0.0.2:8080", "http://10.0.0.3:8080"]);
+        let mut map = GroupcacheMap::new(50);
+        map.add(&peers);
+        assert_eq!(map.len(), 150);
+        assert_eq!(map.points()[0], (9116923, "http://10.0.0.1:8080"));
+        assert_eq!(map.points()[149], (4244761782, "http://10.0.0.2:8080"));
+        assert_eq!(checksum(&map.points(), &peers), 5134466454969057189);
+        assert_keys(&peers, (0..100).map(|i| format!("user:{}", i)), "0100100001121012101122202220112211221122000200220012021021121222000221020112210020022221221221102020", |key| map.get_node(key));
+
+        //a fourth peer, the pool added in a different order and in two calls
+        let peers = names(&["http://10.0.0.1:8080", "http://10.0.0.2:8080", "http://10.0.0.3:8080", "http://10.0.0.4:8080"]);
+        let mut reordered = GroupcacheMap::new(50);
+        reordered.add(&[peers[3].clone(), peers[1].clone()]);
+        reordered.add(&[peers[0].clone(), peers[2].clone()]);
+        map.add(&peers[3..]);
+        assert_eq!(map.points(), reordered.points());
+        assert_eq!(checksum(&reordered.points(), &peers), 10550336515736197881);
+        assert_keys(&peers, (0..100).map(|i| format!("user:{}", i)), "0103130031321012101132233233112333323322000200220012321021123332030321333313210330022333221321333320", |key| reordered.get_node(key));
+    }
+
+    #[test]
+    fn test_empty() {
+        let mut map: GroupcacheMap<NodeInfo> = GroupcacheMap::new(50);
+        assert!(map.is_empty());
+        assert!(map.get_node("user:0").is_none());
+        map.add(&[]);
+        assert!(map.is_empty());
+        map.add(&names(&["http://10.0.0.1:8080"]));
+        assert_eq!(map.get_node("user:0").unwrap().node_name, "http://10.0.0.1:8080");
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use crate::bisect::bisect_right_ord;
+use crate::hashing_ring::{hash_digest, hash_key, hash_val, weighted_factor, RingNode, WithWeightInfo};
+
+
+///Digests per node in the Python `hash_ring` package, before weighting.
+const HASH_RING_POINTS_PER_NODE: usize = 40;
+
+
+///A ring laid out exactly like the Python `hash_ring` package's `HashRing`, so a
+///port of a Python service keeps its keys where they were. Everything is
+///fixed the way `ring.py` has it:
+///
+///* node `i` of `n` gets `floor(40 * n * weight / total_weight)` digests, with
+///  Python 2's integer division for `new` and float division for
+///  `with_float_weights`,
+///* nodes are walked in the order given; a name listed twice is counted and
+///  placed twice, the way `self.nodes` is,
+///* each `md5("{node}-{j}")` digest yields the 3 little endian points at bytes 0,
+///  4 and 8 (`_hash_val` with `x + i * 4`),
+///* when two points collide the node placed last owns both copies, which stay in
+///  the sorted keys, like the `self.ring` dict and `_sorted_keys` list,
+///* a key belongs to the first point strictly after its hash (`bisect`).
+///
+///`ConsistentHashing::new` with no other options places keys the same way for
+///integer weights; this type pins the layout regardless of builder options.
+///Nodes are identified by `ring_id`, which stands in for `str(node)`.
+pub struct HashRingPyCompat<T: RingNode + Hash + Clone + WithWeightInfo> {
+    ring: HashMap<u32, T>,
+    sorted_keys: Vec<u32>,
+}
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> HashRingPyCompat<T> {
+    ///`HashRing(nodes, weights)` with integer weights from `get_weight`. A total
+    ///weight of 0, a `ZeroDivisionError` in Python, gives an empty ring.
+    pub fn new(nodes: &[T]) -> HashRingPyCompat<T> {
+        let total_weight: usize = nodes.iter().map(|node| node.get_weight()).sum();
+        HashRingPyCompat::generate_circle(nodes, |node| {
+            weighted_factor(HASH_RING_POINTS_PER_NODE, nodes.len(), node.get_weight(), total_weight)
+        })
+    }
+
+    ///`HashRing(nodes, weights)` with float weights from `get_weight_f64`, summed
+    ///and divided in double precision like Python floats.
+    pub fn with_float_weights(nodes: &[T]) -> HashRingPyCompat<T> {
+        let total_weight = nodes.iter().fold(0.0f64, |total, node| total + node.get_weight_f64());
+        HashRingPyCompat::generate_circle(nodes, |node| {
+            let factor = ((HASH_RING_POINTS_PER_NODE * nodes.len()) as f64 * node.get_weight_f64() / total_weight).floor();
+            if factor.is_finite() && factor > 0.0 {
+                factor as usize
+            } else {
+                0
+            }
+        })
+    }
+
+    //_generate_circle
+    fn generate_circle<F: Fn(&T) -> usize>(nodes: &[T], factor_of: F) -> HashRingPyCompat<T> {
+        let mut ring = HashMap::new();
+        let mut sorted_keys = Vec::new();
+        for node in nodes {
+            for j in 0..factor_of(node) {
+                let b_key = hash_digest(&format!("{}-{}", node.ring_id(), j));
+                for i in 0..3 {
+                    let key = hash_val(&b_key, i * 4);
+                    ring.insert(key, node.clone());
+                    sorted_keys.push(key);
+                }
+            }
+        }
+        sorted_keys.sort();
+        HashRingPyCompat { ring, sorted_keys }
+    }
+
+    ///Number of points, duplicates included, i.e. `len(_sorted_keys)`.
+    pub fn len(&self) -> usize {
+        self.sorted_keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sorted_keys.is_empty()
+    }
+
+    ///Lists `_sorted_keys` in order as `(point, ring_id)` pairs.
+    pub fn points(&self) -> Vec<(u32, &str)> {
+        self.sorted_keys.iter().map(|key| (*key, self.ring[key].ring_id())).collect()
+    }
+
+    ///Given a string key the node `HashRing.get_node` returns is returned. If the
+    ///ring is empty, `None` is returned.
+    pub fn get_node(&self, string_key: &str) -> Option<T> {
+        self.get_node_pos(string_key).map(|pos| self.ring[&self.sorted_keys[pos]].clone())
+    }
+
+    ///The nodes `HashRing.iterate_nodes` yields for the key: every node once, in
+    ///ring order from the key's point on. Python hands out distinct nodes whatever
+    ///`distinct` is set to, so there's no such switch here. An empty ring gives an
+    ///empty vector instead of Python's `(None, None)`.
+    pub fn iterate_nodes(&self, string_key: &str) -> Vec<T> {
+        let pos = match self.get_node_pos(string_key) {
+            Some(pos) => pos,
+            None => return Vec::new(),
+        };
+        let mut returned_values: Vec<&str> = Vec::new();
+        let mut nodes = Vec::new();
+        for key in self.sorted_keys[pos..].iter().chain(self.sorted_keys[..pos].iter()) {
+            let node = &self.ring[key];
+            if !returned_values.contains(&node.ring_id()) {
+                returned_values.push(node.ring_id());
+                nodes.push(node.clone());
+            }
+        }
+        nodes
+    }
+
+    fn get_node_pos(&self, string_key: &str) -> Option<usize> {
+        if self.ring.is_empty() {
+            return None;
+        }
+        let pos = bisect_right_ord(&self.sorted_keys, hash_key(string_key), None, None);
+        if pos == self.sorted_keys.len() {
+            Some(0)
+        } else {
+            Some(pos)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing_ring::{ConsistentHashing, NodeInfo, NodeInfoWithF64Weight, NodeInfoWithWeight};
+    use crate::test_support::{assert_keys, checksum};
+
+    fn iterated<T: RingNode + Hash + Clone + WithWeightInfo>(ring: &HashRingPyCompat<T>, nodes: &[T], key: &str) -> Vec<usize> {
+        ring.iterate_nodes(key).iter()
+            .map(|node| nodes.iter().position(|n| n.ring_id() == node.ring_id()).unwrap())
+            .collect()
+    }
+
+    //vectors from hash_ring 1.3.1's ring.py run under Python 3 (keys utf-8 encoded,
+    //integer weights divided with //); each digit is the index of the node `key-{i}`
+    //maps to
+    #[test]
+    fn test_unweighted_vectors() {
+        let nodes = vec![
+            NodeInfo::new("192.168.0.246:11212"),
+            NodeInfo::new("192.168.0.247:11212"),
+            NodeInfo::new("192.168.0.249:11212"),
+        ];
+        let ring = HashRingPyCompat::new(&nodes);
+        assert_eq!(ring.len(), 360);
+        assert_eq!(ring.points()[0], (9371452, "192.168.0.246:11212"));
+        assert_eq!(ring.points()[359], (4293116021, "192.168.0.246:11212"));
+        assert_eq!(checksum(&ring.points(), &nodes), 7211737962420375362);
+        assert_keys(&nodes, (0..100).map(|i| format!("key-{}", i)), "1221121120212111100120021121120100021200222212210001021202012011112011210201010021122012001101200220", |key| ring.get_node(key));
+        assert_eq!(iterated(&ring, &nodes, "key-0"), vec![1, 0, 2]);
+
+        //the generic ring agrees when nothing but the nodes is configured
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, None);
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(ring.get_node(&key).unwrap().node_name, consistent_hasing_ring.get_node(&key).unwrap().node_name);
+        }
+    }
+
+    #[test]
+    fn test_weighted_vectors() {
+        let nodes = vec![
+            NodeInfoWithWeight::new("192.168.0.246:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.247:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.249:11212", 5),
+        ];
+        let ring = HashRingPyCompat::new(&nodes);
+        assert_eq!(ring.len(), 360);
+        assert_eq!(ring.points()[359], (4289208759, "192.168.0.247:11212"));
+        assert_eq!(checksum(&ring.points(), &nodes), 15177848796396921486);
+        assert_keys(&nodes, (0..100).map(|i| format!("key-{}", i)), "0222222120222221120120021222222212022222222212210222021222012222212221222221022221122022021221222221", |key| ring.get_node(key));
+        assert_eq!(iterated(&ring, &nodes, "key-0"), vec![0, 2, 1]);
+        assert_eq!(iterated(&ring, &nodes, "my_key"), vec![1, 0, 2]);
+    }
+
+    #[test]
+    fn test_float_weighted_vectors() {
+        let nodes = vec![
+            NodeInfoWithF64Weight::new("192.168.0.246:11212", 0.5),
+            NodeInfoWithF64Weight::new("192.168.0.247:11212", 1.7),
+            NodeInfoWithF64Weight::new("192.168.0.249:11212", 1.0),
+        ];
+        let ring = HashRingPyCompat::with_float_weights(&nodes);
+        assert_eq!(ring.len(), 354);
+        assert_eq!(checksum(&ring.points(), &nodes), 16807985623690514346);
+        assert_keys(&nodes, (0..100).map(|i| format!("key-{}", i)), "1211121120212111110110021121121112021210222212210121021202112211112211212221011021122012011111201121", |key| ring.get_node(key));
+    }
+
+    #[test]
+    fn test_duplicate_node_vectors() {
+        let nodes = vec![NodeInfo::new("a"), NodeInfo::new("a"), NodeInfo::new("b")];
+        let ring = HashRingPyCompat::new(&nodes);
+        //"a" is placed twice, every one of its points is listed twice
+        assert_eq!(ring.len(), 360);
+        assert_eq!(ring.points()[0], (3747649, "a"));
+        assert_eq!(ring.points()[1], (3747649, "a"));
+        assert_eq!(ring.points()[359], (4290153535, "b"));
+        assert_eq!(checksum(&ring.points(), &nodes), 14827302646097825454);
+        assert_keys(&nodes, (0..100).map(|i| format!("key-{}", i)), "2200022222222222200202022002222222200222200202220220220000020002220020000202222222202000020022222002", |key| ring.get_node(key));
+        assert_eq!(iterated(&ring, &nodes, "key-0"), vec![2, 0]);
+    }
+
+    #[test]
+    fn test_empty() {
+        let ring: HashRingPyCompat<NodeInfo> = HashRingPyCompat::new(&[]);
+        assert!(ring.is_empty());
+        assert!(ring.get_node("my_key").is_none());
+        assert!(ring.iterate_nodes("my_key").is_empty());
+        assert!(HashRingPyCompat::new(&[NodeInfoWithWeight::new("a", 0)]).is_empty());
+        assert!(HashRingPyCompat::with_float_weights(&[NodeInfoWithF64Weight::new("a", 0.0)]).is_empty());
+    }
+}
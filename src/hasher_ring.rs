@@ -0,0 +1,199 @@
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{BuildHasher, Hash, Hasher};
+use crate::bisect::bisect_right_by_key;
+
+
+///Deterministic `BuildHasher` for `HasherRing`: `DefaultHasher` (SipHash with
+///fixed keys) with the seed hashed in first. Placement is the same from one run
+///to the next for a given seed, but `DefaultHasher`'s algorithm may change
+///between Rust releases; pin a hasher of your own where that matters.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FixedState {
+    seed: u64,
+}
+
+impl FixedState {
+    pub fn with_seed(seed: u64) -> FixedState {
+        FixedState { seed }
+    }
+}
+
+impl BuildHasher for FixedState {
+    type Hasher = DefaultHasher;
+
+    fn build_hasher(&self) -> DefaultHasher {
+        let mut hasher = DefaultHasher::new();
+        hasher.write_u64(self.seed);
+        hasher
+    }
+}
+
+
+///A consistent hashing ring that hashes with any `BuildHasher` instead of md5,
+///so it's available without the `md5` feature. Node and key are hashed through
+///their `Hash` impls and ring keys are the full 64-bit hashes; a node's points are
+///the hashes of `(node, 0u64)`, `(node, 1u64)`, ..., the same on 32 and 64-bit
+///targets given the same hasher. A key belongs to the first point
+///strictly after its hash, wrapping around, as in `ConsistentHashing`.
+///
+///With a randomly seeded hasher (e.g. `RandomState`) placement changes on every
+///run; use `FixedState` or another deterministic hasher if it has to be stable.
+pub struct HasherRing<T: Hash + Eq + Clone, S: BuildHasher> {
+    build_hasher: S,
+    points_per_node: usize,
+    nodes: Vec<T>,
+    //(ring key, node index), sorted by ring key
+    points: Vec<(u64, usize)>,
+}
+
+impl<T: Hash + Eq + Clone, S: BuildHasher> HasherRing<T, S> {
+    pub fn new(build_hasher: S, points_per_node_setting: Option<usize>) -> HasherRing<T, S> {
+        HasherRing {
+            build_hasher,
+            points_per_node: points_per_node_setting.unwrap_or(120), //default value = 120
+            nodes: Vec::new(),
+            points: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    ///Adds a node, returning false if it's already on the ring.
+    pub fn add_node(&mut self, node: T) -> bool {
+        if self.nodes.contains(&node) {
+            return false;
+        }
+        let index = self.nodes.len();
+        for replica in 0..self.points_per_node {
+            //a u64 rather than the usize, which would hash as 4 bytes on 32-bit targets
+            let point = self.hash((&node, replica as u64));
+            self.points.push((point, index));
+        }
+        self.points.sort_by_key(|&(point, _)| point);
+        self.nodes.push(node);
+        true
+    }
+
+    ///Removes a node, returning whether it was on the ring.
+    pub fn remove_node(&mut self, node: &T) -> bool {
+        let index = match self.nodes.iter().position(|n| n == node) {
+            Some(index) => index,
+            None => return false,
+        };
+        self.points.retain(|&(_, owner)| owner != index);
+        //the last node moves into the freed index
+        let last = self.nodes.len() - 1;
+        self.nodes.swap_remove(index);
+        for point in self.points.iter_mut().filter(|point| point.1 == last) {
+            point.1 = index;
+        }
+        true
+    }
+
+    ///Given a key the node owning it is returned.
+    ///If the ring is empty, `None` is returned.
+    pub fn get_node<K: Hash + ?Sized>(&self, key: &K) -> Option<&T> {
+        if self.points.is_empty() {
+            return None;
+        }
+        let pos = bisect_right_by_key(&self.points, &self.hash(key), |&(point, _)| point);
+        Some(&self.nodes[self.points[pos % self.points.len()].1])
+    }
+
+    fn hash<H: Hash>(&self, value: H) -> u64 {
+        self.build_hasher.hash_one(value)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::collections::hash_map::RandomState;
+
+    fn ring(seed: u64) -> HasherRing<String, FixedState> {
+        let mut ring = HasherRing::new(FixedState::with_seed(seed), None);
+        for i in 0..5 {
+            ring.add_node(format!("192.168.0.{}:11212", 101 + i));
+        }
+        ring
+    }
+
+    #[test]
+    fn test_fixed_seed_is_deterministic() {
+        let first = ring(42);
+        let second = ring(42);
+        assert_eq!(first.points, second.points);
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            assert_eq!(first.get_node(&key), second.get_node(&key));
+        }
+
+        let other_seed = ring(43);
+        let moved = (0..200).map(|i| format!("key-{}", i))
+            .filter(|key| first.get_node(key) != other_seed.get_node(key))
+            .count();
+        assert!(moved > 0);
+    }
+
+    #[test]
+    fn test_points_independent_of_pointer_width() {
+        let ring = ring(42);
+        let node = "192.168.0.101:11212".to_string();
+        let expected: Vec<u64> = (0..120u64).map(|replica| ring.hash((&node, replica))).collect();
+        let mut points: Vec<u64> = ring.points.iter().filter(|&&(_, owner)| ring.nodes[owner] == node).map(|&(point, _)| point).collect();
+        let mut sorted_expected = expected.clone();
+        sorted_expected.sort();
+        points.sort();
+        assert_eq!(points, sorted_expected);
+        //placement on 64-bit targets is what it was when replicas were hashed as usize
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(expected[7], ring.hash((&node, 7usize)));
+    }
+
+    #[test]
+    fn test_distribution() {
+        let ring = ring(7);
+        let mut counts: HashMap<&String, usize> = HashMap::new();
+        for i in 0..10_000u32 {
+            *counts.entry(ring.get_node(&i).unwrap()).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 5);
+        assert!(counts.values().all(|&count| count > 1_000 && count < 3_000), "{:?}", counts);
+    }
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut ring: HasherRing<&str, RandomState> = HasherRing::new(RandomState::new(), Some(50));
+        assert!(ring.get_node("my_key").is_none());
+        assert!(ring.add_node("a"));
+        assert!(!ring.add_node("a"));
+        ring.add_node("b");
+        ring.add_node("c");
+        assert_eq!(ring.len(), 3);
+        assert_eq!(ring.points.len(), 150);
+
+        let before: Vec<&str> = (0..200u32).map(|i| *ring.get_node(&i).unwrap()).collect();
+        assert!(ring.remove_node(&"a"));
+        assert!(!ring.remove_node(&"a"));
+        assert_eq!(ring.points.len(), 100);
+        for (i, owner) in (0..200u32).zip(before.iter()) {
+            let now = *ring.get_node(&i).unwrap();
+            assert_ne!(now, "a");
+            if *owner != "a" {
+                assert_eq!(now, *owner);
+            }
+        }
+        ring.remove_node(&"b");
+        ring.remove_node(&"c");
+        assert!(ring.is_empty());
+        assert!(ring.get_node("my_key").is_none());
+    }
+}
@@ -1,209 +1,5792 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
-use std::collections::hash_map::DefaultHasher;
-use md5;
-use bisect::bisect_right;
+use std::fmt;
+use std::error::Error;
+use std::borrow::{Borrow, Cow};
+use std::sync::{Arc, OnceLock};
+use std::ops::Range;
+use std::convert::TryFrom;
+use crate::slot_table::SlotTable;
+use crate::ketama::KetamaContinuum;
+use crate::point_store::{Point, PointStore};
+#[cfg(feature = "lookup-cache")]
+use crate::lookup_cache::LookupCache;
+#[cfg(feature = "lookup-cache")]
+use std::sync::{Mutex, PoisonError};
 
 
-pub struct ConsistentHashing<T: ToString + Hash + Clone + WithWeightInfo> {
-    hashing_ring: HashMap<u32, T>,
-    real_nodes: HashMap<String, T>,
-    sorted_keys: Vec<u32>,
+pub struct ConsistentHashing<T: RingNode + Hash + Clone + WithWeightInfo> {
+    //the points as (token, index into `owners`), in token order
+    points: PointStore,
+    //every registered node once, in no particular order
+    owners: Vec<Arc<T>>,
+    //name -> index into `owners`
+    real_nodes: HashMap<ByName<T>, u32>,
     interleave_count: usize,
+    replica_factor: usize,
+    //digests per unit of weight, replacing the share of interleave_count if set
+    points_per_weight: Option<usize>,
     total_weight: usize,
+    load_epsilon: Option<f64>,
+    loads: HashMap<String, usize>,
+    probes: usize,
+    drained: HashSet<String>,
+    //gradual drains: (steps, steps taken)
+    draining: HashMap<String, (usize, usize)>,
+    //nodes lookups pass over while they keep their points
+    down: HashSet<String>,
+    //digests per node, kept while it's drained so undraining restores its points
+    factors: HashMap<ByName<T>, usize>,
+    #[cfg(feature = "lookup-cache")]
+    cache: Option<Mutex<LookupCache<Point>>>,
+    //`None` for the default `"{node}-{index}"`, hashed without a label per digest
+    label_format: Option<LabelFormatter>,
+    point_scheme: PointScheme,
+    byte_order: ByteOrder,
+    //prepended to point labels and keys before hashing
+    seed: String,
+    on_lookup: Option<LookupHook<T>>,
+    //(cumulative weight, index into `owners`) of the nodes up and on the ring, in
+    //name order, built on the first weighted random pick after a change
+    routable_weights: OnceLock<Vec<(usize, u32)>>,
+    //the ids of `get_node_id`, built on first use after a node joins or leaves
+    node_ids: OnceLock<NodeIds>,
+    #[cfg(test)]
+    sort_count: usize,
 }
 
-impl<T: ToString + Hash + Clone + WithWeightInfo> ConsistentHashing<T> {
+///What membership changes touch on a ring, saved by `save_state` so a change
+///that went wrong can be taken back with `restore_state`.
+pub(crate) struct SavedState<T> {
+    points: PointStore,
+    owners: Vec<Arc<T>>,
+    real_nodes: HashMap<ByName<T>, u32>,
+    total_weight: usize,
+    loads: HashMap<String, usize>,
+    drained: HashSet<String>,
+    draining: HashMap<String, (usize, usize)>,
+    down: HashSet<String>,
+    factors: HashMap<ByName<T>, usize>,
+}
+
+///A registered node as a key of the ring's maps by name, hashed and compared as
+///its `ring_id`, so the maps share the node instead of holding copies of its name.
+struct ByName<T>(Arc<T>);
+
+impl<T: RingNode> Borrow<str> for ByName<T> {
+    fn borrow(&self) -> &str {
+        self.0.ring_id()
+    }
+}
+
+impl<T: RingNode> PartialEq for ByName<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.ring_id() == other.0.ring_id()
+    }
+}
+
+impl<T: RingNode> Eq for ByName<T> {}
+
+impl<T: RingNode> Hash for ByName<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.ring_id().hash(state)
+    }
+}
+
+impl<T> Clone for ByName<T> {
+    fn clone(&self) -> Self {
+        ByName(Arc::clone(&self.0))
+    }
+}
+
+impl<T: RingNode> fmt::Debug for ByName<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.0.ring_id(), f)
+    }
+}
+
+///The ids `get_node_id` hands out: a node's position among the nodes sorted by
+///name.
+struct NodeIds {
+    //id of the node at each index in `owners`
+    of_owner: Vec<u32>,
+    //index in `owners` of the node with each id
+    owners: Vec<u32>,
+}
+
+///How a node's virtual points are derived from it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PointScheme {
+    ///Ketama style: digest `i` is taken of the label `"{node}-{i}"` (see
+    ///`ConsistentHashingBuilder::label_format`) and yields 3 points. The default.
+    Labels,
+    ///Point `i` is `h1 + i * h2` (mod 2^32), `h1` and `h2` coming from two separate
+    ///digests of the node's `ring_id`, `h2` forced odd. A node's points are spread
+    ///evenly around the ring instead of at random and never collide with each
+    ///other, which gives more even arcs for the same number of points.
+    DoubleHashing,
+}
+
+///How 4 bytes of an md5 digest are read as a `u32`, for ring points and key
+///hashes alike. Ketama ports differ here, so a ring migrated from one has to read
+///digests the way it did.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ByteOrder {
+    ///The first byte is the least significant one, as in libketama and `hash_key`.
+    ///The default.
+    LittleEndian,
+    ///The first byte is the most significant one.
+    BigEndian,
+}
+
+///How the ring keeps its points in order, picked with
+///`ConsistentHashingBuilder::backend`. Both place every key alike; they differ in
+///what membership changes and lookups cost.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    ///A sorted vector: lookups are a binary search over one contiguous slice, but
+    ///every membership change re-sorts or scans every point. The default, and the
+    ///better pick unless nodes come and go every few seconds.
+    SortedVec,
+    ///A `BTreeMap` from token to owner: adding or removing a node costs
+    ///O(log points) per point of that node instead of a pass over the whole ring,
+    ///for slower lookups. Suits rings under an autoscaler.
+    BTree,
+}
+
+impl ByteOrder {
+    ///Reads the `u32` at `offset` of `digest`.
+    fn read(self, digest: &[u8], offset: usize) -> u32 {
+        match self {
+            ByteOrder::LittleEndian => hash_val(digest, offset),
+            ByteOrder::BigEndian => hash_val(digest, offset).swap_bytes(),
+        }
+    }
+}
+
+///Digests a node with `factor` digests still has after `taken` of `steps` drain steps.
+fn kept_digests(factor: usize, steps: usize, taken: usize) -> usize {
+    factor - factor * taken / steps
+}
+
+///`(h1, h2)` for `PointScheme::DoubleHashing`.
+//whether owner `a` keeps a token it and owner `b` both have a point at: the one
+//with the lower `ring_id` does, so the winner doesn't depend on insert order
+fn name_order<T: RingNode>(owners: &[Arc<T>]) -> impl Fn(u32, u32) -> bool + '_ {
+    move |a, b| owners[a as usize].ring_id() < owners[b as usize].ring_id()
+}
+
+fn double_hashing_seeds(ring_id: &str) -> (u32, u32) {
+    let start = hash_val(&hash_digest(ring_id), 0);
+    let step = hash_val(&hash_digest(&format!("{}-step", ring_id)), 0);
+    (start, step | 1)
+}
+
+///64-bit FNV-1a, which `fingerprint` and `ring_digest` are taken with.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(0xcbf29ce484222325)
+    }
+
+    fn feed(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= *byte as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+}
+
+///`s` with `seed` in front.
+fn with_seed<'a>(seed: &str, s: &'a str) -> Cow<'a, str> {
+    if seed.is_empty() {
+        Cow::Borrowed(s)
+    } else {
+        Cow::Owned(format!("{}{}", seed, s))
+    }
+}
+
+///`n` in decimal, written to the end of `digits`.
+fn decimal(mut n: usize, digits: &mut [u8; 20]) -> &[u8] {
+    let mut start = digits.len();
+    loop {
+        start -= 1;
+        digits[start] = b'0' + (n % 10) as u8;
+        n /= 10;
+        if n == 0 {
+            return &digits[start..];
+        }
+    }
+}
+
+///What a node's points are derived from besides the node itself. Split off the
+///ring so it can be shared with other threads, whatever the ring's nodes are.
+struct PointSource<'a> {
+    point_scheme: PointScheme,
+    byte_order: ByteOrder,
+    seed: &'a str,
+    label_format: Option<&'a LabelFormatter>,
+}
+
+impl<'a> PointSource<'a> {
+    ///The ring keys of the digests `replicas` of the node `ring_id`, 3 per digest,
+    ///in order.
+    fn digest_points(&self, ring_id: &str, weight: usize, replicas: Range<usize>) -> Vec<u32> {
+        let mut points = Vec::with_capacity(replicas.len() * 3);
+        self.for_each_point(ring_id, weight, replicas, |key| points.push(key));
+        points
+    }
+
+    ///Hands the keys `digest_points` returns to `emit` one by one instead. With the
+    ///default label format nothing is allocated.
+    fn for_each_point<F: FnMut(u32)>(&self, ring_id: &str, weight: usize, replicas: Range<usize>, mut emit: F) {
+        match self.point_scheme {
+            PointScheme::DoubleHashing => {
+                let (start, step) = double_hashing_seeds(&with_seed(self.seed, ring_id));
+                for i in replicas.start * 3..replicas.end * 3 {
+                    emit(start.wrapping_add((i as u32).wrapping_mul(step)));
+                }
+            }
+            PointScheme::Labels => {
+                let byte_order = self.byte_order;
+                //each 16-byte digest yields 3 points, read from bytes 0..4, 4..8 and 8..12
+                let mut push = |digest: md5::Digest| (0..3).for_each(|offset| emit(byte_order.read(&digest[..], offset * 4)));
+                match self.label_format {
+                    Some(label_format) => {
+                        for replica in replicas {
+                            let label = label_format(ring_id, weight, replica);
+                            push(md5::compute(with_seed(self.seed, &label).as_bytes()));
+                        }
+                    }
+                    None => {
+                        //"{seed}{node}-" hashed once, then only the index per digest
+                        let mut prefix = md5::Context::new();
+                        prefix.consume(self.seed);
+                        prefix.consume(ring_id);
+                        prefix.consume(b"-");
+                        let mut digits = [0u8; 20];
+                        for replica in replicas {
+                            let mut context = prefix.clone();
+                            context.consume(decimal(replica, &mut digits));
+                            push(context.compute());
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+///Builds the label a virtual point digest is taken from, out of the node's
+///`ring_id`, its weight and the digest's index.
+type LabelFormatter = Box<dyn Fn(&str, usize, usize) -> String + Send + Sync>;
+
+///Called by `get_node` with the key and the node it resolved to.
+type LookupHook<T> = Box<dyn Fn(&str, Option<&T>) + Send + Sync>;
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> ConsistentHashing<T> {
+    ///when you are running a cluster of Memcached
+    ///servers it could happen to not all server can allocate the
+    ///same amount of memory. You might have a Memcached server
+    ///with 128mb, 512mb, 128mb. If you would the array structure
+    ///all servers would have the same weight in the consistent
+    ///hashing scheme. Spreading the keys 33/33/33 over the servers.
+    ///But as server 2 has more memory available you might want to
+    ///give it more weight so more keys get stored on that server.
+    ///When you are using a object, the key should represent the
+    ///server location syntax and the value the weight of the server.
+    #[allow(clippy::ptr_arg)]
     pub fn new(real_nodes: &Vec<T>, interleave_count_setting: Option<usize>) -> ConsistentHashing<T> {
-        ///when you are running a cluster of Memcached
-        ///servers it could happen to not all server can allocate the
-        ///same amount of memory. You might have a Memcached server
-        ///with 128mb, 512mb, 128mb. If you would the array structure
-        ///all servers would have the same weight in the consistent
-        ///hashing scheme. Spreading the keys 33/33/33 over the servers.
-        ///But as server 2 has more memory available you might want to
-        ///give it more weight so more keys get stored on that server.
-        ///When you are using a object, the key should represent the
-        ///server location syntax and the value the weight of the server.
-        ///
-
-        let interleave_count = match interleave_count_setting {
-            Some(count) => count,
-            None => 40, //default value = 40
+        let mut builder = ConsistentHashing::builder(real_nodes);
+        if let Some(count) = interleave_count_setting {
+            builder = builder.interleave_count(count);
+        }
+        builder.build()
+    }
+
+    ///Builds a ring where the nodes split `interleave_count * nodes` digests in
+    ///proportion to `get_weight_f64`, rounded with `apportion`, so fractional
+    ///weights like 1.7 and 0.25 keep their ratios. Nodes are ordered by `ring_id`
+    ///before apportioning, so the order they're passed in doesn't matter. Nodes
+    ///added later with `add_node` get their points from `get_weight` as usual.
+    ///
+    ///Fails on a NaN, infinite or negative weight.
+    pub fn with_f64_weights(real_nodes: &[T], interleave_count_setting: Option<usize>) -> Result<ConsistentHashing<T>, WeightError> {
+        let mut nodes: Vec<&T> = real_nodes.iter().collect();
+        nodes.sort_by(|a, b| a.ring_id().cmp(b.ring_id()));
+        let names: Vec<&str> = nodes.iter().map(|node| node.ring_id()).collect();
+        let weights: Vec<f64> = nodes.iter().map(|node| node.get_weight_f64()).collect();
+
+        let mut builder = ConsistentHashing::builder(&[]);
+        if let Some(count) = interleave_count_setting {
+            builder = builder.interleave_count(count);
+        }
+        let mut ring = builder.build();
+        let factors = apportion(&names, &weights, ring.interleave_count.saturating_mul(nodes.len()))?;
+        for (node, factor) in nodes.into_iter().zip(factors) {
+            ring.total_weight += node.get_weight();
+            let index = ring.register(Arc::new(node.clone()));
+            ring.add_virtual_nodes(index, factor);
+        }
+        ring.check_total_weight();
+        ring.sort_keys();
+        Ok(ring)
+    }
+
+    ///Builds a ring with just one node and the default interleave count; every key
+    ///resolves to that node until more are added.
+    pub fn single(node: T) -> ConsistentHashing<T> {
+        ConsistentHashing::builder(&[node]).build()
+    }
+
+    ///Starts a `ConsistentHashingBuilder` for the given nodes, for settings
+    ///that `new` doesn't take.
+    pub fn builder(real_nodes: &[T]) -> ConsistentHashingBuilder<T> {
+        ConsistentHashingBuilder {
+            real_nodes: real_nodes.to_vec(),
+            interleave_count: 40, //default value = 40
+            replica_factor: 1, //default value = 1
+            points_per_weight: None,
+            load_epsilon: None,
+            probes: 1,
+            #[cfg(feature = "lookup-cache")]
+            lookup_cache: 0,
+            label_format: None,
+            point_scheme: PointScheme::Labels,
+            byte_order: ByteOrder::LittleEndian,
+            seed: String::new(),
+            on_lookup: None,
+            backend: Backend::SortedVec,
+        }
+    }
+
+    ///Generates the ring, moving the nodes into it.
+    fn generate_hashing_ring(&mut self, real_nodes: Vec<T>) {
+        //real nodes number
+        let nodes_num = real_nodes.len();
+        //calculate total weight
+        let total_weight: usize = real_nodes.iter().map(|node| node.get_weight()).sum();
+        self.total_weight = total_weight;
+        //with every weight the same, and not 0, each node's share is interleave_count
+        let uniform = self.points_per_weight.is_none()
+            && real_nodes.first().is_some_and(|first| first.get_weight() > 0 && real_nodes.iter().all(|node| node.get_weight() == first.get_weight()));
+
+        let mut placed: Vec<(u32, usize)> = Vec::with_capacity(nodes_num);
+        for node_entity in real_nodes {
+            let factor = if uniform {
+                self.interleave_count.saturating_mul(self.replica_factor)
+            } else {
+                self.factor_of(&node_entity, nodes_num)
+            };
+            debug_assert_eq!(factor, self.factor_of(&node_entity, nodes_num), "uniform weights got a different share for {}", node_entity.ring_id());
+            //save real node
+            let index = self.register(Arc::new(node_entity));
+            placed.push((index, factor));
+        }
+        #[cfg(not(feature = "rayon"))]
+        self.add_points_sequentially(&placed);
+        #[cfg(feature = "rayon")]
+        self.add_points_in_parallel(&placed);
+        self.sort_keys();
+        //a name listed twice is registered once
+        self.total_weight = self.owners.iter().map(|node| node.get_weight()).sum();
+
+    }
+
+    ///Adds the points of every `(index in owners, digests)` pair, in order.
+    #[cfg_attr(all(feature = "rayon", not(test)), allow(dead_code))]
+    fn add_points_sequentially(&mut self, placed: &[(u32, usize)]) {
+        for (index, factor) in placed.iter() {
+            self.add_virtual_nodes(*index, *factor);
+        }
+    }
+
+    ///`add_points_sequentially` with the digests of every node taken on rayon's
+    ///thread pool. The points are pushed in the same order afterwards, so the ring
+    ///comes out the same.
+    #[cfg(feature = "rayon")]
+    fn add_points_in_parallel(&mut self, placed: &[(u32, usize)]) {
+        use rayon::prelude::*;
+
+        let source = self.point_source();
+        let nodes: Vec<(&str, usize, usize)> = placed.iter()
+            .map(|(index, factor)| {
+                let node_entity = &self.owners[*index as usize];
+                (node_entity.ring_id(), node_entity.get_weight(), *factor)
+            })
+            .collect();
+        let tokens: Vec<Vec<u32>> = nodes.par_iter()
+            .map(|(ring_id, weight, factor)| source.digest_points(ring_id, *weight, 0..*factor))
+            .collect();
+        for ((index, factor), tokens) in placed.iter().zip(tokens) {
+            self.factors.insert(self.key_of(*index), *factor);
+            for key in tokens {
+                self.points.push((key, *index), name_order(&self.owners));
+            }
+        }
+    }
+
+    ///Registered weight of the node, drained or not.
+    pub fn weight_of(&self, name: &str) -> Option<usize> {
+        self.registered(name).map(|node| node.get_weight())
+    }
+
+    ///Sum of the weights of the registered nodes, drained ones included.
+    pub fn total_weight(&self) -> usize {
+        self.total_weight
+    }
+
+    ///`total_weight` is kept up to date incrementally; debug builds check after every
+    ///change of membership or weight that it still is the sum of the node weights.
+    fn check_total_weight(&self) {
+        debug_assert_eq!(self.total_weight, self.owners.iter().map(|node| node.get_weight()).sum::<usize>(),
+                         "total_weight drifted from the node weights");
+    }
+
+    ///Number of virtual points on the ring, 3 per digest; drained nodes have none.
+    ///Points that collide on a token count once.
+    pub fn virtual_node_count(&self) -> usize {
+        self.points.len()
+    }
+
+    ///Changes `interleave_count` and regenerates every node's virtual points from
+    ///scratch, as `new` would for the registered nodes. Membership, weights, loads
+    ///and drained nodes are kept; drained nodes get their new points on undrain.
+    ///
+    ///Panics if `count` is 0.
+    pub fn set_interleave_count(&mut self, count: usize) {
+        assert!(count > 0, "interleave count must be at least 1");
+        self.interleave_count = count;
+        self.points.clear();
+        self.factors.clear();
+        self.draining.clear();
+
+        //in the order they were registered, like `new`; `sort_keys` gives colliding
+        //points the same owner whatever the order anyway
+        let nodes_num = self.owners.len();
+        for index in 0..nodes_num as u32 {
+            let node_entity = Arc::clone(&self.owners[index as usize]);
+            let factor = self.factor_of(&node_entity, nodes_num);
+            if self.drained.contains(node_entity.ring_id()) {
+                self.factors.insert(ByName(node_entity), factor);
+            } else {
+                self.add_virtual_nodes(index, factor);
+            }
+        }
+        self.sort_keys();
+        self.ring_changed();
+    }
+
+    ///Replaces the ring's membership with `nodes` and regenerates every point from
+    ///scratch, as the builder that made this ring would for `nodes`. Loads and down
+    ///marks of the nodes that are still listed are kept; drains end, gradual or
+    ///not.
+    pub fn rebuild(&mut self, nodes: &[T]) {
+        self.points.clear();
+        self.owners.clear();
+        self.real_nodes.clear();
+        self.factors.clear();
+        self.drained.clear();
+        self.draining.clear();
+        self.generate_hashing_ring(nodes.to_vec());
+        let real_nodes = &self.real_nodes;
+        self.loads.retain(|name, _| real_nodes.contains_key(name.as_str()));
+        self.down.retain(|name| real_nodes.contains_key(name.as_str()));
+        self.ring_changed();
+    }
+
+    ///Awaits `provider`, e.g. a call to a discovery service, and `rebuild`s the
+    ///ring from the nodes it returns, behind the `async` feature. Only the fetch is
+    ///asynchronous; the rebuild itself runs to completion without yielding, so
+    ///the ring never shows a half-built state, and it works with any executor.
+    #[cfg(feature = "async")]
+    pub async fn refresh_from<F, Fut>(&mut self, provider: F)
+        where F: FnOnce() -> Fut, Fut: std::future::Future<Output = Vec<T>> {
+        let nodes = provider().await;
+        self.rebuild(&nodes);
+    }
+
+    ///Adds a node with its weight share of virtual points, the same way
+    ///`undrain_node` puts points back; the points of the nodes already on the ring
+    ///stay where they are. Returns the node's id, or `None`, leaving the ring alone,
+    ///if a node with the same `ring_id` is already registered.
+    pub fn add_node(&mut self, node: T) -> Option<NodeId> {
+        if !self.insert_node(&node) {
+            return None;
+        }
+        self.check_total_weight();
+        self.sort_keys();
+        self.ring_changed();
+        Some(NodeId::from(node.ring_id()))
+    }
+
+    ///Registers the node and adds its virtual points, without sorting.
+    fn insert_node(&mut self, node: &T) -> bool {
+        let name = node.ring_id().to_string();
+        if self.real_nodes.contains_key(name.as_str()) {
+            return false;
+        }
+        self.total_weight += node.get_weight();
+        let index = self.register(Arc::new(node.clone()));
+
+        let factor = self.factor_of(node, self.real_nodes.len());
+        self.add_virtual_nodes(index, factor);
+        true
+    }
+
+    ///Sorts the points by token. Of points that collide, the one of the node with
+    ///the lowest `ring_id` keeps the token, whatever order the nodes were added in;
+    ///the others are set aside until it's removed, so every token is on the ring
+    ///once.
+    fn sort_keys(&mut self) {
+        self.points.sort(name_order(&self.owners));
+        #[cfg(test)]
+        {
+            self.sort_count += 1;
+        }
+    }
+
+    ///Returns the id of the node registered under `name`.
+    pub fn node_id(&self, name: &str) -> Option<NodeId> {
+        self.registered(name).map(|node| NodeId::from(node.ring_id()))
+    }
+
+    ///Changes a node's weight and regenerates its virtual points for it; the other
+    ///nodes' points stay where they are. A drained node just keeps the new weight
+    ///for when it's undrained. Returns whether the node is registered.
+    pub fn update_weight(&mut self, id: &NodeId, weight: usize) -> bool
+        where T: WithMutableWeight {
+        self.update_weight_by_name(id.as_str(), weight)
+    }
+
+    ///`update_weight` by node name.
+    pub fn update_weight_by_name(&mut self, name: &str, weight: usize) -> bool
+        where T: WithMutableWeight {
+        let mut node = match self.registered(name) {
+            Some(node) => T::clone(node),
+            None => return false,
+        };
+        self.total_weight = self.total_weight - node.get_weight() + weight;
+        node.set_weight(weight);
+        let factor = self.factor_of(&node, self.real_nodes.len());
+        self.draining.remove(name);
+        //while the old node is registered, so its points can still be found
+        let drained = self.drained.contains(name);
+        if !drained {
+            self.remove_virtual_nodes(name);
+        }
+        let index = self.register(Arc::new(node));
+        self.check_total_weight();
+        if !drained {
+            self.add_virtual_nodes(index, factor);
+            self.sort_keys();
+        } else {
+            self.factors.insert(self.key_of(index), factor);
+        }
+        self.ring_changed();
+        true
+    }
+
+    ///Returns the ring positions of the node's virtual points, in ring order.
+    ///Empty if the node is unknown or drained.
+    pub fn points_for_node(&self, id: &NodeId) -> Vec<u32> {
+        self.points_for_node_by_name(id.as_str())
+    }
+
+    ///`points_for_node` by node name.
+    pub fn points_for_node_by_name(&self, name: &str) -> Vec<u32> {
+        self.owned_points()
+            .filter(|(_, owner)| owner.ring_id() == name)
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    ///Number of digests a node gets: its weight share of `interleave_count` per
+    ///node, or `points_per_weight` times its weight if that's set, times
+    ///`replica_factor`.
+    fn factor_of(&self, node_entity: &T, nodes_num: usize) -> usize {
+        if let Some(points_per_weight) = self.points_per_weight {
+            return points_per_weight.saturating_mul(node_entity.get_weight()).saturating_mul(self.replica_factor);
+        }
+        if self.total_weight == 0 {
+            return 0;
+        }
+        let factor = weighted_factor(self.interleave_count, nodes_num, node_entity.get_weight(), self.total_weight);
+        factor.saturating_mul(self.replica_factor)
+    }
+
+    ///Adds `factor` digests worth of virtual points (3 per digest) for the node at
+    ///`index` in `owners`. `points` is left unsorted, callers sort once they're done.
+    fn add_virtual_nodes(&mut self, index: u32, factor: usize) {
+        self.factors.insert(self.key_of(index), factor);
+        self.insert_points(index, 0..factor);
+    }
+
+    ///Puts the points of the digests `replicas` of the node at `index` on the ring,
+    ///unsorted.
+    fn insert_points(&mut self, index: u32, replicas: Range<usize>) {
+        //not `point_source`, which would borrow all of the ring: the points go on
+        //it as they're hashed
+        let source = PointSource {
+            point_scheme: self.point_scheme,
+            byte_order: self.byte_order,
+            seed: &self.seed,
+            label_format: self.label_format.as_ref(),
+        };
+        let node_entity = &self.owners[index as usize];
+        let (points, owners) = (&mut self.points, &self.owners);
+        source.for_each_point(node_entity.ring_id(), node_entity.get_weight(), replicas, |key| points.push((key, index), name_order(owners)));
+    }
+
+    ///Registers the node in `real_nodes` and `owners`, replacing the node registered
+    ///under the same `ring_id` if there is one, and returns its index in `owners`.
+    fn register(&mut self, node: Arc<T>) -> u32 {
+        match self.owner_index(node.ring_id()) {
+            Some(index) => {
+                //the keys hold the old node, which has to go
+                let key = ByName(Arc::clone(&node));
+                self.real_nodes.remove(node.ring_id());
+                self.real_nodes.insert(key.clone(), index);
+                if let Some(factor) = self.factors.remove(node.ring_id()) {
+                    self.factors.insert(key, factor);
+                }
+                self.owners[index as usize] = node;
+                index
+            }
+            None => {
+                let index = self.owners.len() as u32;
+                self.real_nodes.insert(ByName(Arc::clone(&node)), index);
+                self.owners.push(node);
+                self.node_ids.take();
+                index
+            }
+        }
+    }
+
+    ///Drops the node from `real_nodes` and `owners`, the last node in `owners`
+    ///taking its index. Its points must be off the ring already.
+    fn unregister(&mut self, name: &str) -> Option<Arc<T>> {
+        let index = self.real_nodes.remove(name)?;
+        self.node_ids.take();
+        let last = (self.owners.len() - 1) as u32;
+        if index == last {
+            return self.owners.pop();
+        }
+        let tokens = self.owner_tokens(last);
+        let node = self.owners.swap_remove(index as usize);
+        *self.real_nodes.get_mut(self.owners[index as usize].ring_id()).expect("every owner is registered") = index;
+        self.points.renumber(last, index, &tokens);
+        Some(node)
+    }
+
+    ///The key the node at `index` goes into the maps by name under.
+    fn key_of(&self, index: u32) -> ByName<T> {
+        ByName(Arc::clone(&self.owners[index as usize]))
+    }
+
+    ///Index of the node registered under `name` in `owners`.
+    fn owner_index(&self, name: &str) -> Option<u32> {
+        self.real_nodes.get(name).copied()
+    }
+
+    ///The node registered under `name`.
+    fn registered(&self, name: &str) -> Option<&Arc<T>> {
+        self.real_nodes.get(name).map(|index| &self.owners[*index as usize])
+    }
+
+    ///Every token the node at `index` may hold a point at, all of its digests'
+    ///points, if the point store needs them to find its points.
+    fn owner_tokens(&self, index: u32) -> Vec<u32> {
+        if !self.points.needs_tokens() {
+            return Vec::new();
+        }
+        let node_entity = Arc::clone(&self.owners[index as usize]);
+        let factor = self.factors.get(node_entity.ring_id()).copied().unwrap_or(0);
+        self.digest_points(&node_entity, 0..factor)
+    }
+
+    ///The owner of `point`, which must be on the ring.
+    fn owner_at(&self, point: Point) -> &T {
+        &self.owners[point.1 as usize]
+    }
+
+    ///The points in ring order with their owners.
+    fn owned_points(&self) -> impl Iterator<Item = (u32, &T)> + '_ {
+        self.points.iter().map(move |(key, index)| (key, &*self.owners[index as usize]))
+    }
+
+    //the points' tokens in ring order
+    #[cfg(test)]
+    fn sorted_keys(&self) -> Vec<u32> {
+        self.points.iter().map(|point| point.0).collect()
+    }
+
+    //the owner of the point at `key`, which has to be on the ring
+    #[cfg(test)]
+    fn owner_of(&self, key: u32) -> &T {
+        let index = self.points.get(key).unwrap_or_else(|| panic!("no point at {}", key));
+        &self.owners[index as usize]
+    }
+
+    ///The ring keys of the node's digests `replicas`, 3 per digest, in order.
+    fn digest_points(&self, node_entity: &T, replicas: Range<usize>) -> Vec<u32> {
+        self.point_source().digest_points(node_entity.ring_id(), node_entity.get_weight(), replicas)
+    }
+
+    fn point_source(&self) -> PointSource<'_> {
+        PointSource {
+            point_scheme: self.point_scheme,
+            byte_order: self.byte_order,
+            seed: &self.seed,
+            label_format: self.label_format.as_ref(),
+        }
+    }
+
+    ///Removes every virtual point owned by the node, leaving `real_nodes` alone.
+    ///Returns the removed points in ring order.
+    fn remove_virtual_nodes(&mut self, name: &str) -> Vec<u32> {
+        let index = match self.owner_index(name) {
+            Some(index) => index,
+            None => return Vec::new(),
+        };
+        let tokens = self.owner_tokens(index);
+        self.points.remove_owner(index, &tokens, name_order(&self.owners))
+    }
+
+
+    ///Removes the node together with all of its virtual points. Returns the ring
+    ///keys it vacated in ascending order, empty if no node matched.
+    pub fn remove_node(&mut self, id: &NodeId) -> Vec<u32> {
+        self.remove_node_by_name(id.as_str())
+    }
+
+    ///Removes the node registered under `name` together with all of its virtual
+    ///points. Returns the ring keys it vacated in ascending order, empty if no node
+    ///matched. A drained node has no points left, so removing it also returns an
+    ///empty vector; use `contains_node` first to tell the two apart.
+    pub fn remove_node_by_name(&mut self, name: &str) -> Vec<u32> {
+        let removed = self.remove_virtual_nodes(name);
+        let node = match self.unregister(name) {
+            Some(node) => node,
+            None => return Vec::new(),
+        };
+        self.total_weight -= node.get_weight();
+        self.check_total_weight();
+        self.loads.remove(name);
+        self.drained.remove(name);
+        self.draining.remove(name);
+        self.down.remove(name);
+        self.factors.remove(name);
+        self.ring_changed();
+        removed
+    }
+
+    ///Releases the spare capacity the ring's collections keep after heavy churn.
+    pub fn shrink_to_fit(&mut self) {
+        self.points.shrink_to_fit();
+        self.owners.shrink_to_fit();
+        self.real_nodes.shrink_to_fit();
+        self.loads.shrink_to_fit();
+        self.drained.shrink_to_fit();
+        self.draining.shrink_to_fit();
+        self.down.shrink_to_fit();
+        self.factors.shrink_to_fit();
+    }
+
+    ///Estimates the bytes held by the ring's own structures, counting allocated
+    ///capacity rather than length. Heap data owned by the nodes themselves isn't
+    ///included, apart from the names used as keys of loads, drains and down marks.
+    pub fn memory_footprint(&self) -> usize {
+        use std::mem::size_of;
+
+        //hash tables store one control byte per bucket next to the entry
+        let owners = self.owners.capacity() * size_of::<Arc<T>>();
+        let nodes = self.real_nodes.capacity() * (size_of::<ByName<T>>() + size_of::<u32>() + 1);
+        //each node is allocated once, behind two reference counts
+        let shared = self.owners.len() * (size_of::<T>() + 2 * size_of::<usize>());
+        let loads = self.loads.capacity() * (size_of::<String>() + size_of::<usize>() + 1);
+        let drained = self.drained.capacity() * (size_of::<String>() + 1);
+        let draining = self.draining.capacity() * (size_of::<String>() + size_of::<(usize, usize)>() + 1);
+        let down = self.down.capacity() * (size_of::<String>() + 1);
+        let factors = self.factors.capacity() * (size_of::<ByName<T>>() + size_of::<usize>() + 1);
+        let names: usize = self.loads.keys().chain(self.drained.iter()).chain(self.draining.keys()).chain(self.down.iter())
+            .map(|name| name.capacity())
+            .sum();
+        size_of::<Self>() + owners + nodes + shared + loads + drained + draining + down + factors + names + self.points_heap_bytes()
+    }
+
+    fn points_heap_bytes(&self) -> usize {
+        self.points.heap_bytes()
+    }
+
+    ///Returns whether a node is registered under `name`, drained or not.
+    pub fn contains_node(&self, name: &str) -> bool {
+        self.real_nodes.contains_key(name)
+    }
+
+    ///Returns the physical nodes sorted by name, drained ones included, so output
+    ///built from them doesn't depend on `HashMap` iteration order.
+    pub fn nodes_in_order(&self) -> Vec<&T> {
+        let mut nodes: Vec<&T> = self.owners.iter().map(|node| &**node).collect();
+        nodes.sort_by(|a, b| a.ring_id().cmp(b.ring_id()));
+        nodes
+    }
+
+    ///A copy of the membership, weights, points and per-node state, the settings
+    ///left out, for `restore_state`.
+    pub(crate) fn save_state(&self) -> SavedState<T> {
+        SavedState {
+            points: self.points.clone(),
+            owners: self.owners.clone(),
+            real_nodes: self.real_nodes.clone(),
+            total_weight: self.total_weight,
+            loads: self.loads.clone(),
+            drained: self.drained.clone(),
+            draining: self.draining.clone(),
+            down: self.down.clone(),
+            factors: self.factors.clone(),
+        }
+    }
+
+    ///Puts back what `save_state` saved, as if nothing had changed since.
+    pub(crate) fn restore_state(&mut self, state: SavedState<T>) {
+        self.points = state.points;
+        self.owners = state.owners;
+        self.real_nodes = state.real_nodes;
+        self.total_weight = state.total_weight;
+        self.loads = state.loads;
+        self.drained = state.drained;
+        self.draining = state.draining;
+        self.down = state.down;
+        self.factors = state.factors;
+        self.node_ids.take();
+        self.ring_changed();
+    }
+
+    ///A 64-bit FNV-1a hash of the ring's state: `interleave_count`, every node in
+    ///name order with its weight and whether it's drained, then every point with
+    ///its owner's `ring_id`. Rings with the same settings that place every key alike
+    ///have the same fingerprint, however they were built, and a difference in
+    ///membership, weights or points almost surely shows, whatever setting or history
+    ///caused it. Loads and down marks aren't included. The digest is fixed by the
+    ///tests, so it can be compared across versions of this crate.
+    pub fn fingerprint(&self) -> u64 {
+        let mut fingerprint = Fnv1a::new();
+        fingerprint.feed(&(self.interleave_count as u64).to_le_bytes());
+        self.feed_nodes(&mut fingerprint);
+        for (key, owner) in self.owned_points() {
+            let owner = owner.ring_id();
+            fingerprint.feed(&key.to_le_bytes());
+            fingerprint.feed(&(owner.len() as u64).to_le_bytes());
+            fingerprint.feed(owner.as_bytes());
+        }
+        fingerprint.0
+    }
+
+    //every node in name order with its weight and whether it's drained
+    fn feed_nodes(&self, hash: &mut Fnv1a) {
+        for node in self.nodes_in_order() {
+            hash.feed(&(node.ring_id().len() as u64).to_le_bytes());
+            hash.feed(node.ring_id().as_bytes());
+            hash.feed(&(node.get_weight() as u64).to_le_bytes());
+            hash.feed(&[self.drained.contains(node.ring_id()) as u8]);
+        }
+    }
+
+    ///`fingerprint` as 16 lowercase hex digits, for logs.
+    pub fn fingerprint_hex(&self) -> String {
+        format!("{:016x}", self.fingerprint())
+    }
+
+    ///A 64-bit FNV-1a hash of what the ring was configured with: `interleave_count`
+    ///and the other settings that shape placement (replica factor, points per
+    ///weight, probes, point scheme, byte order, seed, bounded load, whether a
+    ///`label_format` is set), then every node in name order with its weight,
+    ///whether it's drained and how many digests it was given. Unlike `fingerprint`
+    ///it doesn't walk the points, so it's cheap to recompute on every poll of a
+    ///config service.
+    ///
+    ///The digest counts are what a ring's history of membership changes leaves
+    ///behind, and colliding points go to the lowest `ring_id` whatever order the
+    ///nodes came in, so rings with equal digests route alike however they were built.
+    ///The one thing that can't be hashed is the `label_format` closure itself:
+    ///rings with different custom formats are taken to be configured alike. Loads
+    ///and down marks aren't included.
+    pub fn ring_digest(&self) -> u64 {
+        let mut digest = Fnv1a::new();
+        let mut feed = |bytes: &[u8]| digest.feed(bytes);
+        feed(&(self.interleave_count as u64).to_le_bytes());
+        feed(&(self.replica_factor as u64).to_le_bytes());
+        feed(&(self.points_per_weight.unwrap_or(0) as u64).to_le_bytes());
+        feed(&(self.probes as u64).to_le_bytes());
+        feed(&[(self.point_scheme == PointScheme::DoubleHashing) as u8, (self.byte_order == ByteOrder::BigEndian) as u8]);
+        feed(&self.load_epsilon.map_or(0, f64::to_bits).to_le_bytes());
+        feed(&(self.seed.len() as u64).to_le_bytes());
+        feed(self.seed.as_bytes());
+        feed(&[self.label_format.is_some() as u8]);
+        self.feed_nodes(&mut digest);
+        for node in self.nodes_in_order() {
+            digest.feed(&(self.factors.get(node.ring_id()).copied().unwrap_or(0) as u64).to_le_bytes());
+        }
+        digest.0
+    }
+
+    ///Like `get_node`, but returns the owner's position in `nodes_in_order` as a
+    ///compact id. Ids only depend on the set of node names, so rings built from
+    ///the same membership agree on them; adding or removing a node renumbers the
+    ///nodes sorted after it. Not related to `NodeId`.
+    ///
+    ///The ids are looked up in a table that's built on the first call after the
+    ///membership changes, so encoding placements in bulk costs a lookup per key.
+    pub fn get_node_id(&self, key: &str) -> Option<u32> {
+        let pos = self.resolved_pos(self.get_node_pos(key)?)?;
+        self.node_ids().of_owner.get(pos.1 as usize).copied()
+    }
+
+    ///Resolves an id from `get_node_id` back to its node.
+    pub fn node_by_id(&self, id: u32) -> Option<&T> {
+        let index = *self.node_ids().owners.get(id as usize)?;
+        Some(&self.owners[index as usize])
+    }
+
+    fn node_ids(&self) -> &NodeIds {
+        self.node_ids.get_or_init(|| {
+            let mut owners: Vec<u32> = (0..self.owners.len() as u32).collect();
+            owners.sort_by(|a, b| self.owners[*a as usize].ring_id().cmp(self.owners[*b as usize].ring_id()));
+            let mut of_owner = vec![0; owners.len()];
+            for (id, index) in owners.iter().enumerate() {
+                of_owner[*index as usize] = id as u32;
+            }
+            NodeIds { of_owner, owners }
+        })
+    }
+
+    ///Stops routing keys to the node by taking its virtual points off the ring,
+    ///while keeping it registered, e.g. until its connections have drained during
+    ///a graceful shutdown. Does nothing if the node is unknown or already drained.
+    pub fn drain_node(&mut self, name: &str) {
+        if !self.real_nodes.contains_key(name) || !self.drained.insert(name.to_string()) {
+            return;
+        }
+        self.draining.remove(name);
+        self.remove_virtual_nodes(name);
+        self.ring_changed();
+    }
+
+    ///Starts decommissioning the node over `steps` calls to `advance_drain`, so its
+    ///keys move off it a slice at a time instead of all at once. Does nothing if the
+    ///node is unknown, drained or already being drained. A `drain_node`, a weight
+    ///change or a new interleave count ends the gradual drain.
+    ///
+    ///Panics if `steps` is 0.
+    pub fn start_drain(&mut self, name: &str, steps: usize) {
+        assert!(steps > 0, "a drain needs at least one step");
+        if !self.real_nodes.contains_key(name) || self.drained.contains(name) || self.draining.contains_key(name) {
+            return;
+        }
+        self.draining.insert(name.to_string(), (steps, 0));
+    }
+
+    ///Takes the next step of a drain started with `start_drain`: roughly `1/steps` of
+    ///the node's points come off the ring, the ones of its highest digest indices
+    ///first, so only the keys on those points move. The last step removes the node
+    ///like `remove_node_by_name`. Does nothing if the node isn't being drained.
+    pub fn advance_drain(&mut self, name: &str) {
+        let (steps, taken) = match self.draining.get(name) {
+            Some(&progress) => progress,
+            None => return,
+        };
+        if taken + 1 >= steps {
+            self.remove_node_by_name(name);
+            return;
+        }
+        let factor = self.factors[name];
+        let node_entity = Arc::clone(self.registered(name).expect("a node being drained is registered"));
+        let removed = self.digest_points(&node_entity, kept_digests(factor, steps, taken + 1)..kept_digests(factor, steps, taken));
+        //a colliding point may belong to another node
+        let index = self.owner_index(name).expect("a node being drained is registered");
+        self.points.remove_points(index, &removed, name_order(&self.owners));
+        self.draining.insert(name.to_string(), (steps, taken + 1));
+        self.ring_changed();
+    }
+
+    ///Aborts a gradual drain, putting back every point it took off.
+    pub fn cancel_drain(&mut self, name: &str) {
+        let (steps, taken) = match self.draining.remove(name) {
+            Some(progress) => progress,
+            None => return,
+        };
+        let factor = self.factors[name];
+        let index = self.owner_index(name).expect("a node being drained is registered");
+        self.insert_points(index, kept_digests(factor, steps, taken)..factor);
+        self.sort_keys();
+        self.ring_changed();
+    }
+
+    ///`(points removed, points before the drain)` of a gradual drain, `None` if the
+    ///node isn't being drained.
+    pub fn drain_progress(&self, name: &str) -> Option<(usize, usize)> {
+        let &(steps, taken) = self.draining.get(name)?;
+        let factor = self.factors[name];
+        Some(((factor - kept_digests(factor, steps, taken)) * 3, factor * 3))
+    }
+
+    ///Marks the node as down, e.g. while it restarts: it keeps its virtual points,
+    ///but lookups pass over it to the next node clockwise that is up. Unlike
+    ///`drain_node` nothing on the ring changes, so `set_node_up` brings back exactly
+    ///the placement from before. Does nothing if the node is unknown.
+    pub fn set_node_down(&mut self, name: &str) {
+        if self.real_nodes.contains_key(name) {
+            self.down.insert(name.to_string());
+            self.routable_weights.take();
+        }
+    }
+
+    ///Lets lookups route to a node marked down again.
+    pub fn set_node_up(&mut self, name: &str) {
+        if self.down.remove(name) {
+            self.routable_weights.take();
+        }
+    }
+
+    ///Returns whether the node is marked down.
+    pub fn is_node_down(&self, name: &str) -> bool {
+        self.down.contains(name)
+    }
+
+    ///Puts a drained node's virtual points back on the ring.
+    ///Does nothing if the node isn't drained.
+    pub fn undrain_node(&mut self, name: &str) {
+        if !self.drained.remove(name) {
+            return;
+        }
+        let index = self.owner_index(name).expect("a drained node is registered");
+        let factor = self.factors[name];
+        self.add_virtual_nodes(index, factor);
+        self.sort_keys();
+        self.ring_changed();
+    }
+
+    ///Returns the half-open ranges `[start, end)` of key hashes (see `hash_key`) that
+    ///resolve to the node, in ascending order, adjacent ranges merged. An `end` of 0
+    ///stands for 2^32, so the range wrapping past `u32::MAX` comes out as two
+    ///segments, `(start, 0)` and `(0, end)`. Empty for unknown or drained nodes.
+    pub fn owner_arcs(&self, name: &str) -> Vec<(u32, u32)> {
+        let mut arcs: Vec<(u64, u64)> = Vec::new();
+        let mut previous = None;
+        for (key, owner) in self.owned_points() {
+            let start = previous.replace(key);
+            if owner.ring_id() != name {
+                continue;
+            }
+            //a point owns the keys from the previous point (inclusive) up to itself
+            match start {
+                Some(start) => arcs.push((start as u64, key as u64)),
+                None => {
+                    arcs.push((0, key as u64));
+                    arcs.push((self.points.last().expect("a point is on the ring").0 as u64, 1u64 << 32));
+                }
+            }
+        }
+        arcs.sort();
+
+        let mut merged: Vec<(u64, u64)> = Vec::new();
+        for (start, end) in arcs.into_iter().filter(|(start, end)| start < end) {
+            match merged.last_mut() {
+                Some(last) if last.1 == start => last.1 = end,
+                _ => merged.push((start, end)),
+            }
+        }
+        merged.into_iter().map(|(start, end)| (start as u32, end as u32)).collect()
+    }
+
+    ///Describes the arc every point owns, in ring order: point `i` owns the key
+    ///hashes from the point before it (inclusive) up to itself (exclusive), so the
+    ///first point owns the wraparound arc from the last point across zero. Together
+    ///the ranges tile the whole `u32` space once. Empty for an empty ring.
+    pub fn dump_ranges(&self) -> Vec<RangeOwnership> {
+        let mut ranges: Vec<RangeOwnership> = Vec::new();
+        let mut previous = None;
+        for (key, owner) in self.owned_points() {
+            let start = previous.replace(key);
+            ranges.push(RangeOwnership {
+                start_token: start.unwrap_or_else(|| self.points.last().expect("a point is on the ring").0),
+                end_token: key,
+                node_name: owner.ring_id().to_string(),
+                is_wraparound: start.is_none(),
+            });
+        }
+        ranges
+    }
+
+    ///`dump_ranges` as a JSON array, e.g. for poking at with `jq`.
+    #[cfg(feature = "serde")]
+    pub fn dump_json(&self) -> String {
+        serde_json::to_string(&self.dump_ranges()).expect("ranges serialize")
+    }
+
+    ///Returns the fraction (0.0 to 1.0) of the `u32` keyspace each node owns, keyed
+    ///by node name. Drained nodes own nothing and are left out.
+    pub fn percent_owned(&self) -> HashMap<String, f64> {
+        let mut owned: HashMap<String, u64> = HashMap::new();
+        let mut previous = None;
+        for (key, owner) in self.owned_points() {
+            //a point owns the keys from the previous point (inclusive) up to itself
+            let arc = match previous.replace(key) {
+                Some(start) => (key - start) as u64,
+                None => (key as u64) + (1u64 << 32) - (self.points.last().expect("a point is on the ring").0 as u64),
+            };
+            *owned.entry(owner.ring_id().to_string()).or_insert(0) += arc;
+        }
+        owned.into_iter().map(|(name, arc)| (name, arc as f64 / (1u64 << 32) as f64)).collect()
+    }
+
+    ///Dumps the ring's points as a JSON array of `{"key": <u32>, "node": "<ring_id>"}`
+    ///objects sorted by key, e.g. for plotting the ring on a dashboard. Drained nodes
+    ///have no points and don't appear.
+    pub fn as_json(&self) -> String {
+        let mut json = String::from("[");
+        for (i, (key, owner)) in self.owned_points().enumerate() {
+            if i > 0 {
+                json.push(',');
+            }
+            json.push_str(&format!("{{\"key\":{},\"node\":", key));
+            push_json_string(&mut json, owner.ring_id());
+            json.push('}');
+        }
+        json.push(']');
+        json
+    }
+
+    ///Lays the ring's nodes (drained ones left out) out the way libketama does, for
+    ///placing keys exactly like the C library. libketama's fixed 40 points per
+    ///server are used, whatever `interleave_count` and `replica_factor` are.
+    pub fn to_ketama_compatible(&self) -> KetamaContinuum<T> {
+        let nodes: Vec<T> = self.nodes_in_order().into_iter()
+            .filter(|node| !self.drained.contains(node.ring_id()))
+            .cloned()
+            .collect();
+        KetamaContinuum::new(&nodes)
+    }
+
+    ///Precomputes a `SlotTable` of `num_slots` fixed slots, slot `s` going to the
+    ///node that owns ring position `s * 2^32 / num_slots`. Nodes are indexed by name.
+    ///Returns `None` if the ring is empty, `num_slots` is 0 or there are more than
+    ///65536 nodes.
+    pub fn to_slot_table(&self, num_slots: usize) -> Option<SlotTable<T>> {
+        if self.points.is_empty() || num_slots == 0 {
+            return None;
+        }
+        let mut nodes: Vec<T> = self.owners.iter()
+            .filter(|node| !self.drained.contains(node.ring_id()))
+            .map(|node| T::clone(node))
+            .collect();
+        nodes.sort_by(|a, b| a.ring_id().cmp(b.ring_id()));
+        let indices: HashMap<&str, u16> = nodes.iter().enumerate()
+            .map(|(index, node)| (node.ring_id(), index as u16))
+            .collect();
+
+        let slots: Vec<u16> = (0..num_slots as u64)
+            .map(|slot| {
+                let position = ((slot << 32) / num_slots as u64) as u32;
+                indices[self.owner_at(self.pos_for_hash(position)).ring_id()]
+            })
+            .collect();
+        SlotTable::from_indices(&nodes, &slots).ok()
+    }
+
+    ///Given a string key a corresponding node in the hash ring is returned.
+    ///If the hash ring is empty, `None` is returned.
+    ///
+    ///In bounded-load mode (see `ConsistentHashingBuilder::bounded_load`) nodes that
+    ///are at their load cap are skipped and the next node clockwise under its cap is
+    ///returned instead.
+    ///
+    ///Nodes marked down (see `set_node_down`) are passed over the same way; if every
+    ///node is down, `None` is returned. `get_node_raw` ignores both.
+    pub fn get_node(&self, string_key: &str) -> Option<T>{
+        self.get_node_ref(string_key).cloned()
+    }
+
+    ///Like `get_node`, but borrows the node from the ring instead of cloning it.
+    pub fn get_node_ref(&self, string_key: &str) -> Option<&T> {
+        let node = self.resolve_node(string_key);
+        if let Some(on_lookup) = &self.on_lookup {
+            on_lookup(string_key, node);
+        }
+        node
+    }
+
+    ///Like `get_node` for a key whose ring position was computed already, e.g. by
+    ///an earlier stage of a pipeline: `get_node_for_hash(hash_key(k))` is
+    ///`get_node(k)` on rings without a seed, with `ByteOrder::LittleEndian` and a
+    ///single probe. Bounded loads and down marks apply as for `get_node`; the
+    ///lookup cache and the `on_lookup` hook, which need the key, don't.
+    pub fn get_node_for_hash(&self, hash: u32) -> Option<T> {
+        if self.points.is_empty() {
+            return None;
+        }
+        self.resolve_pos(self.pos_for_hash(hash)).cloned()
+    }
+
+    ///Like `get_node`, but says why no node was found: `EmptyRing` if the ring has
+    ///no points, `NoEligibleNode` if every node on it is marked down.
+    pub fn try_get_node(&self, key: &str) -> Result<T, LookupError> {
+        self.get_node(key).ok_or_else(|| self.lookup_error())
+    }
+
+    ///Like `get_node_where`, but says why no node was found: `EmptyRing` if the ring
+    ///has no points, `NoEligibleNode` if `pred` rejected every node on it.
+    pub fn try_get_node_where<P: Fn(&T) -> bool>(&self, key: &str, pred: P) -> Result<&T, LookupError> {
+        self.get_node_where(key, pred).ok_or_else(|| self.lookup_error())
+    }
+
+    fn lookup_error(&self) -> LookupError {
+        if self.points.is_empty() {
+            LookupError::EmptyRing
+        } else {
+            LookupError::NoEligibleNode
+        }
+    }
+
+    ///Returns the node owning the key's ring position, whether it's down or over
+    ///its load cap, i.e. the owner `get_node` would pick if every node were healthy.
+    pub fn get_node_raw(&self, string_key: &str) -> Option<T> {
+        let pos = self.get_node_pos(string_key)?;
+        self.node_at(pos).cloned()
+    }
+
+    ///Writes the name of the node `get_node` would return into `out`, which is cleared
+    ///first, and returns whether a node was found. Reusing `out` across calls avoids
+    ///the clone of the node and the allocation of its name.
+    pub fn node_name_into(&self, key: &str, out: &mut String) -> bool {
+        out.clear();
+        match self.resolve_node(key) {
+            Some(node) => {
+                out.push_str(node.ring_id());
+                true
+            }
+            None => false,
+        }
+    }
+
+    ///Returns whether `node_name` is the node `get_node` would return for the key,
+    ///without cloning the node. False on an empty ring or when every node is down.
+    pub fn owns(&self, node_name: &str, key: &str) -> bool {
+        self.resolve_node(key).is_some_and(|node| node.ring_id() == node_name)
+    }
+
+    ///Returns up to `n` distinct physical nodes for the key: the node `get_node`
+    ///returns, followed by the next distinct nodes clockwise from it. Nodes marked
+    ///down are left out.
+    pub fn get_nodes(&self, string_key: &str, n: usize) -> Vec<T> {
+        let mut nodes: Vec<T> = Vec::new();
+        let pos = match self.get_node_pos(string_key) {
+            Some(pos) => pos,
+            None => return nodes,
+        };
+        let pos = if self.load_epsilon.is_some() { self.bounded_load_pos(pos) } else { pos };
+
+        let mut seen: Vec<&str> = Vec::new();
+        for point in self.points.walk(pos.0) {
+            if nodes.len() >= n || seen.len() == self.real_nodes.len() {
+                break;
+            }
+            let node = self.owner_at(point);
+            let name = node.ring_id();
+            if !seen.contains(&name) {
+                seen.push(name);
+                if !self.down.contains(name) {
+                    nodes.push(T::clone(node));
+                }
+            }
+        }
+        nodes
+    }
+
+    ///The key's preference list: the first `n` distinct physical nodes from its
+    ///position clockwise, exactly what `get_nodes` returns. `write_set` and
+    ///`read_set` are prefixes of it.
+    pub fn preference_list(&self, key: &str, n: usize) -> Vec<T> {
+        self.get_nodes(key, n)
+    }
+
+    ///The `w` nodes to write the key to, the head of its preference list.
+    ///
+    ///As writes and reads take prefixes of the same list, a write set of `w` and a
+    ///read set of `r` share their first `min(w, r)` nodes, at least the
+    ///`w + r - n` a quorum over `n` replicas needs, as long as both are at most `n`
+    ///and the ring doesn't change in between.
+    pub fn write_set(&self, key: &str, w: usize) -> Vec<T> {
+        self.preference_list(key, w)
+    }
+
+    ///The `r` nodes to read the key from, the head of its preference list. See
+    ///`write_set` for how the two overlap.
+    pub fn read_set(&self, key: &str, r: usize) -> Vec<T> {
+        self.preference_list(key, r)
+    }
+
+    ///Up to `n` distinct physical nodes clockwise from the key's hash, each with the
+    ///clockwise ring distance from the hash to the node's first point after it, so
+    ///nearer nodes come first and distances never decrease along the list. Nodes
+    ///marked down are left out. Distances are measured from the key's own hash, so
+    ///probes and bounded loads don't come into it. A point sitting exactly on the
+    ///hash is only reached after going all the way around and counts as `u32::MAX`.
+    pub fn nearest_n_with_distance(&self, key: &str, n: usize) -> Vec<(T, u32)> {
+        let mut nodes: Vec<(T, u32)> = Vec::new();
+        if self.points.is_empty() {
+            return nodes;
+        }
+        let hash = self.key_hash(key);
+        let pos = self.pos_for_hash(hash);
+
+        let mut seen: HashSet<&str> = HashSet::new();
+        for point in self.points.walk(pos.0) {
+            if nodes.len() >= n || seen.len() == self.real_nodes.len() {
+                break;
+            }
+            let node = self.owner_at(point);
+            if seen.insert(node.ring_id()) && !self.down.contains(node.ring_id()) {
+                let distance = match point.0.wrapping_sub(hash) {
+                    0 => u32::MAX,
+                    distance => distance,
+                };
+                nodes.push((T::clone(node), distance));
+            }
+        }
+        nodes
+    }
+
+    ///Returns the node `get_node` picks for the key together with every other
+    ///physical node as fallbacks, in the order to retry them: clockwise from the
+    ///primary, each once, nodes marked down left out. `None` if no node is found.
+    #[must_use]
+    pub fn lookup(&self, key: &str) -> Option<LookupResult<T>> {
+        let mut nodes = self.get_nodes(key, self.real_nodes.len()).into_iter();
+        let primary = nodes.next()?;
+        Some(LookupResult { primary, fallbacks: nodes.collect() })
+    }
+
+    ///Returns the primary node for the key and the next distinct physical node
+    ///clockwise from it as a backup. `None` if there are fewer than two nodes.
+    pub fn get_two_nodes(&self, key: &str) -> Option<(T, T)> {
+        let mut nodes = self.get_nodes(key, 2).into_iter();
+        match (nodes.next(), nodes.next()) {
+            (Some(primary), Some(backup)) => Some((primary, backup)),
+            _ => None,
+        }
+    }
+
+    ///Walks clockwise from the key's position and returns the first node `pred`
+    ///accepts, e.g. `|node| node.tags().get("ssd").is_some_and(|v| v == "true")`.
+    ///Each physical node is tested once, so this stops after every node on the
+    ///ring has been rejected and then returns `None`. Loads aren't considered,
+    ///even in bounded-load mode.
+    pub fn get_node_where<P: Fn(&T) -> bool>(&self, key: &str, pred: P) -> Option<&T> {
+        let pos = self.get_node_pos(key)?;
+        self.distinct_nodes_from(pos).find(|node| pred(node))
+    }
+
+    ///Like `get_nodes` restricted to the nodes `pred` accepts: up to `n` distinct
+    ///physical nodes, in clockwise order from the key's position.
+    pub fn get_nodes_where<P: Fn(&T) -> bool>(&self, key: &str, n: usize, pred: P) -> Vec<&T> {
+        match self.get_node_pos(key) {
+            Some(pos) => self.distinct_nodes_from(pos).filter(|node| pred(node)).take(n).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    ///Like `get_nodes`, but spreads the replicas over availability zones: walking
+    ///clockwise, a node whose zone already has a replica is passed over until every
+    ///zone on the ring has one. Once the zones are used up, the remaining slots are
+    ///filled with the passed-over nodes in clockwise order.
+    pub fn get_nodes_cross_zone(&self, key: &str, n: usize) -> Vec<T>
+        where T: WithZone
+    {
+        let pos = match self.get_node_pos(key) {
+            Some(pos) => pos,
+            None => return Vec::new(),
+        };
+        let pos = if self.load_epsilon.is_some() { self.bounded_load_pos(pos) } else { pos };
+
+        let mut zones: HashSet<&str> = HashSet::new();
+        let mut picked: Vec<T> = Vec::new();
+        let mut passed_over: Vec<&T> = Vec::new();
+        for node in self.distinct_nodes_from(pos) {
+            if picked.len() >= n {
+                break;
+            }
+            if zones.insert(node.zone()) {
+                picked.push(node.clone());
+            } else {
+                passed_over.push(node);
+            }
+        }
+        let missing = n.saturating_sub(picked.len());
+        picked.extend(passed_over.into_iter().take(missing).cloned());
+        picked
+    }
+
+    ///Session-style overflow routing: the first node clockwise from the key's
+    ///position whose `occupancy` is below its `capacity`, nodes marked down passed
+    ///over. `None` if every node is full or the ring is empty.
+    ///
+    ///The answer is only as deterministic as the occupancy snapshot: a key keeps
+    ///finding the node it was placed on as long as the nodes before it stay full,
+    ///but once one of them frees up, new lookups stop there. Use
+    ///`overflow_chain_with_occupancy` to record where a key actually went.
+    pub fn get_node_with_occupancy(&self, key: &str, occupancy: &dyn Fn(&T) -> usize) -> Option<&T>
+        where T: WithCapacity
+    {
+        self.overflow_chain_with_occupancy(key, occupancy).accepted
+    }
+
+    ///Like `get_node_with_occupancy`, also returning the full nodes that were
+    ///passed over on the way, in clockwise order.
+    pub fn overflow_chain_with_occupancy(&self, key: &str, occupancy: &dyn Fn(&T) -> usize) -> OverflowChain<'_, T>
+        where T: WithCapacity
+    {
+        let mut chain = OverflowChain { full: Vec::new(), accepted: None };
+        let pos = match self.get_node_pos(key) {
+            Some(pos) => pos,
+            None => return chain,
+        };
+        for node in self.distinct_nodes_from(pos) {
+            if occupancy(node) < node.capacity() {
+                chain.accepted = Some(node);
+                break;
+            }
+            chain.full.push(node);
+        }
+        chain
+    }
+
+    ///The physical nodes in clockwise order from `pos`, each one once.
+    fn distinct_nodes_from<'a>(&'a self, pos: Point) -> impl Iterator<Item = &'a T> + 'a {
+        let mut seen: HashSet<&'a str> = HashSet::new();
+        self.points.walk(pos.0)
+            .map(move |point| self.owner_at(point))
+            .filter(move |node: &&'a T| seen.insert(node.ring_id()))
+            .filter(move |node| !self.down.contains(node.ring_id()))
+    }
+
+    ///The first point clockwise from `pos`, `pos` included, whose node isn't down.
+    fn up_pos(&self, pos: Point) -> Option<Point> {
+        if self.down.is_empty() {
+            return Some(pos);
+        }
+        self.points.walk(pos.0)
+            .find(|candidate| !self.down.contains(self.owner_at(*candidate).ring_id()))
+    }
+
+    fn resolve_node(&self, string_key: &str) -> Option<&T> {
+        let pos = self.get_node_pos(string_key)?;
+        self.resolve_pos(pos)
+    }
+
+    ///The node `get_node` settles on starting from the point `pos`, after bounded
+    ///loads and down marks.
+    fn resolve_pos(&self, pos: Point) -> Option<&T> {
+        self.node_at(self.resolved_pos(pos)?)
+    }
+
+    ///The point `resolve_pos` settles on.
+    fn resolved_pos(&self, mut pos: Point) -> Option<Point> {
+        if self.load_epsilon.is_some() {
+            pos = self.bounded_load_pos(pos);
+        }
+        self.up_pos(pos)
+    }
+
+    ///The node owning the point `pos`. `None` rather than a panic if the point has
+    ///no owner, which would be a bug in the ring.
+    fn node_at(&self, pos: Point) -> Option<&T> {
+        self.owners.get(pos.1 as usize).map(|node| &**node)
+    }
+
+    ///Sets the current load (e.g. number of assigned keys or connections) of a node,
+    ///which bounded-load mode takes into account when routing.
+    pub fn set_load(&mut self, name: &str, load: usize) {
+        if self.real_nodes.contains_key(name) {
+            self.loads.insert(name.to_string(), load);
+        }
+    }
+
+    ///Returns the load last set for the node, 0 if none was set.
+    pub fn load_of(&self, name: &str) -> usize {
+        self.loads.get(name).cloned().unwrap_or(0)
+    }
+
+    ///Returns the node's load cap for the next assignment in bounded-load mode,
+    ///i.e. `capacity_of(name, total_load + 1, 1 + epsilon)`. `None` if the mode is
+    ///off or the node isn't on the ring or is marked down.
+    pub fn load_cap(&self, name: &str) -> Option<usize> {
+        let epsilon = self.load_epsilon?;
+        let total_load = self.ring_load(&self.loads);
+        self.capacity_of(name, total_load + 1, 1.0 + epsilon)
+    }
+
+    ///Consistent hashing with bounded loads, with the loads supplied by the caller
+    ///(e.g. from its connection counts). Every node on the ring accepts at most
+    ///`capacity_of(name, total_load + 1, c)` assignments, its weight share of the
+    ///load scaled by `c`; the key goes to the first node clockwise from its position
+    ///that is still under its bound.
+    ///
+    ///As long as `loads` only reports load for nodes on the ring, some node is always
+    ///under the bound. If a provider nevertheless reports every node at or above it,
+    ///the least-loaded node is returned (the first one clockwise on ties).
+    ///If the hash ring is empty, `None` is returned.
+    ///
+    ///Panics if `c` is not greater than 1.0.
+    pub fn get_node_bounded(&self, key: &str, loads: &dyn LoadProvider, c: f64) -> Option<&T> {
+        assert!(c > 1.0, "bounded load factor must be greater than 1.0");
+        let pos = self.get_node_pos(key)?;
+        let pos = self.up_pos(self.bounded_pos(pos, loads, c))?;
+        self.node_at(pos)
+    }
+
+    ///Bounded-load capacity of a node: `ceil(c * total_load * weight / total_weight)`,
+    ///where `total_weight` only counts the nodes keys can be routed to (drained
+    ///nodes and nodes marked down are left out), so the caps add up to at least
+    ///`c * total_load`. A node with 4 times the weight of another can take 4 times
+    ///the assignments before it spills over. `None` if the node isn't on the ring
+    ///or is marked down.
+    pub fn capacity_of(&self, name: &str, total_load: usize, c: f64) -> Option<usize> {
+        if !self.is_routable(name) {
+            return None;
+        }
+        let weight = self.registered(name)?.get_weight();
+        Some(capacity(weight, self.ring_weight(), total_load, c))
+    }
+
+    //whether keys may be routed to the node, which may not be registered
+    fn is_routable(&self, name: &str) -> bool {
+        !self.drained.contains(name) && !self.down.contains(name)
+    }
+
+    ///Sum of the weights of the nodes keys can be routed to.
+    fn ring_weight(&self) -> usize {
+        self.owners.iter()
+            .filter(|node| self.is_routable(node.ring_id()))
+            .map(|node| node.get_weight())
+            .sum()
+    }
+
+    ///Sum of the loads of the nodes keys can be routed to.
+    fn ring_load(&self, loads: &dyn LoadProvider) -> usize {
+        self.owners.iter()
+            .filter(|node| self.is_routable(node.ring_id()))
+            .map(|node| loads.load(node.ring_id()))
+            .sum()
+    }
+
+    fn bounded_load_pos(&self, pos: Point) -> Point {
+        match self.load_epsilon {
+            Some(epsilon) => self.bounded_pos(pos, &self.loads, 1.0 + epsilon),
+            None => pos,
+        }
+    }
+
+    ///Walks clockwise from `pos` to the first point whose node is under the load cap,
+    ///falling back to the least-loaded node if there is none.
+    fn bounded_pos(&self, pos: Point, loads: &dyn LoadProvider, c: f64) -> Point {
+        let total_load = self.ring_load(loads) + 1;
+        let ring_weight = self.ring_weight();
+        //by owner index, filled in as the walk meets each owner
+        let mut caps: Vec<Option<usize>> = vec![None; self.owners.len()];
+        let mut least_loaded = (usize::MAX, pos);
+        for candidate in self.points.walk(pos.0) {
+            let node = self.owner_at(candidate);
+            let name = node.ring_id();
+            if self.down.contains(name) {
+                continue;
+            }
+            let load = loads.load(name);
+            let cap = *caps[candidate.1 as usize].get_or_insert_with(|| capacity(node.get_weight(), ring_weight, total_load, c));
+            if load < cap {
+                return candidate;
+            }
+            if load < least_loaded.0 {
+                least_loaded = (load, candidate);
+            }
+        }
+        least_loaded.1
+    }
+
+    ///Resolves every key pulled from `keys` lazily, yielding `(key, node)` pairs
+    ///in input order. Nothing is looked up until the caller asks for the next item,
+    ///so huge inputs can be streamed through with flat memory.
+    pub fn route_iter<'a, I: Iterator<Item = String> + 'a>(&'a self, keys: I) -> impl Iterator<Item = (String, Option<T>)> + 'a {
+        keys.map(move |key| {
+            let node = self.get_node(&key);
+            (key, node)
+        })
+    }
+
+    ///Picks a physical node at random, behind the `rand` feature, each node that
+    ///keys can be routed to being chosen with probability proportional to
+    ///`get_weight()`. Drained nodes and nodes marked down are never picked. Useful
+    ///for spreading work that doesn't need deterministic routing, e.g. background
+    ///jobs. If no such node has any weight, `None` is returned.
+    ///
+    ///A draw is a binary search of a table of the nodes' weights, which is built
+    ///on the first draw after the ring changes.
+    #[cfg(feature = "rand")]
+    pub fn weighted_random_node(&self, rng: &mut impl rand::Rng) -> Option<T> {
+        let weights = self.routable_weights.get_or_init(|| self.cumulative_weights());
+        let &(total, _) = weights.last()?;
+        //scale the draw into [0, total) without modulo bias
+        let target = ((rng.gen::<u64>() as u128 * total as u128) >> 64) as usize;
+        let pos = weights.partition_point(|(end, _)| *end <= target);
+        Some(T::clone(&self.owners[weights[pos].1 as usize]))
+    }
+
+    //the table of `routable_weights`: in name order, so a seeded rng gives
+    //reproducible picks, and without the nodes of no weight
+    #[cfg(feature = "rand")]
+    fn cumulative_weights(&self) -> Vec<(usize, u32)> {
+        let mut indices: Vec<u32> = (0..self.owners.len() as u32)
+            .filter(|index| {
+                let node = &self.owners[*index as usize];
+                node.get_weight() > 0 && !self.drained.contains(node.ring_id()) && !self.down.contains(node.ring_id())
+            })
+            .collect();
+        indices.sort_by(|a, b| self.owners[*a as usize].ring_id().cmp(self.owners[*b as usize].ring_id()));
+        let mut total = 0;
+        indices.into_iter()
+            .map(|index| {
+                total += self.owners[index as usize].get_weight();
+                (total, index)
+            })
+            .collect()
+    }
+
+    ///Given a string key the point it resolves to is returned.
+    ///If the hash ring is empty, `None` is returned.
+    fn get_node_pos(&self, string_key: &str) -> Option<Point>{
+        if self.points.is_empty() {
+            return None;
+        }
+
+        Some(self.cached_pos(string_key))
+    }
+
+    fn uncached_pos(&self, string_key: &str) -> Point {
+        if self.probes > 1 {
+            return self.multi_probe_pos(string_key);
+        }
+        self.pos_for_hash(self.key_hash(string_key))
+    }
+
+    #[cfg(feature = "lookup-cache")]
+    fn cached_pos(&self, string_key: &str) -> Point {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => return self.uncached_pos(string_key),
+        };
+        if let Some(pos) = cache.lock().unwrap_or_else(PoisonError::into_inner).get(string_key) {
+            return pos;
+        }
+        let pos = self.uncached_pos(string_key);
+        cache.lock().unwrap_or_else(PoisonError::into_inner).insert(string_key, pos);
+        pos
+    }
+
+    #[cfg(not(feature = "lookup-cache"))]
+    fn cached_pos(&self, string_key: &str) -> Point {
+        self.uncached_pos(string_key)
+    }
+
+    ///Forgets every cached lookup; called whenever the ring's points change.
+    #[cfg(feature = "lookup-cache")]
+    fn invalidate_cache(&self) {
+        if let Some(cache) = &self.cache {
+            cache.lock().unwrap_or_else(PoisonError::into_inner).clear();
+        }
+    }
+
+    #[cfg(not(feature = "lookup-cache"))]
+    fn invalidate_cache(&self) {}
+
+    ///Drops what was worked out from the ring's nodes and points, after a change
+    ///to either.
+    fn ring_changed(&mut self) {
+        self.invalidate_cache();
+        self.routable_weights.take();
+    }
+
+    ///Ring position of a key, its digest read in the ring's byte order.
+    fn key_hash(&self, string_key: &str) -> u32 {
+        self.byte_order.read(&hash_digest(&self.seeded(string_key)), 0)
+    }
+
+    ///`s` with the ring's seed in front, as point labels and keys are hashed.
+    fn seeded<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        with_seed(&self.seed, s)
+    }
+
+    ///The first ring point clockwise from `key`: the one with the smallest ring key
+    ///strictly greater than `key`, wrapping around to the first point past the
+    ///largest. A key that lands exactly on a ring point therefore belongs to the
+    ///next point. The ring must not be empty.
+    fn pos_for_hash(&self, key: u32) -> Point {
+        self.points.successor(key).expect("lookups need a point on the ring")
+    }
+
+    ///Multi-probe lookup: the key is hashed `probes` times (probe 0 is the plain key,
+    ///probe i is `"{key}-{i}"`) and the point closest clockwise to any of its probes
+    ///wins. Earlier probes win ties.
+    fn multi_probe_pos(&self, string_key: &str) -> Point {
+        let mut best_pos = (0, 0);
+        let mut best_distance = u32::MAX;
+        for probe in 0..self.probes {
+            let probe_key = if probe == 0 {
+                self.key_hash(string_key)
+            } else {
+                self.key_hash(&format!("{}-{}", string_key, probe))
+            };
+            let pos = self.pos_for_hash(probe_key);
+            let distance = pos.0.wrapping_sub(probe_key);
+            if probe == 0 || distance < best_distance {
+                best_pos = pos;
+                best_distance = distance;
+            }
+        }
+        best_pos
+    }
+
+
+
+}
+
+///Adds every node like `add_node` does (duplicates are skipped), but sorts the
+///ring once at the end instead of once per node.
+impl<T: RingNode + Hash + Clone + WithWeightInfo> Extend<T> for ConsistentHashing<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, nodes: I) {
+        let mut added = false;
+        for node in nodes {
+            added |= self.insert_node(&node);
+        }
+        self.check_total_weight();
+        if added {
+            self.sort_keys();
+            self.ring_changed();
+        }
+    }
+}
+
+///Consumes the ring, yielding every node once, drained ones included, in no
+///particular order. The nodes are moved out, not cloned.
+impl<T: RingNode + Hash + Clone + WithWeightInfo> IntoIterator for ConsistentHashing<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        //the points only hold indices, so once the maps by name are gone `owners`
+        //has the one reference to unwrap
+        let mut ring = self;
+        ring.real_nodes.clear();
+        ring.factors.clear();
+        let nodes: Vec<T> = ring.owners.into_iter()
+            .map(|node| Arc::try_unwrap(node).unwrap_or_else(|node| T::clone(&node)))
+            .collect();
+        nodes.into_iter()
+    }
+}
+
+
+impl ConsistentHashing<NodeInfoWithWeight> {
+    ///Builds a ring straight from `(name, weight)` pairs, without having to define
+    ///a node type, e.g. `ConsistentHashing::from_weighted(&[("a", 1), ("b", 2)], None)`.
+    pub fn from_weighted(pairs: &[(&str, usize)], interleave_count: Option<usize>) -> ConsistentHashing<NodeInfoWithWeight> {
+        let nodes: Vec<NodeInfoWithWeight> = pairs.iter()
+            .map(|&(name, weight)| NodeInfoWithWeight::new(name, weight))
+            .collect();
+        ConsistentHashing::new(&nodes, interleave_count)
+    }
+
+    ///Builds a ring from text with one `host:port weight` node per line, e.g. a
+    ///file operators keep by hand. A line with just a name has weight 1; blank
+    ///lines and lines starting with `#` are skipped. Fails with
+    ///`io::ErrorKind::InvalidData` naming the line if a weight isn't a number or a
+    ///line has more than two fields, and with the reader's error if reading fails.
+    pub fn from_reader<R: std::io::Read>(reader: R, interleave_count: Option<usize>) -> std::io::Result<ConsistentHashing<NodeInfoWithWeight>> {
+        use std::io::{BufRead, BufReader, Error as IoError, ErrorKind};
+
+        let mut nodes: Vec<NodeInfoWithWeight> = Vec::new();
+        for (i, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: String| IoError::new(ErrorKind::InvalidData, format!("line {}: {}", i + 1, reason));
+            let mut fields = line.split_whitespace();
+            let name = fields.next().unwrap();
+            let weight = match fields.next() {
+                Some(weight) => weight.parse().map_err(|_| invalid(format!("weight {:?} is not a number", weight)))?,
+                None => 1,
+            };
+            if fields.next().is_some() {
+                return Err(invalid(format!("expected `host:port weight`, got {:?}", line)));
+            }
+            nodes.push(NodeInfoWithWeight::new(name, weight));
+        }
+        Ok(ConsistentHashing::new(&nodes, interleave_count))
+    }
+
+    ///Writes the ring in a compact binary layout for `from_snapshot`, far smaller
+    ///than a serde encoding of it. What's kept is what `Serialize` keeps: the
+    ///settings that shape placement, the nodes with their weights and the points.
+    ///
+    ///Layout, integers as LEB128 varints unless noted: the magic `CHRS`, a version
+    ///byte, `interleave_count`, `replica_factor`, `probes`, a point scheme byte
+    ///(0 labels, 1 double hashing), a byte order byte (0 little endian, 1 big
+    ///endian), a byte flagging bounded load followed by its
+    ///epsilon as little endian `f64` if set, in version 2 the seed's length and
+    ///bytes, in version 3 `points_per_weight` (0 if not set), the node count and
+    ///per node its name
+    ///length, name, weight, a drained byte and digest count; then the point count
+    ///and per point the distance to the previous point (to 0 for the first one)
+    ///and the index of its node in the node table. A ring is written in the oldest
+    ///version that holds its settings, e.g. version 1 without a seed and
+    ///`points_per_weight`, so older readers still take it.
+    pub fn to_snapshot(&self) -> Vec<u8> {
+        let mut out: Vec<u8> = Vec::new();
+        out.extend_from_slice(SNAPSHOT_MAGIC);
+        let version = if self.points_per_weight.is_some() {
+            3
+        } else if !self.seed.is_empty() {
+            2
+        } else {
+            1
+        };
+        out.push(version);
+        push_varint(&mut out, self.interleave_count as u64);
+        push_varint(&mut out, self.replica_factor as u64);
+        push_varint(&mut out, self.probes as u64);
+        out.push(match self.point_scheme {
+            PointScheme::Labels => 0,
+            PointScheme::DoubleHashing => 1,
+        });
+        out.push(match self.byte_order {
+            ByteOrder::LittleEndian => 0,
+            ByteOrder::BigEndian => 1,
+        });
+        match self.load_epsilon {
+            Some(epsilon) => {
+                out.push(1);
+                out.extend_from_slice(&epsilon.to_le_bytes());
+            }
+            None => out.push(0),
+        }
+        if version >= 2 {
+            push_varint(&mut out, self.seed.len() as u64);
+            out.extend_from_slice(self.seed.as_bytes());
+        }
+        if version >= 3 {
+            push_varint(&mut out, self.points_per_weight.unwrap_or(0) as u64);
+        }
+
+        let nodes = self.nodes_in_order();
+        let mut indices: HashMap<&str, usize> = HashMap::new();
+        push_varint(&mut out, nodes.len() as u64);
+        for (index, node) in nodes.iter().enumerate() {
+            indices.insert(&node.node_name, index);
+            push_varint(&mut out, node.node_name.len() as u64);
+            out.extend_from_slice(node.node_name.as_bytes());
+            push_varint(&mut out, node.weight as u64);
+            out.push(self.drained.contains(&node.node_name) as u8);
+            push_varint(&mut out, self.factors.get(node.node_name.as_str()).cloned().unwrap_or(0) as u64);
+        }
+
+        let points: Vec<(u32, usize)> = self.owned_points()
+            .map(|(key, owner)| (key, indices[owner.node_name.as_str()]))
+            .collect();
+        push_varint(&mut out, points.len() as u64);
+        let mut previous = 0;
+        for (key, index) in points {
+            push_varint(&mut out, (key - previous) as u64);
+            push_varint(&mut out, index as u64);
+            previous = key;
+        }
+        out
+    }
+
+    ///Restores a ring written by `to_snapshot`, checking it the way `Deserialize`
+    ///does. Truncated, corrupt or inconsistent input is an error, never a panic.
+    pub fn from_snapshot(snapshot: &[u8]) -> Result<ConsistentHashing<NodeInfoWithWeight>, SnapshotError> {
+        let mut reader = SnapshotReader { bytes: snapshot, pos: 0 };
+        if reader.take(SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = reader.byte()?;
+        if version == 0 || version > SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion { version });
+        }
+        let interleave_count = reader.usize()?;
+        let replica_factor = reader.usize()?;
+        let probes = reader.usize()?;
+        let point_scheme = match reader.byte()? {
+            0 => PointScheme::Labels,
+            1 => PointScheme::DoubleHashing,
+            other => return Err(SnapshotError::Invalid { reason: format!("unknown point scheme {}", other) }),
+        };
+        let byte_order = match reader.byte()? {
+            0 => ByteOrder::LittleEndian,
+            1 => ByteOrder::BigEndian,
+            other => return Err(SnapshotError::Invalid { reason: format!("unknown byte order {}", other) }),
+        };
+        let load_epsilon = match reader.byte()? {
+            0 => None,
+            1 => {
+                let mut bytes = [0u8; 8];
+                bytes.copy_from_slice(reader.take(8)?);
+                Some(f64::from_le_bytes(bytes))
+            }
+            other => return Err(SnapshotError::Invalid { reason: format!("bad bounded load flag {}", other) }),
+        };
+        let seed = if version >= 2 {
+            let seed_len = reader.usize()?;
+            String::from_utf8(reader.take(seed_len)?.to_vec())
+                .map_err(|_| SnapshotError::Invalid { reason: "seed is not UTF-8".to_string() })?
+        } else {
+            String::new()
+        };
+        let points_per_weight = if version >= 3 {
+            match reader.usize()? {
+                0 => None,
+                points_per_weight => Some(points_per_weight),
+            }
+        } else {
+            None
+        };
+
+        let nodes_num = reader.usize()?;
+        let mut nodes: Vec<NodeInfoWithWeight> = Vec::new();
+        let mut drained: Vec<String> = Vec::new();
+        let mut factors: Vec<(String, usize)> = Vec::new();
+        for _ in 0..nodes_num {
+            let name_len = reader.usize()?;
+            let name = String::from_utf8(reader.take(name_len)?.to_vec())
+                .map_err(|_| SnapshotError::Invalid { reason: "node name is not UTF-8".to_string() })?;
+            let weight = reader.usize()?;
+            match reader.byte()? {
+                0 => {}
+                1 => drained.push(name.clone()),
+                other => return Err(SnapshotError::Invalid { reason: format!("bad drained flag {} for node {}", other, name) }),
+            }
+            factors.push((name.clone(), reader.usize()?));
+            nodes.push(NodeInfoWithWeight::new(name, weight));
+        }
+
+        let points_num = reader.usize()?;
+        let mut points: Vec<(u32, String)> = Vec::new();
+        let mut key: u64 = 0;
+        for _ in 0..points_num {
+            let delta = reader.varint()?;
+            key = key.checked_add(delta)
+                .filter(|key| *key <= u32::MAX as u64)
+                .ok_or_else(|| SnapshotError::Invalid { reason: format!("ring key {} + {} is out of range", key, delta) })?;
+            let index = reader.usize()?;
+            let owner = nodes.get(index)
+                .ok_or_else(|| SnapshotError::Invalid { reason: format!("ring key {} refers to node #{} of {}", key, index, nodes.len()) })?;
+            points.push((key as u32, owner.node_name.clone()));
+        }
+        if reader.pos != snapshot.len() {
+            return Err(SnapshotError::Invalid { reason: format!("{} trailing bytes", snapshot.len() - reader.pos) });
+        }
+
+        RingState { interleave_count, replica_factor, points_per_weight, probes, load_epsilon, point_scheme, byte_order, seed, nodes, drained, factors, points }
+            .into_ring()
+            .map_err(|reason| SnapshotError::Invalid { reason })
+    }
+}
+
+const SNAPSHOT_MAGIC: &[u8] = b"CHRS";
+const SNAPSHOT_VERSION: u8 = 3;
+
+fn push_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+struct SnapshotReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> SnapshotReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if self.bytes.len() - self.pos < len {
+            return Err(SnapshotError::Truncated);
+        }
+        let bytes = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn varint(&mut self) -> Result<u64, SnapshotError> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            let bits = (byte & 0x7f) as u64;
+            if shift == 63 && bits > 1 {
+                break;
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(SnapshotError::Invalid { reason: "varint overflows 64 bits".to_string() })
+    }
+
+    fn usize(&mut self) -> Result<usize, SnapshotError> {
+        let value = self.varint()?;
+        if value > usize::MAX as u64 {
+            return Err(SnapshotError::Invalid { reason: format!("{} doesn't fit a usize", value) });
+        }
+        Ok(value as usize)
+    }
+}
+
+///Error returned by `ConsistentHashing::from_snapshot`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SnapshotError {
+    BadMagic,
+    UnsupportedVersion { version: u8 },
+    Truncated,
+    Invalid { reason: String },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::BadMagic => write!(f, "not a ring snapshot"),
+            SnapshotError::UnsupportedVersion { version } => write!(f, "unsupported ring snapshot version {}", version),
+            SnapshotError::Truncated => write!(f, "ring snapshot is truncated"),
+            SnapshotError::Invalid { reason } => write!(f, "invalid ring snapshot: {}", reason),
+        }
+    }
+}
+
+impl Error for SnapshotError {}
+
+///Error returned by `ConsistentHashing::try_get_node` and `try_get_node_where`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LookupError {
+    EmptyRing,
+    NoEligibleNode,
+}
+
+impl fmt::Display for LookupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LookupError::EmptyRing => write!(f, "the ring has no points"),
+            LookupError::NoEligibleNode => write!(f, "no node on the ring is eligible for the key"),
+        }
+    }
+}
+
+impl Error for LookupError {}
+
+
+///Reports the current load of nodes for `ConsistentHashing::get_node_bounded`.
+pub trait LoadProvider {
+    ///Current load (assigned keys, open connections, ...) of the node registered
+    ///under `name`.
+    fn load(&self, name: &str) -> usize;
+}
+
+impl LoadProvider for HashMap<String, usize> {
+    fn load(&self, name: &str) -> usize {
+        self.get(name).cloned().unwrap_or(0)
+    }
+}
+
+
+///Builder for `ConsistentHashing`, started with `ConsistentHashing::builder`.
+pub struct ConsistentHashingBuilder<T: RingNode + Hash + Clone + WithWeightInfo> {
+    real_nodes: Vec<T>,
+    interleave_count: usize,
+    replica_factor: usize,
+    points_per_weight: Option<usize>,
+    load_epsilon: Option<f64>,
+    probes: usize,
+    #[cfg(feature = "lookup-cache")]
+    lookup_cache: usize,
+    label_format: Option<LabelFormatter>,
+    point_scheme: PointScheme,
+    byte_order: ByteOrder,
+    seed: String,
+    on_lookup: Option<LookupHook<T>>,
+    backend: Backend,
+}
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> ConsistentHashingBuilder<T> {
+    ///Number of virtual points per node, before weighting. Defaults to 40.
+    pub fn interleave_count(mut self, interleave_count: usize) -> Self {
+        self.interleave_count = interleave_count;
+        self
+    }
+
+    ///Multiplies every node's number of virtual points, whatever its weight, for a
+    ///denser ring. Higher values smooth out the key distribution at the cost of
+    ///build time and memory. Defaults to 1.
+    ///
+    ///Panics if `replica_factor` is 0.
+    pub fn replica_factor(mut self, replica_factor: usize) -> Self {
+        assert!(replica_factor > 0, "replica factor must be at least 1");
+        self.replica_factor = replica_factor;
+        self
+    }
+
+    ///Gives every node `points_per_weight * weight` digests (times
+    ///`replica_factor`) instead of its weight share of `interleave_count` per node.
+    ///A node's points then depend on nothing but its own name and weight, so
+    ///building a ring with a node more or less, or draining and undraining one,
+    ///moves only the keys on that node's arcs; the others stay put, barring points
+    ///that collide. The ring grows with the total weight rather than staying at
+    ///`interleave_count` digests per node. Not set by default.
+    ///
+    ///Panics if `points_per_weight` is 0.
+    pub fn points_per_weight(mut self, points_per_weight: usize) -> Self {
+        assert!(points_per_weight > 0, "points per weight must be at least 1");
+        self.points_per_weight = Some(points_per_weight);
+        self
+    }
+
+    ///Turns on consistent hashing with bounded loads: no node is handed a key
+    ///while its load is at `(1 + epsilon)` times its weight share of the total
+    ///load (see `ConsistentHashing::capacity_of`), the key spills over to the next
+    ///node clockwise instead. Loads are reported with `ConsistentHashing::set_load`.
+    ///
+    ///Panics if `epsilon` isn't a positive number.
+    pub fn bounded_load(mut self, epsilon: f64) -> Self {
+        assert!(epsilon > 0.0, "bounded load epsilon must be positive");
+        self.load_epsilon = Some(epsilon);
+        self
+    }
+
+    ///Turns on multi-probe consistent hashing: every lookup hashes the key `probes`
+    ///times and picks the node whose point is nearest to one of the probes. This
+    ///balances far better than raising `interleave_count`, at the cost of `probes`
+    ///hashes per lookup instead of one. Defaults to 1, i.e. a plain lookup.
+    ///
+    ///Panics if `probes` is 0.
+    pub fn probes(mut self, probes: usize) -> Self {
+        assert!(probes > 0, "need at least one probe");
+        self.probes = probes;
+        self
+    }
+
+    ///Remembers the ring position of the last `capacity` distinct keys looked up, so
+    ///hot keys skip hashing and the binary search. Any change to the ring's points
+    ///empties the cache. Defaults to 0, i.e. no cache.
+    #[cfg(feature = "lookup-cache")]
+    pub fn lookup_cache(mut self, capacity: usize) -> Self {
+        self.lookup_cache = capacity;
+        self
+    }
+
+    ///Replaces the `"{node}-{index}"` labels virtual points are hashed from, e.g. to
+    ///place nodes the way another system does. The formatter gets the node's
+    ///`ring_id`, weight and the digest index, and is used for every point the ring
+    ///ever adds, so nodes added later are placed consistently.
+    pub fn label_format<F>(mut self, label_format: F) -> Self
+        where F: Fn(&str, usize, usize) -> String + Send + Sync + 'static {
+        self.label_format = Some(Box::new(label_format));
+        self
+    }
+
+    ///Picks how virtual points are derived from a node, see `PointScheme`. Defaults
+    ///to `PointScheme::Labels`; the other schemes place nodes differently, so a ring
+    ///has to keep the scheme it was deployed with.
+    pub fn point_scheme(mut self, point_scheme: PointScheme) -> Self {
+        self.point_scheme = point_scheme;
+        self
+    }
+
+    ///Sets how ring points and key hashes are read from md5 digests, see
+    ///`ByteOrder`. Defaults to `ByteOrder::LittleEndian`.
+    pub fn byte_order(mut self, byte_order: ByteOrder) -> Self {
+        self.byte_order = byte_order;
+        self
+    }
+
+    ///Prepends `seed` to every point label and key before it's hashed, i.e. points
+    ///come from `md5("{seed}{node}-{j}")` and keys from `md5("{seed}{key}")`. Rings
+    ///with different seeds over the same nodes place keys independently of each
+    ///other, e.g. to keep two replica sets from failing over onto the same nodes.
+    ///Defaults to the empty seed, which leaves placement as it was; `hash_key`
+    ///always hashes unseeded.
+    pub fn seed(mut self, seed: impl Into<String>) -> Self {
+        self.seed = seed.into();
+        self
+    }
+
+    ///Registers a hook `get_node` calls with every key and the node it resolved
+    ///to (`None` on an empty ring), to log or sample placement decisions in one
+    ///place. It runs on the caller's thread before `get_node` returns, so it should
+    ///be cheap. Without a hook `get_node` does no extra work.
+    pub fn on_lookup<F>(mut self, on_lookup: F) -> Self
+        where F: Fn(&str, Option<&T>) + Send + Sync + 'static {
+        self.on_lookup = Some(Box::new(on_lookup));
+        self
+    }
+
+    ///Picks how the ring keeps its points, see `Backend`. Defaults to
+    ///`Backend::SortedVec`. Placement doesn't depend on it, and a ring read back
+    ///with `Deserialize` or `from_snapshot` uses the default.
+    pub fn backend(mut self, backend: Backend) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    pub fn build(self) -> ConsistentHashing<T> {
+        let mut new_consitent_hashing = ConsistentHashing {
+            points: PointStore::new(self.backend),
+            owners: Vec::new(),
+            real_nodes: HashMap::new(),
+            interleave_count: self.interleave_count,
+            replica_factor: self.replica_factor,
+            points_per_weight: self.points_per_weight,
+            total_weight: 0,
+            load_epsilon: self.load_epsilon,
+            loads: HashMap::new(),
+            probes: self.probes,
+            drained: HashSet::new(),
+            draining: HashMap::new(),
+            down: HashSet::new(),
+            factors: HashMap::new(),
+            #[cfg(feature = "lookup-cache")]
+            cache: if self.lookup_cache > 0 { Some(Mutex::new(LookupCache::new(self.lookup_cache))) } else { None },
+            label_format: self.label_format,
+            point_scheme: self.point_scheme,
+            byte_order: self.byte_order,
+            seed: self.seed,
+            on_lookup: self.on_lookup,
+            routable_weights: OnceLock::new(),
+            node_ids: OnceLock::new(),
+            #[cfg(test)]
+            sort_count: 0,
+        };
+
+        new_consitent_hashing.generate_hashing_ring(self.real_nodes);
+        new_consitent_hashing
+    }
+}
+
+
+///Projects the keyspace fraction each node would own in a ring built from `nodes`,
+///without keeping the ring around. Handy for answering "how does the split change
+///if I add a 512mb node?" before touching a live cluster.
+pub fn capacity_plan<T: RingNode + Hash + Clone + WithWeightInfo>(nodes: &[T], interleave_count: usize) -> HashMap<String, f64> {
+    ConsistentHashing::builder(nodes)
+        .interleave_count(interleave_count)
+        .build()
+        .percent_owned()
+}
+
+
+///Appends `s` to `out` as a quoted, escaped JSON string.
+fn push_json_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+
+///Fraction (0.0 to 1.0) of the `sample` keys that `get_node` routes to a different
+///node (compared by `ring_id`) in `new` than in `old`, e.g. to gate a
+///reconfiguration on how much of the keyspace it would move. 0.0 for an empty
+///sample.
+pub fn rebalance_cost<T: RingNode + Hash + Clone + WithWeightInfo>(old: &ConsistentHashing<T>, new: &ConsistentHashing<T>, sample: &[String]) -> f64 {
+    if sample.is_empty() {
+        return 0.0;
+    }
+    let moved = sample.iter()
+        .filter(|key| old.resolve_node(key).map(|node| node.ring_id()) != new.resolve_node(key).map(|node| node.ring_id()))
+        .count();
+    moved as f64 / sample.len() as f64
+}
+
+
+///`interleave_count * nodes_num * weight / total_weight`, the number of digests a
+///node gets, worked out in 128 bits so it can't overflow a 32-bit `usize`, and
+///saturated at `usize::MAX`. 0 if `total_weight` is.
+//`ceil(c * total_load * weight / ring_weight)`, 0 if no weight is left on the ring
+fn capacity(weight: usize, ring_weight: usize, total_load: usize, c: f64) -> usize {
+    if ring_weight == 0 {
+        return 0;
+    }
+    (c * total_load as f64 * weight as f64 / ring_weight as f64).ceil() as usize
+}
+
+pub(crate) fn weighted_factor(interleave_count: usize, nodes_num: usize, weight: usize, total_weight: usize) -> usize {
+    //a product past u128::MAX divided by a usize total is out of usize range anyway
+    let product = (interleave_count as u128).saturating_mul(nodes_num as u128).saturating_mul(weight as u128);
+    match product.checked_div(total_weight as u128) {
+        Some(factor) => usize::try_from(factor).unwrap_or(usize::MAX),
+        None => 0,
+    }
+}
+
+pub(crate) fn hash_digest(key: &str) -> Vec<u8> {
+    let digest = md5::compute(key);
+    digest.to_vec()
+}
+
+///64-bit hash of a `(node_name, key)` pair, used by the rendezvous schemes.
+///The two parts are separated by a zero byte so `("a-b", "c")` and `("a", "b-c")`
+///don't collide.
+pub(crate) fn hash_pair64(node_name: &str, key: &str) -> u64 {
+    let mut context = md5::Context::new();
+    context.consume(node_name.as_bytes());
+    context.consume([0u8]);
+    context.consume(key.as_bytes());
+    digest_u64(&context.compute())
+}
+
+///64-bit hash of arbitrary bytes, taken from the same md5 digest as the ring keys.
+pub(crate) fn hash_bytes64(data: &[u8]) -> u64 {
+    digest_u64(&md5::compute(data))
+}
+
+fn digest_u64(digest: &md5::Digest) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+///Reads the 4 bytes of the digest starting at `offset` as a little-endian `u32`.
+///Bytes past the end of a short digest read as 0 instead of panicking.
+pub(crate) fn hash_val(b_key: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        if let Some(value) = offset.checked_add(i).and_then(|index| b_key.get(index)) {
+            *byte = *value;
+        }
+    }
+    u32::from_le_bytes(bytes)
+}
+
+
+///Returns the `u32` ring position of `key`, the same one lookups bisect with.
+///Lets tooling reason about placement without duplicating the md5 plumbing.
+pub fn hash_key(key: &str) -> u32 {
+    gen_key(key)
+}
+
+fn gen_key(string_key: &str) -> u32 {
+    let b_key = hash_digest(string_key);
+    hash_val(&b_key, 0)
+}
+
+
+pub trait WithWeightInfo {
+    fn get_weight(&self) -> usize;
+
+    ///The weight `ConsistentHashing::with_f64_weights` apportions points by.
+    ///Defaults to `get_weight`; node types with fractional weights override it.
+    fn get_weight_f64(&self) -> f64 {
+        self.get_weight() as f64
+    }
+}
+
+///Error returned for weights that can't be apportioned.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WeightError {
+    NotANumber { node: String },
+    Infinite { node: String },
+    Negative { node: String, weight: f64 },
+}
+
+impl fmt::Display for WeightError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WeightError::NotANumber { node } => write!(f, "weight of node {} is NaN", node),
+            WeightError::Infinite { node } => write!(f, "weight of node {} is infinite", node),
+            WeightError::Negative { node, weight } => write!(f, "weight of node {} is negative ({})", node, weight),
+        }
+    }
+}
+
+impl Error for WeightError {}
+
+///Splits `budget` between `weights` in proportion, rounding with the largest
+///remainder method: everyone gets the floor of their exact share and the units
+///left over go to the largest fractional parts, ties to the earlier weight. The
+///counts always add up to `budget`, unless all weights are 0 (then all are 0).
+///Weights have to be finite and non-negative; `names` (one per weight) are only
+///used for the error.
+pub fn apportion(names: &[&str], weights: &[f64], budget: usize) -> Result<Vec<usize>, WeightError> {
+    for (name, &weight) in names.iter().zip(weights.iter()) {
+        if weight.is_nan() {
+            return Err(WeightError::NotANumber { node: name.to_string() });
+        }
+        if weight.is_infinite() {
+            return Err(WeightError::Infinite { node: name.to_string() });
+        }
+        if weight < 0.0 {
+            return Err(WeightError::Negative { node: name.to_string(), weight });
+        }
+    }
+    let total: f64 = weights.iter().sum();
+    if total == 0.0 {
+        return Ok(vec![0; weights.len()]);
+    }
+
+    let quotas: Vec<f64> = weights.iter().map(|weight| budget as f64 * weight / total).collect();
+    let mut counts: Vec<usize> = quotas.iter().map(|quota| quota.floor() as usize).collect();
+    let assigned: usize = counts.iter().sum();
+    let mut by_remainder: Vec<usize> = (0..weights.len()).collect();
+    by_remainder.sort_by(|&a, &b| {
+        let (ra, rb) = (quotas[a] - quotas[a].floor(), quotas[b] - quotas[b].floor());
+        rb.partial_cmp(&ra).unwrap_or(std::cmp::Ordering::Equal).then(a.cmp(&b))
+    });
+    for &index in by_remainder.iter().take(budget.saturating_sub(assigned)) {
+        counts[index] += 1;
+    }
+    Ok(counts)
+}
+
+///The identity a node is placed by: its virtual point labels are derived from
+///`ring_id`, and it's what every `_by_name` method and name-keyed map refers to.
+///Kept apart from `Display` so decorating a node's log output doesn't move it
+///on the ring. Two nodes with the same `ring_id` are the same node.
+pub trait RingNode {
+    fn ring_id(&self) -> &str;
+}
+
+///Object-safe bundle of what a ring needs from a node, for mixing node types in
+///one `ConsistentHashing<Box<dyn DynNode>>`. It's implemented for every
+///`RingNode + WithWeightInfo + Clone` type, and `Box<dyn DynNode>` implements the
+///ring's traits in turn, hashing and placing by `ring_id` like the concrete types.
+pub trait DynNode: RingNode + WithWeightInfo + Send + Sync {
+    fn clone_box(&self) -> Box<dyn DynNode>;
+}
+
+impl<N: RingNode + WithWeightInfo + Clone + Send + Sync + 'static> DynNode for N {
+    fn clone_box(&self) -> Box<dyn DynNode> {
+        Box::new(self.clone())
+    }
+}
+
+impl Clone for Box<dyn DynNode> {
+    fn clone(&self) -> Box<dyn DynNode> {
+        (**self).clone_box()
+    }
+}
+
+impl Hash for Box<dyn DynNode> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ring_id().hash(state);
+    }
+}
+
+impl RingNode for Box<dyn DynNode> {
+    fn ring_id(&self) -> &str {
+        (**self).ring_id()
+    }
+}
+
+impl WithWeightInfo for Box<dyn DynNode> {
+    fn get_weight(&self) -> usize {
+        (**self).get_weight()
+    }
+
+    fn get_weight_f64(&self) -> f64 {
+        (**self).get_weight_f64()
+    }
+}
+
+///For nodes that can only hold so many keys (sessions, connections, ...), see
+///`ConsistentHashing::get_node_with_occupancy`.
+pub trait WithCapacity {
+    fn capacity(&self) -> usize;
+}
+
+///An arc of the ring and its owner, see `ConsistentHashing::dump_ranges`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RangeOwnership {
+    ///First key hash of the arc.
+    pub start_token: u32,
+    ///The owning point, the first hash past the arc.
+    pub end_token: u32,
+    pub node_name: String,
+    ///Whether the arc crosses zero, i.e. runs from `start_token` to `u32::MAX` and
+    ///on from 0 to just before `end_token`.
+    pub is_wraparound: bool,
+}
+
+impl RangeOwnership {
+    ///Number of key hashes in the arc, up to 2^32.
+    pub fn len(&self) -> u64 {
+        if self.is_wraparound {
+            self.end_token as u64 + (1u64 << 32) - self.start_token as u64
+        } else {
+            (self.end_token - self.start_token) as u64
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+///The preference list `ConsistentHashing::lookup` returns for a key.
+#[must_use]
+#[derive(Debug, Clone)]
+pub struct LookupResult<T> {
+    pub primary: T,
+    ///The other physical nodes, in the order to fall back to them.
+    pub fallbacks: Vec<T>,
+}
+
+///Where `ConsistentHashing::overflow_chain_with_occupancy` placed a key.
+#[derive(Debug, Clone)]
+pub struct OverflowChain<'a, T> {
+    ///The full nodes passed over, owner first.
+    pub full: Vec<&'a T>,
+    ///The node that took the key, `None` if every node was full.
+    pub accepted: Option<&'a T>,
+}
+
+///For node types carrying `key=value` metadata, e.g. to route some keys only to
+///nodes tagged `ssd=true` with `ConsistentHashing::get_node_where`.
+pub trait WithTags {
+    fn tags(&self) -> &HashMap<String, String>;
+}
+
+///For node types that know their availability zone, see
+///`ConsistentHashing::get_nodes_cross_zone`.
+pub trait WithZone {
+    fn zone(&self) -> &str;
+}
+
+///For node types whose weight can be changed in place, see
+///`ConsistentHashing::update_weight`.
+pub trait WithMutableWeight: WithWeightInfo {
+    fn set_weight(&mut self, weight: usize);
+}
+
+///A node's `ring_id`, as handed out by `ConsistentHashing::add_node` and taken by
+///the id-based methods. Clones share the string. It's just the id, so one written
+///out with `to_string` and read back with `NodeId::from` refers to the same node,
+///in any ring.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(Arc<str>);
+
+impl NodeId {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<&str> for NodeId {
+    fn from(id: &str) -> NodeId {
+        NodeId(Arc::from(id))
+    }
+}
+
+impl From<String> for NodeId {
+    fn from(id: String) -> NodeId {
+        NodeId(Arc::from(id))
+    }
+}
+
+impl fmt::Display for NodeId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Borrow<str> for NodeId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for NodeId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeInfoWithWeight {
+    pub node_name: String,
+    pub weight: usize,
+}
+
+impl NodeInfoWithWeight {
+    pub fn new(node_name: impl Into<String>, weight: usize) -> NodeInfoWithWeight {
+        NodeInfoWithWeight{node_name: node_name.into(), weight}
+    }
+}
+
+impl fmt::Display for NodeInfoWithWeight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.node_name)
+    }
+}
+
+impl Hash for NodeInfoWithWeight {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.node_name.hash(state);
+    }
+}
+
+impl WithWeightInfo for NodeInfoWithWeight {
+    fn get_weight(&self) -> usize {
+        self.weight
+    }
+}
+
+impl WithMutableWeight for NodeInfoWithWeight {
+    fn set_weight(&mut self, weight: usize) {
+        self.weight = weight;
+    }
+}
+
+impl RingNode for NodeInfoWithWeight {
+    fn ring_id(&self) -> &str {
+        &self.node_name
+    }
+}
+
+///A node with a fractional weight, for `ConsistentHashing::with_f64_weights`.
+///Its integer weight, used everywhere else, is the fractional one rounded up.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeInfoWithF64Weight {
+    pub node_name: String,
+    pub weight: f64,
+}
+
+impl NodeInfoWithF64Weight {
+    pub fn new(node_name: impl Into<String>, weight: f64) -> NodeInfoWithF64Weight {
+        NodeInfoWithF64Weight{node_name: node_name.into(), weight}
+    }
+}
+
+impl fmt::Display for NodeInfoWithF64Weight {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.node_name)
+    }
+}
+
+impl Hash for NodeInfoWithF64Weight {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.node_name.hash(state);
+    }
+}
+
+impl WithWeightInfo for NodeInfoWithF64Weight {
+    fn get_weight(&self) -> usize {
+        self.weight.ceil() as usize
+    }
+
+    fn get_weight_f64(&self) -> f64 {
+        self.weight
+    }
+}
+
+impl RingNode for NodeInfoWithF64Weight {
+    fn ring_id(&self) -> &str {
+        &self.node_name
+    }
+}
+
+///A weighted node with tags, see `WithTags`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeInfoWithTags {
+    pub node_name: String,
+    pub weight: usize,
+    pub tags: HashMap<String, String>,
+}
+
+impl NodeInfoWithTags {
+    pub fn new(node_name: impl Into<String>, weight: usize) -> NodeInfoWithTags {
+        NodeInfoWithTags{node_name: node_name.into(), weight, tags: HashMap::new()}
+    }
+
+    pub fn with_tag(mut self, key: impl Into<String>, value: impl Into<String>) -> NodeInfoWithTags {
+        self.tags.insert(key.into(), value.into());
+        self
+    }
+}
+
+impl fmt::Display for NodeInfoWithTags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.node_name)
+    }
+}
+
+impl Hash for NodeInfoWithTags {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.node_name.hash(state);
+    }
+}
+
+impl WithWeightInfo for NodeInfoWithTags {
+    fn get_weight(&self) -> usize {
+        self.weight
+    }
+}
+
+impl WithMutableWeight for NodeInfoWithTags {
+    fn set_weight(&mut self, weight: usize) {
+        self.weight = weight;
+    }
+}
+
+impl RingNode for NodeInfoWithTags {
+    fn ring_id(&self) -> &str {
+        &self.node_name
+    }
+}
+
+impl WithTags for NodeInfoWithTags {
+    fn tags(&self) -> &HashMap<String, String> {
+        &self.tags
+    }
+}
+
+///A weighted node in an availability zone, see `WithZone`.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeInfoWithZone {
+    pub node_name: String,
+    pub zone: String,
+    pub weight: usize,
+}
+
+impl NodeInfoWithZone {
+    pub fn new(node_name: impl Into<String>, zone: impl Into<String>, weight: usize) -> NodeInfoWithZone {
+        NodeInfoWithZone{node_name: node_name.into(), zone: zone.into(), weight}
+    }
+}
+
+impl fmt::Display for NodeInfoWithZone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.node_name)
+    }
+}
+
+impl Hash for NodeInfoWithZone {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.node_name.hash(state);
+    }
+}
+
+impl WithWeightInfo for NodeInfoWithZone {
+    fn get_weight(&self) -> usize {
+        self.weight
+    }
+}
+
+impl WithMutableWeight for NodeInfoWithZone {
+    fn set_weight(&mut self, weight: usize) {
+        self.weight = weight;
+    }
+}
+
+impl RingNode for NodeInfoWithZone {
+    fn ring_id(&self) -> &str {
+        &self.node_name
+    }
+}
+
+impl WithZone for NodeInfoWithZone {
+    fn zone(&self) -> &str {
+        &self.zone
+    }
+}
+
+#[deprecated(note = "renamed to `NodeInfoWithWeight`")]
+pub type NodeInfoWithWeigth = NodeInfoWithWeight;
+
+#[deprecated(note = "`NodeInfoWithWeight` owns its name now")]
+pub type OwnedNodeInfo = NodeInfoWithWeight;
+
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct NodeInfo {
+    pub node_name: String,
+}
+
+impl NodeInfo {
+    pub fn new(node_name: impl Into<String>) -> NodeInfo {
+        NodeInfo{node_name: node_name.into()}
+    }
+}
+
+impl fmt::Display for NodeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.node_name)
+    }
+}
+
+impl Hash for NodeInfo {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.node_name.hash(state);
+    }
+}
+
+impl WithWeightInfo for NodeInfo {
+    fn get_weight(&self) -> usize {
+        1
+    }
+}
+
+impl RingNode for NodeInfo {
+    fn ring_id(&self) -> &str {
+        &self.node_name
+    }
+}
+
+///Everything a ring is restored from by `from_snapshot` and, with the `serde`
+///feature, `Deserialize`.
+#[cfg_attr(feature = "serde", derive(serde::Deserialize))]
+struct RingState<T> {
+    interleave_count: usize,
+    replica_factor: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    points_per_weight: Option<usize>,
+    probes: usize,
+    load_epsilon: Option<f64>,
+    point_scheme: PointScheme,
+    byte_order: ByteOrder,
+    #[cfg_attr(feature = "serde", serde(default))]
+    seed: String,
+    nodes: Vec<T>,
+    drained: Vec<String>,
+    factors: Vec<(String, usize)>,
+    points: Vec<(u32, String)>,
+}
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> RingState<T> {
+    ///Rebuilds the ring, checking that the state is one a ring could have been in.
+    fn into_ring(self) -> Result<ConsistentHashing<T>, String> {
+        if self.replica_factor == 0 {
+            return Err("replica factor must be at least 1".to_string());
+        }
+        if self.probes == 0 {
+            return Err("need at least one probe".to_string());
+        }
+        if self.points_per_weight == Some(0) {
+            return Err("points per weight must be at least 1".to_string());
+        }
+        if self.load_epsilon.is_some_and(|epsilon| epsilon.is_nan() || epsilon <= 0.0) {
+            return Err("bounded load epsilon must be positive".to_string());
+        }
+
+        let mut ring = ConsistentHashing::builder(&[]).build();
+        ring.interleave_count = self.interleave_count;
+        ring.replica_factor = self.replica_factor;
+        ring.points_per_weight = self.points_per_weight;
+        ring.probes = self.probes;
+        ring.load_epsilon = self.load_epsilon;
+        ring.point_scheme = self.point_scheme;
+        ring.byte_order = self.byte_order;
+        ring.seed = self.seed;
+        for node in self.nodes {
+            let name = node.ring_id().to_string();
+            if ring.real_nodes.contains_key(name.as_str()) {
+                return Err(format!("node {} is listed twice", name));
+            }
+            ring.total_weight += node.get_weight();
+            ring.register(Arc::new(node));
+        }
+        let indices: HashMap<String, u32> = ring.owners.iter().enumerate()
+            .map(|(index, node)| (node.ring_id().to_string(), index as u32))
+            .collect();
+        for name in self.drained {
+            if !ring.real_nodes.contains_key(name.as_str()) {
+                return Err(format!("drained node {} is not a node of the ring", name));
+            }
+            ring.drained.insert(name);
+        }
+        for (name, factor) in self.factors {
+            let index = match ring.owner_index(&name) {
+                Some(index) => index,
+                None => return Err(format!("digest count for unknown node {}", name)),
+            };
+            ring.factors.insert(ring.key_of(index), factor);
+        }
+        if let Some(node) = ring.owners.iter().find(|node| !ring.factors.contains_key(node.ring_id())) {
+            return Err(format!("no digest count for node {}", node.ring_id()));
+        }
+
+        for (key, owner) in self.points {
+            let last = ring.points.last();
+            if last.is_some_and(|last| last.0 > key) {
+                return Err(format!("ring key {} is out of order", key));
+            }
+            let index = match indices.get(&owner) {
+                Some(index) => *index,
+                None => return Err(format!("ring key {} belongs to unknown node {}", key, owner)),
+            };
+            if ring.drained.contains(&owner) {
+                return Err(format!("ring key {} belongs to drained node {}", key, owner));
+            }
+            if last.is_some_and(|last| last.0 == key && last.1 != index) {
+                return Err(format!("ring key {} has two owners", key));
+            }
+            //written twice by versions that kept colliding points
+            if last.is_some_and(|last| last.0 == key) {
+                continue;
+            }
+            ring.points.push((key, index), name_order(&ring.owners));
+        }
+        Ok(ring)
+    }
+}
+
+
+///`Serialize`/`Deserialize` for the ring, behind the `serde` feature. What's
+///written is the nodes, the settings that shape placement and every virtual
+///point with its owner's `ring_id`, so a deserialized ring places keys exactly
+///like the one serialized, whatever label format built it. Loads, down marks,
+///gradual drains, the lookup cache, the label format and the lookup hook are
+///per-process and not included, so points added after deserializing (`add_node`,
+///`undrain_node`, `update_weight`, ...) come from the default labels.
+#[cfg(feature = "serde")]
+mod serde_support {
+    use super::*;
+    use serde::de::{Deserialize, Deserializer, Error as DeError};
+    use serde::ser::{Serialize, Serializer};
+
+    #[derive(serde::Serialize)]
+    struct RingStateRef<'a, T> {
+        interleave_count: usize,
+        replica_factor: usize,
+        points_per_weight: Option<usize>,
+        probes: usize,
+        load_epsilon: Option<f64>,
+        point_scheme: PointScheme,
+        byte_order: ByteOrder,
+        seed: &'a str,
+        nodes: Vec<&'a T>,
+        drained: Vec<&'a str>,
+        factors: Vec<(&'a str, usize)>,
+        points: Vec<(u32, &'a str)>,
+    }
+
+
+    impl<T: RingNode + Hash + Clone + WithWeightInfo + Serialize> Serialize for ConsistentHashing<T> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut drained: Vec<&str> = self.drained.iter().map(|name| name.as_str()).collect();
+            drained.sort();
+            let mut factors: Vec<(&str, usize)> = self.factors.iter().map(|(name, factor)| (name.0.ring_id(), *factor)).collect();
+            factors.sort();
+            RingStateRef {
+                interleave_count: self.interleave_count,
+                replica_factor: self.replica_factor,
+                points_per_weight: self.points_per_weight,
+                probes: self.probes,
+                load_epsilon: self.load_epsilon,
+                point_scheme: self.point_scheme,
+                byte_order: self.byte_order,
+                seed: &self.seed,
+                nodes: self.nodes_in_order(),
+                drained,
+                factors,
+                points: self.owned_points()
+                    .map(|(key, owner)| (key, owner.ring_id()))
+                    .collect(),
+            }.serialize(serializer)
+        }
+    }
+
+    impl<'de, T: RingNode + Hash + Clone + WithWeightInfo + Deserialize<'de>> Deserialize<'de> for ConsistentHashing<T> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            RingState::deserialize(deserializer)?.into_ring().map_err(D::Error::custom)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn nodes() -> Vec<NodeInfoWithWeight> {
+            vec![
+                NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+                NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+                NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+            ]
+        }
+
+        fn ring() -> ConsistentHashing<NodeInfoWithWeight> {
+            let mut consistent_hasing_ring = ConsistentHashing::builder(&nodes())
+                .label_format(|node, _, index| format!("{}#{}", node, index))
+                .probes(2)
+                .build();
+            consistent_hasing_ring.drain_node("192.168.0.103:11212");
+            consistent_hasing_ring
+        }
+
+        fn assert_same_placement(a: &ConsistentHashing<NodeInfoWithWeight>, b: &ConsistentHashing<NodeInfoWithWeight>) {
+            assert_eq!(a.sorted_keys(), b.sorted_keys());
+            for i in 0..500 {
+                let key = format!("key-{}", i);
+                assert_eq!(a.get_node(&key).unwrap().node_name, b.get_node(&key).unwrap().node_name);
+            }
+        }
+
+        #[test]
+        fn test_json_round_trip() {
+            let consistent_hasing_ring = ring();
+            let json = serde_json::to_string(&consistent_hasing_ring).unwrap();
+            let restored: ConsistentHashing<NodeInfoWithWeight> = serde_json::from_str(&json).unwrap();
+            assert_same_placement(&consistent_hasing_ring, &restored);
+            assert_eq!(restored.total_weight, 4);
+
+            //a drained node comes back with its points
+            let mut original = ConsistentHashing::new(&nodes(), None);
+            original.drain_node("192.168.0.103:11212");
+            let mut restored: ConsistentHashing<NodeInfoWithWeight> = serde_json::from_str(&serde_json::to_string(&original).unwrap()).unwrap();
+            original.undrain_node("192.168.0.103:11212");
+            restored.undrain_node("192.168.0.103:11212");
+            assert_same_placement(&original, &restored);
+
+            //the seed keeps keys hashing where they did, and is optional
+            let seeded = ConsistentHashing::builder(&nodes()).seed("ring-b").build();
+            let restored: ConsistentHashing<NodeInfoWithWeight> = serde_json::from_str(&serde_json::to_string(&seeded).unwrap()).unwrap();
+            assert_same_placement(&seeded, &restored);
+            let mut unseeded = serde_json::to_value(ConsistentHashing::new(&nodes(), None)).unwrap();
+            unseeded.as_object_mut().unwrap().remove("seed");
+            let restored: ConsistentHashing<NodeInfoWithWeight> = serde_json::from_value(unseeded).unwrap();
+            assert_same_placement(&ConsistentHashing::new(&nodes(), None), &restored);
+
+            let per_weight = ConsistentHashing::builder(&nodes()).points_per_weight(10).build();
+            let mut restored: ConsistentHashing<NodeInfoWithWeight> = serde_json::from_str(&serde_json::to_string(&per_weight).unwrap()).unwrap();
+            restored.add_node(NodeInfoWithWeight::new("192.168.0.104:11212", 3));
+            assert_eq!(restored.factors["192.168.0.104:11212"], 30);
+        }
+
+        #[test]
+        fn test_bincode_round_trip() {
+            let consistent_hasing_ring = ring();
+            let bytes = bincode::serialize(&consistent_hasing_ring).unwrap();
+            let restored: ConsistentHashing<NodeInfoWithWeight> = bincode::deserialize(&bytes).unwrap();
+            assert_same_placement(&consistent_hasing_ring, &restored);
+
+            assert!(bincode::deserialize::<ConsistentHashing<NodeInfoWithWeight>>(&bytes[..bytes.len() / 2]).is_err());
+        }
+
+        #[test]
+        fn test_rejects_corrupt_state() {
+            let json: serde_json::Value = serde_json::to_value(ring()).unwrap();
+            let corrupt = |edit: &dyn Fn(&mut serde_json::Value)| {
+                let mut value = json.clone();
+                edit(&mut value);
+                serde_json::from_value::<ConsistentHashing<NodeInfoWithWeight>>(value).err().map(|err| err.to_string())
+            };
+            assert!(corrupt(&|_| {}).is_none());
+
+            let swapped = corrupt(&|value| value["points"].as_array_mut().unwrap().swap(0, 1)).unwrap();
+            assert!(swapped.contains("out of order"), "{}", swapped);
+            let unknown = corrupt(&|value| value["points"][0][1] = "192.168.0.199:11212".into()).unwrap();
+            assert!(unknown.contains("unknown node"), "{}", unknown);
+            let drained = corrupt(&|value| value["points"][0][1] = "192.168.0.103:11212".into()).unwrap();
+            assert!(drained.contains("drained node"), "{}", drained);
+            let twice = corrupt(&|value| {
+                let first = value["nodes"][0].clone();
+                value["nodes"].as_array_mut().unwrap().push(first);
+            }).unwrap();
+            assert!(twice.contains("listed twice"), "{}", twice);
+            let no_factor = corrupt(&|value| { value["factors"].as_array_mut().unwrap().pop(); }).unwrap();
+            assert!(no_factor.contains("no digest count"), "{}", no_factor);
+            assert!(corrupt(&|value| value["probes"] = 0.into()).is_some());
+            assert!(serde_json::from_str::<ConsistentHashing<NodeInfoWithWeight>>("{\"nodes\": []}").is_err());
+        }
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    // 注意这个惯用法：在 tests 模块中，从外部作用域导入所有名字。
+    use super::*;
+    use crate::bisect::bisect_right;
+    use crate::test_support::xorshift;
+
+    #[test]
+    fn test_init() {
+        let nodes: Vec<NodeInfo> = vec![NodeInfo::new("192.168.0.101:11212")];
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let node = consistent_hasing_ring.get_node("my_key").unwrap();
+        assert_eq!(node.to_string(), "192.168.0.101:11212");
+    }
+
+
+    #[test]
+    fn test_route_iter() {
+        use std::cell::Cell;
+
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let keys: Vec<String> = (0..10).map(|i| format!("key-{}", i)).collect();
+
+        let pulled = Cell::new(0);
+        let source = keys.iter().cloned().inspect(|_| pulled.set(pulled.get() + 1));
+        let mut routed = consistent_hasing_ring.route_iter(source);
+        assert_eq!(pulled.get(), 0);
+
+        for key in &keys {
+            let (routed_key, node) = routed.next().unwrap();
+            assert_eq!(&routed_key, key);
+            assert_eq!(node.map(|n| n.to_string()), consistent_hasing_ring.get_node(key).map(|n| n.to_string()));
+        }
+        assert_eq!(pulled.get(), keys.len());
+        assert!(routed.next().is_none());
+    }
+
+    #[cfg(feature = "rand")]
+    #[test]
+    fn test_weighted_random_node() {
+        use rand::SeedableRng;
+
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 5),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let mut shares = |ring: &ConsistentHashing<NodeInfoWithWeight>| {
+            let draws = 80_000;
+            let mut counts: HashMap<String, usize> = HashMap::new();
+            for _ in 0..draws {
+                let node = ring.weighted_random_node(&mut rng).unwrap();
+                *counts.entry(node.to_string()).or_insert(0) += 1;
+            }
+            counts.into_iter().map(|(name, count)| (name, count as f64 / draws as f64)).collect::<HashMap<String, f64>>()
+        };
+
+        let picked = shares(&consistent_hasing_ring);
+        for node in &nodes {
+            let expected = node.weight as f64 / 8.0;
+            assert!((picked[&node.to_string()] - expected).abs() < 0.01, "{}: {} vs {}", node.node_name, picked[&node.to_string()], expected);
+        }
+
+        //drained and down nodes aren't picked, and come back with their weight
+        consistent_hasing_ring.drain_node("192.168.0.103:11212");
+        consistent_hasing_ring.set_node_down("192.168.0.101:11212");
+        assert_eq!(shares(&consistent_hasing_ring).keys().collect::<Vec<&String>>(), vec!["192.168.0.102:11212"]);
+        consistent_hasing_ring.undrain_node("192.168.0.103:11212");
+        consistent_hasing_ring.set_node_up("192.168.0.101:11212");
+        consistent_hasing_ring.add_node(NodeInfoWithWeight::new("192.168.0.104:11212", 2));
+        let picked = shares(&consistent_hasing_ring);
+        assert!((picked["192.168.0.104:11212"] - 0.2).abs() < 0.01);
+        assert!((picked["192.168.0.103:11212"] - 0.5).abs() < 0.01);
+
+        let empty: ConsistentHashing<NodeInfo> = ConsistentHashing::new(&vec![], None);
+        assert!(empty.weighted_random_node(&mut rng).is_none());
+        let mut all_down = ConsistentHashing::new(&nodes, None);
+        for node in &nodes {
+            all_down.set_node_down(&node.node_name);
+        }
+        assert!(all_down.weighted_random_node(&mut rng).is_none());
+    }
+
+    #[test]
+    fn test_remove_node_by_name() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+
+        let sorted_keys = consistent_hasing_ring.sorted_keys().clone();
+        assert!(consistent_hasing_ring.remove_node_by_name("192.168.0.199:11212").is_empty());
+        assert_eq!(consistent_hasing_ring.sorted_keys(), sorted_keys);
+        assert_eq!(consistent_hasing_ring.real_nodes.len(), 3);
+
+        assert!(!consistent_hasing_ring.remove_node_by_name("192.168.0.102:11212").is_empty());
+        assert_eq!(consistent_hasing_ring.real_nodes.len(), 2);
+        assert_eq!(consistent_hasing_ring.total_weight, 2);
+        assert_eq!(consistent_hasing_ring.owners.len(), 2);
+        assert!(consistent_hasing_ring.owned_points().all(|(_, n)| n.node_name != "192.168.0.102:11212"));
+        for i in 0..100 {
+            let node = consistent_hasing_ring.get_node(&format!("key-{}", i)).unwrap();
+            assert_ne!(node.node_name, "192.168.0.102:11212");
+        }
+        assert!(consistent_hasing_ring.remove_node_by_name("192.168.0.102:11212").is_empty());
+    }
+
+    #[test]
+    fn test_remove_node_returns_vacated_keys() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let mut occupied: Vec<u32> = consistent_hasing_ring.owned_points()
+            .filter(|(_, node)| node.node_name == "192.168.0.102:11212")
+            .map(|(key, _)| key)
+            .collect();
+        occupied.sort_unstable();
+        let before = consistent_hasing_ring.sorted_keys().len();
+
+        let removed = consistent_hasing_ring.remove_node_by_name("192.168.0.102:11212");
+        assert_eq!(removed, occupied);
+        assert!(removed.iter().all(|key| consistent_hasing_ring.sorted_keys().binary_search(key).is_err()));
+        assert_eq!(consistent_hasing_ring.sorted_keys().len() + removed.len(), before);
+
+        let id = consistent_hasing_ring.node_id("192.168.0.101:11212").unwrap();
+        let points = consistent_hasing_ring.points_for_node(&id);
+        assert_eq!(consistent_hasing_ring.remove_node(&id), points);
+        assert!(consistent_hasing_ring.remove_node(&id).is_empty());
+    }
+
+    #[test]
+    fn test_node_id() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 1),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let id = consistent_hasing_ring.add_node(NodeInfoWithWeight::new("192.168.0.103:11212", 1)).unwrap();
+        assert_eq!(id, consistent_hasing_ring.node_id("192.168.0.103:11212").unwrap());
+        assert!(consistent_hasing_ring.node_id("192.168.0.199:11212").is_none());
+        assert_eq!(consistent_hasing_ring.points_for_node(&id).len(), 120);
+        assert_eq!(consistent_hasing_ring.points_for_node(&id), consistent_hasing_ring.points_for_node_by_name("192.168.0.103:11212"));
+
+        //works as a HashMap key looked up by &str
+        let mut stats: HashMap<NodeId, usize> = HashMap::new();
+        stats.insert(id.clone(), 7);
+        assert_eq!(stats["192.168.0.103:11212"], 7);
+
+        //round-tripped as a plain string, and used on another ring instance
+        let serialized = id.to_string();
+        let restored = NodeId::from(serialized);
+        assert_eq!(restored, id);
+        let mut other = ConsistentHashing::new(&nodes, Some(40));
+        other.add_node(NodeInfoWithWeight::new("192.168.0.103:11212", 1));
+        assert_eq!(other.points_for_node(&restored), consistent_hasing_ring.points_for_node(&id));
+        assert!(!other.remove_node(&restored).is_empty());
+        assert!(!other.contains_node("192.168.0.103:11212"));
+        assert!(other.remove_node(&restored).is_empty());
+    }
+
+    #[test]
+    fn test_update_weight() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 1),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let id = consistent_hasing_ring.node_id("192.168.0.102:11212").unwrap();
+        let other_points = consistent_hasing_ring.points_for_node_by_name("192.168.0.101:11212");
+
+        assert!(consistent_hasing_ring.update_weight(&id, 3));
+        assert_eq!(consistent_hasing_ring.total_weight, 4);
+        //40 * 2 nodes * 3 / 4 digests
+        assert_eq!(consistent_hasing_ring.points_for_node(&id).len(), 60 * 3);
+        assert_eq!(consistent_hasing_ring.points_for_node_by_name("192.168.0.101:11212"), other_points);
+        assert!(consistent_hasing_ring.owned_points().filter(|(_, n)| n.node_name == "192.168.0.102:11212").all(|(_, n)| n.weight == 3));
+
+        consistent_hasing_ring.drain_node("192.168.0.102:11212");
+        assert!(consistent_hasing_ring.update_weight_by_name("192.168.0.102:11212", 1));
+        assert!(consistent_hasing_ring.points_for_node(&id).is_empty());
+        consistent_hasing_ring.undrain_node("192.168.0.102:11212");
+        assert_eq!(consistent_hasing_ring.points_for_node(&id).len(), 120);
+
+        assert!(!consistent_hasing_ring.update_weight_by_name("192.168.0.199:11212", 2));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, None);
+        let fingerprint = consistent_hasing_ring.fingerprint();
+        assert_eq!(fingerprint, 16044561507532354835);
+        assert_eq!(consistent_hasing_ring.fingerprint_hex(), format!("{:016x}", fingerprint));
+        let empty: ConsistentHashing<NodeInfoWithWeight> = ConsistentHashing::new(&vec![], None);
+        assert_eq!(empty.fingerprint(), 13926624666948683437);
+
+        //built in any order, points that collide included
+        let partly_shared = |node: &str, _: usize, index: usize| {
+            if index.is_multiple_of(3) { format!("shared-{}", index) } else { format!("{}-{}", node, index) }
+        };
+        let colliding = ConsistentHashing::builder(&nodes).label_format(partly_shared).build().fingerprint();
+        for order in [[0, 1, 2], [0, 2, 1], [1, 0, 2], [1, 2, 0], [2, 0, 1], [2, 1, 0]] {
+            let shuffled: Vec<NodeInfoWithWeight> = order.iter().map(|i| nodes[*i].clone()).collect();
+            assert_eq!(ConsistentHashing::new(&shuffled, None).fingerprint(), fingerprint);
+            assert_eq!(ConsistentHashing::builder(&shuffled).label_format(partly_shared).build().fingerprint(), colliding);
+        }
+        assert_ne!(colliding, fingerprint);
+        //where a node's points don't depend on the others, node by node too
+        let full = ConsistentHashing::builder(&nodes).points_per_weight(40).build();
+        let mut incremental = ConsistentHashing::builder(&nodes[2..]).points_per_weight(40).build();
+        incremental.add_node(nodes[0].clone());
+        incremental.add_node(nodes[1].clone());
+        assert_eq!(incremental.fingerprint(), full.fingerprint());
+
+        for node in nodes.iter() {
+            let mut reweighted = nodes.clone();
+            reweighted.iter_mut().find(|n| n.node_name == node.node_name).unwrap().weight += 1;
+            assert_ne!(ConsistentHashing::new(&reweighted, None).fingerprint(), fingerprint);
+        }
+        assert_ne!(ConsistentHashing::new(&nodes, Some(80)).fingerprint(), fingerprint);
+        assert_ne!(ConsistentHashing::builder(&nodes).seed("ring-a").build().fingerprint(), fingerprint);
+
+        consistent_hasing_ring.update_weight_by_name("192.168.0.101:11212", 2);
+        assert_ne!(consistent_hasing_ring.fingerprint(), fingerprint);
+        consistent_hasing_ring.update_weight_by_name("192.168.0.101:11212", 1);
+        assert_eq!(consistent_hasing_ring.fingerprint(), fingerprint);
+        consistent_hasing_ring.drain_node("192.168.0.101:11212");
+        assert_ne!(consistent_hasing_ring.fingerprint(), fingerprint);
+        consistent_hasing_ring.undrain_node("192.168.0.101:11212");
+        assert_eq!(consistent_hasing_ring.fingerprint(), fingerprint);
+    }
+
+    #[test]
+    fn test_ring_digest() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let digest = consistent_hasing_ring.ring_digest();
+        let mut reversed = nodes.clone();
+        reversed.reverse();
+        let same_config = ConsistentHashing::new(&reversed, Some(40));
+        assert_eq!(same_config.ring_digest(), digest);
+        assert_eq!(same_config.fingerprint(), consistent_hasing_ring.fingerprint());
+        //even when every point collides, rings with equal digests route alike
+        let shared = |_: &str, _: usize, index: usize| format!("shared-{}", index);
+        let colliding = ConsistentHashing::builder(&nodes).interleave_count(40).label_format(shared).build();
+        let colliding_reversed = ConsistentHashing::builder(&reversed).interleave_count(40).label_format(shared).build();
+        assert_eq!(colliding_reversed.ring_digest(), colliding.ring_digest());
+        assert_eq!(colliding_reversed.dump_ranges(), colliding.dump_ranges());
+        assert_eq!(colliding_reversed.fingerprint(), colliding.fingerprint());
+
+        for node in nodes.iter() {
+            let mut reweighted = nodes.clone();
+            reweighted.iter_mut().find(|n| n.node_name == node.node_name).unwrap().weight += 1;
+            assert_ne!(ConsistentHashing::new(&reweighted, Some(40)).ring_digest(), digest);
+        }
+        assert_ne!(ConsistentHashing::new(&nodes, Some(80)).ring_digest(), digest);
+        assert_ne!(ConsistentHashing::builder(&nodes).seed("ring-a").build().ring_digest(), digest);
+        assert_ne!(ConsistentHashing::builder(&nodes).points_per_weight(40).build().ring_digest(), digest);
+        assert_ne!(ConsistentHashing::new(&nodes[..2].to_vec(), Some(40)).ring_digest(), digest);
+        assert_ne!(ConsistentHashing::builder(&nodes).interleave_count(40).label_format(|node, _, index| format!("{}#{}", node, index)).build().ring_digest(), digest);
+
+        //the same membership, reached by a join, shared interleave_count out differently
+        let mut joined = ConsistentHashing::new(&nodes[..2].to_vec(), Some(40));
+        joined.add_node(nodes[2].clone());
+        assert_ne!(joined.fingerprint(), consistent_hasing_ring.fingerprint());
+        assert_ne!(joined.ring_digest(), digest);
+
+        consistent_hasing_ring.update_weight_by_name("192.168.0.101:11212", 3);
+        assert_ne!(consistent_hasing_ring.ring_digest(), digest);
+        consistent_hasing_ring.update_weight_by_name("192.168.0.101:11212", 1);
+        assert_eq!(consistent_hasing_ring.ring_digest(), digest);
+        consistent_hasing_ring.drain_node("192.168.0.101:11212");
+        assert_ne!(consistent_hasing_ring.ring_digest(), digest);
+        //loads and down marks don't count
+        consistent_hasing_ring.undrain_node("192.168.0.101:11212");
+        consistent_hasing_ring.set_node_down("192.168.0.102:11212");
+        assert_eq!(consistent_hasing_ring.ring_digest(), digest);
+    }
+
+    #[test]
+    fn test_get_node_id() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.101:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 1),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            let id = consistent_hasing_ring.get_node_id(&key).unwrap();
+            let node = consistent_hasing_ring.node_by_id(id).unwrap();
+            assert_eq!(node.node_name, consistent_hasing_ring.get_node(&key).unwrap().node_name);
+            assert_eq!(consistent_hasing_ring.get_node_id(&key), Some(id));
+        }
+        assert_eq!(consistent_hasing_ring.node_by_id(0).unwrap().node_name, "192.168.0.101:11212");
+        assert_eq!(consistent_hasing_ring.node_by_id(2).unwrap().node_name, "192.168.0.103:11212");
+        assert!(consistent_hasing_ring.node_by_id(3).is_none());
+
+        //same membership, same ids, whatever the input order
+        let mut reversed = nodes.clone();
+        reversed.reverse();
+        let rebuilt = ConsistentHashing::new(&reversed, Some(40));
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(rebuilt.get_node_id(&key), consistent_hasing_ring.get_node_id(&key));
+        }
+
+        //joins and leaves renumber the nodes after them, the table with them
+        consistent_hasing_ring.get_node_id("my_key");
+        consistent_hasing_ring.add_node(NodeInfoWithWeight::new("192.168.0.100:11212", 1));
+        consistent_hasing_ring.remove_node_by_name("192.168.0.102:11212");
+        let in_order = consistent_hasing_ring.nodes_in_order();
+        for (id, node) in in_order.iter().enumerate() {
+            assert!(std::ptr::eq(consistent_hasing_ring.node_by_id(id as u32).unwrap(), *node));
+        }
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            let id = consistent_hasing_ring.get_node_id(&key).unwrap();
+            assert_eq!(in_order[id as usize].node_name, consistent_hasing_ring.get_node(&key).unwrap().node_name);
+        }
+
+        let empty: ConsistentHashing<NodeInfo> = ConsistentHashing::new(&vec![], None);
+        assert!(empty.get_node_id("my_key").is_none());
+    }
+
+    #[test]
+    fn test_uniform_weights() {
+        let nodes: Vec<NodeInfo> = (0..5).map(|i| NodeInfo::new(format!("192.168.0.{}:11212", 101 + i))).collect();
+        let weighted: Vec<NodeInfoWithWeight> = (0..5).map(|i| NodeInfoWithWeight::new(format!("192.168.0.{}:11212", 101 + i), 3)).collect();
+        for replica_factor in [1, 2] {
+            let uniform = ConsistentHashing::builder(&nodes).interleave_count(40).replica_factor(replica_factor).build();
+            //add_node works out each share from the weights
+            let mut general = ConsistentHashing::builder(&[]).interleave_count(40).replica_factor(replica_factor).build();
+            for node in nodes.iter() {
+                general.add_node(node.clone());
+            }
+            assert_eq!(uniform.factors, general.factors);
+            assert!(uniform.factors.values().all(|factor| *factor == 40 * replica_factor));
+            assert_eq!(uniform.fingerprint(), general.fingerprint());
+            for i in 0..200 {
+                let key = format!("key-{}", i);
+                assert_eq!(uniform.get_node(&key).unwrap().node_name, general.get_node(&key).unwrap().node_name);
+            }
+
+            //any weight the nodes share gives the same placement
+            let heavier = ConsistentHashing::builder(&weighted).interleave_count(40).replica_factor(replica_factor).build();
+            assert_eq!(heavier.dump_ranges(), uniform.dump_ranges());
+        }
+
+        let zero: Vec<NodeInfoWithWeight> = (0..3).map(|i| NodeInfoWithWeight::new(format!("192.168.0.{}:11212", 101 + i), 0)).collect();
+        assert!(ConsistentHashing::new(&zero, None).get_node("my_key").is_none());
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_parallel_build_matches_sequential() {
+        type Builder = fn(&[NodeInfoWithWeight]) -> ConsistentHashingBuilder<NodeInfoWithWeight>;
+        let nodes: Vec<NodeInfoWithWeight> = (0..200).map(|i| NodeInfoWithWeight::new(format!("10.0.{}.{}:11211", i / 100, i % 100), 1 + i % 5)).collect();
+        let builders: Vec<Builder> = vec![
+            |nodes| ConsistentHashing::builder(nodes).interleave_count(160),
+            |nodes| ConsistentHashing::builder(nodes).seed("ring-a").byte_order(ByteOrder::BigEndian),
+            |nodes| ConsistentHashing::builder(nodes).points_per_weight(10).point_scheme(PointScheme::DoubleHashing),
+            |nodes| ConsistentHashing::builder(nodes).label_format(|node, weight, index| format!("{}:{}:{}", node, weight, index)),
+            //every node's points collide with every other node's, so push order decides the owners
+            |nodes| ConsistentHashing::builder(nodes).label_format(|_, _, index| format!("shared-{}", index)),
+            |nodes| ConsistentHashing::builder(nodes).backend(Backend::BTree),
+        ];
+        for builder in builders {
+            let parallel = builder(&nodes).build();
+            let mut sequential = builder(&nodes).build();
+            let placed: Vec<(u32, usize)> = nodes.iter()
+                .map(|node| (sequential.owner_index(&node.node_name).unwrap(), sequential.factors[node.node_name.as_str()]))
+                .collect();
+            sequential.points.clear();
+            sequential.factors.clear();
+            sequential.add_points_sequentially(&placed);
+            sequential.sort_keys();
+
+            assert_eq!(parallel.sorted_keys(), sequential.sorted_keys());
+            assert_eq!(parallel.dump_ranges(), sequential.dump_ranges());
+            assert_eq!(parallel.fingerprint(), sequential.fingerprint());
+        }
+    }
+
+    #[test]
+    fn test_points_share_nodes() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let assert_shared = |ring: &ConsistentHashing<NodeInfoWithWeight>| {
+            for node in ring.owners.iter() {
+                assert!(ring.points_for_node_by_name(&node.node_name).iter().all(|point| std::ptr::eq(ring.owner_of(*point), &**node)));
+                //`owners` and the keys by name share the only copy
+                assert!(Arc::ptr_eq(&ring.real_nodes.get_key_value(node.ring_id()).unwrap().0 .0, node));
+                assert!(Arc::ptr_eq(&ring.factors.get_key_value(node.ring_id()).unwrap().0 .0, node));
+                assert_eq!(Arc::strong_count(node), 3);
+            }
+        };
+        assert_shared(&consistent_hasing_ring);
+
+        consistent_hasing_ring.update_weight_by_name("192.168.0.101:11212", 3);
+        assert!(consistent_hasing_ring.owned_points().filter(|(_, n)| n.node_name == "192.168.0.101:11212").all(|(_, n)| n.weight == 3));
+        assert_shared(&consistent_hasing_ring);
+        consistent_hasing_ring.drain_node("192.168.0.102:11212");
+        consistent_hasing_ring.add_node(NodeInfoWithWeight::new("192.168.0.104:11212", 1));
+        assert_shared(&consistent_hasing_ring);
+        let node = Arc::clone(consistent_hasing_ring.registered("192.168.0.103:11212").unwrap());
+        consistent_hasing_ring.remove_node_by_name("192.168.0.103:11212");
+        assert_eq!(Arc::strong_count(&node), 1);
+
+        let owner = consistent_hasing_ring.get_node_ref("my_key").unwrap();
+        assert!(std::ptr::eq(owner, &**consistent_hasing_ring.registered(&owner.node_name).unwrap()));
+        assert_eq!(owner.node_name, consistent_hasing_ring.get_node("my_key").unwrap().node_name);
+    }
+
+    //what every mutation has to leave intact: points sorted by token, owners in
+    //range and registered, every token once, drained nodes off
+    fn assert_points_consistent(ring: &ConsistentHashing<NodeInfoWithWeight>) {
+        let points: Vec<Point> = ring.points.iter().collect();
+        assert!(points.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(points.iter().all(|point| (point.1 as usize) < ring.owners.len()));
+        assert_eq!(ring.owners.len(), ring.real_nodes.len());
+        for (index, node) in ring.owners.iter().enumerate() {
+            assert_eq!(ring.real_nodes[node.node_name.as_str()], index as u32);
+        }
+        for name in ring.drained.iter() {
+            assert!(ring.points_for_node_by_name(name).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_points_stay_consistent() {
+        let mut rng = xorshift(0x9e37_79b9_7f4a_7c15);
+        let name = |i: u64| format!("192.168.0.{}:11212", 100 + i);
+
+        let mut consistent_hasing_ring = ConsistentHashing::builder(&[]).points_per_weight(10).build();
+        for _ in 0..2000 {
+            let node = name(rng(12));
+            match rng(8) {
+                0 | 1 => {
+                    consistent_hasing_ring.add_node(NodeInfoWithWeight::new(node, 1 + rng(4) as usize));
+                }
+                2 => {
+                    consistent_hasing_ring.remove_node_by_name(&node);
+                }
+                3 => {
+                    consistent_hasing_ring.update_weight_by_name(&node, 1 + rng(4) as usize);
+                }
+                4 => consistent_hasing_ring.drain_node(&node),
+                5 => consistent_hasing_ring.undrain_node(&node),
+                6 => {
+                    consistent_hasing_ring.start_drain(&node, 3);
+                    consistent_hasing_ring.advance_drain(&node);
+                }
+                _ => consistent_hasing_ring.cancel_drain(&node),
+            }
+            assert_points_consistent(&consistent_hasing_ring);
+        }
+
+        //points only depend on a node's own weight, so whatever the history the ring
+        //ends up as if built from scratch
+        let names: Vec<String> = consistent_hasing_ring.nodes_in_order().iter().map(|node| node.node_name.clone()).collect();
+        for name in names.iter() {
+            consistent_hasing_ring.undrain_node(name);
+            consistent_hasing_ring.cancel_drain(name);
+        }
+        let nodes: Vec<NodeInfoWithWeight> = consistent_hasing_ring.nodes_in_order().into_iter().cloned().collect();
+        assert!(nodes.len() > 3);
+        let rebuilt = ConsistentHashing::builder(&nodes).points_per_weight(10).build();
+        assert_eq!(consistent_hasing_ring.dump_ranges(), rebuilt.dump_ranges());
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let mut rng = xorshift(0x2545_f491_4f6c_dd1d);
+        let name = |i: u64| format!("192.168.0.{}:11212", 100 + i);
+        //every fourth digest of every node lands on the same points
+        let colliding = |node: &str, _: usize, index: usize| {
+            if index.is_multiple_of(4) { format!("shared-{}", index) } else { format!("{}-{}", node, index) }
+        };
+        let configs: Vec<fn(ConsistentHashingBuilder<NodeInfoWithWeight>) -> ConsistentHashingBuilder<NodeInfoWithWeight>> = vec![
+            |builder| builder,
+            |builder| builder.points_per_weight(10).point_scheme(PointScheme::DoubleHashing),
+            |builder| builder.probes(3).bounded_load(0.25),
+        ];
+        for (i, config) in configs.iter().enumerate() {
+            let build = |backend: Backend| {
+                let builder = config(ConsistentHashing::builder(&[]).backend(backend));
+                if i == 0 { builder.label_format(colliding) } else { builder }.build()
+            };
+            let mut sorted_vec = build(Backend::SortedVec);
+            let mut btree = build(Backend::BTree);
+            for step in 0..600 {
+                let node = name(rng(12));
+                let weight = 1 + rng(4) as usize;
+                for ring in [&mut sorted_vec, &mut btree] {
+                    match step % 10 {
+                        0..=2 => {
+                            ring.add_node(NodeInfoWithWeight::new(node.clone(), weight));
+                        }
+                        3 => {
+                            ring.remove_node_by_name(&node);
+                        }
+                        4 => {
+                            ring.update_weight_by_name(&node, weight);
+                        }
+                        5 => ring.drain_node(&node),
+                        6 => ring.undrain_node(&node),
+                        7 => {
+                            ring.start_drain(&node, 3);
+                            ring.advance_drain(&node);
+                        }
+                        8 => ring.cancel_drain(&node),
+                        _ if weight == 1 => ring.set_interleave_count(20 + step % 30),
+                        _ => {
+                            ring.set_load(&node, weight);
+                            ring.set_node_down(&node);
+                            ring.set_node_up(&name((step % 12) as u64));
+                        }
+                    }
+                    assert_points_consistent(ring);
+                }
+                assert_eq!(sorted_vec.fingerprint(), btree.fingerprint(), "config {} step {}", i, step);
+                assert_eq!(sorted_vec.virtual_node_count(), btree.virtual_node_count());
+                assert_eq!(sorted_vec.dump_ranges(), btree.dump_ranges());
+                for k in 0..20 {
+                    let key = format!("key-{}-{}", step, k);
+                    let names = |ring: &ConsistentHashing<NodeInfoWithWeight>| {
+                        ring.get_nodes(&key, 3).into_iter().map(|node| node.node_name).collect::<Vec<String>>()
+                    };
+                    assert_eq!(sorted_vec.get_node(&key).map(|node| node.node_name), btree.get_node(&key).map(|node| node.node_name));
+                    assert_eq!(names(&sorted_vec), names(&btree), "{}", key);
+                }
+            }
+            assert!(btree.virtual_node_count() > 0);
+        }
+    }
+
+    #[test]
+    fn test_into_iter() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        consistent_hasing_ring.drain_node("192.168.0.103:11212");
+
+        let mut consumed: Vec<NodeInfoWithWeight> = consistent_hasing_ring.into_iter().collect();
+        consumed.sort_by(|a, b| a.node_name.cmp(&b.node_name));
+        assert_eq!(consumed.len(), nodes.len());
+        for (node, original) in consumed.iter().zip(nodes.iter()) {
+            assert_eq!((node.node_name.as_str(), node.weight), (original.node_name.as_str(), original.weight));
+        }
+
+        let empty: ConsistentHashing<NodeInfo> = ConsistentHashing::new(&vec![], None);
+        assert_eq!(empty.into_iter().count(), 0);
+    }
+
+    #[test]
+    fn test_extend() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+        ];
+        let more = vec![
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.104:11212", 3),
+        ];
+        let mut one_by_one = ConsistentHashing::new(&nodes, Some(40));
+        for node in more.iter() {
+            one_by_one.add_node(node.clone());
+        }
+        let mut extended = ConsistentHashing::new(&nodes, Some(40));
+        let sorts = extended.sort_count;
+        extended.extend(more.clone());
+        assert_eq!(extended.sort_count, sorts + 1);
+
+        assert_eq!(extended.sorted_keys(), one_by_one.sorted_keys());
+        assert_eq!(extended.total_weight, one_by_one.total_weight);
+        assert_eq!(extended.real_nodes.len(), 4);
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(extended.get_node(&key).unwrap().node_name, one_by_one.get_node(&key).unwrap().node_name);
+        }
+
+        //nothing new, nothing to sort
+        extended.extend(more);
+        assert_eq!(extended.sort_count, sorts + 1);
+        assert_eq!(extended.sorted_keys(), one_by_one.sorted_keys());
+    }
+
+    #[test]
+    fn test_add_node() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let sorted_keys = consistent_hasing_ring.sorted_keys().clone();
+
+        assert!(consistent_hasing_ring.add_node(NodeInfo::new("192.168.0.101:11212")).is_none());
+        assert_eq!(consistent_hasing_ring.sorted_keys(), sorted_keys);
+
+        assert_eq!(consistent_hasing_ring.add_node(NodeInfo::new("192.168.0.103:11212")).unwrap().as_str(), "192.168.0.103:11212");
+        assert_eq!(consistent_hasing_ring.total_weight, 3);
+        assert_eq!(consistent_hasing_ring.sorted_keys().len(), 3 * 40 * 3);
+        //the existing points don't move
+        assert!(sorted_keys.iter().all(|key| consistent_hasing_ring.sorted_keys().binary_search(key).is_ok()));
+
+        let all: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        assert_eq!(consistent_hasing_ring.sorted_keys(), ConsistentHashing::new(&all, Some(40)).sorted_keys());
+
+        let mut empty: ConsistentHashing<NodeInfo> = ConsistentHashing::new(&vec![], None);
+        assert!(empty.add_node(NodeInfo::new("192.168.0.101:11212")).is_some());
+        assert_eq!(empty.get_node("my_key").unwrap().node_name, "192.168.0.101:11212");
+    }
+
+    #[cfg(feature = "lookup-cache")]
+    #[test]
+    fn test_lookup_cache() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let mut cached = ConsistentHashing::builder(&nodes).lookup_cache(16).build();
+        let uncached = ConsistentHashing::new(&nodes, None);
+        let cached_len = |ring: &ConsistentHashing<NodeInfo>| ring.cache.as_ref().unwrap().lock().unwrap().len();
+
+        for _ in 0..3 {
+            for i in 0..32 {
+                let key = format!("key-{}", i % 8);
+                assert_eq!(cached.get_node(&key).unwrap().node_name, uncached.get_node(&key).unwrap().node_name);
+            }
+        }
+        assert_eq!(cached_len(&cached), 8);
+
+        //a hit for a key whose owner is removed must not return the removed node
+        let owner = cached.get_node("key-0").unwrap().node_name;
+        assert!(!cached.remove_node_by_name(&owner).is_empty());
+        assert_eq!(cached_len(&cached), 0);
+        assert_ne!(cached.get_node("key-0").unwrap().node_name, owner);
+
+        cached.get_node("key-1");
+        assert!(cached.add_node(NodeInfo::new(owner.clone())).is_some());
+        assert_eq!(cached_len(&cached), 0);
+        assert_eq!(cached.get_node("key-0").unwrap().node_name, owner);
+
+        cached.drain_node(&owner);
+        assert_eq!(cached_len(&cached), 0);
+        assert_ne!(cached.get_node("key-0").unwrap().node_name, owner);
+        cached.undrain_node(&owner);
+        assert_eq!(cached.get_node("key-0").unwrap().node_name, owner);
+
+        let no_cache = ConsistentHashing::builder(&nodes).lookup_cache(0).build();
+        assert!(no_cache.cache.is_none());
+    }
+
+    #[test]
+    fn test_bounded_load() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+            NodeInfo::new("192.168.0.104:11212"),
+        ];
+        let epsilon = 0.25;
+        let mut consistent_hasing_ring = ConsistentHashing::builder(&nodes).bounded_load(epsilon).build();
+
+        //half of the traffic hits a handful of hot keys
+        let total = 2000;
+        for i in 0..total {
+            let key = if i % 2 == 0 { format!("hot-{}", i % 3) } else { format!("key-{}", i) };
+            let node = consistent_hasing_ring.get_node(&key).unwrap().to_string();
+            let load = consistent_hasing_ring.load_of(&node);
+            consistent_hasing_ring.set_load(&node, load + 1);
+        }
+
+        let cap = ((1.0 + epsilon) * total as f64 / nodes.len() as f64).ceil() as usize;
+        for node in &nodes {
+            let load = consistent_hasing_ring.load_of(&node.node_name);
+            assert!(load <= cap, "{} has load {} over cap {}", node.node_name, load, cap);
+        }
+        let total_load: usize = nodes.iter().map(|n| consistent_hasing_ring.load_of(&n.node_name)).sum();
+        assert_eq!(total_load, total);
+    }
+
+    #[test]
+    fn test_bounded_load_off_by_default() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, None);
+        let owner = consistent_hasing_ring.get_node("my_key").unwrap().to_string();
+        consistent_hasing_ring.set_load(&owner, 1_000_000);
+        assert_eq!(consistent_hasing_ring.get_node("my_key").unwrap().to_string(), owner);
+        assert!(consistent_hasing_ring.load_cap(&owner).is_none());
+    }
+
+    #[test]
+    fn test_node_name_into() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+
+        let mut buffer = String::from("stale contents");
+        for i in 0..20 {
+            let key = format!("key-{}", i);
+            assert!(consistent_hasing_ring.node_name_into(&key, &mut buffer));
+            assert_eq!(buffer, consistent_hasing_ring.get_node(&key).unwrap().to_string());
+        }
+
+        let empty: ConsistentHashing<NodeInfo> = ConsistentHashing::new(&vec![], None);
+        assert!(!empty.node_name_into("key", &mut buffer));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_owns() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        for i in 0..50 {
+            let key = format!("key-{}", i);
+            let owner = consistent_hasing_ring.get_node(&key).unwrap().node_name;
+            for node in nodes.iter() {
+                assert_eq!(consistent_hasing_ring.owns(&node.node_name, &key), node.node_name == owner);
+            }
+        }
+        assert!(!consistent_hasing_ring.owns("192.168.0.199:11212", "key-0"));
+
+        //a node that's down owns nothing, its keys go to the next node
+        let owner = consistent_hasing_ring.get_node("key-0").unwrap().node_name;
+        consistent_hasing_ring.set_node_down(&owner);
+        assert!(!consistent_hasing_ring.owns(&owner, "key-0"));
+        assert!(consistent_hasing_ring.owns(&consistent_hasing_ring.get_node("key-0").unwrap().node_name, "key-0"));
+
+        let empty: ConsistentHashing<NodeInfo> = ConsistentHashing::new(&vec![], None);
+        assert!(!empty.owns("192.168.0.101:11212", "key-0"));
+    }
+
+    #[test]
+    fn test_get_nodes() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        for i in 0..50 {
+            let key = format!("key-{}", i);
+            let replicas = consistent_hasing_ring.get_nodes(&key, 2);
+            assert_eq!(replicas.len(), 2);
+            assert_eq!(replicas[0].node_name, consistent_hasing_ring.get_node(&key).unwrap().node_name);
+            assert_ne!(replicas[0].node_name, replicas[1].node_name);
+        }
+        assert_eq!(consistent_hasing_ring.get_nodes("my_key", 5).len(), 3);
+    }
+
+    fn peak_to_mean(ring: &ConsistentHashing<NodeInfo>, nodes: usize, keys: usize) -> f64 {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for i in 0..keys {
+            let node = ring.get_node(&format!("key-{}", i)).unwrap();
+            *counts.entry(node.to_string()).or_insert(0) += 1;
+        }
+        let peak = counts.values().cloned().max().unwrap();
+        peak as f64 / (keys as f64 / nodes as f64)
+    }
+
+    #[test]
+    fn test_multi_probe() {
+        let nodes: Vec<NodeInfo> = (0..20).map(|i| NodeInfo::new(format!("10.0.0.{}:11211", i))).collect();
+
+        let single = ConsistentHashing::builder(&nodes).interleave_count(1).build();
+        let probed = ConsistentHashing::builder(&nodes).interleave_count(1).probes(21).build();
+        let single_peak = peak_to_mean(&single, nodes.len(), 20_000);
+        let probed_peak = peak_to_mean(&probed, nodes.len(), 20_000);
+        assert!(probed_peak < single_peak, "probes=21 {} vs probes=1 {}", probed_peak, single_peak);
+        assert!(probed_peak < 1.5, "probes=21 peak-to-mean {}", probed_peak);
+
+        //a single probe is a plain lookup
+        let one_probe = ConsistentHashing::builder(&nodes).interleave_count(1).probes(1).build();
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(one_probe.get_node(&key).unwrap().node_name, single.get_node(&key).unwrap().node_name);
+        }
+
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            let replicas = probed.get_nodes(&key, 3);
+            assert_eq!(replicas[0].node_name, probed.get_node(&key).unwrap().node_name);
+            let mut names: Vec<&str> = replicas.iter().map(|n| n.node_name.as_str()).collect();
+            names.sort();
+            names.dedup();
+            assert_eq!(names.len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_nodes_in_order() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.103:11212"),
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("10.0.0.1:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+        ];
+        let expected = vec!["10.0.0.1:11212", "192.168.0.101:11212", "192.168.0.102:11212", "192.168.0.103:11212"];
+        let mut reversed = nodes.clone();
+        reversed.reverse();
+        for order in &[nodes, reversed] {
+            let consistent_hasing_ring = ConsistentHashing::new(order, None);
+            let names: Vec<&str> = consistent_hasing_ring.nodes_in_order().iter().map(|node| node.node_name.as_str()).collect();
+            assert_eq!(names, expected);
+        }
+    }
+
+    #[test]
+    fn test_drain_node() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let keys: Vec<String> = (0..200).map(|i| format!("key-{}", i)).collect();
+        let before: Vec<String> = keys.iter().map(|k| consistent_hasing_ring.get_node(k).unwrap().node_name).collect();
+        let sorted_keys = consistent_hasing_ring.sorted_keys().clone();
+
+        consistent_hasing_ring.drain_node("192.168.0.102:11212");
+        consistent_hasing_ring.drain_node("192.168.0.102:11212");
+        assert!(consistent_hasing_ring.contains_node("192.168.0.102:11212"));
+        for key in &keys {
+            assert_ne!(consistent_hasing_ring.get_node(key).unwrap().node_name, "192.168.0.102:11212");
+        }
+
+        consistent_hasing_ring.undrain_node("192.168.0.102:11212");
+        consistent_hasing_ring.undrain_node("192.168.0.102:11212");
+        assert_eq!(consistent_hasing_ring.sorted_keys(), sorted_keys);
+        let after: Vec<String> = keys.iter().map(|k| consistent_hasing_ring.get_node(k).unwrap().node_name).collect();
+        assert_eq!(before, after);
+
+        assert!(!consistent_hasing_ring.contains_node("192.168.0.199:11212"));
+        consistent_hasing_ring.drain_node("192.168.0.199:11212");
+        assert_eq!(consistent_hasing_ring.sorted_keys(), sorted_keys);
+    }
+
+    #[test]
+    fn test_percent_owned_sums_to_one() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let owned = consistent_hasing_ring.percent_owned();
+        assert_eq!(owned.len(), 3);
+        assert!((owned.values().sum::<f64>() - 1.0).abs() < 1e-9);
+
+        let empty: ConsistentHashing<NodeInfo> = ConsistentHashing::new(&vec![], None);
+        assert!(empty.percent_owned().is_empty());
+    }
+
+    #[test]
+    fn test_replica_factor_smooths_distribution() {
+        let nodes: Vec<NodeInfoWithWeight> = (0..10)
+            .map(|i| NodeInfoWithWeight::new(format!("192.168.0.{}:11212", 101 + i), 1))
+            .collect();
+        let variance = |replica_factor: usize| {
+            let consistent_hasing_ring = ConsistentHashing::builder(&nodes).interleave_count(4).replica_factor(replica_factor).build();
+            assert_eq!(consistent_hasing_ring.sorted_keys().len(), 10 * 4 * 3 * replica_factor);
+            let owned = consistent_hasing_ring.percent_owned();
+            owned.values().map(|share| (share - 0.1) * (share - 0.1)).sum::<f64>() / 10.0
+        };
+        assert!(variance(25) < variance(1) / 4.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "replica factor")]
+    fn test_replica_factor_zero() {
+        ConsistentHashing::builder(&[NodeInfo::new("192.168.0.101:11212")]).replica_factor(0);
+    }
+
+    #[test]
+    fn test_points_per_weight() {
+        let nodes: Vec<NodeInfoWithWeight> = (0..5).map(|i| NodeInfoWithWeight::new(format!("192.168.0.{}:11212", 101 + i), 1 + i % 3)).collect();
+        let keys: Vec<String> = (0..2000).map(|i| format!("key-{}", i)).collect();
+        //keys that stay on the old nodes when a ring is rebuilt with one more
+        let moved_among_old = |old: &ConsistentHashing<NodeInfoWithWeight>, new: &ConsistentHashing<NodeInfoWithWeight>| {
+            keys.iter()
+                .filter(|key| {
+                    let owner = new.get_node(key).unwrap().node_name;
+                    owner != "192.168.0.105:11212" && owner != old.get_node(key).unwrap().node_name
+                })
+                .count()
+        };
+
+        let old = ConsistentHashing::builder(&nodes[..4]).points_per_weight(40).build();
+        let new = ConsistentHashing::builder(&nodes).points_per_weight(40).build();
+        assert_eq!(new.factors["192.168.0.102:11212"], 80);
+        for node in &nodes[..4] {
+            assert_eq!(old.points_for_node_by_name(&node.node_name), new.points_for_node_by_name(&node.node_name));
+        }
+        assert_eq!(moved_among_old(&old, &new), 0);
+        //a key either stays or moves to the new node
+        assert!(keys.iter().any(|key| new.get_node(key).unwrap().node_name == "192.168.0.105:11212"));
+
+        //shares of interleave_count shift every node's points
+        let old_shares = ConsistentHashing::new(&nodes[..4].to_vec(), None);
+        let new_shares = ConsistentHashing::new(&nodes, None);
+        assert!(moved_among_old(&old_shares, &new_shares) > 0);
+
+        //draining and undraining puts the node's points back, adding it goes through
+        //the same count
+        let mut drained = ConsistentHashing::builder(&nodes).points_per_weight(40).build();
+        drained.drain_node("192.168.0.105:11212");
+        assert_eq!(drained.sorted_keys(), old.sorted_keys());
+        drained.undrain_node("192.168.0.105:11212");
+        assert_eq!(drained.sorted_keys(), new.sorted_keys());
+        let mut grown = old;
+        grown.add_node(nodes[4].clone());
+        assert_eq!(grown.sorted_keys(), new.sorted_keys());
+        assert!(grown.update_weight_by_name("192.168.0.101:11212", 3));
+        assert_eq!(grown.factors["192.168.0.101:11212"], 120);
+
+        let replicated = ConsistentHashing::builder(&nodes).points_per_weight(40).replica_factor(2).build();
+        assert_eq!(replicated.factors["192.168.0.102:11212"], 160);
+    }
+
+    #[test]
+    #[should_panic(expected = "points per weight")]
+    fn test_points_per_weight_zero() {
+        ConsistentHashing::builder(&[NodeInfo::new("192.168.0.101:11212")]).points_per_weight(0);
+    }
+
+    #[test]
+    fn test_capacity_plan() {
+        let mut nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 128),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 128),
+        ];
+        let current = capacity_plan(&nodes, 40);
+        nodes.push(NodeInfoWithWeight::new("192.168.0.103:11212", 512));
+        let projected = capacity_plan(&nodes, 40);
+        assert_eq!(current.len(), 2);
+        assert_eq!(projected.len(), 3);
+        assert!(projected["192.168.0.103:11212"] > 0.5);
+
+        //the projection matches how a real ring spreads keys
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let samples = 50_000;
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for i in 0..samples {
+            let node = consistent_hasing_ring.get_node(&format!("key-{}", i)).unwrap();
+            *counts.entry(node.to_string()).or_insert(0) += 1;
+        }
+        for (name, fraction) in &projected {
+            let actual = counts[name] as f64 / samples as f64;
+            assert!((actual - fraction).abs() < 0.02, "{}: {} vs {}", name, actual, fraction);
+        }
+    }
+
+    #[test]
+    fn test_get_node_bounded() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+            NodeInfo::new("192.168.0.104:11212"),
+            NodeInfo::new("192.168.0.105:11212"),
+        ];
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let c = 1.1;
+
+        //a few hot keys take most of the traffic
+        let mut loads: HashMap<String, usize> = HashMap::new();
+        let total = 3000;
+        for i in 0..total {
+            let key = if i % 4 != 0 { format!("hot-{}", i % 2) } else { format!("key-{}", i) };
+            let node = consistent_hasing_ring.get_node_bounded(&key, &loads, c).unwrap().to_string();
+            *loads.entry(node).or_insert(0) += 1;
+        }
+
+        let bound = (c * total as f64 / nodes.len() as f64).ceil() as usize;
+        assert_eq!(loads.values().sum::<usize>(), total);
+        assert!(loads.values().all(|&load| load <= bound), "{:?} over {}", loads, bound);
+
+        //without load the plain owner is returned
+        let no_load: HashMap<String, usize> = HashMap::new();
+        assert_eq!(
+            consistent_hasing_ring.get_node_bounded("my_key", &no_load, c).unwrap().node_name,
+            consistent_hasing_ring.get_node("my_key").unwrap().node_name
+        );
+
+        let empty: ConsistentHashing<NodeInfo> = ConsistentHashing::new(&vec![], None);
+        assert!(empty.get_node_bounded("my_key", &no_load, c).is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_get_node_bounded_rejects_small_factor() {
+        let nodes: Vec<NodeInfo> = vec![NodeInfo::new("192.168.0.101:11212")];
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let loads: HashMap<String, usize> = HashMap::new();
+        consistent_hasing_ring.get_node_bounded("my_key", &loads, 1.0);
+    }
+
+    #[test]
+    fn test_capacity_of_follows_weights() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 4),
+        ];
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        assert_eq!(consistent_hasing_ring.capacity_of("192.168.0.101:11212", 600, 1.1), Some(110));
+        assert_eq!(consistent_hasing_ring.capacity_of("192.168.0.102:11212", 600, 1.1), Some(110));
+        assert_eq!(consistent_hasing_ring.capacity_of("192.168.0.103:11212", 600, 1.1), Some(440));
+        assert_eq!(consistent_hasing_ring.capacity_of("192.168.0.199:11212", 600, 1.1), None);
+    }
+
+    #[test]
+    fn test_get_node_bounded_weighted() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 4),
+        ];
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let c = 1.1;
+
+        let mut loads: HashMap<String, usize> = HashMap::new();
+        let total = 1200;
+        for i in 0..total {
+            let key = if i % 3 == 0 { "hot".to_string() } else { format!("key-{}", i) };
+            let node = consistent_hasing_ring.get_node_bounded(&key, &loads, c).unwrap().to_string();
+            *loads.entry(node).or_insert(0) += 1;
+        }
+        for node in &nodes {
+            let bound = consistent_hasing_ring.capacity_of(&node.node_name, total, c).unwrap();
+            assert!(loads[&node.node_name] <= bound, "{} over {}", loads[&node.node_name], bound);
+        }
+        //the heavy node absorbs proportionally more before spilling
+        assert!(loads["192.168.0.103:11212"] > 3 * loads["192.168.0.101:11212"]);
+        assert!(loads["192.168.0.103:11212"] > 3 * loads["192.168.0.102:11212"]);
+
+        //a saturated light node spills to the next node clockwise
+        let key = (0..).map(|i| format!("key-{}", i))
+            .find(|k| consistent_hasing_ring.get_node(k).unwrap().weight == 1)
+            .unwrap();
+        let chain = consistent_hasing_ring.get_nodes(&key, 3);
+        let mut loads: HashMap<String, usize> = HashMap::new();
+        loads.insert(chain[0].to_string(), 10);
+        let spilled = consistent_hasing_ring.get_node_bounded(&key, &loads, c).unwrap();
+        assert_eq!(spilled.node_name, chain[1].node_name);
+    }
+
+    #[test]
+    fn test_get_node_bounded_with_a_node_down() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 4),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        consistent_hasing_ring.set_node_down("192.168.0.103:11212");
+        let c = 1.1;
+        //the nodes still up share the whole load between them
+        assert_eq!(consistent_hasing_ring.capacity_of("192.168.0.101:11212", 600, c), Some(330));
+        assert_eq!(consistent_hasing_ring.capacity_of("192.168.0.103:11212", 600, c), None);
+
+        let mut loads: HashMap<String, usize> = HashMap::new();
+        //load a down node still reports doesn't count towards the total
+        loads.insert("192.168.0.103:11212".to_string(), 500);
+        let total = 1200;
+        for i in 0..total {
+            let key = if i % 3 == 0 { "hot".to_string() } else { format!("key-{}", i) };
+            let node = consistent_hasing_ring.get_node_bounded(&key, &loads, c).unwrap().to_string();
+            *loads.entry(node).or_insert(0) += 1;
+        }
+        assert_eq!(loads["192.168.0.103:11212"], 500);
+        for node in &nodes[..2] {
+            let bound = consistent_hasing_ring.capacity_of(&node.node_name, total, c).unwrap();
+            assert!(loads[&node.node_name] <= bound, "{} over {}", loads[&node.node_name], bound);
+        }
+    }
+
+    #[test]
+    fn test_hash_key_is_pinned() {
+        //md5("my_key") little-endian over its first four bytes
+        assert_eq!(hash_key("my_key"), 1793382046);
+        assert_eq!(hash_key("192.168.0.101:11212"), 552911443);
+        assert_eq!(hash_key("my_key"), gen_key("my_key"));
+    }
+
+    #[test]
+    fn test_get_node_for_hash() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let same = |ring: &ConsistentHashing<NodeInfoWithWeight>, key: &str, hash: u32| {
+            assert_eq!(ring.get_node(key).map(|node| node.node_name), ring.get_node_for_hash(hash).map(|node| node.node_name), "{}", key);
+        };
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            same(&consistent_hasing_ring, &key, hash_key(&key));
+        }
+        consistent_hasing_ring.set_node_down("192.168.0.102:11212");
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            same(&consistent_hasing_ring, &key, hash_key(&key));
+            assert_ne!(consistent_hasing_ring.get_node_for_hash(hash_key(&key)).unwrap().node_name, "192.168.0.102:11212");
+        }
+
+        let mut bounded = ConsistentHashing::builder(&nodes).bounded_load(0.25).build();
+        bounded.set_load("192.168.0.101:11212", 100);
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            same(&bounded, &key, hash_key(&key));
+        }
+
+        //a seed changes the key's position, not how a position resolves
+        let seeded = ConsistentHashing::builder(&nodes).seed("ring-a").build();
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            same(&seeded, &key, seeded.key_hash(&key));
+        }
+
+        let empty: ConsistentHashing<NodeInfoWithWeight> = ConsistentHashing::new(&vec![], None);
+        assert!(empty.get_node_for_hash(hash_key("my_key")).is_none());
+    }
+
+    #[test]
+    fn test_hash_val() {
+        let digest = [0x01, 0x02, 0x03, 0x04, 0x05, 0x06];
+        assert_eq!(hash_val(&digest, 0), 0x04030201);
+        assert_eq!(hash_val(&digest, 2), 0x06050403);
+        assert_eq!(ByteOrder::BigEndian.read(&digest, 2), 0x03040506);
+        //what's missing of a short digest reads as 0
+        assert_eq!(hash_val(&digest, 4), 0x0605);
+        assert_eq!(hash_val(&digest, 6), 0);
+        assert_eq!(hash_val(&[], usize::MAX), 0);
+    }
+
+    #[test]
+    fn test_nodes_from_runtime_names() {
+        //e.g. a config file with one "host:port weight" per line
+        let config = String::from("192.168.0.101:11212 128\n192.168.0.102:11212 512\n192.168.0.103:11212 128\n");
+        let parsed: Vec<(String, usize)> = config.lines()
+            .map(|line| {
+                let mut fields = line.split_whitespace();
+                (fields.next().unwrap().to_string(), fields.next().unwrap().parse().unwrap())
+            })
+            .collect();
+        let nodes: Vec<NodeInfoWithWeight> = parsed.iter().map(|(name, weight)| NodeInfoWithWeight::new(name.clone(), *weight)).collect();
+        let names: Vec<String> = parsed.into_iter().map(|(name, _)| name).collect();
+        drop(config);
+
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, None);
+        assert_eq!(consistent_hasing_ring.total_weight, 768);
+        let node = consistent_hasing_ring.get_node("my_key").unwrap();
+        assert!(names.contains(&node.node_name));
+
+        let unweighted: Vec<NodeInfo> = names.into_iter().map(NodeInfo::new).collect();
+        let consistent_hasing_ring = ConsistentHashing::new(&unweighted, None);
+        assert_eq!(consistent_hasing_ring.nodes_in_order().len(), 3);
+        assert_eq!(NodeInfo::new("a").to_string(), NodeInfo::new(String::from("a")).to_string());
+    }
+
+    //logs with decoration around the name, which mustn't affect placement
+    #[derive(Clone, Debug, Hash)]
+    struct DecoratedNode {
+        name: String,
+    }
+
+    impl fmt::Display for DecoratedNode {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "memcached<{}>", self.name)
+        }
+    }
+
+    impl RingNode for DecoratedNode {
+        fn ring_id(&self) -> &str {
+            &self.name
+        }
+    }
+
+    impl WithWeightInfo for DecoratedNode {
+        fn get_weight(&self) -> usize {
+            1
+        }
+    }
+
+    #[test]
+    fn test_placement_follows_ring_id() {
+        let names = ["192.168.0.101:11212", "192.168.0.102:11212", "192.168.0.103:11212"];
+        let decorated: Vec<DecoratedNode> = names.iter().map(|name| DecoratedNode{name: name.to_string()}).collect();
+        let plain: Vec<NodeInfo> = names.iter().map(|&name| NodeInfo::new(name)).collect();
+        let mut decorated_ring = ConsistentHashing::new(&decorated, None);
+        let plain_ring = ConsistentHashing::new(&plain, None);
+
+        assert_eq!(decorated_ring.sorted_keys(), plain_ring.sorted_keys());
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(decorated_ring.get_node(&key).unwrap().name, plain_ring.get_node(&key).unwrap().node_name);
+        }
+        let mut buffer = String::new();
+        assert!(decorated_ring.node_name_into("my_key", &mut buffer));
+        assert!(names.contains(&buffer.as_str()));
+
+        assert!(decorated_ring.contains_node("192.168.0.102:11212"));
+        assert!(!decorated_ring.contains_node("memcached<192.168.0.102:11212>"));
+        assert!(!decorated_ring.remove_node_by_name("192.168.0.102:11212").is_empty());
+        assert_eq!(decorated_ring.sorted_keys().len(), 2 * 40 * 3);
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let text = "# cache pool\n192.168.0.101:11212 1\n\n  192.168.0.102:11212\t2\n192.168.0.103:11212\n";
+        let consistent_hasing_ring = ConsistentHashing::from_reader(text.as_bytes(), Some(40)).unwrap();
+        let members: Vec<(&str, usize)> = consistent_hasing_ring.nodes_in_order().iter()
+            .map(|node| (node.node_name.as_str(), node.weight))
+            .collect();
+        assert_eq!(members, vec![("192.168.0.101:11212", 1), ("192.168.0.102:11212", 2), ("192.168.0.103:11212", 1)]);
+        let reference = ConsistentHashing::from_weighted(&members, Some(40));
+        assert_eq!(consistent_hasing_ring.fingerprint(), reference.fingerprint());
+
+        let err = ConsistentHashing::from_reader("a 1\nb two\n".as_bytes(), None).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "line 2: weight \"two\" is not a number");
+        let err = ConsistentHashing::from_reader("a 1 extra\n".as_bytes(), None).err().unwrap();
+        assert_eq!(err.to_string(), "line 1: expected `host:port weight`, got \"a 1 extra\"");
+        assert!(ConsistentHashing::from_reader("# nothing yet\n".as_bytes(), None).unwrap().nodes_in_order().is_empty());
+    }
+
+    #[test]
+    fn test_from_weighted() {
+        let consistent_hasing_ring = ConsistentHashing::from_weighted(&[("a", 1), ("b", 2), ("c", 1)], None);
+        assert!(consistent_hasing_ring.contains_node("b"));
+        assert_eq!(consistent_hasing_ring.total_weight, 4);
+
+        let node = consistent_hasing_ring.get_node("my_key").unwrap();
+        assert!(["a", "b", "c"].contains(&node.node_name.as_str()));
+
+        let nodes = vec![
+            NodeInfoWithWeight::new("a", 1),
+            NodeInfoWithWeight::new("b", 2),
+            NodeInfoWithWeight::new("c", 1),
+        ];
+        let reference = ConsistentHashing::new(&nodes, None);
+        for i in 0..50 {
+            let key = format!("key-{}", i);
+            assert_eq!(consistent_hasing_ring.get_node(&key).unwrap().node_name, reference.get_node(&key).unwrap().node_name);
+        }
+    }
+
+    //run with and without the smallvec feature: rings below and above the 256
+    //inline points have to place keys like a plain sorted Vec does
+    #[test]
+    fn test_sorted_keys_storage() {
+        let nodes: Vec<NodeInfo> = (1..=4).map(|i| NodeInfo::new(format!("192.168.0.10{}:11212", i))).collect();
+        for count in 1..=nodes.len() {
+            let consistent_hasing_ring = ConsistentHashing::new(&nodes[..count].to_vec(), Some(40));
+            assert_eq!(consistent_hasing_ring.sorted_keys().len(), 120 * count);
+            let mut reference: Vec<u32> = consistent_hasing_ring.points.iter().map(|point| point.0).collect();
+            reference.sort();
+            assert_eq!(consistent_hasing_ring.sorted_keys()[..], reference[..]);
+            for i in 0..200 {
+                let key = format!("key-{}", i);
+                let hash = hash_key(&key);
+                let point = reference.iter().find(|point| **point > hash).unwrap_or(&reference[0]);
+                assert_eq!(consistent_hasing_ring.get_node(&key).unwrap().node_name, consistent_hasing_ring.owner_of(*point).node_name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_weighted_factor() {
+        assert_eq!(weighted_factor(40, 3, 2, 4), 60);
+        assert_eq!(weighted_factor(40, 3, 2, 0), 0);
+        //the product is 6e9, past u32::MAX, but the factor fits
+        assert_eq!(weighted_factor(100_000, 3, 20_000, 60_000), 100_000);
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(weighted_factor(u32::MAX as usize, u32::MAX as usize, 2, u32::MAX as usize), 2 * u32::MAX as usize);
+        #[cfg(target_pointer_width = "32")]
+        assert_eq!(weighted_factor(u32::MAX as usize, u32::MAX as usize, 2, u32::MAX as usize), usize::MAX);
+        //a factor that doesn't fit saturates instead of wrapping
+        assert_eq!(weighted_factor(usize::MAX, 3, 2, 3), usize::MAX);
+        assert_eq!(weighted_factor(usize::MAX, usize::MAX, usize::MAX, 1), usize::MAX);
+
+        let nodes: Vec<NodeInfoWithWeight> = vec![NodeInfoWithWeight::new("192.168.0.101:11212", 1)];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        consistent_hasing_ring.replica_factor = usize::MAX;
+        assert_eq!(consistent_hasing_ring.factor_of(&nodes[0], 1), usize::MAX);
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let pairs: Vec<(String, usize)> = (0..50).map(|i| (format!("10.0.0.{}:11211", i), 1)).collect();
+        let borrowed: Vec<(&str, usize)> = pairs.iter().map(|(name, weight)| (name.as_str(), *weight)).collect();
+        let mut consistent_hasing_ring = ConsistentHashing::from_weighted(&borrowed, Some(40));
+
+        for (name, _) in &pairs[2..] {
+            assert!(!consistent_hasing_ring.remove_node_by_name(name).is_empty());
+        }
+        let before = consistent_hasing_ring.memory_footprint();
+        assert!(consistent_hasing_ring.points.as_vec().capacity() > 10 * consistent_hasing_ring.points.len());
+
+        consistent_hasing_ring.shrink_to_fit();
+        assert!(consistent_hasing_ring.memory_footprint() < before / 4);
+        #[cfg(not(feature = "smallvec"))]
+        assert_eq!(consistent_hasing_ring.points.as_vec().capacity(), consistent_hasing_ring.points.len());
+        //80 points fit inline
+        #[cfg(feature = "smallvec")]
+        assert!(!consistent_hasing_ring.points.as_vec().spilled());
+        assert_eq!(consistent_hasing_ring.owners.capacity(), consistent_hasing_ring.owners.len());
+        assert!(consistent_hasing_ring.real_nodes.capacity() <= 4 * consistent_hasing_ring.real_nodes.len());
+        assert!(consistent_hasing_ring.get_node("my_key").is_some());
+    }
+
+    #[test]
+    fn test_dump_ranges_tile_keyspace() {
+        let nodes: Vec<NodeInfo> = (0..5).map(|i| NodeInfo::new(format!("192.168.0.{}:11212", 101 + i))).collect();
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let ranges = consistent_hasing_ring.dump_ranges();
+        assert_eq!(ranges.len(), consistent_hasing_ring.virtual_node_count());
+        assert_eq!(ranges.iter().map(|range| range.len()).sum::<u64>(), 1u64 << 32);
+        assert_eq!(ranges.iter().filter(|range| range.is_wraparound).count(), 1);
+        //each arc starts where the previous one ended
+        for (previous, range) in ranges.iter().zip(ranges.iter().skip(1)) {
+            assert_eq!(range.start_token, previous.end_token);
+        }
+        assert_eq!(ranges[0].start_token, ranges[ranges.len() - 1].end_token);
+        let owner_of = |hash: u32| &consistent_hasing_ring.owner_at(consistent_hasing_ring.pos_for_hash(hash)).node_name;
+        for range in &ranges {
+            assert_eq!(owner_of(range.start_token), &range.node_name);
+            assert_eq!(owner_of(range.end_token.wrapping_sub(1)), &range.node_name);
+        }
+
+        #[cfg(feature = "serde")]
+        {
+            let parsed: Vec<RangeOwnership> = serde_json::from_str(&consistent_hasing_ring.dump_json()).unwrap();
+            assert_eq!(parsed, ranges);
+        }
+    }
+
+    #[test]
+    fn test_byte_order() {
+        let digest = hash_digest("192.168.0.101:11212-0");
+        assert_eq!(ByteOrder::LittleEndian.read(&digest, 4), u32::from_le_bytes([digest[4], digest[5], digest[6], digest[7]]));
+        assert_eq!(ByteOrder::BigEndian.read(&digest, 4), u32::from_be_bytes([digest[4], digest[5], digest[6], digest[7]]));
+        assert_ne!(ByteOrder::LittleEndian.read(&digest, 0), ByteOrder::BigEndian.read(&digest, 0));
+        assert_eq!(ByteOrder::LittleEndian.read(&digest, 0), hash_key("192.168.0.101:11212-0"));
+
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+        ];
+        let little = ConsistentHashing::new(&nodes, Some(40));
+        let big = ConsistentHashing::builder(&nodes).byte_order(ByteOrder::BigEndian).build();
+        assert_eq!(big.sorted_keys().len(), little.sorted_keys().len());
+        let mut swapped: Vec<u32> = little.sorted_keys().iter().map(|key| key.swap_bytes()).collect();
+        swapped.sort();
+        assert_eq!(big.sorted_keys()[..], swapped[..]);
+        //keys are read the same way as the points
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(big.key_hash(&key), hash_key(&key).swap_bytes());
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let nodes: Vec<NodeInfo> = (0..5).map(|i| NodeInfo::new(format!("192.168.0.{}:11212", 101 + i))).collect();
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            let result = consistent_hasing_ring.lookup(&key).unwrap();
+            assert_eq!(result.primary.node_name, consistent_hasing_ring.get_node(&key).unwrap().node_name);
+            let mut all: Vec<String> = result.fallbacks.iter().map(|node| node.node_name.clone()).collect();
+            assert_eq!(all, consistent_hasing_ring.get_nodes(&key, 5)[1..].iter().map(|node| node.node_name.clone()).collect::<Vec<String>>());
+            all.push(result.primary.node_name);
+            all.sort();
+            all.dedup();
+            assert_eq!(all.len(), 5);
+        }
+
+        consistent_hasing_ring.set_node_down("192.168.0.101:11212");
+        let result = consistent_hasing_ring.lookup("my_key").unwrap();
+        assert_eq!(result.fallbacks.len(), 3);
+        assert!(result.fallbacks.iter().chain(Some(&result.primary)).all(|node| node.node_name != "192.168.0.101:11212"));
+
+        let single = ConsistentHashing::single(NodeInfo::new("192.168.0.101:11212"));
+        assert!(single.lookup("my_key").unwrap().fallbacks.is_empty());
+        let empty: ConsistentHashing<NodeInfo> = ConsistentHashing::new(&vec![], None);
+        assert!(empty.lookup("my_key").is_none());
+    }
+
+    #[test]
+    fn test_get_two_nodes() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            let (primary, backup) = consistent_hasing_ring.get_two_nodes(&key).unwrap();
+            assert_eq!(primary.node_name, consistent_hasing_ring.get_node(&key).unwrap().node_name);
+            assert_ne!(primary.node_name, backup.node_name);
+        }
+
+        let single = ConsistentHashing::new(&vec![NodeInfo::new("192.168.0.101:11212")], Some(40));
+        assert!(single.get_two_nodes("my_key").is_none());
+    }
+
+    #[test]
+    fn test_nearest_n_with_distance() {
+        let nodes: Vec<NodeInfo> = (0..5).map(|i| NodeInfo::new(format!("192.168.0.{}:11212", 101 + i))).collect();
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            let nearest = consistent_hasing_ring.nearest_n_with_distance(&key, 3);
+            assert_eq!(nearest.len(), 3);
+            assert_eq!(nearest[0].0.node_name, consistent_hasing_ring.get_node(&key).unwrap().node_name);
+            let owner_point = consistent_hasing_ring.pos_for_hash(hash_key(&key)).0;
+            assert_eq!(nearest[0].1, owner_point.wrapping_sub(hash_key(&key)));
+            assert!(nearest.windows(2).all(|pair| pair[0].1 <= pair[1].1), "{}", key);
+            let names: Vec<String> = nearest.into_iter().map(|(node, _)| node.node_name).collect();
+            let expected: Vec<String> = consistent_hasing_ring.get_nodes(&key, 3).into_iter().map(|node| node.node_name).collect();
+            assert_eq!(names, expected);
+        }
+
+        //every node at most once however many are asked for, down nodes left out
+        assert_eq!(consistent_hasing_ring.nearest_n_with_distance("my_key", 10).len(), 5);
+        consistent_hasing_ring.set_node_down("192.168.0.101:11212");
+        let nearest = consistent_hasing_ring.nearest_n_with_distance("my_key", 10);
+        assert_eq!(nearest.len(), 4);
+        assert!(nearest.iter().all(|(node, _)| node.node_name != "192.168.0.101:11212"));
+        assert!(nearest.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+
+        //a key hashing right onto a point is a full turn away from it
+        let mut on_point: ConsistentHashing<NodeInfo> = ConsistentHashing::new(&vec![], None);
+        let index = on_point.register(Arc::new(NodeInfo::new("192.168.0.101:11212")));
+        on_point.points.push((hash_key("my_key"), index), name_order(&on_point.owners));
+        assert_eq!(on_point.nearest_n_with_distance("my_key", 1)[0].1, u32::MAX);
+
+        let empty: ConsistentHashing<NodeInfo> = ConsistentHashing::new(&vec![], None);
+        assert!(empty.nearest_n_with_distance("my_key", 3).is_empty());
+    }
+
+    #[test]
+    fn test_read_write_sets_overlap() {
+        let nodes: Vec<NodeInfo> = (0..6).map(|i| NodeInfo::new(format!("192.168.0.{}:11212", 101 + i))).collect();
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let n = 5;
+        for down in [None, Some("192.168.0.103:11212")] {
+            if let Some(name) = down {
+                consistent_hasing_ring.set_node_down(name);
+            }
+            for i in 0..100 {
+                let key = format!("key-{}", i);
+                let preference_list: Vec<String> = consistent_hasing_ring.preference_list(&key, n).into_iter().map(|node| node.node_name).collect();
+                assert_eq!(preference_list.len(), n);
+                for w in 1..=n {
+                    for r in (n + 1 - w)..=n {
+                        let write_set: Vec<String> = consistent_hasing_ring.write_set(&key, w).into_iter().map(|node| node.node_name).collect();
+                        let read_set: Vec<String> = consistent_hasing_ring.read_set(&key, r).into_iter().map(|node| node.node_name).collect();
+                        assert_eq!(write_set[..], preference_list[..w]);
+                        assert_eq!(read_set[..], preference_list[..r]);
+                        let overlap = write_set.iter().filter(|name| read_set.contains(name)).count();
+                        assert!(overlap >= w + r - n, "w={} r={} overlap={}", w, r, overlap);
+                    }
+                }
+            }
+        }
+    }
+
+    //parses what as_json writes back into (key, node) pairs, checking the syntax
+    fn parse_layout(json: &str) -> Vec<(u32, String)> {
+        let mut chars = json.chars().peekable();
+        let expect = |chars: &mut std::iter::Peekable<std::str::Chars>, literal: &str| {
+            for c in literal.chars() {
+                assert_eq!(chars.next(), Some(c));
+            }
+        };
+        let mut layout = Vec::new();
+        expect(&mut chars, "[");
+        while chars.peek() != Some(&']') {
+            if !layout.is_empty() {
+                expect(&mut chars, ",");
+            }
+            expect(&mut chars, "{\"key\":");
+            let mut key = String::new();
+            while chars.peek().is_some_and(|c| c.is_ascii_digit()) {
+                key.push(chars.next().unwrap());
+            }
+            expect(&mut chars, ",\"node\":\"");
+            let mut node = String::new();
+            loop {
+                match chars.next().unwrap() {
+                    '"' => break,
+                    '\\' => match chars.next().unwrap() {
+                        'n' => node.push('\n'),
+                        'u' => {
+                            let hex: String = (0..4).map(|_| chars.next().unwrap()).collect();
+                            node.push(std::char::from_u32(u32::from_str_radix(&hex, 16).unwrap()).unwrap());
+                        }
+                        escaped => node.push(escaped),
+                    },
+                    c => node.push(c),
+                }
+            }
+            expect(&mut chars, "}");
+            layout.push((key.parse().unwrap(), node));
+        }
+        expect(&mut chars, "]");
+        assert!(chars.next().is_none());
+        layout
+    }
+
+    #[derive(Clone)]
+    struct CacheNode {
+        host: String,
+        memory_mb: usize,
+    }
+
+    impl RingNode for CacheNode {
+        fn ring_id(&self) -> &str {
+            &self.host
+        }
+    }
+
+    impl WithWeightInfo for CacheNode {
+        fn get_weight(&self) -> usize {
+            self.memory_mb / 128
+        }
+    }
+
+    #[derive(Clone)]
+    struct ArchiveNode {
+        bucket: String,
+    }
+
+    impl RingNode for ArchiveNode {
+        fn ring_id(&self) -> &str {
+            &self.bucket
+        }
+    }
+
+    impl WithWeightInfo for ArchiveNode {
+        fn get_weight(&self) -> usize {
+            1
+        }
+    }
+
+    #[test]
+    fn test_mixed_node_types() {
+        let nodes: Vec<Box<dyn DynNode>> = vec![
+            Box::new(CacheNode { host: "192.168.0.101:11212".to_string(), memory_mb: 256 }),
+            Box::new(ArchiveNode { bucket: "archive-1".to_string() }),
+            Box::new(CacheNode { host: "192.168.0.102:11212".to_string(), memory_mb: 128 }),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let named = ConsistentHashing::new(&vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 2),
+            NodeInfoWithWeight::new("archive-1", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 1),
+        ], Some(40));
+        assert_eq!(consistent_hasing_ring.sorted_keys(), named.sorted_keys());
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(consistent_hasing_ring.get_node(&key).unwrap().ring_id(), named.get_node(&key).unwrap().node_name);
+        }
+
+        consistent_hasing_ring.add_node(Box::new(ArchiveNode { bucket: "archive-2".to_string() }));
+        assert!(consistent_hasing_ring.contains_node("archive-2"));
+        assert!(!consistent_hasing_ring.remove_node_by_name("192.168.0.101:11212").is_empty());
+        assert_eq!(consistent_hasing_ring.nodes_in_order().iter().map(|node| node.ring_id()).collect::<Vec<&str>>(),
+                   vec!["192.168.0.102:11212", "archive-1", "archive-2"]);
+    }
+
+    #[derive(Clone, Debug)]
+    struct SessionNode {
+        name: String,
+        capacity: usize,
+    }
+
+    impl RingNode for SessionNode {
+        fn ring_id(&self) -> &str {
+            &self.name
+        }
+    }
+
+    impl WithWeightInfo for SessionNode {
+        fn get_weight(&self) -> usize {
+            1
+        }
+    }
+
+    impl Hash for SessionNode {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.name.hash(state);
+        }
+    }
+
+    impl WithCapacity for SessionNode {
+        fn capacity(&self) -> usize {
+            self.capacity
+        }
+    }
+
+    fn snapshot_ring() -> ConsistentHashing<NodeInfoWithWeight> {
+        let nodes: Vec<NodeInfoWithWeight> = (0..5).map(|i| NodeInfoWithWeight::new(format!("192.168.0.{}:11212", 101 + i), 1 + i % 2)).collect();
+        let mut consistent_hasing_ring = ConsistentHashing::builder(&nodes)
+            .label_format(|node, _, index| format!("{}#{}", node, index))
+            .bounded_load(0.25)
+            .build();
+        consistent_hasing_ring.drain_node("192.168.0.105:11212");
+        consistent_hasing_ring
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let consistent_hasing_ring = snapshot_ring();
+        let snapshot = consistent_hasing_ring.to_snapshot();
+        let mut restored = ConsistentHashing::from_snapshot(&snapshot).unwrap();
+        assert_eq!(restored.sorted_keys(), consistent_hasing_ring.sorted_keys());
+        assert_eq!(restored.interleave_count, 40);
+        assert_eq!(restored.load_epsilon, Some(0.25));
+        assert_eq!(restored.total_weight(), consistent_hasing_ring.total_weight());
+        for i in 0..500 {
+            let key = format!("key-{}", i);
+            assert_eq!(restored.get_node(&key).unwrap().node_name, consistent_hasing_ring.get_node(&key).unwrap().node_name);
+        }
+        assert_eq!(restored.to_snapshot(), snapshot);
+
+        restored.undrain_node("192.168.0.105:11212");
+        assert_eq!(restored.points_for_node_by_name("192.168.0.105:11212").len(), consistent_hasing_ring.factors["192.168.0.105:11212"] * 3);
+
+        let double = ConsistentHashing::builder(&[NodeInfoWithWeight::new("192.168.0.101:11212", 1)])
+            .point_scheme(PointScheme::DoubleHashing)
+            .byte_order(ByteOrder::BigEndian)
+            .probes(3)
+            .build();
+        let restored = ConsistentHashing::from_snapshot(&double.to_snapshot()).unwrap();
+        assert_eq!(restored.point_scheme, PointScheme::DoubleHashing);
+        assert_eq!(restored.byte_order, ByteOrder::BigEndian);
+        assert_eq!(restored.probes, 3);
+        let empty: ConsistentHashing<NodeInfoWithWeight> = ConsistentHashing::new(&vec![], None);
+        assert!(ConsistentHashing::from_snapshot(&empty.to_snapshot()).unwrap().get_node("my_key").is_none());
+
+        //a seed takes version 2 and points_per_weight version 3
+        let per_weight = ConsistentHashing::builder(&[NodeInfoWithWeight::new("192.168.0.101:11212", 2)]).points_per_weight(10).build();
+        let per_weight_snapshot = per_weight.to_snapshot();
+        assert_eq!(per_weight_snapshot[4], 3);
+        let mut restored = ConsistentHashing::from_snapshot(&per_weight_snapshot).unwrap();
+        assert_eq!(restored.points_per_weight, Some(10));
+        assert_eq!(restored.sorted_keys(), per_weight.sorted_keys());
+        restored.add_node(NodeInfoWithWeight::new("192.168.0.102:11212", 1));
+        assert_eq!(restored.factors["192.168.0.102:11212"], 10);
+        assert_eq!(snapshot[4], 1);
+        let seeded = ConsistentHashing::builder(&[NodeInfoWithWeight::new("192.168.0.101:11212", 1), NodeInfoWithWeight::new("192.168.0.102:11212", 1)])
+            .seed("ring-b")
+            .build();
+        let seeded_snapshot = seeded.to_snapshot();
+        assert_eq!(seeded_snapshot[4], 2);
+        let restored = ConsistentHashing::from_snapshot(&seeded_snapshot).unwrap();
+        assert_eq!(restored.seed, "ring-b");
+        for i in 0..500 {
+            let key = format!("key-{}", i);
+            assert_eq!(restored.get_node(&key).unwrap().node_name, seeded.get_node(&key).unwrap().node_name);
+        }
+        for len in 0..seeded_snapshot.len() {
+            assert!(ConsistentHashing::from_snapshot(&seeded_snapshot[..len]).is_err(), "{}", len);
+        }
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_vec(&consistent_hasing_ring).unwrap();
+            assert!(snapshot.len() * 3 < json.len(), "{} points: snapshot {} bytes, serde_json {} bytes", consistent_hasing_ring.virtual_node_count(), snapshot.len(), json.len());
+        }
+    }
+
+    #[test]
+    fn test_snapshot_malformed() {
+        let snapshot = snapshot_ring().to_snapshot();
+        for len in 0..snapshot.len() {
+            assert!(ConsistentHashing::from_snapshot(&snapshot[..len]).is_err(), "{}", len);
+        }
+        assert_eq!(ConsistentHashing::from_snapshot(&snapshot[..20]).err(), Some(SnapshotError::Truncated));
+        assert_eq!(ConsistentHashing::from_snapshot(b"CHR").err(), Some(SnapshotError::Truncated));
+        assert_eq!(ConsistentHashing::from_snapshot(b"JSON{}").err(), Some(SnapshotError::BadMagic));
+
+        let edit = |pos: usize, byte: u8| {
+            let mut bytes = snapshot.clone();
+            bytes[pos] = byte;
+            ConsistentHashing::from_snapshot(&bytes).err().map(|err| err.to_string())
         };
-        let mut new_consitent_hashing = ConsistentHashing {
-            hashing_ring: HashMap::new(),
-            real_nodes: HashMap::new(),
-            sorted_keys: Vec::new(),
-            interleave_count,
-            total_weight: 0,
+        assert_eq!(edit(4, 4).unwrap(), "unsupported ring snapshot version 4");
+        assert_eq!(edit(4, 0).unwrap(), "unsupported ring snapshot version 0");
+        //header: magic, version, 40, 1, 1, scheme, byte order, bounded load flag and epsilon
+        assert_eq!(edit(8, 7).unwrap(), "invalid ring snapshot: unknown point scheme 7");
+        assert_eq!(edit(9, 3).unwrap(), "invalid ring snapshot: unknown byte order 3");
+        assert_eq!(edit(10, 2).unwrap(), "invalid ring snapshot: bad bounded load flag 2");
+        assert_eq!(edit(7, 0).unwrap(), "invalid ring snapshot: need at least one probe");
+        //the first node's name
+        assert_eq!(edit(21, 0xff).unwrap(), "invalid ring snapshot: node name is not UTF-8");
+
+        let mut trailing = snapshot.clone();
+        trailing.push(0);
+        assert_eq!(ConsistentHashing::from_snapshot(&trailing).err().unwrap().to_string(), "invalid ring snapshot: 1 trailing bytes");
+
+        //a last point referring to a node past the table
+        let mut bad_index = snapshot.clone();
+        *bad_index.last_mut().unwrap() = 9;
+        assert!(ConsistentHashing::from_snapshot(&bad_index).err().unwrap().to_string().contains("refers to node #9 of 5"));
+
+        let mut overflow = snapshot[..5].to_vec();
+        overflow.extend_from_slice(&[0xff; 11]);
+        assert_eq!(ConsistentHashing::from_snapshot(&overflow).err().unwrap().to_string(), "invalid ring snapshot: varint overflows 64 bits");
+
+        //one node "a", then the point deltas 1 and u64::MAX, whose sum overflows
+        let mut huge_delta = b"CHRS".to_vec();
+        huge_delta.extend_from_slice(&[1, 40, 1, 1, 0, 0, 0, 1, 1, b'a', 1, 0, 1, 2, 1, 0]);
+        huge_delta.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01, 0]);
+        assert_eq!(ConsistentHashing::from_snapshot(&huge_delta).err().unwrap().to_string(),
+            format!("invalid ring snapshot: ring key 1 + {} is out of range", u64::MAX));
+    }
+
+    #[test]
+    fn test_total_weight_tracks_nodes() {
+        let sum = |ring: &ConsistentHashing<NodeInfoWithWeight>| -> usize {
+            ring.nodes_in_order().iter().map(|node| node.weight).sum()
         };
+        let nodes = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, None);
+        assert_eq!(consistent_hasing_ring.total_weight(), 3);
+        assert_eq!(consistent_hasing_ring.weight_of("192.168.0.102:11212"), Some(2));
+        assert_eq!(consistent_hasing_ring.weight_of("192.168.0.199:11212"), None);
 
-        new_consitent_hashing.generate_hashing_ring(real_nodes);
-        return new_consitent_hashing;
+        consistent_hasing_ring.add_node(NodeInfoWithWeight::new("192.168.0.103:11212", 4));
+        assert_eq!(consistent_hasing_ring.total_weight(), sum(&consistent_hasing_ring));
+        consistent_hasing_ring.add_node(NodeInfoWithWeight::new("192.168.0.103:11212", 8));
+        assert_eq!(consistent_hasing_ring.total_weight(), sum(&consistent_hasing_ring));
+        consistent_hasing_ring.update_weight_by_name("192.168.0.101:11212", 5);
+        assert_eq!(consistent_hasing_ring.weight_of("192.168.0.101:11212"), Some(5));
+        assert_eq!(consistent_hasing_ring.total_weight(), sum(&consistent_hasing_ring));
+        consistent_hasing_ring.drain_node("192.168.0.102:11212");
+        assert_eq!(consistent_hasing_ring.weight_of("192.168.0.102:11212"), Some(2));
+        assert_eq!(consistent_hasing_ring.total_weight(), sum(&consistent_hasing_ring));
+        consistent_hasing_ring.remove_node_by_name("192.168.0.102:11212");
+        consistent_hasing_ring.remove_node_by_name("192.168.0.102:11212");
+        assert_eq!(consistent_hasing_ring.total_weight(), sum(&consistent_hasing_ring));
+        consistent_hasing_ring.extend(vec![NodeInfoWithWeight::new("192.168.0.104:11212", 3), NodeInfoWithWeight::new("192.168.0.101:11212", 9)]);
+        assert_eq!(consistent_hasing_ring.total_weight(), sum(&consistent_hasing_ring));
+        assert_eq!(consistent_hasing_ring.total_weight(), 12);
     }
 
-    fn generate_hashing_ring(&mut self, real_nodes: &Vec<T>) {
-        ///Generates the ring.
-        ///
-        //real nodes number
-        let nodes_num = real_nodes.len();
-        //calculate total weight
-        let mut total_weight: usize = 0;
-        for i in 0..nodes_num {
-            total_weight += real_nodes[i].get_weight();
+    #[test]
+    fn test_inconsistent_ring_returns_none() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        //points without owners
+        consistent_hasing_ring.owners.clear();
+        assert!(consistent_hasing_ring.get_node("my_key").is_none());
+        assert!(consistent_hasing_ring.get_node_raw("my_key").is_none());
+        let mut name = String::new();
+        assert!(!consistent_hasing_ring.node_name_into("my_key", &mut name));
+        assert!(consistent_hasing_ring.node_at((0, u32::MAX)).is_none());
+    }
+
+    #[test]
+    fn test_get_node_with_occupancy() {
+        let nodes: Vec<SessionNode> = (0..3)
+            .map(|i| SessionNode { name: format!("192.168.0.{}:11212", 101 + i), capacity: 10 })
+            .collect();
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let saturated = "192.168.0.102:11212";
+        let occupancy = |node: &SessionNode| if node.name == saturated { 10 } else { 3 };
+        let empty = |_: &SessionNode| 0;
+        let mut spilled = 0;
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            let owner = consistent_hasing_ring.get_node(&key).unwrap();
+            assert_eq!(consistent_hasing_ring.get_node_with_occupancy(&key, &empty).unwrap().name, owner.name);
+
+            let chain = consistent_hasing_ring.overflow_chain_with_occupancy(&key, &occupancy);
+            let accepted = chain.accepted.unwrap();
+            assert_eq!(consistent_hasing_ring.get_node_with_occupancy(&key, &occupancy).unwrap().name, accepted.name);
+            if owner.name == saturated {
+                //spills over to the next distinct node clockwise
+                spilled += 1;
+                assert_eq!(chain.full.iter().map(|node| node.name.as_str()).collect::<Vec<&str>>(), vec![saturated]);
+                assert_eq!(accepted.name, consistent_hasing_ring.get_nodes(&key, 2)[1].name);
+            } else {
+                assert!(chain.full.is_empty());
+                assert_eq!(accepted.name, owner.name);
+            }
         }
-        self.total_weight = total_weight;
+        assert!(spilled > 0);
 
-        for i in 0..nodes_num {
-            let node_entity = &real_nodes[i];
-            //save real node
-            self.real_nodes.insert(node_entity.to_string(), node_entity.clone());
+        let full = |_: &SessionNode| 10;
+        let chain = consistent_hasing_ring.overflow_chain_with_occupancy("my_key", &full);
+        assert!(chain.accepted.is_none());
+        assert_eq!(chain.full.len(), 3);
+        assert!(consistent_hasing_ring.get_node_with_occupancy("my_key", &full).is_none());
+    }
+
+    #[test]
+    fn test_rebalance_cost() {
+        let nodes: Vec<NodeInfo> = (0..5).map(|i| NodeInfo::new(format!("192.168.0.{}:11212", 101 + i))).collect();
+        let old = ConsistentHashing::new(&nodes, Some(160));
+        let sample: Vec<String> = (0..10_000).map(|i| format!("key-{}", i)).collect();
+        assert_eq!(rebalance_cost(&old, &old, &sample), 0.0);
+        assert_eq!(rebalance_cost(&old, &old, &[]), 0.0);
+
+        let mut new = ConsistentHashing::new(&nodes, Some(160));
+        new.remove_node_by_name("192.168.0.103:11212");
+        let cost = rebalance_cost(&old, &new, &sample);
+        assert!((cost - 0.2).abs() < 0.05, "{}", cost);
+
+        let empty: ConsistentHashing<NodeInfo> = ConsistentHashing::new(&vec![], None);
+        assert_eq!(rebalance_cost(&old, &empty, &sample), 1.0);
+    }
+
+    #[test]
+    fn test_gradual_drain() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let original = consistent_hasing_ring.sorted_keys().clone();
+        let keys: Vec<String> = (0..2000).map(|i| format!("key-{}", i)).collect();
+        let draining = "192.168.0.102:11212";
+        assert!(consistent_hasing_ring.drain_progress(draining).is_none());
 
-            let weight = 0;
-            let factor = ((self.interleave_count * nodes_num * weight) / total_weight) as usize;
-            for j in 0..factor {
-                let b_key = hash_digest(&format!("{}-{}", node_entity.to_string(), j));
-                for i in 0..3 {
-                    let key = hash_val(&b_key, Box::new(move |x| x+i*4));
-                    self.hashing_ring.insert(key, node_entity.clone());
-                    self.sorted_keys.push(key);
+        consistent_hasing_ring.start_drain(draining, 4);
+        assert_eq!(consistent_hasing_ring.drain_progress(draining), Some((0, 120)));
+        for step in 1..4 {
+            let before: Vec<(u32, String)> = keys.iter()
+                .map(|key| {
+                    let pos = consistent_hasing_ring.get_node_pos(key).unwrap();
+                    (pos.0, consistent_hasing_ring.get_node(key).unwrap().node_name)
+                })
+                .collect();
+            consistent_hasing_ring.advance_drain(draining);
+            assert_eq!(consistent_hasing_ring.drain_progress(draining), Some((30 * step, 120)));
+            assert_eq!(consistent_hasing_ring.points_for_node_by_name(draining).len(), 120 - 30 * step);
+
+            let mut moved = 0;
+            for (key, (point, owner)) in keys.iter().zip(before) {
+                let point_removed = consistent_hasing_ring.points.get(point).is_none();
+                let now = consistent_hasing_ring.get_node(key).unwrap().node_name;
+                if point_removed {
+                    //the next point may well be one the node still has
+                    assert_eq!(owner, draining);
+                    if now != draining {
+                        moved += 1;
+                    }
+                } else {
+                    assert_eq!(now, owner);
                 }
             }
+            assert!(moved > 0);
         }
-        self.sorted_keys.sort();
 
+        //the highest digest indices went first
+        let kept: Vec<u32> = consistent_hasing_ring.points_for_node_by_name(draining);
+        let mut first_digests = consistent_hasing_ring.digest_points(&nodes[1], 0..10);
+        first_digests.sort();
+        assert_eq!(kept, first_digests);
+
+        consistent_hasing_ring.cancel_drain(draining);
+        assert!(consistent_hasing_ring.drain_progress(draining).is_none());
+        assert_eq!(consistent_hasing_ring.sorted_keys(), original);
+
+        consistent_hasing_ring.start_drain(draining, 2);
+        consistent_hasing_ring.advance_drain(draining);
+        consistent_hasing_ring.advance_drain(draining);
+        assert!(!consistent_hasing_ring.contains_node(draining));
+        assert!(consistent_hasing_ring.drain_progress(draining).is_none());
+        assert_eq!(consistent_hasing_ring.virtual_node_count(), 240);
+    }
+
+    #[test]
+    #[should_panic(expected = "a drain needs at least one step")]
+    fn test_gradual_drain_zero_steps() {
+        let mut consistent_hasing_ring = ConsistentHashing::single(NodeInfo::new("192.168.0.101:11212"));
+        consistent_hasing_ring.start_drain("192.168.0.101:11212", 0);
     }
 
+    #[test]
+    fn test_node_down_and_up() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let keys: Vec<String> = (0..200).map(|i| format!("key-{}", i)).collect();
+        let placement = |ring: &ConsistentHashing<NodeInfo>| -> HashMap<String, String> {
+            keys.iter().map(|key| (key.clone(), ring.get_node(key).unwrap().node_name)).collect()
+        };
+        let before = placement(&consistent_hasing_ring);
+
+        consistent_hasing_ring.set_node_down("192.168.0.102:11212");
+        consistent_hasing_ring.set_node_down("192.168.0.199:11212");
+        assert!(consistent_hasing_ring.is_node_down("192.168.0.102:11212"));
+        assert!(!consistent_hasing_ring.is_node_down("192.168.0.199:11212"));
+        let mut drained = ConsistentHashing::new(&nodes, Some(40));
+        drained.drain_node("192.168.0.102:11212");
+        for key in &keys {
+            let node = consistent_hasing_ring.get_node(key).unwrap();
+            assert_ne!(node.node_name, "192.168.0.102:11212");
+            //the next node clockwise, as if the node's points were gone
+            assert_eq!(node.node_name, drained.get_node(key).unwrap().node_name);
+            assert_eq!(consistent_hasing_ring.get_node_raw(key).unwrap().node_name, before[key]);
+
+            let replicas = consistent_hasing_ring.get_nodes(key, 3);
+            assert_eq!(replicas.len(), 2);
+            assert!(replicas.iter().all(|replica| replica.node_name != "192.168.0.102:11212"));
+        }
 
-    fn get_node(&self, string_key: &String) -> Option<T>{
-        ///Given a string key a corresponding node in the hash ring is returned.
-        ///If the hash ring is empty, `None` is returned.
+        consistent_hasing_ring.set_node_down("192.168.0.101:11212");
+        consistent_hasing_ring.set_node_down("192.168.0.103:11212");
+        assert!(consistent_hasing_ring.get_node("my_key").is_none());
+        assert!(consistent_hasing_ring.get_nodes("my_key", 3).is_empty());
+        assert_eq!(consistent_hasing_ring.get_node_raw("key-0").unwrap().node_name, before["key-0"]);
 
-        let pos = self.get_node_pos(string_key);
-        match pos {
-            Some(pos) => self.hashing_ring[self.sorted_keys[pos]],
-            None => None,
+        for node in &nodes {
+            consistent_hasing_ring.set_node_up(&node.node_name);
         }
+        assert_eq!(placement(&consistent_hasing_ring), before);
     }
 
-    fn get_node_pos(&self, string_key: &String) -> Option<T>{
-        ///Given a string key a corresponding node in the hash ring is returned along with it's position in the ring.
-        ///If the hash ring is empty, (`None`, `None`) is returned.
+    #[test]
+    fn test_as_json() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("cache \"a\"\\1\n\u{1}"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(10));
+        let layout = parse_layout(&consistent_hasing_ring.as_json());
+        assert_eq!(layout.len(), consistent_hasing_ring.virtual_node_count());
+        for ((key, node), sorted_key) in layout.iter().zip(consistent_hasing_ring.sorted_keys().iter()) {
+            assert_eq!(key, sorted_key);
+            assert_eq!(node, &consistent_hasing_ring.owner_of(*key).node_name);
+        }
+        assert!(layout.iter().any(|(_, node)| node == "cache \"a\"\\1\n\u{1}"));
+
+        consistent_hasing_ring.drain_node("192.168.0.101:11212");
+        let layout = parse_layout(&consistent_hasing_ring.as_json());
+        assert_eq!(layout.len(), consistent_hasing_ring.virtual_node_count());
+        assert!(layout.iter().all(|(_, node)| node != "192.168.0.101:11212"));
 
-        if self.hashing_ring.len() <= 0 {
-            return None;
+        let empty: ConsistentHashing<NodeInfo> = ConsistentHashing::new(&vec![], None);
+        assert_eq!(empty.as_json(), "[]");
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_deprecated_weight_alias() {
+        let nodes: Vec<NodeInfoWithWeigth> = vec![
+            NodeInfoWithWeigth::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeigth { node_name: "192.168.0.102:11212".to_string(), weight: 2 },
+        ];
+        let consistent_hasing_ring: ConsistentHashing<NodeInfoWithWeight> = ConsistentHashing::new(&nodes, None);
+        let renamed = ConsistentHashing::new(&vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+        ], None);
+        assert_eq!(consistent_hasing_ring.sorted_keys(), renamed.sorted_keys());
+        assert_eq!(consistent_hasing_ring.get_node("my_key").unwrap().node_name, renamed.get_node("my_key").unwrap().node_name);
+    }
+
+    //variance of the distances between neighbouring points, wrapping around
+    fn arc_variance(sorted_keys: &[u32]) -> f64 {
+        let arcs: Vec<f64> = (0..sorted_keys.len())
+            .map(|i| sorted_keys[(i + 1) % sorted_keys.len()].wrapping_sub(sorted_keys[i]) as f64)
+            .collect();
+        let mean = arcs.iter().sum::<f64>() / arcs.len() as f64;
+        arcs.iter().map(|arc| (arc - mean) * (arc - mean)).sum::<f64>() / arcs.len() as f64
+    }
+
+    #[test]
+    fn test_double_hashing_points() {
+        let nodes: Vec<NodeInfo> = (0..5).map(|i| NodeInfo::new(format!("192.168.0.{}:11212", 101 + i))).collect();
+        let labels = ConsistentHashing::new(&nodes, Some(40));
+        let double = ConsistentHashing::builder(&nodes).point_scheme(PointScheme::DoubleHashing).build();
+        assert_eq!(double.virtual_node_count(), labels.virtual_node_count());
+        let (labels_variance, double_variance) = (arc_variance(&labels.sorted_keys()), arc_variance(&double.sorted_keys()));
+        assert!(double_variance < labels_variance, "{} vs {}", double_variance, labels_variance);
+
+        for node in &nodes {
+            let (start, step) = double_hashing_seeds(&node.node_name);
+            let mut expected: Vec<u32> = (0..120u32).map(|i| start.wrapping_add(i.wrapping_mul(step))).collect();
+            expected.sort();
+            assert_eq!(double.points_for_node_by_name(&node.node_name), expected);
         }
 
-        let key = gen_key(string_key);
-        //https://rust-algo.club/searching/binary_search/index.html
-        let mut pos = bisect_right(&self.sorted_keys, key, None, None);
+        //adding, draining and undraining work the same way
+        let mut grown = ConsistentHashing::builder(&nodes[..4]).point_scheme(PointScheme::DoubleHashing).build();
+        grown.add_node(nodes[4].clone());
+        grown.drain_node("192.168.0.105:11212");
+        grown.undrain_node("192.168.0.105:11212");
+        assert_eq!(grown.points_for_node_by_name("192.168.0.105:11212"), double.points_for_node_by_name("192.168.0.105:11212"));
+    }
 
-        if pos == self.sorted_keys.len() {
-            return 0;
-        }else{
-            return pos;
+    #[test]
+    fn test_on_lookup() {
+        use std::sync::Mutex;
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+        ];
+        let hook_seen = Arc::clone(&seen);
+        let consistent_hasing_ring = ConsistentHashing::builder(&nodes)
+            .on_lookup(move |key, node: Option<&NodeInfo>| {
+                hook_seen.lock().unwrap().push((key.to_string(), node.map(|node| node.node_name.clone())));
+            })
+            .build();
+        let first = consistent_hasing_ring.get_node("my_key").unwrap();
+        let second = consistent_hasing_ring.get_node("key-1").unwrap();
+        assert_eq!(*seen.lock().unwrap(), vec![
+            ("my_key".to_string(), Some(first.node_name)),
+            ("key-1".to_string(), Some(second.node_name)),
+        ]);
+
+        let empty_seen = Arc::new(Mutex::new(Vec::new()));
+        let hook_seen = Arc::clone(&empty_seen);
+        let empty = ConsistentHashing::builder(&[])
+            .on_lookup(move |key, node: Option<&NodeInfo>| hook_seen.lock().unwrap().push((key.to_string(), node.is_some())))
+            .build();
+        assert!(empty.get_node("my_key").is_none());
+        assert_eq!(*empty_seen.lock().unwrap(), vec![("my_key".to_string(), false)]);
+    }
+
+    #[test]
+    fn test_set_interleave_count() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        consistent_hasing_ring.drain_node("192.168.0.103:11212");
+        let before = consistent_hasing_ring.virtual_node_count();
+        let members: Vec<String> = consistent_hasing_ring.nodes_in_order().iter().map(|node| node.node_name.clone()).collect();
+
+        consistent_hasing_ring.set_interleave_count(160);
+        assert!(consistent_hasing_ring.virtual_node_count() > before);
+        let after: Vec<String> = consistent_hasing_ring.nodes_in_order().iter().map(|node| node.node_name.clone()).collect();
+        assert_eq!(after, members);
+        assert!(consistent_hasing_ring.points_for_node_by_name("192.168.0.103:11212").is_empty());
+
+        //same points as a ring built with the new count
+        consistent_hasing_ring.undrain_node("192.168.0.103:11212");
+        let rebuilt = ConsistentHashing::new(&nodes, Some(160));
+        assert_eq!(consistent_hasing_ring.sorted_keys(), rebuilt.sorted_keys());
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(consistent_hasing_ring.get_node(&key).unwrap().node_name, rebuilt.get_node(&key).unwrap().node_name);
+        }
+
+        //setting the count the ring already has changes nothing, colliding points
+        //included, in whatever order the nodes were given
+        let shared = |_: &str, _: usize, index: usize| format!("shared-{}", index);
+        let reversed: Vec<NodeInfoWithWeight> = nodes.iter().rev().cloned().collect();
+        for nodes in [&nodes, &reversed] {
+            let mut colliding = ConsistentHashing::builder(nodes).interleave_count(40).label_format(shared).build();
+            let fingerprint = colliding.fingerprint();
+            colliding.set_interleave_count(40);
+            assert_eq!(colliding.fingerprint(), fingerprint);
+            let mut plain = ConsistentHashing::new(nodes, Some(40));
+            let fingerprint = plain.fingerprint();
+            plain.set_interleave_count(40);
+            assert_eq!(plain.fingerprint(), fingerprint);
         }
     }
 
+    #[test]
+    fn test_rebuild() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::builder(&nodes[..2]).points_per_weight(20).build();
+        consistent_hasing_ring.set_node_down("192.168.0.101:11212");
+        consistent_hasing_ring.set_node_down("192.168.0.102:11212");
+        consistent_hasing_ring.drain_node("192.168.0.102:11212");
+        consistent_hasing_ring.set_load("192.168.0.102:11212", 7);
 
+        let members = vec![nodes[1].clone(), nodes[2].clone()];
+        consistent_hasing_ring.rebuild(&members);
+        let rebuilt = ConsistentHashing::builder(&members).points_per_weight(20).build();
+        assert_eq!(consistent_hasing_ring.fingerprint(), rebuilt.fingerprint());
+        assert_eq!(consistent_hasing_ring.total_weight(), 3);
+        assert!(!consistent_hasing_ring.contains_node("192.168.0.101:11212"));
+        assert!(!consistent_hasing_ring.is_node_down("192.168.0.101:11212"));
+        assert!(consistent_hasing_ring.is_node_down("192.168.0.102:11212"));
+        assert_eq!(consistent_hasing_ring.load_of("192.168.0.102:11212"), 7);
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(consistent_hasing_ring.get_node(&key).unwrap().node_name, "192.168.0.103:11212");
+        }
 
-}
+        consistent_hasing_ring.rebuild(&[]);
+        assert!(consistent_hasing_ring.get_node("my_key").is_none());
+        assert_eq!(consistent_hasing_ring.total_weight(), 0);
+    }
 
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_refresh_from() {
+        use std::future::Future;
+        use std::pin::Pin;
+        use std::task::{Context, Poll, Waker};
 
-fn hashing<DT: Hash>(data: &DT) -> u64 {
-    let mut hasher = DefaultHasher::new();
-    data.hash(&mut hasher);
-    hasher.finish()
-}
+        //a discovery call that isn't ready the first time it's polled
+        struct Discovery(Option<Vec<NodeInfoWithWeight>>, bool);
+        impl Future for Discovery {
+            type Output = Vec<NodeInfoWithWeight>;
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                if !self.1 {
+                    self.1 = true;
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                Poll::Ready(self.0.take().unwrap())
+            }
+        }
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let mut future = Box::pin(future);
+            let mut cx = Context::from_waker(Waker::noop());
+            loop {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+            }
+        }
 
-fn hash_digest(key: &String) -> Vec<u8> {
-    let digest = md5::compute(key);
-    digest.to_vec()
-}
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes[..1].to_vec(), Some(40));
+        let discovered = nodes.clone();
+        block_on(consistent_hasing_ring.refresh_from(|| Discovery(Some(discovered), false)));
+        assert_eq!(consistent_hasing_ring.fingerprint(), ConsistentHashing::new(&nodes, Some(40)).fingerprint());
+        assert!(consistent_hasing_ring.contains_node("192.168.0.102:11212"));
 
-fn hash_val(b_key: &Vec<u8>, entry_fn: Box<dyn Fn(usize) -> usize>) -> u32 {
-    (b_key[entry_fn(3)] as u32) << 24
-        | (b_key[entry_fn(2)] as u32) << 16
-        | (b_key[entry_fn(1)] as u32) << 8
-        | (b_key[entry_fn(0)] as u32)
-}
+        block_on(consistent_hasing_ring.refresh_from(|| async { Vec::new() }));
+        assert!(consistent_hasing_ring.get_node("my_key").is_none());
+    }
 
+    #[test]
+    #[should_panic(expected = "interleave count must be at least 1")]
+    fn test_set_interleave_count_zero() {
+        let mut consistent_hasing_ring = ConsistentHashing::single(NodeInfo::new("192.168.0.101:11212"));
+        consistent_hasing_ring.set_interleave_count(0);
+    }
 
-fn gen_key(string_key: &String) -> u32 {
-    let b_key = hash_digest(string_key);
-    hash_val(&b_key, Box::new(move |x| x))
-}
+    #[test]
+    fn test_get_nodes_cross_zone() {
+        let mut nodes: Vec<NodeInfoWithZone> = Vec::new();
+        for i in 0..9 {
+            let zone = format!("az-{}", i % 3);
+            nodes.push(NodeInfoWithZone::new(format!("192.168.0.{}:11212", 101 + i), zone, 1));
+        }
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        let mut repeats_in_plain_walk = 0;
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            let replicas = consistent_hasing_ring.get_nodes_cross_zone(&key, 3);
+            let zones: HashSet<&str> = replicas.iter().map(|node| node.zone()).collect();
+            assert_eq!(zones.len(), 3, "{}", key);
+            assert_eq!(replicas[0].node_name, consistent_hasing_ring.get_node(&key).unwrap().node_name);
 
+            let plain: HashSet<String> = consistent_hasing_ring.get_nodes(&key, 3).into_iter().map(|node| node.zone).collect();
+            if plain.len() < 3 {
+                repeats_in_plain_walk += 1;
+            }
 
-pub trait WithWeightInfo {
-    fn get_weight(&self) -> usize;
-}
+            //past the zones, the nodes passed over follow in clockwise order
+            let five = consistent_hasing_ring.get_nodes_cross_zone(&key, 5);
+            assert_eq!(five.len(), 5);
+            let clockwise: Vec<String> = consistent_hasing_ring.get_nodes(&key, 9).into_iter()
+                .map(|node| node.node_name)
+                .filter(|name| replicas.iter().all(|replica| &replica.node_name != name))
+                .collect();
+            let rest: Vec<String> = five[3..].iter().map(|node| node.node_name.clone()).collect();
+            assert_eq!(rest, clockwise[..2].to_vec());
 
-#[derive(Clone, Debug)]
-pub struct NodeInfoWithWeigth {
-    pub node_name: &'static str,
-    pub weight: usize,
-}
+            let names: HashSet<String> = consistent_hasing_ring.get_nodes_cross_zone(&key, 20).into_iter().map(|node| node.node_name).collect();
+            assert_eq!(names.len(), 9);
+        }
+        assert!(repeats_in_plain_walk > 0);
 
-impl ToString for NodeInfoWithWeigth {
-    fn to_string(&self) -> String {
-        format!("{}", self.node_name)
+        let empty: ConsistentHashing<NodeInfoWithZone> = ConsistentHashing::new(&vec![], None);
+        assert!(empty.get_nodes_cross_zone("my_key", 3).is_empty());
     }
-}
 
-impl Hash for NodeInfoWithWeigth {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.node_name.hash(state);
+    fn is_ssd(node: &NodeInfoWithTags) -> bool {
+        node.tags().get("ssd").is_some_and(|value| value == "true")
     }
-}
 
-impl WithWeightInfo for NodeInfoWithWeigth {
-    fn get_weight(&self) -> usize {
-        self.weight
+    #[test]
+    fn test_get_node_where() {
+        let nodes = vec![
+            NodeInfoWithTags::new("192.168.0.101:11212", 1).with_tag("ssd", "false"),
+            NodeInfoWithTags::new("192.168.0.102:11212", 1).with_tag("ssd", "true"),
+            NodeInfoWithTags::new("192.168.0.103:11212", 1),
+        ];
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            //matches one node
+            assert_eq!(consistent_hasing_ring.get_node_where(&key, is_ssd).unwrap().node_name, "192.168.0.102:11212");
+            let ssd = consistent_hasing_ring.get_nodes_where(&key, 3, is_ssd);
+            assert_eq!(ssd.len(), 1);
+            assert_eq!(ssd[0].node_name, "192.168.0.102:11212");
+
+            //matches all nodes
+            let owner = consistent_hasing_ring.get_node(&key).unwrap();
+            assert_eq!(consistent_hasing_ring.get_node_where(&key, |_| true).unwrap().node_name, owner.node_name);
+            let all: Vec<String> = consistent_hasing_ring.get_nodes_where(&key, 3, |_| true).iter()
+                .map(|node| node.node_name.clone())
+                .collect();
+            let expected: Vec<String> = consistent_hasing_ring.get_nodes(&key, 3).into_iter()
+                .map(|node| node.node_name)
+                .collect();
+            assert_eq!(all, expected);
+
+            //matches no node
+            assert!(consistent_hasing_ring.get_node_where(&key, |node| node.tags().contains_key("nvme")).is_none());
+            assert!(consistent_hasing_ring.get_nodes_where(&key, 3, |_| false).is_empty());
+        }
+
+        let empty: ConsistentHashing<NodeInfoWithTags> = ConsistentHashing::new(&vec![], None);
+        assert!(empty.get_node_where("my_key", |_| true).is_none());
+        assert!(empty.get_nodes_where("my_key", 3, |_| true).is_empty());
     }
-}
 
+    #[test]
+    fn test_try_get_node() {
+        let nodes = vec![
+            NodeInfoWithTags::new("192.168.0.101:11212", 1).with_tag("ssd", "false"),
+            NodeInfoWithTags::new("192.168.0.102:11212", 1).with_tag("ssd", "true"),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
+        assert_eq!(consistent_hasing_ring.try_get_node("my_key").unwrap().node_name, consistent_hasing_ring.get_node("my_key").unwrap().node_name);
+        assert_eq!(consistent_hasing_ring.try_get_node_where("my_key", is_ssd).unwrap().node_name, "192.168.0.102:11212");
+        assert_eq!(consistent_hasing_ring.try_get_node_where("my_key", |_| false).unwrap_err(), LookupError::NoEligibleNode);
 
-#[derive(Clone, Debug)]
-pub struct NodeInfo {
-    pub node_name: &'static str,
-}
+        consistent_hasing_ring.set_node_down("192.168.0.101:11212");
+        consistent_hasing_ring.set_node_down("192.168.0.102:11212");
+        assert_eq!(consistent_hasing_ring.try_get_node("my_key").unwrap_err(), LookupError::NoEligibleNode);
 
-impl ToString for NodeInfo {
-    fn to_string(&self) -> String {
-        format!("{}", self.node_name)
+        let empty: ConsistentHashing<NodeInfoWithTags> = ConsistentHashing::new(&vec![], None);
+        assert_eq!(empty.try_get_node("my_key").unwrap_err(), LookupError::EmptyRing);
+        assert_eq!(empty.try_get_node_where("my_key", |_| true).unwrap_err(), LookupError::EmptyRing);
+        //nodes whose weights give them no points
+        let weightless = ConsistentHashing::new(&vec![NodeInfoWithWeight::new("192.168.0.101:11212", 0)], None);
+        assert_eq!(weightless.try_get_node("my_key").unwrap_err(), LookupError::EmptyRing);
+        assert_eq!(LookupError::EmptyRing.to_string(), "the ring has no points");
     }
-}
 
-impl Hash for NodeInfo {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.node_name.hash(state);
+    #[test]
+    fn test_single() {
+        let consistent_hasing_ring = ConsistentHashing::single(NodeInfo::new("192.168.0.101:11212"));
+        assert_eq!(consistent_hasing_ring.interleave_count, 40);
+        for key in &["", "a", "my_key", "192.168.0.102:11212", "\u{1F600}"] {
+            assert_eq!(consistent_hasing_ring.get_node(key).unwrap().node_name, "192.168.0.101:11212");
+        }
+        for i in 0..100 {
+            assert_eq!(consistent_hasing_ring.get_node(&format!("key-{}", i)).unwrap().node_name, "192.168.0.101:11212");
+        }
     }
-}
 
-impl WithWeightInfo for NodeInfo {
-    fn get_weight(&self) -> usize {
-        1
+    #[test]
+    fn test_virtual_node_keys_pinned() {
+        //3 little-endian u32s from md5("192.168.0.101:11212-0") and md5("192.168.0.101:11212-39")
+        let consistent_hasing_ring = ConsistentHashing::single(NodeInfo::new("192.168.0.101:11212"));
+        assert_eq!(consistent_hasing_ring.sorted_keys().len(), 120);
+        for key in &[788471788, 3040819032, 1308380120, 3366195521, 1377279007, 3008091292] {
+            assert!(consistent_hasing_ring.sorted_keys().binary_search(key).is_ok());
+            assert_eq!(consistent_hasing_ring.owner_of(*key).node_name, "192.168.0.101:11212");
+        }
     }
-}
 
+    //tokens of a small ring under the point labels' variants, fixed so reworking
+    //how labels are built and hashed can't move a point
+    #[test]
+    fn test_label_tokens_are_pinned() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+        ];
+        let plain = ConsistentHashing::builder(&nodes).interleave_count(2).build();
+        let seeded = ConsistentHashing::builder(&nodes).interleave_count(2).seed("ring-a").build();
+        let big_endian = ConsistentHashing::builder(&nodes).interleave_count(2).byte_order(ByteOrder::BigEndian).build();
+        let custom = ConsistentHashing::builder(&nodes).interleave_count(2).label_format(|node, weight, index| format!("{}:{}:{}", node, weight, index)).build();
+        assert_eq!(plain.sorted_keys(), vec![788471788, 819991693, 1267591839, 1308380120, 1626509365, 1992068749, 2048331023, 3040819032, 3995884840]);
+        assert_eq!(seeded.sorted_keys(), vec![83681718, 766711711, 1365591811, 1542538493, 2198959762, 2286858112, 2961023831, 3998039478, 4023470946]);
+        assert_eq!(big_endian.sorted_keys(), vec![252516218, 677194990, 898691680, 1480015797, 2366955568, 2374941814, 2682948939, 3628858445, 3961519918]);
+        assert_eq!(custom.sorted_keys(), vec![339953483, 374743229, 476241722, 1083041614, 1655357311, 1843338650, 2817015919, 2817572007, 4010182506]);
+    }
 
+    #[test]
+    fn test_colliding_points_are_deduplicated_and_restored() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 1),
+        ];
+        //both nodes' digests come from the same labels, so every point collides
+        let shared = |_: &str, _: usize, index: usize| format!("shared-{}", index);
+        for backend in [Backend::SortedVec, Backend::BTree] {
+            let mut consistent_hasing_ring = ConsistentHashing::builder(&nodes).interleave_count(10).label_format(shared).backend(backend).build();
+            let keys = consistent_hasing_ring.sorted_keys();
+            assert_eq!(keys.len(), 30);
+            assert!(keys.windows(2).all(|pair| pair[0] < pair[1]));
+            assert_eq!(consistent_hasing_ring.virtual_node_count(), keys.len());
+            assert_eq!(consistent_hasing_ring.dump_ranges().len(), keys.len());
+            //the node with the lowest ring_id keeps every token
+            assert_eq!(consistent_hasing_ring.points_for_node_by_name("192.168.0.101:11212"), keys);
+            assert_eq!(consistent_hasing_ring.percent_owned()["192.168.0.101:11212"], 1.0);
 
-#[cfg(test)]
-mod tests {
-    // 注意这个惯用法：在 tests 模块中，从外部作用域导入所有名字。
-    use super::*;
-    use crate::bisect::bisect_right;
+            //a node joining later takes them over only if it sorts first
+            consistent_hasing_ring.add_node(NodeInfoWithWeight::new("192.168.0.103:11212", 1));
+            assert!(consistent_hasing_ring.points_for_node_by_name("192.168.0.103:11212").is_empty());
+            consistent_hasing_ring.add_node(NodeInfoWithWeight::new("192.168.0.100:11212", 1));
+            assert_eq!(consistent_hasing_ring.points_for_node_by_name("192.168.0.100:11212"), keys);
+            assert_eq!(consistent_hasing_ring.sorted_keys(), keys);
+            //the points 100 took over go back to 101, as if 100 had never joined
+            consistent_hasing_ring.remove_node_by_name("192.168.0.100:11212");
+            consistent_hasing_ring.remove_node_by_name("192.168.0.103:11212");
+            let fresh = ConsistentHashing::builder(&nodes).interleave_count(10).label_format(shared).backend(backend).build();
+            assert_eq!(consistent_hasing_ring.dump_ranges(), fresh.dump_ranges());
+            assert_eq!(consistent_hasing_ring.fingerprint(), fresh.fingerprint());
+
+            //and then on to 102
+            consistent_hasing_ring.remove_node_by_name("192.168.0.101:11212");
+            let fresh = ConsistentHashing::builder(&nodes[1..]).interleave_count(10).label_format(shared).backend(backend).build();
+            assert_eq!(consistent_hasing_ring.fingerprint(), fresh.fingerprint());
+            assert_eq!(consistent_hasing_ring.points_for_node_by_name("192.168.0.102:11212"), keys);
+            consistent_hasing_ring.remove_node_by_name("192.168.0.102:11212");
+            assert!(consistent_hasing_ring.get_node("my_key").is_none());
+        }
+    }
 
     #[test]
-    fn test_init() {
-        let mut nodes: Vec<NodeInfo>= Vec::new();
-        nodes.push(NodeInfo{node_name: "192.168.0.101:11212"});
-        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(40));
-        //assert_eq!(add(1, 2), 3);
+    fn test_colliding_points_ignore_insert_order() {
+        let nodes: Vec<NodeInfoWithWeight> = (0..6).map(|i| NodeInfoWithWeight::new(format!("192.168.0.{}:11212", 101 + i), 1)).collect();
+        //every other point collides with the same point of every other node
+        let partly_shared = |node: &str, _: usize, index: usize| {
+            if index.is_multiple_of(2) { format!("shared-{}", index) } else { format!("{}-{}", node, index) }
+        };
+        for backend in [Backend::SortedVec, Backend::BTree] {
+            let build = |nodes: &[NodeInfoWithWeight]| {
+                ConsistentHashing::builder(nodes).interleave_count(20).label_format(partly_shared).backend(backend).build()
+            };
+            let grow = |nodes: &[NodeInfoWithWeight]| {
+                let mut ring = build(&[]);
+                for node in nodes {
+                    ring.add_node(node.clone());
+                }
+                ring
+            };
+            let expected = build(&nodes);
+            for step in 1..nodes.len() {
+                //reversed and rotated
+                let mut shuffled: Vec<NodeInfoWithWeight> = nodes.iter().rev().cloned().collect();
+                shuffled.rotate_left(step);
+                let ring = build(&shuffled);
+                assert_eq!(ring.dump_ranges(), expected.dump_ranges());
+                assert_eq!(ring.fingerprint(), expected.fingerprint());
+
+                //or added one at a time
+                assert_eq!(grow(&shuffled).dump_ranges(), expected.dump_ranges());
+            }
+        }
+    }
+
+    #[test]
+    fn test_label_format() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 128),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 128),
+        ];
+        let default = ConsistentHashing::new(&nodes, None);
+        let explicit = ConsistentHashing::builder(&nodes).label_format(|node, _, index| format!("{}-{}", node, index)).build();
+        assert_eq!(default.sorted_keys(), explicit.sorted_keys());
+
+        //3 little-endian u32s from md5("192.168.0.101:11212#0") and md5("192.168.0.101:11212:128:0")
+        let hashed = ConsistentHashing::builder(&nodes).label_format(|node, _, index| format!("{}#{}", node, index)).build();
+        let weighted = ConsistentHashing::builder(&nodes).label_format(|node, weight, index| format!("{}:{}:{}", node, weight, index)).build();
+        for key in &[186851036, 2476238249, 1452222865] {
+            assert_eq!(hashed.owner_of(*key).node_name, "192.168.0.101:11212");
+        }
+        for key in &[3447703778, 2148168082, 1214956308] {
+            assert_eq!(weighted.owner_of(*key).node_name, "192.168.0.101:11212");
+        }
+        assert_ne!(hashed.sorted_keys(), default.sorted_keys());
+        assert_ne!(hashed.sorted_keys(), weighted.sorted_keys());
+        let moved = (0..1000).map(|i| format!("key-{}", i))
+            .filter(|key| hashed.get_node(key).unwrap().node_name != default.get_node(key).unwrap().node_name)
+            .count();
+        assert!(moved > 0);
+
+        //nodes added later get labels from the same formatter
+        let mut grown = ConsistentHashing::builder(&nodes[..1]).label_format(|node, _, index| format!("{}#{}", node, index)).build();
+        grown.add_node(nodes[1].clone());
+        assert_eq!(grown.sorted_keys(), hashed.sorted_keys());
+        assert!(!grown.remove_node_by_name("192.168.0.102:11212").is_empty());
+        assert!(grown.owned_points().all(|(_, node)| node.node_name == "192.168.0.101:11212"));
+    }
+
+    //testdata/placement.txt, the same vectors on every target
+    #[cfg_attr(not(target_arch = "wasm32"), test)]
+    #[cfg_attr(target_arch = "wasm32", wasm_bindgen_test::wasm_bindgen_test)]
+    fn test_placement_vectors() {
+        fn check(seed: &str, nodes: &[NodeInfoWithWeight], keys: &[(&str, &str)]) {
+            let consistent_hasing_ring = ConsistentHashing::builder(nodes).seed(seed).build();
+            for (key, node) in keys {
+                assert_eq!(consistent_hasing_ring.get_node(key).unwrap().node_name, *node, "{:?} with seed {:?}", key, seed);
+            }
+        }
+
+        let mut rings = 0;
+        let mut seed: Option<&str> = None;
+        let mut nodes = Vec::new();
+        let mut keys = Vec::new();
+        for line in include_str!("../testdata/placement.txt").lines() {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if line == "ring" || line.starts_with("ring ") {
+                if let Some(seed) = seed {
+                    check(seed, &nodes, &keys);
+                    rings += 1;
+                }
+                seed = Some(line.strip_prefix("ring seed=").unwrap_or(""));
+                nodes.clear();
+                keys.clear();
+            } else if let Some(node) = line.strip_prefix("node ") {
+                let (name, weight) = node.rsplit_once(' ').unwrap();
+                nodes.push(NodeInfoWithWeight::new(name, weight.parse().unwrap()));
+            } else {
+                keys.push(line.rsplit_once('\t').unwrap());
+            }
+        }
+        check(seed.unwrap(), &nodes, &keys);
+        rings += 1;
+        assert_eq!(rings, 2);
+    }
+
+    #[test]
+    fn test_seed() {
+        let nodes: Vec<NodeInfo> = (0..5).map(|i| NodeInfo::new(format!("192.168.0.{}:11212", 101 + i))).collect();
+        let unseeded = ConsistentHashing::new(&nodes, None);
+        let empty_seed = ConsistentHashing::builder(&nodes).seed("").build();
+        assert_eq!(empty_seed.sorted_keys(), unseeded.sorted_keys());
+
+        //md5("{seed}{node}-{j}") points and md5("{seed}{key}") keys
+        let a = ConsistentHashing::builder(&nodes).seed("ring-a").build();
+        let b = ConsistentHashing::builder(&nodes).seed("ring-b").build();
+        let digest = hash_digest("ring-a192.168.0.101:11212-0");
+        assert!(a.points_for_node_by_name("192.168.0.101:11212").contains(&ByteOrder::LittleEndian.read(&digest, 0)));
+        assert_eq!(a.key_hash("my_key"), hash_key("ring-amy_key"));
+
+        //the rings agree on a key about as often as chance would have it, 1 in 5
+        let keys = 10_000;
+        let same = (0..keys)
+            .filter(|i| {
+                let key = format!("key-{}", i);
+                a.get_node(&key).unwrap().node_name == b.get_node(&key).unwrap().node_name
+            })
+            .count();
+        assert!(same > keys / 5 - keys / 20 && same < keys / 5 + keys / 20, "{} of {}", same, keys);
+
+        //double hashing seeds its points too
+        let double_a = ConsistentHashing::builder(&nodes).point_scheme(PointScheme::DoubleHashing).seed("ring-a").build();
+        let double_b = ConsistentHashing::builder(&nodes).point_scheme(PointScheme::DoubleHashing).seed("ring-b").build();
+        assert_ne!(double_a.sorted_keys(), double_b.sorted_keys());
+
+        //nodes added later are placed with the seed
+        let mut grown = ConsistentHashing::builder(&nodes[..4]).seed("ring-a").build();
+        grown.add_node(nodes[4].clone());
+        assert_eq!(grown.sorted_keys(), a.sorted_keys());
+    }
+
+    #[test]
+    fn test_to_ketama_compatible() {
+        let nodes: Vec<NodeInfoWithWeight> = vec![
+            NodeInfoWithWeight::new("10.0.1.1:11211", 600),
+            NodeInfoWithWeight::new("10.0.1.2:11211", 300),
+            NodeInfoWithWeight::new("10.0.1.3:11211", 200),
+        ];
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(10));
+        let continuum = consistent_hasing_ring.to_ketama_compatible();
+        assert_eq!(continuum.points(), KetamaContinuum::new(&nodes).points());
+        assert_eq!(continuum.get_node("my_key").unwrap().node_name, "10.0.1.2:11211");
+
+        consistent_hasing_ring.drain_node("10.0.1.2:11211");
+        let continuum = consistent_hasing_ring.to_ketama_compatible();
+        assert_eq!(continuum.points(), KetamaContinuum::new(&[nodes[0].clone(), nodes[2].clone()]).points());
+    }
+
+    #[test]
+    fn test_apportion() {
+        let names = ["a", "b", "c"];
+        assert_eq!(apportion(&names, &[1.0, 1.0, 1.0], 10), Ok(vec![4, 3, 3]));
+        assert_eq!(apportion(&names, &[1.7, 0.25, 1.0], 120), Ok(vec![69, 10, 41]));
+        assert_eq!(apportion(&names, &[0.0, 0.0, 0.0], 120), Ok(vec![0, 0, 0]));
+        assert_eq!(apportion(&names, &[0.0, 2.0, 0.0], 7), Ok(vec![0, 7, 0]));
+        for budget in 0..200 {
+            let counts = apportion(&names, &[1.7, 0.25, 0.333], budget).unwrap();
+            assert_eq!(counts.iter().sum::<usize>(), budget);
+        }
+
+        assert_eq!(apportion(&names, &[1.0, f64::NAN, 1.0], 10), Err(WeightError::NotANumber{node: "b".to_string()}));
+        assert_eq!(apportion(&names, &[f64::INFINITY, 1.0, 1.0], 10), Err(WeightError::Infinite{node: "a".to_string()}));
+        assert_eq!(apportion(&names, &[1.0, 1.0, -0.5], 10), Err(WeightError::Negative{node: "c".to_string(), weight: -0.5}));
+        assert_eq!(WeightError::NotANumber{node: "b".to_string()}.to_string(), "weight of node b is NaN");
+    }
+
+    #[test]
+    fn test_with_f64_weights() {
+        let nodes = vec![
+            NodeInfoWithF64Weight::new("192.168.0.101:11212", 1.7),
+            NodeInfoWithF64Weight::new("192.168.0.102:11212", 0.25),
+            NodeInfoWithF64Weight::new("192.168.0.103:11212", 1.0),
+        ];
+        let consistent_hasing_ring = ConsistentHashing::with_f64_weights(&nodes, Some(400)).unwrap();
+        //the whole 400 * 3 digest budget is used
+        assert_eq!(consistent_hasing_ring.sorted_keys().len(), 1200 * 3);
+        assert_eq!(consistent_hasing_ring.points_for_node_by_name("192.168.0.102:11212").len(), 3 * 102);
+
+        let total = 2.95;
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        let draws = 30_000;
+        for i in 0..draws {
+            let node = consistent_hasing_ring.get_node(&format!("key-{}", i)).unwrap();
+            *counts.entry(node.node_name).or_insert(0) += 1;
+        }
+        for node in nodes.iter() {
+            let share = counts[&node.node_name] as f64 / draws as f64;
+            assert!((share - node.weight / total).abs() < 0.03, "{} got {} for {}", node.node_name, share, node.weight / total);
+        }
+
+        let mut reversed = nodes.clone();
+        reversed.reverse();
+        assert_eq!(ConsistentHashing::with_f64_weights(&reversed, Some(400)).unwrap().sorted_keys(), consistent_hasing_ring.sorted_keys());
+
+        let bad = vec![NodeInfoWithF64Weight::new("192.168.0.101:11212", 1.0), NodeInfoWithF64Weight::new("192.168.0.102:11212", -1.0)];
+        match ConsistentHashing::with_f64_weights(&bad, None) {
+            Err(WeightError::Negative{node, ..}) => assert_eq!(node, "192.168.0.102:11212"),
+            _ => panic!("negative weight accepted"),
+        }
+
+        //integer weights apportion to the same split as new when that split is exact
+        let even = vec![NodeInfoWithWeight::new("192.168.0.101:11212", 2), NodeInfoWithWeight::new("192.168.0.102:11212", 2)];
+        assert_eq!(ConsistentHashing::with_f64_weights(&even, None).unwrap().sorted_keys(), ConsistentHashing::new(&even, None).sorted_keys());
     }
 
+    #[test]
+    fn test_owner_arcs_cover_keyspace() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, Some(2));
+        let mut all: Vec<(u64, u64, &str)> = Vec::new();
+        for node in nodes.iter() {
+            let arcs = consistent_hasing_ring.owner_arcs(&node.node_name);
+            assert!(!arcs.is_empty());
+            let owned: u64 = arcs.iter().map(|&(start, end)| if end == 0 { (1u64 << 32) - start as u64 } else { (end - start) as u64 }).sum();
+            let fraction = consistent_hasing_ring.percent_owned()[&node.node_name];
+            assert!((owned as f64 / (1u64 << 32) as f64 - fraction).abs() < 1e-9);
+            for (start, end) in arcs {
+                let end = if end == 0 { 1u64 << 32 } else { end as u64 };
+                all.push((start as u64, end, &node.node_name));
+            }
+        }
+        all.sort();
+        //contiguous from 0 to 2^32, no gaps and no overlaps
+        assert_eq!(all[0].0, 0);
+        assert_eq!(all[all.len() - 1].1, 1u64 << 32);
+        for pair in all.windows(2) {
+            assert_eq!(pair[0].1, pair[1].0);
+        }
+        for &(start, end, name) in all.iter() {
+            for hash in &[start, (start + end) / 2, end - 1] {
+                let pos = consistent_hasing_ring.pos_for_hash(*hash as u32);
+                assert_eq!(consistent_hasing_ring.owner_at(pos).node_name, name);
+            }
+        }
+        assert!(consistent_hasing_ring.owner_arcs("192.168.0.199:11212").is_empty());
+    }
 
     #[test]
     fn test_besect() {
@@ -213,3 +5796,145 @@ mod tests {
     }
 
 }
+
+
+#[cfg(test)]
+mod successor_tests {
+    use super::*;
+
+    //a ring whose points sit at known positions
+    fn ring_with_keys(keys: &[u32]) -> ConsistentHashing<NodeInfo> {
+        let names = ["a", "b", "c", "d", "e"];
+        let mut ring = ConsistentHashing::new(&vec![], None);
+        for (i, key) in keys.iter().enumerate() {
+            let index = ring.register(Arc::new(NodeInfo::new(names[i % names.len()])));
+            ring.points.push((*key, index), name_order(&ring.owners));
+        }
+        ring.sort_keys();
+        ring
+    }
+
+    #[test]
+    fn test_key_equal_to_ring_key() {
+        let ring = ring_with_keys(&[100, 200, 300]);
+        assert_eq!(ring.pos_for_hash(100).0, 200);
+        assert_eq!(ring.pos_for_hash(200).0, 300);
+    }
+
+    #[test]
+    fn test_key_just_below_ring_key() {
+        let ring = ring_with_keys(&[100, 200, 300]);
+        assert_eq!(ring.pos_for_hash(99).0, 100);
+        assert_eq!(ring.pos_for_hash(199).0, 200);
+        assert_eq!(ring.pos_for_hash(299).0, 300);
+    }
+
+    #[test]
+    fn test_key_just_above_ring_key() {
+        let ring = ring_with_keys(&[100, 200, 300]);
+        assert_eq!(ring.pos_for_hash(101).0, 200);
+        assert_eq!(ring.pos_for_hash(201).0, 300);
+    }
+
+    #[test]
+    fn test_minimum() {
+        let ring = ring_with_keys(&[100, 200, 300]);
+        assert_eq!(ring.pos_for_hash(0).0, 100);
+
+        let ring = ring_with_keys(&[0, 200, 300]);
+        assert_eq!(ring.pos_for_hash(0).0, 200);
+    }
+
+    #[test]
+    fn test_maximum_wraps_around() {
+        let ring = ring_with_keys(&[100, 200, 300]);
+        assert_eq!(ring.pos_for_hash(300).0, 100);
+        assert_eq!(ring.pos_for_hash(301).0, 100);
+        assert_eq!(ring.pos_for_hash(u32::MAX).0, 100);
+
+        let ring = ring_with_keys(&[100, 200, u32::MAX]);
+        assert_eq!(ring.pos_for_hash(u32::MAX - 1).0, u32::MAX);
+        assert_eq!(ring.pos_for_hash(u32::MAX).0, 100);
+    }
+
+    #[test]
+    fn test_single_point_owns_everything() {
+        let ring = ring_with_keys(&[12345]);
+        for key in &[0, 12344, 12345, 12346, u32::MAX] {
+            assert_eq!(ring.pos_for_hash(*key).0, 12345);
+        }
+    }
+
+    #[test]
+    fn test_get_node_wraps_around() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let ring = ConsistentHashing::new(&nodes, Some(2));
+        let last = *ring.sorted_keys().last().unwrap();
+        let first_owner = &ring.owner_at(ring.points.iter().next().unwrap()).node_name;
+        let wrapping: Vec<String> = (0..10_000).map(|i| format!("key-{}", i)).filter(|key| gen_key(key) >= last).collect();
+        assert!(!wrapping.is_empty());
+        for key in &wrapping {
+            assert_eq!(&ring.get_node(key).unwrap().node_name, first_owner);
+        }
+    }
+
+    #[test]
+    fn test_nodes_without_points() {
+        //nodes registered but none of their points, as during a partial mutation
+        let mut ring = ring_with_keys(&[100, 200, 300]);
+        ring.points.clear();
+        assert!(ring.get_node("my_key").is_none());
+        assert!(ring.get_nodes("my_key", 2).is_empty());
+    }
+
+    #[test]
+    fn test_dump_ranges_wrap_around() {
+        let ring = ring_with_keys(&[100, 200, 300]);
+        let ranges = ring.dump_ranges();
+        assert_eq!(ranges[0], RangeOwnership { start_token: 300, end_token: 100, node_name: "a".to_string(), is_wraparound: true });
+        assert_eq!(ranges[0].len(), (1u64 << 32) - 200);
+        assert_eq!(ranges[2], RangeOwnership { start_token: 200, end_token: 300, node_name: "c".to_string(), is_wraparound: false });
+        assert_eq!(ranges.iter().map(|range| range.len()).sum::<u64>(), 1u64 << 32);
+
+        let single = ring_with_keys(&[7]).dump_ranges();
+        assert_eq!(single.len(), 1);
+        assert_eq!(single[0].len(), 1u64 << 32);
+        assert!(ring_with_keys(&[]).dump_ranges().is_empty());
+    }
+
+    #[test]
+    fn test_owner_arcs_wrap_around() {
+        let ring = ring_with_keys(&[100, 200, 300]);
+        assert_eq!(ring.owner_arcs("a"), vec![(0, 100), (300, 0)]);
+        assert_eq!(ring.owner_arcs("b"), vec![(100, 200)]);
+        assert_eq!(ring.owner_arcs("c"), vec![(200, 300)]);
+
+        //adjacent points of one node merge, and a lone point at 0 owns everything
+        let ring = ring_with_keys(&[0]);
+        assert_eq!(ring.owner_arcs("a"), vec![(0, 0)]);
+        let ring = ring_with_keys(&[100, 200, 300, 400, 500, 600]);
+        assert_eq!(ring.owner_arcs("a"), vec![(0, 100), (500, 0)]);
+        let ring = ring_with_keys(&[7]);
+        assert_eq!(ring.owner_arcs("a"), vec![(0, 0)]);
+    }
+
+    #[test]
+    fn test_get_node_follows_successor() {
+        let nodes: Vec<NodeInfo> = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+        ];
+        let ring = ConsistentHashing::new(&nodes, Some(40));
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            let hash = gen_key(&key);
+            let sorted_keys = ring.sorted_keys();
+            let expected = sorted_keys.iter().find(|&&point| point > hash).unwrap_or(&sorted_keys[0]);
+            assert_eq!(ring.get_node(&key).unwrap().node_name, ring.owner_of(*expected).node_name);
+        }
+    }
+}
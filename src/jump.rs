@@ -0,0 +1,114 @@
+use crate::hashing_ring::hash_bytes64;
+
+
+///Jump consistent hash (Lamping & Veach, "A Fast, Minimal Memory, Consistent
+///Hash Algorithm").
+///
+///Maps `key` to a bucket in `[0, buckets)`. Growing the bucket count from N to
+///N+1 moves only ~1/(N+1) of the keys, all of them into the new bucket. Buckets
+///are plain numbers: there are no named nodes and only the last bucket can be
+///removed, which is what makes it so much cheaper than a ring.
+///
+///Panics if `buckets` is 0.
+pub fn jump_hash(key: u64, buckets: u32) -> u32 {
+    assert!(buckets > 0, "jump_hash needs at least one bucket");
+    let mut key = key;
+    let mut b: i64 = -1;
+    let mut j: i64 = 0;
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1i64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as u32
+}
+
+
+///Shards string or byte keys into a fixed number of numbered partitions with
+///`jump_hash`, hashing the key to 64 bits with md5 first.
+pub struct JumpSharder {
+    buckets: u32,
+}
+
+impl JumpSharder {
+    ///Panics if `buckets` is 0.
+    pub fn new(buckets: u32) -> JumpSharder {
+        assert!(buckets > 0, "JumpSharder needs at least one bucket");
+        JumpSharder { buckets }
+    }
+
+    pub fn buckets(&self) -> u32 {
+        self.buckets
+    }
+
+    ///Changes the bucket count. Only keys in (or moving into) the buckets
+    ///being added or dropped change partition.
+    ///
+    ///Panics if `buckets` is 0.
+    pub fn set_buckets(&mut self, buckets: u32) {
+        assert!(buckets > 0, "JumpSharder needs at least one bucket");
+        self.buckets = buckets;
+    }
+
+    pub fn get_bucket(&self, key: &str) -> u32 {
+        self.get_bucket_bytes(key.as_bytes())
+    }
+
+    pub fn get_bucket_bytes(&self, key: &[u8]) -> u32 {
+        jump_hash(hash_bytes64(key), self.buckets)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reference_vectors() {
+        assert_eq!(jump_hash(1, 1), 0);
+        assert_eq!(jump_hash(42, 57), 43);
+        assert_eq!(jump_hash(0xDEAD10CC, 1), 0);
+        assert_eq!(jump_hash(0xDEAD10CC, 666), 361);
+        assert_eq!(jump_hash(256, 1024), 520);
+    }
+
+    #[test]
+    fn test_in_range() {
+        for key in 0..1000u64 {
+            for buckets in 1..20 {
+                assert!(jump_hash(key, buckets) < buckets);
+            }
+        }
+    }
+
+    #[test]
+    fn test_growing_moves_only_new_share() {
+        let keys: Vec<String> = (0..10_000).map(|i| format!("key-{}", i)).collect();
+        let mut sharder = JumpSharder::new(1);
+        let mut before: Vec<u32> = keys.iter().map(|k| sharder.get_bucket(k)).collect();
+
+        for buckets in 2..=16 {
+            sharder.set_buckets(buckets);
+            let after: Vec<u32> = keys.iter().map(|k| sharder.get_bucket(k)).collect();
+            let mut moved = 0;
+            for (old, new) in before.iter().zip(after.iter()) {
+                if old != new {
+                    //keys only ever move into the new bucket
+                    assert_eq!(*new, buckets - 1);
+                    moved += 1;
+                }
+            }
+            let expected = 1.0 / buckets as f64;
+            let actual = moved as f64 / keys.len() as f64;
+            assert!((actual - expected).abs() < 0.02, "{} buckets: {} vs {}", buckets, actual, expected);
+            before = after;
+        }
+    }
+
+    #[test]
+    fn test_bytes_and_str_agree() {
+        let sharder = JumpSharder::new(10);
+        assert_eq!(sharder.get_bucket("my_key"), sharder.get_bucket_bytes(b"my_key"));
+    }
+}
@@ -0,0 +1,457 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use crate::bisect::bisect_left_by_key;
+use crate::hashing_ring::{hash_digest, hash_key, hash_val, RingNode, WithMutableWeight, WithWeightInfo};
+
+
+///Points per server in libketama, before weighting.
+const KETAMA_POINTS_PER_SERVER: f64 = 40.0;
+
+///`MEMCACHED_POINTS_PER_SERVER_KETAMA`, libmemcached's points per server before
+///weighting.
+const LIBMEMCACHED_POINTS_PER_SERVER: f32 = 160.0;
+
+///`MEMCACHED_DEFAULT_PORT`, left out of libmemcached's point labels.
+const LIBMEMCACHED_DEFAULT_PORT: u16 = 11211;
+
+///The port twemproxy leaves out of a server's default name, like libmemcached.
+const TWEMPROXY_DEFAULT_PORT: u16 = 11211;
+
+
+///Which C library's continuum a `KetamaContinuum` reproduces. They agree on the
+///digests (4 little endian points per md5) and on the lookup, but not on how many
+///digests a server gets, what they are taken of or, for twemproxy, how keys hash.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KetamaCompat {
+    ///libketama's `ketama_create_continuum`: `floorf(pct * 40.0 * numservers)`
+    ///digests of `"{server}-{k}"`. What `KetamaContinuum::new` builds.
+    Libketama,
+    ///libmemcached's `update_continuum` with `MEMCACHED_BEHAVIOR_KETAMA_WEIGHTED`,
+    ///as used by PHP's memcached extension: `floor(pct * 160 / 4 * numservers)`
+    ///digests, in the same mix of float and double arithmetic. A digest is taken
+    ///of `"{host}-{k}"` for servers on the default port 11211 and of
+    ///`"{host}:{port}-{k}"` for the others.
+    Libmemcached,
+    ///twemproxy's `ketama_update` for a pool with `distribution: ketama`: the
+    ///libmemcached point count, digests of `"{name}-{k}"` where `name` is the
+    ///`ring_id` verbatim (see `TwemproxyServer`), and keys hashed with the pool's
+    ///`hash`.
+    Twemproxy(TwemproxyHash),
+}
+
+///The twemproxy `hash:` settings `KetamaCompat::Twemproxy` supports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TwemproxyHash {
+    ///`hash: md5`, the first 4 digest bytes little endian, same as `hash_key`.
+    Md5,
+    ///`hash: fnv1a_64`, which despite its name is FNV-1a computed in 32 bits from
+    ///the truncated 64 bit offset basis and prime, with key bytes sign extended.
+    Fnv1a64,
+}
+
+
+///A continuum laid out exactly like libketama's, so keys land on the same server
+///as with the C library (and the PHP/Python clients built on it) for the same
+///server list. The differences to `ConsistentHashing` are all libketama's:
+///
+///* every server gets `floorf(pct * 40.0 * numservers)` digests, `pct` being its
+///  share of the total weight (memory) computed in single precision,
+///* each `md5("{server}-{k}")` digest yields 4 points instead of 3,
+///* a key belongs to the first point at or after its hash, not strictly after.
+///
+///Servers are identified by `ring_id`, which has to be the `"ip:port"` string
+///libketama was configured with. Keys hash to the first 4 digest bytes, little
+///endian, like `ketama_hashi` (and `hash_key`). `with_compat` builds libmemcached's
+///or twemproxy's variant of the continuum instead.
+pub struct KetamaContinuum<T: RingNode + Hash + Clone + WithWeightInfo> {
+    //sorted by point
+    points: Vec<(u32, T)>,
+    compat: KetamaCompat,
+}
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> KetamaContinuum<T> {
+    pub fn new(servers: &[T]) -> KetamaContinuum<T> {
+        KetamaContinuum::with_compat(servers, KetamaCompat::Libketama)
+    }
+
+    ///Builds the continuum the way `compat` says. For `KetamaCompat::Libmemcached`
+    ///a `ring_id` is split at its last `:` into the host and port libmemcached was
+    ///given; one without a numeric port is taken as a host on the default port.
+    pub fn with_compat(servers: &[T], compat: KetamaCompat) -> KetamaContinuum<T> {
+        let total_weight: usize = servers.iter().map(|server| server.get_weight()).sum();
+        let mut points: Vec<(u32, T)> = Vec::new();
+        if total_weight > 0 {
+            for server in servers {
+                let pct = server.get_weight() as f32 / total_weight as f32;
+                let ks = match compat {
+                    //the product is a double in ketama.c, floorf rounds it back to a float
+                    KetamaCompat::Libketama => ((pct as f64 * KETAMA_POINTS_PER_SERVER * servers.len() as f32 as f64) as f32).floor() as usize,
+                    //float arithmetic up to the double nudge, then truncated to a float
+                    //again; twemproxy floors with floorf, to the same result
+                    KetamaCompat::Libmemcached | KetamaCompat::Twemproxy(_) => {
+                        let share = pct * LIBMEMCACHED_POINTS_PER_SERVER / 4.0 * servers.len() as f32;
+                        ((share as f64 + 0.0000000001) as f32 as f64).floor() as usize
+                    }
+                };
+                for k in 0..ks {
+                    let digest = hash_digest(&point_label(server.ring_id(), k, compat));
+                    for h in 0..4 {
+                        points.push((hash_val(&digest, h * 4), server.clone()));
+                    }
+                }
+            }
+        }
+        points.sort_by_key(|&(point, _)| point);
+        KetamaContinuum { points, compat }
+    }
+
+    ///Number of points on the continuum.
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    ///Lists the continuum in order as `(point, ring_id)` pairs, for diffing against
+    ///a dump of libketama's `mcs` array.
+    pub fn points(&self) -> Vec<(u32, &str)> {
+        self.points.iter().map(|(point, server)| (*point, server.ring_id())).collect()
+    }
+
+    ///Given a string key the server libketama's `ketama_get_server` (or the other
+    ///library's equivalent) picks is returned. If the continuum is empty, `None` is
+    ///returned.
+    pub fn get_node(&self, key: &str) -> Option<T> {
+        if self.points.is_empty() {
+            return None;
+        }
+        let hash = match self.compat {
+            KetamaCompat::Twemproxy(TwemproxyHash::Fnv1a64) => twemproxy_fnv1a_64(key),
+            _ => hash_key(key),
+        };
+        Some(self.points[self.pos_for_hash(hash)].1.clone())
+    }
+
+    //first point >= hash, wrapping around past the last one
+    fn pos_for_hash(&self, hash: u32) -> usize {
+        let pos = bisect_left_by_key(&self.points, &hash, |&(point, _)| point);
+        if pos == self.points.len() {
+            0
+        } else {
+            pos
+        }
+    }
+}
+
+//the string digest `k` of a server is taken of
+fn point_label(ring_id: &str, k: usize, compat: KetamaCompat) -> String {
+    match compat {
+        KetamaCompat::Libketama | KetamaCompat::Twemproxy(_) => format!("{}-{}", ring_id, k),
+        KetamaCompat::Libmemcached => {
+            let (host, port) = match ring_id.rsplit_once(':') {
+                Some((host, port)) => match port.parse::<u16>() {
+                    Ok(port) => (host, port),
+                    Err(_) => (ring_id, LIBMEMCACHED_DEFAULT_PORT),
+                },
+                None => (ring_id, LIBMEMCACHED_DEFAULT_PORT),
+            };
+            if port == LIBMEMCACHED_DEFAULT_PORT {
+                format!("{}-{}", host, k)
+            } else {
+                format!("{}:{}-{}", host, port, k)
+            }
+        }
+    }
+}
+
+//twemproxy's hash_fnv1a_64, including its 32 bit arithmetic and `(uint32_t)` of a
+//signed char
+fn twemproxy_fnv1a_64(key: &str) -> u32 {
+    let mut hash = 0xcbf29ce484222325u64 as u32;
+    for byte in key.bytes() {
+        hash ^= byte as i8 as u32;
+        hash = hash.wrapping_mul(0x100000001b3u64 as u32);
+    }
+    hash
+}
+
+
+///A server of a twemproxy pool, named the way nutcracker names it for ketama: by
+///the name given after the weight in the `servers:` entry, or else `host` for a
+///server on port 11211 and `host:port` for any other. The name is its `ring_id`.
+#[derive(Clone, Debug)]
+pub struct TwemproxyServer {
+    name: String,
+    pub weight: usize,
+}
+
+impl TwemproxyServer {
+    ///A server at `host_port` without a name of its own.
+    pub fn new(host_port: &str, weight: usize) -> TwemproxyServer {
+        let name = match host_port.rsplit_once(':') {
+            Some((host, port)) if port.parse() == Ok(TWEMPROXY_DEFAULT_PORT) => host,
+            _ => host_port,
+        };
+        TwemproxyServer { name: name.to_string(), weight }
+    }
+
+    ///Gives the server the name from its `servers:` entry.
+    pub fn with_name(mut self, name: impl Into<String>) -> TwemproxyServer {
+        self.name = name.into();
+        self
+    }
+
+    ///Parses a `servers:` entry, `"host:port:weight"` or `"host:port:weight name"`.
+    ///Returns `None` if it doesn't have that shape.
+    pub fn parse(entry: &str) -> Option<TwemproxyServer> {
+        let mut fields = entry.split_whitespace();
+        let address = fields.next()?;
+        let name = fields.next();
+        if fields.next().is_some() {
+            return None;
+        }
+        let (host_port, weight) = address.rsplit_once(':')?;
+        let (host, port) = host_port.rsplit_once(':')?;
+        if host.is_empty() || port.parse::<u16>().is_err() {
+            return None;
+        }
+        let server = TwemproxyServer::new(host_port, weight.parse().ok()?);
+        Some(match name {
+            Some(name) => server.with_name(name),
+            None => server,
+        })
+    }
+}
+
+impl fmt::Display for TwemproxyServer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+impl Hash for TwemproxyServer {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+    }
+}
+
+impl WithWeightInfo for TwemproxyServer {
+    fn get_weight(&self) -> usize {
+        self.weight
+    }
+}
+
+impl WithMutableWeight for TwemproxyServer {
+    fn set_weight(&mut self, weight: usize) {
+        self.weight = weight;
+    }
+}
+
+impl RingNode for TwemproxyServer {
+    fn ring_id(&self) -> &str {
+        &self.name
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::hashing_ring::{NodeInfo, NodeInfoWithWeight};
+    use crate::test_support::{assert_keys, checksum};
+
+    //vectors from a line-by-line port of ketama.c's ketama_create_continuum and
+    //ketama_get_server, including its float arithmetic
+    #[test]
+    fn test_weighted_vectors() {
+        let servers = vec![
+            NodeInfoWithWeight::new("10.0.1.1:11211", 600),
+            NodeInfoWithWeight::new("10.0.1.2:11211", 300),
+            NodeInfoWithWeight::new("10.0.1.3:11211", 200),
+        ];
+        let continuum = KetamaContinuum::new(&servers);
+        assert_eq!(continuum.len(), 472);
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for (_, server) in continuum.points() {
+            *counts.entry(server).or_insert(0) += 1;
+        }
+        assert_eq!(counts["10.0.1.1:11211"], 260);
+        assert_eq!(counts["10.0.1.2:11211"], 128);
+        assert_eq!(counts["10.0.1.3:11211"], 84);
+        assert_eq!(continuum.points()[0], (10171922, "10.0.1.1:11211"));
+        assert_eq!(continuum.points()[1], (24991403, "10.0.1.2:11211"));
+        assert_eq!(continuum.points()[471], (4284233799, "10.0.1.2:11211"));
+
+        let expected = [
+            ("my_key", "10.0.1.2:11211"),
+            ("key-0", "10.0.1.3:11211"),
+            ("key-1", "10.0.1.3:11211"),
+            ("key-2", "10.0.1.1:11211"),
+            ("foo", "10.0.1.2:11211"),
+            ("bar", "10.0.1.1:11211"),
+            ("baz", "10.0.1.2:11211"),
+        ];
+        for (key, server) in expected.iter() {
+            assert_eq!(continuum.get_node(key).unwrap().node_name, *server, "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_unweighted_vectors() {
+        let servers = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let continuum = KetamaContinuum::new(&servers);
+        assert_eq!(continuum.len(), 480);
+        assert_eq!(continuum.points()[0], (17290879, "192.168.0.102:11212"));
+        assert_eq!(continuum.points()[479], (4293898094, "192.168.0.102:11212"));
+
+        let expected = [
+            ("my_key", "192.168.0.103:11212"),
+            ("key-0", "192.168.0.102:11212"),
+            ("key-1", "192.168.0.101:11212"),
+            ("key-2", "192.168.0.101:11212"),
+            ("foo", "192.168.0.102:11212"),
+            ("bar", "192.168.0.103:11212"),
+            ("baz", "192.168.0.103:11212"),
+        ];
+        for (key, server) in expected.iter() {
+            assert_eq!(continuum.get_node(key).unwrap().node_name, *server, "{}", key);
+        }
+    }
+
+    //vectors from a port of libmemcached's update_continuum (KETAMA_WEIGHTED branch)
+    //and dispatch_host, compiled as C against OpenSSL's md5; keys are `key-{i}`,
+    //each digit the index of the server the key's at
+    #[test]
+    fn test_libmemcached_vectors() {
+        let servers = vec![
+            NodeInfoWithWeight::new("10.0.1.1:11211", 600),
+            NodeInfoWithWeight::new("10.0.1.2:11211", 300),
+            NodeInfoWithWeight::new("10.0.1.3:11212", 200),
+            NodeInfoWithWeight::new("cache-4.example.com:11211", 100),
+        ];
+        let continuum = KetamaContinuum::with_compat(&servers, KetamaCompat::Libmemcached);
+        assert_eq!(continuum.len(), 636);
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for (_, server) in continuum.points() {
+            *counts.entry(server).or_insert(0) += 1;
+        }
+        assert_eq!(counts["10.0.1.1:11211"], 320);
+        assert_eq!(counts["10.0.1.2:11211"], 160);
+        assert_eq!(counts["10.0.1.3:11212"], 104);
+        assert_eq!(counts["cache-4.example.com:11211"], 52);
+        assert_eq!(continuum.points()[0], (14164473, "10.0.1.2:11211"));
+        assert_eq!(continuum.points()[635], (4294440920, "10.0.1.3:11212"));
+        assert_eq!(checksum(&continuum.points(), &servers), 4583221767379143283);
+        assert_keys(&servers, (0..200).map(|i| format!("key-{}", i)), concat!(
+            "00000112010201010101010100201021131213320012331203300031203210020211010022010220000111121230120032010120",
+            "010010010021000120002030000020010221222001002200000200011202121100030030020000001000201010111102"), |key| continuum.get_node(key));
+    }
+
+    #[test]
+    fn test_libmemcached_unweighted_vectors() {
+        let servers = vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+        ];
+        let continuum = KetamaContinuum::with_compat(&servers, KetamaCompat::Libmemcached);
+        assert_eq!(continuum.len(), 480);
+        assert_eq!(continuum.points()[0], (17290879, "192.168.0.102:11212"));
+        assert_eq!(continuum.points()[479], (4293898094, "192.168.0.102:11212"));
+        assert_eq!(checksum(&continuum.points(), &servers), 13006885645195515277);
+        assert_keys(&servers, (0..200).map(|i| format!("key-{}", i)), concat!(
+            "10000022000210210211200021002002202000002122220000121120202222111011011020200000011210000012210200200021",
+            "212111210102102021101102020121120202001022010002000021211220000202112200222112112210111211012102"), |key| continuum.get_node(key));
+    }
+
+    #[test]
+    fn test_libmemcached_labels() {
+        assert_eq!(point_label("10.0.1.1:11211", 3, KetamaCompat::Libmemcached), "10.0.1.1-3");
+        assert_eq!(point_label("10.0.1.1:11212", 3, KetamaCompat::Libmemcached), "10.0.1.1:11212-3");
+        assert_eq!(point_label("cache-4", 0, KetamaCompat::Libmemcached), "cache-4-0");
+        assert_eq!(point_label("10.0.1.1:11211", 3, KetamaCompat::Libketama), "10.0.1.1:11211-3");
+    }
+
+    //vectors from a port of twemproxy's ketama_update and ketama_dispatch with its
+    //hash_md5 and hash_fnv1a_64, compiled as C against OpenSSL's md5, for the pool
+    //below; keys are `{i}-session`
+    fn twemproxy_pool() -> Vec<TwemproxyServer> {
+        ["10.0.1.1:11211:3", "10.0.1.2:11212:2", "10.0.1.3:11211:1 cache-c"].iter()
+            .map(|entry| TwemproxyServer::parse(entry).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_twemproxy_continuum() {
+        let servers = twemproxy_pool();
+        assert_eq!(servers.iter().map(|server| server.ring_id()).collect::<Vec<&str>>(), vec!["10.0.1.1", "10.0.1.2:11212", "cache-c"]);
+        let continuum = KetamaContinuum::with_compat(&servers, KetamaCompat::Twemproxy(TwemproxyHash::Md5));
+        assert_eq!(continuum.len(), 480);
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for (_, server) in continuum.points() {
+            *counts.entry(server).or_insert(0) += 1;
+        }
+        assert_eq!((counts["10.0.1.1"], counts["10.0.1.2:11212"], counts["cache-c"]), (240, 160, 80));
+        assert_eq!(continuum.points()[0], (17349376, "10.0.1.1"));
+        assert_eq!(continuum.points()[479], (4291619620, "cache-c"));
+        assert_eq!(checksum(&continuum.points(), &servers), 11258612890239112270);
+    }
+
+    #[test]
+    fn test_twemproxy_selections() {
+        let servers = twemproxy_pool();
+        let md5 = KetamaContinuum::with_compat(&servers, KetamaCompat::Twemproxy(TwemproxyHash::Md5));
+        assert_keys(&servers, (0..100).map(|i| format!("{}-session", i)), "2022021002001100000102102010010021000201011221011102210000100111010010020100200012000101101022000000", |key| md5.get_node(key));
+        let fnv = KetamaContinuum::with_compat(&servers, KetamaCompat::Twemproxy(TwemproxyHash::Fnv1a64));
+        assert_eq!(fnv.points(), md5.points());
+        assert_keys(&servers, (0..100).map(|i| format!("{}-session", i)), "0110201102010110211001200102210010101112020001200110001112111202012212102001121001000011002100101000", |key| fnv.get_node(key));
+    }
+
+    #[test]
+    fn test_twemproxy_fnv1a_64() {
+        assert_eq!(twemproxy_fnv1a_64("key-0"), 2515697753);
+        assert_eq!(twemproxy_fnv1a_64("ключ-é"), 141008292);
+        assert_eq!(twemproxy_fnv1a_64(""), 0x84222325);
+    }
+
+    #[test]
+    fn test_twemproxy_server_parse() {
+        assert_eq!(TwemproxyServer::parse("10.0.1.1:11211:3").unwrap().ring_id(), "10.0.1.1");
+        assert_eq!(TwemproxyServer::parse("10.0.1.1:11212:3").unwrap().ring_id(), "10.0.1.1:11212");
+        let named = TwemproxyServer::parse("  10.0.1.1:11211:2 server1 ").unwrap();
+        assert_eq!((named.ring_id(), named.get_weight()), ("server1", 2));
+        assert_eq!(TwemproxyServer::new("10.0.1.1:11211", 1).with_name("a").to_string(), "a");
+        for entry in &["", "10.0.1.1:11211", "10.0.1.1:port:1", ":11211:1", "10.0.1.1:11211:x", "10.0.1.1:11211:1 a b"] {
+            assert!(TwemproxyServer::parse(entry).is_none(), "{}", entry);
+        }
+    }
+
+    #[test]
+    fn test_hash_on_a_point_belongs_to_it() {
+        let continuum = KetamaContinuum::new(&[NodeInfo::new("192.168.0.101:11212"), NodeInfo::new("192.168.0.102:11212")]);
+        let points = continuum.points();
+        for (i, (point, _)) in points.iter().enumerate() {
+            assert_eq!(continuum.pos_for_hash(*point), i);
+        }
+        assert_eq!(continuum.pos_for_hash(points[0].0 - 1), 0);
+        assert_eq!(continuum.pos_for_hash(points[points.len() - 1].0 + 1), 0);
+        assert_eq!(continuum.pos_for_hash(0), 0);
+    }
+
+    #[test]
+    fn test_empty() {
+        let continuum: KetamaContinuum<NodeInfoWithWeight> = KetamaContinuum::new(&[]);
+        assert!(continuum.is_empty());
+        assert!(continuum.get_node("my_key").is_none());
+
+        let zero = KetamaContinuum::new(&[NodeInfoWithWeight::new("10.0.1.1:11211", 0)]);
+        assert!(zero.get_node("my_key").is_none());
+    }
+}
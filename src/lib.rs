@@ -1,9 +1,44 @@
-
-use md5;
-use std::collections::HashMap;
-use std::collections::BinaryHeap;
-
-
-mod hashing_ring;
-mod bisect;
-
+#[cfg(feature = "md5")]
+pub mod hashing_ring;
+pub mod bisect;
+#[cfg(feature = "md5")]
+pub mod rendezvous;
+#[cfg(feature = "md5")]
+pub mod jump;
+#[cfg(feature = "md5")]
+pub mod maglev;
+#[cfg(feature = "md5")]
+pub mod anchor;
+#[cfg(feature = "md5")]
+pub mod slot_table;
+#[cfg(feature = "md5")]
+pub mod topology;
+#[cfg(feature = "md5")]
+pub mod ketama;
+#[cfg(feature = "md5")]
+pub mod ring_map;
+#[cfg(feature = "md5")]
+pub mod socket_node;
+#[cfg(feature = "md5")]
+pub mod envoy;
+#[cfg(feature = "md5")]
+pub mod hash_ring_py;
+#[cfg(feature = "md5")]
+pub mod nginx;
+#[cfg(feature = "md5")]
+pub mod groupcache;
+#[cfg(feature = "md5")]
+pub mod delta;
+pub mod hasher_ring;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "wasm-bindgen")]
+pub mod wasm;
+#[cfg(feature = "lookup-cache")]
+mod lookup_cache;
+#[cfg(feature = "md5")]
+mod point_store;
+#[cfg(all(test, feature = "md5"))]
+mod test_support;
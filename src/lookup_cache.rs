@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+
+///A small least-recently-used map from lookup keys to the ring position they
+///resolved to. Hits and inserts are O(1); evicting scans the entries for the
+///oldest one, which is fine at the few-hundred-entries sizes it's meant for.
+pub(crate) struct LookupCache<P> {
+    capacity: usize,
+    tick: u64,
+    entries: HashMap<String, (P, u64)>,
+}
+
+impl<P: Copy> LookupCache<P> {
+    pub(crate) fn new(capacity: usize) -> LookupCache<P> {
+        LookupCache {
+            capacity,
+            tick: 0,
+            entries: HashMap::with_capacity(capacity),
+        }
+    }
+
+    ///Returns the cached position of `key`, marking it as most recently used.
+    pub(crate) fn get(&mut self, key: &str) -> Option<P> {
+        self.tick += 1;
+        let tick = self.tick;
+        self.entries.get_mut(key).map(|entry| {
+            entry.1 = tick;
+            entry.0
+        })
+    }
+
+    ///Caches the position of `key`, evicting the least recently used key if full.
+    pub(crate) fn insert(&mut self, key: &str, pos: P) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() >= self.capacity && !self.entries.contains_key(key) {
+            let oldest = self.entries.iter()
+                .min_by_key(|(_, &(_, used))| used)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.tick += 1;
+        self.entries.insert(key.to_string(), (pos, self.tick));
+    }
+
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = LookupCache::new(2);
+        cache.insert("a", 1);
+        cache.insert("b", 2);
+        assert_eq!(cache.get("a"), Some(1));
+        cache.insert("c", 3);
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.get("a"), Some(1));
+        assert_eq!(cache.get("c"), Some(3));
+        assert_eq!(cache.len(), 2);
+
+        cache.insert("c", 4);
+        assert_eq!(cache.get("c"), Some(4));
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn test_zero_capacity_caches_nothing() {
+        let mut cache = LookupCache::new(0);
+        cache.insert("a", 1);
+        assert_eq!(cache.get("a"), None);
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut cache = LookupCache::new(4);
+        cache.insert("a", 1);
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.get("a"), None);
+    }
+}
@@ -0,0 +1,210 @@
+use std::hash::Hash;
+use crate::hashing_ring::{hash_bytes64, hash_pair64, RingNode, WithWeightInfo};
+
+
+///Maglev lookup table (Eisenbud et al., "Maglev: A Fast and Reliable Software
+///Network Load Balancer").
+///
+///Every node gets a pseudo-random permutation of the table slots and the nodes
+///take turns claiming their next preferred free slot until the table is full.
+///Lookups are a single md5 plus an array index. Nodes claim slots in proportion
+///to their weight, so a node's share of slots is within one slot of its exact
+///weight share.
+///
+///The table is a pure function of the node set (nodes are ordered by name before
+///population), so independently built tables agree. Because each node's
+///permutation doesn't depend on the other nodes, a membership change only moves
+///the slots of the node that came or went plus a small number of others.
+pub struct MaglevTable<T: RingNode + Hash + Clone + WithWeightInfo> {
+    nodes: Vec<T>,
+    table: Vec<usize>,
+    table_size: usize,
+}
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> MaglevTable<T> {
+    ///Builds the table. `table_size` must be prime and defaults to 65537; it should be
+    ///well above 100 times the number of nodes for the balance to hold.
+    ///
+    ///Panics if `table_size` isn't prime.
+    pub fn new(nodes: &[T], table_size: Option<usize>) -> MaglevTable<T> {
+        let table_size = table_size.unwrap_or(65537); //default value = 65537
+        assert!(is_prime(table_size), "maglev table size must be prime");
+        let mut maglev = MaglevTable {
+            nodes: Vec::new(),
+            table: Vec::new(),
+            table_size,
+        };
+        maglev.rebuild(nodes);
+        maglev
+    }
+
+    ///Repopulates the table for a new node set, keeping the table size.
+    pub fn rebuild(&mut self, nodes: &[T]) {
+        let mut nodes: Vec<T> = nodes.iter().filter(|node| node.get_weight() > 0).cloned().collect();
+        nodes.sort_by(|a, b| a.ring_id().cmp(b.ring_id()));
+        self.table = populate(&nodes, self.table_size);
+        self.nodes = nodes;
+    }
+
+    pub fn table_size(&self) -> usize {
+        self.table_size
+    }
+
+    ///Given a string key the node owning its slot is returned.
+    ///If there are no nodes, `None` is returned.
+    pub fn get_node(&self, key: &str) -> Option<T> {
+        if self.nodes.is_empty() {
+            return None;
+        }
+        let slot = (hash_bytes64(key.as_bytes()) % self.table_size as u64) as usize;
+        Some(self.nodes[self.table[slot]].clone())
+    }
+
+    ///Returns the owner of every slot, by node name.
+    pub fn slot_owners(&self) -> Vec<String> {
+        self.table.iter().map(|&index| self.nodes[index].ring_id().to_string()).collect()
+    }
+}
+
+///Fills `table_size` slots. Each node claims its weight share of slots, rounded
+///with the largest remainder so the shares add up to the table size.
+fn populate<T: RingNode + WithWeightInfo>(nodes: &[T], table_size: usize) -> Vec<usize> {
+    if nodes.is_empty() {
+        return Vec::new();
+    }
+    let size = table_size as u64;
+    let total_weight: u64 = nodes.iter().map(|node| node.get_weight() as u64).sum();
+
+    let mut targets: Vec<u64> = Vec::with_capacity(nodes.len());
+    let mut remainders: Vec<(u64, usize)> = Vec::with_capacity(nodes.len());
+    for (index, node) in nodes.iter().enumerate() {
+        let exact = size * node.get_weight() as u64;
+        targets.push(exact / total_weight);
+        remainders.push((exact % total_weight, index));
+    }
+    let assigned: u64 = targets.iter().sum();
+    remainders.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+    for &(_, index) in remainders.iter().take((size - assigned) as usize) {
+        targets[index] += 1;
+    }
+
+    let permutations: Vec<(u64, u64)> = nodes.iter().map(|node| {
+        let name = node.ring_id();
+        let offset = hash_pair64(name, "maglev-offset") % size;
+        let skip = hash_pair64(name, "maglev-skip") % (size - 1) + 1;
+        (offset, skip)
+    }).collect();
+
+    const EMPTY: usize = usize::MAX;
+    let mut table = vec![EMPTY; table_size];
+    let mut next = vec![0u64; nodes.len()];
+    let mut claimed = vec![0u64; nodes.len()];
+    let mut filled = 0;
+    while filled < table_size {
+        for index in 0..nodes.len() {
+            if claimed[index] == targets[index] {
+                continue;
+            }
+            let (offset, skip) = permutations[index];
+            loop {
+                let slot = ((offset + next[index] * skip) % size) as usize;
+                next[index] += 1;
+                if table[slot] == EMPTY {
+                    table[slot] = index;
+                    claimed[index] += 1;
+                    filled += 1;
+                    break;
+                }
+            }
+        }
+    }
+    table
+}
+
+fn is_prime(n: usize) -> bool {
+    if n < 2 {
+        return false;
+    }
+    let mut divisor = 2;
+    while divisor * divisor <= n {
+        if n.is_multiple_of(divisor) {
+            return false;
+        }
+        divisor += 1;
+    }
+    true
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::hashing_ring::{NodeInfo, NodeInfoWithWeight};
+
+    fn slot_counts<T: RingNode + Hash + Clone + WithWeightInfo>(maglev: &MaglevTable<T>) -> HashMap<String, usize> {
+        let mut counts = HashMap::new();
+        for owner in maglev.slot_owners() {
+            *counts.entry(owner).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn test_weighted_balance() {
+        let nodes = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 3),
+            NodeInfoWithWeight::new("192.168.0.104:11212", 4),
+        ];
+        let maglev = MaglevTable::new(&nodes, None);
+        assert_eq!(maglev.table_size(), 65537);
+
+        let counts = slot_counts(&maglev);
+        for node in &nodes {
+            let share = counts[&node.to_string()] as f64 / 65537.0;
+            let expected = node.weight as f64 / 10.0;
+            assert!((share - expected).abs() < 0.01, "{}: {} vs {}", node.node_name, share, expected);
+        }
+    }
+
+    #[test]
+    fn test_rebuild_disruption() {
+        let mut nodes: Vec<NodeInfo> = (0..6).map(|i| NodeInfo::new(["a", "b", "c", "d", "e", "f"][i])).collect();
+        let mut maglev = MaglevTable::new(&nodes, Some(5003));
+        let before = maglev.slot_owners();
+
+        let removed = nodes.remove(3).to_string();
+        maglev.rebuild(&nodes);
+        let after = maglev.slot_owners();
+
+        let mut collateral = 0;
+        for (old, new) in before.iter().zip(after.iter()) {
+            assert_ne!(new, &removed);
+            if old != &removed && old != new {
+                collateral += 1;
+            }
+        }
+        //only a small fraction of slots not owned by the removed node may move
+        assert!((collateral as f64) < 0.02 * 5003.0, "{} collateral moves", collateral);
+    }
+
+    #[test]
+    fn test_get_node() {
+        let nodes = vec![NodeInfo::new("a"), NodeInfo::new("b")];
+        let maglev = MaglevTable::new(&nodes, Some(251));
+        let owner = maglev.get_node("my_key").unwrap().to_string();
+        assert_eq!(owner, maglev.get_node("my_key").unwrap().to_string());
+
+        let empty: MaglevTable<NodeInfo> = MaglevTable::new(&[], Some(251));
+        assert!(empty.get_node("my_key").is_none());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_table_size_must_be_prime() {
+        let nodes = vec![NodeInfo::new("a")];
+        MaglevTable::new(&nodes, Some(1000));
+    }
+}
@@ -0,0 +1,211 @@
+use std::hash::Hash;
+use crate::bisect::bisect_left_by_key;
+use crate::hashing_ring::{RingNode, WithWeightInfo};
+
+
+///Points per unit of weight in `ngx_http_upstream_init_chash`.
+const NGINX_POINTS_PER_WEIGHT: usize = 160;
+
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 == 1 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+//ngx_crc32_update, on a running crc that starts at 0xffffffff (ngx_crc32_init)
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, byte| CRC32_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+//ngx_crc32_long, plain CRC-32 as in zlib and Go's crc32.ChecksumIEEE
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    crc32_update(0xffffffff, bytes) ^ 0xffffffff
+}
+
+
+///A continuum laid out like the one nginx builds for `hash $key consistent`
+///(`ngx_http_upstream_hash_module`, and `ngx_stream_upstream_hash_module`, which
+///shares the code), so keys go to the same upstream server as through nginx.
+///It follows `ngx_http_upstream_init_chash` as it has been since `consistent` was
+///introduced in nginx 1.7.2:
+///
+///* a server of weight `w` gets `160 * w` points,
+///* the server name is split at its last `:` into host and port (or is a
+///  `unix:` path with no port), and point `j` is the CRC-32 of
+///  `host \0 port prev`, `prev` being point `j - 1` as 4 little endian bytes (0 for
+///  the first point), the scheme of Cache::Memcached::Fast,
+///* points are sorted and duplicate hashes dropped, keeping one of them,
+///* a key hashes to the CRC-32 of its bytes and belongs to the first point at or
+///  after that hash, wrapping around past the last one.
+///
+///A server's `ring_id` has to be the address exactly as written in its `server`
+///directive, e.g. `"10.0.0.1:8080"`, `"cache.example.com:80"` or
+///`"unix:/var/run/cache.sock"`. A weight of 0 counts as nginx's default weight,
+///one. nginx goes on to skip servers that are down or failing, and balances between
+///the addresses of a hostname that resolves to several; neither is modelled.
+pub struct NginxChash<T: RingNode + Hash + Clone + WithWeightInfo> {
+    //sorted by point, no duplicates
+    points: Vec<(u32, T)>,
+}
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> NginxChash<T> {
+    pub fn new(servers: &[T]) -> NginxChash<T> {
+        let mut points: Vec<(u32, T)> = Vec::new();
+        for server in servers {
+            let (host, port) = split_server(server.ring_id());
+            let mut base_hash = crc32_update(0xffffffff, host.as_bytes());
+            base_hash = crc32_update(base_hash, &[0]);
+            base_hash = crc32_update(base_hash, port.as_bytes());
+
+            let mut prev_hash = 0u32;
+            for _ in 0..server.get_weight().max(1) * NGINX_POINTS_PER_WEIGHT {
+                let hash = crc32_update(base_hash, &prev_hash.to_le_bytes()) ^ 0xffffffff;
+                points.push((hash, server.clone()));
+                prev_hash = hash;
+            }
+        }
+        points.sort_by_key(|&(point, _)| point);
+        points.dedup_by_key(|&mut (point, _)| point);
+        NginxChash { points }
+    }
+
+    ///Number of points on the continuum, after duplicates are dropped.
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    ///Lists the continuum in order as `(point, ring_id)` pairs.
+    pub fn points(&self) -> Vec<(u32, &str)> {
+        self.points.iter().map(|(point, server)| (*point, server.ring_id())).collect()
+    }
+
+    ///Given the evaluated `hash` key, e.g. the request URI for `hash $request_uri
+    ///consistent`, the server nginx picks is returned. If the continuum is empty,
+    ///`None` is returned.
+    pub fn get_node(&self, key: &str) -> Option<T> {
+        if self.points.is_empty() {
+            return None;
+        }
+        Some(self.points[self.pos_for_hash(crc32(key.as_bytes()))].1.clone())
+    }
+
+    //ngx_http_upstream_find_chash_point, then point[hash % number]
+    fn pos_for_hash(&self, hash: u32) -> usize {
+        bisect_left_by_key(&self.points, &hash, |&(point, _)| point) % self.points.len()
+    }
+}
+
+//host and port as ngx_http_upstream_init_chash cuts them out of the server name
+fn split_server(server: &str) -> (&str, &str) {
+    if server.len() >= 5 && server.as_bytes()[..5].eq_ignore_ascii_case(b"unix:") {
+        return (&server[5..], "");
+    }
+    match server.rsplit_once(':') {
+        Some((host, port)) => (host, port),
+        None => (server, ""),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing_ring::NodeInfoWithWeight;
+    use crate::test_support::{assert_keys, checksum};
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"/index.html"), 3928116034);
+    }
+
+    #[test]
+    fn test_split_server() {
+        assert_eq!(split_server("10.0.0.1:8080"), ("10.0.0.1", "8080"));
+        assert_eq!(split_server("[::1]:8080"), ("[::1]", "8080"));
+        assert_eq!(split_server("10.0.0.2"), ("10.0.0.2", ""));
+        assert_eq!(split_server("UNIX:/var/run/cache.sock"), ("/var/run/cache.sock", ""));
+    }
+
+    //vectors from a port of ngx_http_upstream_init_chash and the chash peer pick,
+    //compiled as C; each digit is the index of the server `/assets/{i}.js` goes to
+    #[test]
+    fn test_weighted_vectors() {
+        let servers = vec![
+            NodeInfoWithWeight::new("10.0.0.1:8080", 1),
+            NodeInfoWithWeight::new("10.0.0.2:8080", 2),
+            NodeInfoWithWeight::new("cache.example.com:80", 1),
+        ];
+        let continuum = NginxChash::new(&servers);
+        assert_eq!(continuum.len(), 640);
+        assert_eq!(continuum.points()[0], (10773370, "10.0.0.2:8080"));
+        assert_eq!(continuum.points()[639], (4289760168, "cache.example.com:80"));
+        assert_eq!(checksum(&continuum.points(), &servers), 10416653375689225295);
+        assert_keys(&servers, (0..200).map(|i| format!("/assets/{}.js", i)), concat!(
+            "2020101112111112101000120111121110110001011100120111001110122210122112111011111102211212011211012211101212",
+            "1022110122021102101111001111211210121202112021221111111110101202220021010021010011121110102121"), |key| continuum.get_node(key));
+    }
+
+    #[test]
+    fn test_portless_and_unix_vectors() {
+        let servers = vec![
+            NodeInfoWithWeight::new("10.0.0.1:8080", 1),
+            NodeInfoWithWeight::new("10.0.0.2", 1),
+            NodeInfoWithWeight::new("unix:/var/run/cache.sock", 3),
+        ];
+        let continuum = NginxChash::new(&servers);
+        assert_eq!(continuum.len(), 800);
+        assert_eq!(continuum.points()[0], (2324216, "unix:/var/run/cache.sock"));
+        assert_eq!(continuum.points()[799], (4284193791, "unix:/var/run/cache.sock"));
+        assert_eq!(checksum(&continuum.points(), &servers), 4615823865232361698);
+        assert_keys(&servers, (0..200).map(|i| format!("/assets/{}.js", i)), concat!(
+            "0000221122002222201020222220111200222202022020222212201200112121221212222000221022221122212112221222201202",
+            "2022222122202212100222001212122222022212112022201211220220201200122022212222220011012221122212"), |key| continuum.get_node(key));
+    }
+
+    #[test]
+    fn test_duplicate_points() {
+        //the same server listed twice yields the same points, which are kept once
+        let once = NginxChash::new(&[NodeInfoWithWeight::new("10.0.0.1:8080", 1)]);
+        let twice = NginxChash::new(&[NodeInfoWithWeight::new("10.0.0.1:8080", 1), NodeInfoWithWeight::new("10.0.0.1:8080", 1)]);
+        assert_eq!(once.len(), 160);
+        assert_eq!(twice.points(), once.points());
+        //an unset weight is a weight of 1
+        assert_eq!(NginxChash::new(&[NodeInfoWithWeight::new("10.0.0.1:8080", 0)]).points(), once.points());
+    }
+
+    #[test]
+    fn test_hash_on_a_point_belongs_to_it() {
+        let continuum = NginxChash::new(&[NodeInfoWithWeight::new("10.0.0.1:8080", 1), NodeInfoWithWeight::new("10.0.0.2:8080", 1)]);
+        let points = continuum.points();
+        for (i, (point, _)) in points.iter().enumerate() {
+            assert_eq!(continuum.pos_for_hash(*point), i);
+        }
+        assert_eq!(continuum.pos_for_hash(points[points.len() - 1].0 + 1), 0);
+        assert_eq!(continuum.pos_for_hash(0), 0);
+    }
+
+    #[test]
+    fn test_empty() {
+        let continuum: NginxChash<NodeInfoWithWeight> = NginxChash::new(&[]);
+        assert!(continuum.is_empty());
+        assert!(continuum.get_node("/index.html").is_none());
+    }
+}
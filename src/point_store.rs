@@ -0,0 +1,440 @@
+use std::collections::{btree_map, BTreeMap, HashSet};
+use std::iter::Chain;
+use std::ops::Bound;
+use std::slice;
+use crate::bisect::{bisect_left_by_key_branchless, bisect_right_by_key_branchless};
+use crate::hashing_ring::Backend;
+
+
+///A point on the ring: its token and the index of its owner in the ring's `owners`.
+pub(crate) type Point = (u32, u32);
+
+///The sorted vector's storage. With the `smallvec` feature rings of up to 256
+///points keep them inline instead of in a separate heap allocation.
+#[cfg(feature = "smallvec")]
+pub(crate) type Points = smallvec::SmallVec<[Point; 256]>;
+#[cfg(not(feature = "smallvec"))]
+pub(crate) type Points = Vec<Point>;
+
+///The ring's points, kept in token order by one of the two `Backend`s.
+///
+///The vector is only sorted by `sort`, so a batch of pushes costs one sort, and
+///may hold a token more than once until then. The tree is in order after every
+///push. Either way a sorted store holds every token once.
+///
+///Which of the points that collide on a token keeps it is up to the `first`
+///order the caller passes in (the ring's is by `ring_id`), never the order the
+///points were pushed in. The others aren't forgotten: they're kept in
+///`shadowed`, and the first of them comes back when the point holding the token
+///is removed, so removing the node that won a collision leaves the ring as if
+///that node had never been added.
+#[derive(Clone)]
+pub(crate) struct PointStore {
+    tokens: Tokens,
+    //owners whose points lost each token, in no particular order
+    shadowed: BTreeMap<u32, Vec<u32>>,
+}
+
+//inline smallvec points are meant to live in the ring itself
+#[allow(clippy::large_enum_variant)]
+#[derive(Clone)]
+enum Tokens {
+    SortedVec(Points),
+    BTree(BTreeMap<u32, u32>),
+}
+
+//records that `loser`'s point at `token` lost it, once
+fn shadow(shadowed: &mut BTreeMap<u32, Vec<u32>>, token: u32, loser: u32) {
+    let losers = shadowed.entry(token).or_default();
+    if !losers.contains(&loser) {
+        losers.push(loser);
+    }
+}
+
+///The points in ring order from wherever the walk started, every point once.
+pub(crate) enum Walk<'a> {
+    Slice(Chain<slice::Iter<'a, Point>, slice::Iter<'a, Point>>),
+    Tree(Chain<btree_map::Range<'a, u32, u32>, btree_map::Range<'a, u32, u32>>),
+}
+
+impl<'a> Iterator for Walk<'a> {
+    type Item = Point;
+
+    #[inline]
+    fn next(&mut self) -> Option<Point> {
+        match self {
+            Walk::Slice(points) => points.next().copied(),
+            Walk::Tree(points) => points.next().map(|(token, owner)| (*token, *owner)),
+        }
+    }
+}
+
+impl PointStore {
+    pub(crate) fn new(backend: Backend) -> PointStore {
+        let tokens = match backend {
+            Backend::SortedVec => Tokens::SortedVec(Points::new()),
+            Backend::BTree => Tokens::BTree(BTreeMap::new()),
+        };
+        PointStore { tokens, shadowed: BTreeMap::new() }
+    }
+
+    #[inline]
+    pub(crate) fn len(&self) -> usize {
+        match &self.tokens {
+            Tokens::SortedVec(points) => points.len(),
+            Tokens::BTree(points) => points.len(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match &mut self.tokens {
+            Tokens::SortedVec(points) => points.clear(),
+            Tokens::BTree(points) => points.clear(),
+        }
+        self.shadowed.clear();
+    }
+
+    ///The points in ring order.
+    #[inline]
+    pub(crate) fn iter(&self) -> Walk<'_> {
+        match &self.tokens {
+            Tokens::SortedVec(points) => Walk::Slice(points.iter().chain([].iter())),
+            Tokens::BTree(points) => Walk::Tree(points.range(..).chain(points.range(0..0))),
+        }
+    }
+
+    pub(crate) fn last(&self) -> Option<Point> {
+        match &self.tokens {
+            Tokens::SortedVec(points) => points.last().copied(),
+            Tokens::BTree(points) => points.iter().next_back().map(|(token, owner)| (*token, *owner)),
+        }
+    }
+
+    ///The first point clockwise from `key`: the one with the smallest token
+    ///strictly greater than `key`, wrapping around to the first point past the
+    ///largest. `None` if there are no points.
+    #[inline]
+    pub(crate) fn successor(&self, key: u32) -> Option<Point> {
+        match &self.tokens {
+            Tokens::SortedVec(points) => {
+                let pos = bisect_right_by_key_branchless(points, &key, |point| point.0);
+                points.get(pos).or_else(|| points.first()).copied()
+            }
+            Tokens::BTree(points) => points.range((Bound::Excluded(key), Bound::Unbounded)).next()
+                .or_else(|| points.iter().next())
+                .map(|(token, owner)| (*token, *owner)),
+        }
+    }
+
+    ///Every point in ring order, starting at the first one whose token is `from`
+    ///or greater and wrapping around.
+    #[inline]
+    pub(crate) fn walk(&self, from: u32) -> Walk<'_> {
+        match &self.tokens {
+            Tokens::SortedVec(points) => {
+                let pos = bisect_left_by_key_branchless(points, &from, |point| point.0);
+                Walk::Slice(points[pos..].iter().chain(points[..pos].iter()))
+            }
+            Tokens::BTree(points) => Walk::Tree(points.range(from..).chain(points.range(..from))),
+        }
+    }
+
+    ///The owner of the point at `token`, if there is one.
+    #[cfg(test)]
+    pub(crate) fn get(&self, token: u32) -> Option<u32> {
+        match &self.tokens {
+            Tokens::SortedVec(points) => {
+                let pos = bisect_left_by_key_branchless(points, &token, |point| point.0);
+                points.get(pos).filter(|point| point.0 == token).map(|point| point.1)
+            }
+            Tokens::BTree(points) => points.get(&token).copied(),
+        }
+    }
+
+    ///Adds a point. The vector is left unsorted until `sort`; the tree settles a
+    ///collision right away. `first(a, b)` is whether owner `a` keeps a token both
+    ///`a` and `b` have a point at.
+    pub(crate) fn push<F: Fn(u32, u32) -> bool>(&mut self, point: Point, first: F) {
+        match &mut self.tokens {
+            Tokens::SortedVec(points) => points.push(point),
+            Tokens::BTree(points) => match points.entry(point.0) {
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(point.1);
+                }
+                btree_map::Entry::Occupied(mut entry) => {
+                    let held = *entry.get();
+                    if held == point.1 {
+                        return;
+                    }
+                    let loser = if first(point.1, held) { entry.insert(point.1) } else { point.1 };
+                    shadow(&mut self.shadowed, point.0, loser);
+                }
+            },
+        }
+    }
+
+    ///Sorts the vector by token and keeps one point of those that collide, the
+    ///one of the `first` owner as in `push`, which is what the tree does anyway.
+    pub(crate) fn sort<F: Fn(u32, u32) -> bool>(&mut self, first: F) {
+        if let Tokens::SortedVec(points) = &mut self.tokens {
+            let shadowed = &mut self.shadowed;
+            points.sort_unstable_by_key(|point| point.0);
+            points.dedup_by(|later, kept| {
+                let collides = later.0 == kept.0;
+                if collides && later.1 != kept.1 {
+                    let loser = if first(later.1, kept.1) { std::mem::replace(&mut kept.1, later.1) } else { later.1 };
+                    shadow(shadowed, kept.0, loser);
+                }
+                collides
+            });
+        }
+    }
+
+    ///Whether finding an owner's points takes their tokens, as `remove_owner` and
+    ///`renumber` do for the tree, instead of a scan of every point.
+    pub(crate) fn needs_tokens(&self) -> bool {
+        matches!(self.tokens, Tokens::BTree(_))
+    }
+
+    ///Removes every point of `owner` and returns their tokens in ring order.
+    ///`tokens` has to name every token the owner may hold if `needs_tokens`; the
+    ///vector ignores it. The tokens freed go to the `first` of the points that
+    ///lost them, as in `push`.
+    pub(crate) fn remove_owner<F: Fn(u32, u32) -> bool>(&mut self, owner: u32, tokens: &[u32], first: F) -> Vec<u32> {
+        let removed = match &mut self.tokens {
+            Tokens::SortedVec(points) => {
+                let mut removed = Vec::new();
+                points.retain(|point| {
+                    let kept = point.1 != owner;
+                    if !kept {
+                        removed.push(point.0);
+                    }
+                    kept
+                });
+                removed
+            }
+            Tokens::BTree(_) => return self.remove_points(owner, tokens, first),
+        };
+        self.shadowed.retain(|_, losers| {
+            losers.retain(|loser| *loser != owner);
+            !losers.is_empty()
+        });
+        self.restore(&removed, first);
+        removed
+    }
+
+    ///Removes the points at `tokens` that belong to `owner`, leaving the ones a
+    ///collision gave to another node, and returns their tokens in ring order.
+    ///Freed tokens go back as in `remove_owner`.
+    pub(crate) fn remove_points<F: Fn(u32, u32) -> bool>(&mut self, owner: u32, tokens: &[u32], first: F) -> Vec<u32> {
+        let mut removed = Vec::new();
+        match &mut self.tokens {
+            Tokens::SortedVec(points) => {
+                let tokens: HashSet<u32> = tokens.iter().copied().collect();
+                points.retain(|point| {
+                    let kept = point.1 != owner || !tokens.contains(&point.0);
+                    if !kept {
+                        removed.push(point.0);
+                    }
+                    kept
+                });
+            }
+            Tokens::BTree(points) => {
+                for token in tokens {
+                    if points.get(token) == Some(&owner) {
+                        points.remove(token);
+                        removed.push(*token);
+                    }
+                }
+                removed.sort_unstable();
+            }
+        }
+        for token in tokens {
+            if let btree_map::Entry::Occupied(mut losers) = self.shadowed.entry(*token) {
+                losers.get_mut().retain(|loser| *loser != owner);
+                if losers.get().is_empty() {
+                    losers.remove();
+                }
+            }
+        }
+        self.restore(&removed, first);
+        removed
+    }
+
+    //gives each freed token back to the `first` of the points that lost it, if
+    //there are any
+    fn restore<F: Fn(u32, u32) -> bool>(&mut self, freed: &[u32], first: F) {
+        for token in freed {
+            let owner = match self.shadowed.get_mut(token) {
+                Some(losers) => {
+                    let mut best = 0;
+                    for (i, loser) in losers.iter().enumerate().skip(1) {
+                        if first(*loser, losers[best]) {
+                            best = i;
+                        }
+                    }
+                    let owner = losers.swap_remove(best);
+                    if losers.is_empty() {
+                        self.shadowed.remove(token);
+                    }
+                    owner
+                }
+                None => continue,
+            };
+            match &mut self.tokens {
+                Tokens::SortedVec(points) => {
+                    let pos = bisect_left_by_key_branchless(points, token, |point| point.0);
+                    points.insert(pos, (*token, owner));
+                }
+                Tokens::BTree(points) => {
+                    points.insert(*token, owner);
+                }
+            }
+        }
+    }
+
+    ///Hands the points of owner `from` to owner `to`. `tokens` is as for
+    ///`remove_owner`.
+    pub(crate) fn renumber(&mut self, from: u32, to: u32, tokens: &[u32]) {
+        match &mut self.tokens {
+            Tokens::SortedVec(points) => {
+                for point in points.iter_mut().filter(|point| point.1 == from) {
+                    point.1 = to;
+                }
+            }
+            Tokens::BTree(points) => {
+                for token in tokens {
+                    if let Some(owner) = points.get_mut(token).filter(|owner| **owner == from) {
+                        *owner = to;
+                    }
+                }
+            }
+        }
+        for loser in self.shadowed.values_mut().flatten().filter(|loser| **loser == from) {
+            *loser = to;
+        }
+    }
+
+    pub(crate) fn shrink_to_fit(&mut self) {
+        if let Tokens::SortedVec(points) = &mut self.tokens {
+            points.shrink_to_fit();
+        }
+    }
+
+    ///Bytes allocated for the points on the heap. Inline points are part of the
+    ///ring itself. Tree nodes hold up to 11 entries and are taken to be two thirds
+    ///full, each with a parent pointer and two lengths besides the entries.
+    pub(crate) fn heap_bytes(&self) -> usize {
+        let tree_bytes = |len: usize, entry: usize| {
+            let nodes = (len * 3).div_ceil(2 * 11);
+            nodes * (11 * entry + std::mem::size_of::<usize>() + 2 * std::mem::size_of::<u16>())
+        };
+        let shadowed = tree_bytes(self.shadowed.len(), std::mem::size_of::<(u32, Vec<u32>)>())
+            + self.shadowed.values().map(|losers| losers.capacity() * std::mem::size_of::<u32>()).sum::<usize>();
+        shadowed + match &self.tokens {
+            Tokens::SortedVec(points) => {
+                #[cfg(feature = "smallvec")]
+                if !points.spilled() {
+                    return shadowed;
+                }
+                points.capacity() * std::mem::size_of::<Point>()
+            }
+            Tokens::BTree(points) => tree_bytes(points.len(), std::mem::size_of::<Point>()),
+        }
+    }
+
+    ///The vector of a `SortedVec` store.
+    #[cfg(test)]
+    pub(crate) fn as_vec(&self) -> &Points {
+        match &self.tokens {
+            Tokens::SortedVec(points) => points,
+            Tokens::BTree(_) => panic!("the points are in a tree"),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn first(a: u32, b: u32) -> bool {
+        a < b
+    }
+
+    fn stores(points: &[Point]) -> Vec<PointStore> {
+        [Backend::SortedVec, Backend::BTree].iter()
+            .map(|backend| {
+                let mut store = PointStore::new(*backend);
+                for point in points {
+                    store.push(*point, first);
+                }
+                store.sort(first);
+                store
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_successor_and_walk() {
+        for store in stores(&[(300, 2), (100, 0), (200, 1), (u32::MAX, 3)]) {
+            assert_eq!(store.successor(0), Some((100, 0)));
+            assert_eq!(store.successor(100), Some((200, 1)));
+            assert_eq!(store.successor(300), Some((u32::MAX, 3)));
+            assert_eq!(store.successor(u32::MAX), Some((100, 0)));
+            assert_eq!(store.walk(200).collect::<Vec<Point>>(), vec![(200, 1), (300, 2), (u32::MAX, 3), (100, 0)]);
+            assert_eq!(store.walk(301).map(|point| point.0).collect::<Vec<u32>>(), vec![u32::MAX, 100, 200, 300]);
+            assert_eq!(store.iter().map(|point| point.0).collect::<Vec<u32>>(), vec![100, 200, 300, u32::MAX]);
+            assert_eq!(store.get(200), Some(1));
+            assert_eq!(store.get(201), None);
+            assert_eq!(store.last(), Some((u32::MAX, 3)));
+        }
+        for store in stores(&[]) {
+            assert_eq!(store.successor(7), None);
+            assert_eq!(store.walk(7).next(), None);
+        }
+    }
+
+    #[test]
+    fn test_collisions_go_to_the_first_owner() {
+        for mut store in stores(&[(100, 2), (200, 1), (100, 0)]) {
+            assert_eq!(store.get(100), Some(0));
+            assert_eq!(store.successor(50), Some((100, 0)));
+            //the loser has nothing left to remove, the winner takes the token with it
+            assert!(store.remove_owner(2, &[100], first).is_empty());
+            assert_eq!(store.len(), 2);
+            assert_eq!(store.remove_owner(0, &[100], first), vec![100]);
+            assert_eq!(store.iter().collect::<Vec<Point>>(), vec![(200, 1)]);
+        }
+        //the push order doesn't matter, removing the winner gives the token to the
+        //first of the points that lost it
+        for points in [[(100, 3), (200, 1), (100, 2), (100, 0)], [(100, 0), (100, 2), (200, 1), (100, 3)]] {
+            for mut store in stores(&points) {
+                assert_eq!(store.get(100), Some(0));
+                assert_eq!(store.remove_owner(0, &[100], first), vec![100]);
+                assert_eq!(store.get(100), Some(2));
+                store.renumber(2, 0, &[100]);
+                assert_eq!(store.remove_points(0, &[100], first), vec![100]);
+                assert_eq!(store.iter().collect::<Vec<Point>>(), vec![(100, 3), (200, 1)]);
+                assert_eq!(store.remove_owner(3, &[100], first), vec![100]);
+                assert_eq!(store.iter().collect::<Vec<Point>>(), vec![(200, 1)]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_remove_and_renumber() {
+        for mut store in stores(&[(100, 0), (200, 1), (300, 0), (400, 1)]) {
+            assert_eq!(store.remove_points(0, &[300, 400], first), vec![300]);
+            store.renumber(1, 0, &[200, 400]);
+            assert_eq!(store.iter().collect::<Vec<Point>>(), vec![(100, 0), (200, 0), (400, 0)]);
+            assert_eq!(store.remove_owner(0, &[400, 100, 200], first), vec![100, 200, 400]);
+            assert!(store.is_empty());
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::hash::Hash;
+use crate::hashing_ring::{hash_pair64, RingNode, WithWeightInfo};
+
+
+///Rendezvous (highest-random-weight) hashing.
+///
+///Every node scores every key with a 64-bit hash of `(node_name, key)` and the
+///highest score wins. There are no virtual points, so small clusters are balanced
+///without tuning and adding or removing a node only moves the keys that node
+///wins or loses. Lookups are O(n) in the number of nodes, which is what makes it
+///a good fit for clusters of a handful of nodes rather than hundreds.
+pub struct RendezvousHashing<T: RingNode + Hash + Clone + WithWeightInfo> {
+    nodes: HashMap<String, T>,
+}
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> RendezvousHashing<T> {
+    pub fn new(nodes: &[T]) -> RendezvousHashing<T> {
+        let mut rendezvous = RendezvousHashing {
+            nodes: HashMap::new(),
+        };
+        for node in nodes {
+            rendezvous.add_node(node.clone());
+        }
+        rendezvous
+    }
+
+    ///Adds a node, replacing any node already registered under the same name.
+    pub fn add_node(&mut self, node: T) {
+        self.nodes.insert(node.ring_id().to_string(), node);
+    }
+
+    ///Removes a node, returning whether it was registered.
+    pub fn remove_node(&mut self, node: &T) -> bool {
+        self.nodes.remove(node.ring_id()).is_some()
+    }
+
+    ///Given a string key the node with the highest score is returned.
+    ///If there are no nodes, `None` is returned.
+    pub fn get_node(&self, key: &str) -> Option<T> {
+        self.scored(key).into_iter().next().map(|(_, node)| node.clone())
+    }
+
+    ///Returns up to `n` distinct nodes for the key, ordered by descending score.
+    ///The first one is always the node `get_node` would return.
+    pub fn get_nodes(&self, key: &str, n: usize) -> Vec<T> {
+        self.scored(key).into_iter().take(n).map(|(_, node)| node.clone()).collect()
+    }
+
+    fn scored(&self, key: &str) -> Vec<(u64, &T)> {
+        let mut scored: Vec<(u64, &String, &T)> = self.nodes.iter()
+            .map(|(name, node)| (hash_pair64(name, key), name, node))
+            .collect();
+        //ties are broken by name so the order never depends on HashMap layout
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+        scored.into_iter().map(|(score, _, node)| (score, node)).collect()
+    }
+}
+
+///Weighted rendezvous hashing (Thaler/Ravishankar logarithmic method).
+///
+///Each node scores a key with `-weight / ln(h)` where `h` is the 64-bit
+///`(node_name, key)` hash mapped into the open interval (0, 1). The node with
+///the highest score wins, so a node with weight 3 receives three times the keys
+///of a node with weight 1. Changing one node's weight only moves keys to or
+///from that node. Nodes with a weight of 0 never receive keys.
+///
+///The `(node_name, key)` hash can be swapped out with `with_hasher`, e.g. to
+///compare placements against another system's hash function.
+pub struct WeightedRendezvous<T: RingNode + Hash + Clone + WithWeightInfo> {
+    nodes: HashMap<String, T>,
+    hasher: PairHasher,
+}
+
+///Hashes a `(node_name, key)` pair to a uniformly distributed `u64`.
+type PairHasher = Box<dyn Fn(&str, &str) -> u64 + Send + Sync>;
+
+///Rendezvous hashing over the same node types as the ring, for A/B comparisons
+///against ketama placement: an alias of `WeightedRendezvous`, which degrades to
+///plain highest-random-weight hashing when all weights are equal.
+pub type RendezvousRing<T> = WeightedRendezvous<T>;
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> WeightedRendezvous<T> {
+    pub fn new(nodes: &[T]) -> WeightedRendezvous<T> {
+        WeightedRendezvous::with_hasher(nodes, hash_pair64)
+    }
+
+    ///Like `new`, but scoring with `hasher(node_name, key)` instead of the md5
+    ///based default. The hasher's output should be uniform over all of `u64`.
+    pub fn with_hasher<H>(nodes: &[T], hasher: H) -> WeightedRendezvous<T>
+        where H: Fn(&str, &str) -> u64 + Send + Sync + 'static {
+        let mut rendezvous = WeightedRendezvous {
+            nodes: HashMap::new(),
+            hasher: Box::new(hasher),
+        };
+        for node in nodes {
+            rendezvous.add_node(node.clone());
+        }
+        rendezvous
+    }
+
+    ///Adds a node, replacing any node already registered under the same name.
+    ///Re-adding a node with a different weight is how weights are changed.
+    pub fn add_node(&mut self, node: T) {
+        self.nodes.insert(node.ring_id().to_string(), node);
+    }
+
+    ///Removes a node, returning whether it was registered.
+    pub fn remove_node(&mut self, node: &T) -> bool {
+        self.nodes.remove(node.ring_id()).is_some()
+    }
+
+    ///Given a string key the node with the highest weighted score is returned.
+    ///If there are no nodes with a positive weight, `None` is returned.
+    pub fn get_node(&self, key: &str) -> Option<T> {
+        self.scored(key).into_iter().next().map(|(_, node)| node.clone())
+    }
+
+    ///Returns up to `n` distinct nodes for the key, ordered by descending score.
+    pub fn get_nodes(&self, key: &str, n: usize) -> Vec<T> {
+        self.scored(key).into_iter().take(n).map(|(_, node)| node.clone()).collect()
+    }
+
+    fn scored(&self, key: &str) -> Vec<(f64, &T)> {
+        let mut scored: Vec<(f64, &String, &T)> = self.nodes.iter()
+            .filter(|(_, node)| node.get_weight() > 0)
+            .map(|(name, node)| (weighted_score((self.hasher)(name, key), node.get_weight()), name, node))
+            .collect();
+        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal).then_with(|| a.1.cmp(b.1)));
+        scored.into_iter().map(|(score, _, node)| (score, node)).collect()
+    }
+}
+
+///Maps a hash onto the open interval (0, 1) and returns `-weight / ln(h)`.
+///
+///Only the top 52 bits are used and they're offset by half a step, which an `f64`
+///still represents exactly, so `h` can be neither 0 (`ln` = -inf) nor 1 (`ln` = 0,
+///a division by zero). The result is therefore always finite and positive.
+pub(crate) fn weighted_score(hash: u64, weight: usize) -> f64 {
+    let h = ((hash >> 12) as f64 + 0.5) / (1u64 << 52) as f64;
+    -(weight as f64) / h.ln()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing_ring::{NodeInfo, NodeInfoWithWeight};
+
+    fn nodes() -> Vec<NodeInfo> {
+        vec![
+            NodeInfo::new("192.168.0.101:11212"),
+            NodeInfo::new("192.168.0.102:11212"),
+            NodeInfo::new("192.168.0.103:11212"),
+            NodeInfo::new("192.168.0.104:11212"),
+            NodeInfo::new("192.168.0.105:11212"),
+        ]
+    }
+
+    #[test]
+    fn test_get_nodes_ordered_by_score() {
+        let rendezvous = RendezvousHashing::new(&nodes());
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            let replicas = rendezvous.get_nodes(&key, 3);
+            assert_eq!(replicas.len(), 3);
+            assert_eq!(replicas[0].to_string(), rendezvous.get_node(&key).unwrap().to_string());
+            assert_ne!(replicas[0].to_string(), replicas[1].to_string());
+            assert_ne!(replicas[1].to_string(), replicas[2].to_string());
+            assert_ne!(replicas[0].to_string(), replicas[2].to_string());
+        }
+        assert_eq!(rendezvous.get_nodes("key", 10).len(), 5);
+    }
+
+    #[test]
+    fn test_remove_node_only_moves_its_keys() {
+        let nodes = nodes();
+        let mut rendezvous = RendezvousHashing::new(&nodes);
+        let keys: Vec<String> = (0..1000).map(|i| format!("key-{}", i)).collect();
+        let before: Vec<String> = keys.iter().map(|k| rendezvous.get_node(k).unwrap().to_string()).collect();
+
+        assert!(rendezvous.remove_node(&nodes[2]));
+        assert!(!rendezvous.remove_node(&nodes[2]));
+
+        let removed = nodes[2].to_string();
+        for (key, old_owner) in keys.iter().zip(before.iter()) {
+            let new_owner = rendezvous.get_node(key).unwrap().to_string();
+            if *old_owner == removed {
+                assert_ne!(new_owner, removed);
+            } else {
+                assert_eq!(&new_owner, old_owner);
+            }
+        }
+
+        rendezvous.add_node(nodes[2].clone());
+        let after: Vec<String> = keys.iter().map(|k| rendezvous.get_node(k).unwrap().to_string()).collect();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn test_weighted_score_edges() {
+        assert!(weighted_score(0, 1).is_finite());
+        assert!(weighted_score(0, 1) > 0.0);
+        assert!(weighted_score(u64::MAX, 1).is_finite());
+        assert!(weighted_score(u64::MAX, 1) > weighted_score(0, 1));
+        assert_eq!(weighted_score(u64::MAX / 2, 0), 0.0);
+    }
+
+    #[test]
+    fn test_weighted_shares() {
+        let nodes = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 3),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 6),
+        ];
+        let rendezvous = WeightedRendezvous::new(&nodes);
+
+        let samples = 100_000;
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for i in 0..samples {
+            let node = rendezvous.get_node(&format!("key-{}", i)).unwrap();
+            *counts.entry(node.to_string()).or_insert(0) += 1;
+        }
+        for node in &nodes {
+            let expected = node.weight as f64 / 10.0;
+            let actual = counts[&node.to_string()] as f64 / samples as f64;
+            assert!((actual - expected).abs() < 0.02, "{}: {} vs {}", node.node_name, actual, expected);
+        }
+    }
+
+    #[test]
+    fn test_weight_change_only_moves_keys_of_that_node() {
+        let mut nodes = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 2),
+        ];
+        let mut rendezvous = WeightedRendezvous::new(&nodes);
+        let keys: Vec<String> = (0..2000).map(|i| format!("key-{}", i)).collect();
+        let before: Vec<String> = keys.iter().map(|k| rendezvous.get_node(k).unwrap().to_string()).collect();
+
+        //growing a node only pulls keys towards it
+        nodes[1].weight = 4;
+        rendezvous.add_node(nodes[1].clone());
+        let grown: Vec<String> = keys.iter().map(|k| rendezvous.get_node(k).unwrap().to_string()).collect();
+        let mut moved = 0;
+        for (old_owner, new_owner) in before.iter().zip(grown.iter()) {
+            if old_owner != new_owner {
+                assert_eq!(*new_owner, nodes[1].node_name);
+                moved += 1;
+            }
+        }
+        assert!(moved > 0);
+
+        //shrinking it only pushes keys away from it
+        nodes[1].weight = 1;
+        rendezvous.add_node(nodes[1].clone());
+        for (key, old_owner) in keys.iter().zip(grown.iter()) {
+            let new_owner = rendezvous.get_node(key).unwrap().to_string();
+            if *old_owner != new_owner {
+                assert_eq!(*old_owner, nodes[1].node_name);
+            }
+        }
+    }
+
+    //FNV-1a over "node_name\0key", a stand-in for some other system's hash
+    fn fnv1a(node_name: &str, key: &str) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for byte in node_name.bytes().chain(Some(0)).chain(key.bytes()) {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        //fold the high bits down, FNV's low bits alone are weak
+        hash ^ (hash >> 29)
+    }
+
+    #[test]
+    fn test_rendezvous_ring_with_injected_hasher() {
+        let nodes = nodes();
+        let default = RendezvousRing::new(&nodes);
+        let mut injected = RendezvousRing::with_hasher(&nodes, fnv1a);
+        let ring = crate::hashing_ring::ConsistentHashing::new(&nodes, None);
+
+        let keys: Vec<String> = (0..1000).map(|i| format!("key-{}", i)).collect();
+        let before: Vec<String> = keys.iter().map(|k| injected.get_node(k).unwrap().to_string()).collect();
+        let again: Vec<String> = keys.iter().map(|k| injected.get_node(k).unwrap().to_string()).collect();
+        assert_eq!(before, again);
+
+        //the three schemes place keys independently of each other
+        let differs_from_default = keys.iter().zip(before.iter())
+            .filter(|(k, owner)| default.get_node(k).unwrap().to_string() != **owner)
+            .count();
+        let differs_from_ring = keys.iter().zip(before.iter())
+            .filter(|(k, owner)| ring.get_node(k).unwrap().to_string() != **owner)
+            .count();
+        assert!(differs_from_default > 500 && differs_from_ring > 500);
+
+        assert!(injected.remove_node(&nodes[0]));
+        for (key, old_owner) in keys.iter().zip(before.iter()) {
+            let new_owner = injected.get_node(key).unwrap().to_string();
+            if *old_owner != nodes[0].to_string() {
+                assert_eq!(&new_owner, old_owner);
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        let rendezvous: RendezvousHashing<NodeInfo> = RendezvousHashing::new(&[]);
+        assert!(rendezvous.get_node("key").is_none());
+        assert!(rendezvous.get_nodes("key", 2).is_empty());
+    }
+}
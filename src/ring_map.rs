@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use crate::bisect::bisect_right_by_key;
+use crate::hashing_ring::{hash_digest, hash_key, hash_val, weighted_factor, RingNode};
+
+
+///A consistent hashing ring that maps keys to `(node key, payload)` pairs.
+///
+///Only the node key `K` is hashed onto the ring, by its `ring_id`, the same way
+///`ConsistentHashing` places nodes; the payload `V` (a connection pool, TLS
+///config, ...) is stored once per node, so it needn't be `Clone` or `Hash`.
+///Virtual points only refer to their node by index.
+///
+///Nodes get their weight share of points when they're inserted, like
+///`ConsistentHashing::add_node`, and the points of the nodes already there don't
+///move. Inserting the same nodes in the same order into both gives the same
+///placement.
+pub struct HashRingMap<K: RingNode, V> {
+    entries: Vec<(K, usize, V)>,
+    indices: HashMap<String, usize>,
+    //(ring position, entry index), sorted by position
+    points: Vec<(u32, usize)>,
+    interleave_count: usize,
+    total_weight: usize,
+}
+
+impl<K: RingNode, V> HashRingMap<K, V> {
+    pub fn new(interleave_count_setting: Option<usize>) -> HashRingMap<K, V> {
+        HashRingMap {
+            entries: Vec::new(),
+            indices: HashMap::new(),
+            points: Vec::new(),
+            interleave_count: interleave_count_setting.unwrap_or(40), //default value = 40
+            total_weight: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn contains_key(&self, k: &K) -> bool {
+        self.indices.contains_key(k.ring_id())
+    }
+
+    ///Inserts a node with its weight and payload. If a node with the same
+    ///`ring_id` was there, it's replaced, points included, and its payload returned.
+    pub fn insert(&mut self, k: K, weight: usize, v: V) -> Option<V> {
+        let old = self.remove(&k);
+
+        let index = self.entries.len();
+        self.total_weight += weight;
+        self.indices.insert(k.ring_id().to_string(), index);
+        let nodes_num = index + 1;
+        let factor = weighted_factor(self.interleave_count, nodes_num, weight, self.total_weight);
+        for replica in 0..factor {
+            let b_key = hash_digest(&format!("{}-{}", k.ring_id(), replica));
+            for offset in 0..3 {
+                self.points.push((hash_val(&b_key, offset * 4), index));
+            }
+        }
+        self.points.sort_by_key(|&(position, _)| position);
+        self.entries.push((k, weight, v));
+        old
+    }
+
+    ///Given a string key the node owning it and the node's payload are returned.
+    ///If there are no points on the ring, `None` is returned.
+    pub fn get(&self, key: &str) -> Option<(&K, &V)> {
+        let index = self.entry_for(key)?;
+        let (k, _, v) = &self.entries[index];
+        Some((k, v))
+    }
+
+    ///Like `get`, with the payload borrowed mutably.
+    pub fn get_mut(&mut self, key: &str) -> Option<(&K, &mut V)> {
+        let index = self.entry_for(key)?;
+        let (k, _, v) = &mut self.entries[index];
+        Some((k, v))
+    }
+
+    ///Removes the node and its points, returning its payload.
+    pub fn remove(&mut self, k: &K) -> Option<V> {
+        let index = self.indices.remove(k.ring_id())?;
+        self.points.retain(|&(_, owner)| owner != index);
+
+        //the last entry moves into the freed index
+        let last = self.entries.len() - 1;
+        let (_, weight, v) = self.entries.swap_remove(index);
+        if index != last {
+            for point in self.points.iter_mut().filter(|point| point.1 == last) {
+                point.1 = index;
+            }
+            self.indices.insert(self.entries[index].0.ring_id().to_string(), index);
+        }
+        self.total_weight -= weight;
+        Some(v)
+    }
+
+    //same successor rule as ConsistentHashing: the first point strictly after the hash
+    fn entry_for(&self, key: &str) -> Option<usize> {
+        if self.points.is_empty() {
+            return None;
+        }
+        let pos = bisect_right_by_key(&self.points, &hash_key(key), |&(position, _)| position);
+        Some(self.points[pos % self.points.len()].1)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hashing_ring::{ConsistentHashing, NodeInfoWithWeight};
+
+    //not Clone, not Hash
+    struct Pool {
+        host: String,
+        checked_out: usize,
+    }
+
+    fn pool(host: &str) -> Pool {
+        Pool { host: host.to_string(), checked_out: 0 }
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Server(&'static str);
+
+    impl RingNode for Server {
+        fn ring_id(&self) -> &str {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_matches_consistent_hashing() {
+        let names = [("192.168.0.101:11212", 1), ("192.168.0.102:11212", 2), ("192.168.0.103:11212", 1)];
+        let mut map: HashRingMap<Server, Pool> = HashRingMap::new(None);
+        let mut consistent_hasing_ring: ConsistentHashing<NodeInfoWithWeight> = ConsistentHashing::new(&vec![], None);
+        for &(name, weight) in names.iter() {
+            map.insert(Server(name), weight, pool(name));
+            consistent_hasing_ring.add_node(NodeInfoWithWeight::new(name, weight));
+        }
+        assert_eq!(map.len(), 3);
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            let (server, pool) = map.get(&key).unwrap();
+            assert_eq!(server.0, consistent_hasing_ring.get_node(&key).unwrap().node_name);
+            assert_eq!(pool.host, server.0);
+        }
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut map: HashRingMap<Server, Pool> = HashRingMap::new(None);
+        map.insert(Server("192.168.0.101:11212"), 1, pool("192.168.0.101:11212"));
+        map.insert(Server("192.168.0.102:11212"), 1, pool("192.168.0.102:11212"));
+        for _ in 0..3 {
+            map.get_mut("my_key").unwrap().1.checked_out += 1;
+        }
+        let (server, pool) = map.get("my_key").unwrap();
+        assert_eq!(pool.checked_out, 3);
+        assert_eq!(pool.host, server.0);
+    }
+
+    #[test]
+    fn test_insert_replaces_and_remove() {
+        let mut map: HashRingMap<Server, Pool> = HashRingMap::new(Some(10));
+        assert!(map.get("my_key").is_none());
+        assert!(map.insert(Server("a"), 1, pool("first")).is_none());
+        map.insert(Server("b"), 1, pool("b"));
+        map.insert(Server("c"), 1, pool("c"));
+
+        let old = map.insert(Server("a"), 1, pool("second")).unwrap();
+        assert_eq!(old.host, "first");
+        assert_eq!(map.len(), 3);
+
+        //removing an entry that isn't last moves another into its index
+        let removed = map.remove(&Server("b")).unwrap();
+        assert_eq!(removed.host, "b");
+        assert!(map.remove(&Server("b")).is_none());
+        assert!(!map.contains_key(&Server("b")));
+        assert!(map.contains_key(&Server("c")));
+        for i in 0..200 {
+            let (server, pool) = map.get(&format!("key-{}", i)).unwrap();
+            assert_ne!(server.0, "b");
+            match server.0 {
+                "a" => assert_eq!(pool.host, "second"),
+                other => assert_eq!(pool.host, other),
+            }
+        }
+
+        map.remove(&Server("a"));
+        map.remove(&Server("c"));
+        assert!(map.is_empty());
+        assert!(map.get("my_key").is_none());
+    }
+}
@@ -0,0 +1,213 @@
+use std::error::Error;
+use std::fmt;
+use std::hash::Hash;
+use crate::hashing_ring::{hash_key, RingNode, WithWeightInfo};
+
+
+///Error returned by `SlotTable` operations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SlotTableError {
+    ///The slot number is not below the table's slot count.
+    SlotOutOfRange { slot: usize, num_slots: usize },
+    ///A serialized slot refers to a node index past the end of the node list.
+    NodeIndexOutOfRange { slot: usize, index: u16 },
+    ///Node indices are `u16`s, so a table can't hold more than 65536 nodes.
+    TooManyNodes,
+    ///A table needs at least one slot and one node.
+    Empty,
+}
+
+impl fmt::Display for SlotTableError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SlotTableError::SlotOutOfRange { slot, num_slots } => write!(f, "slot {} out of range for {} slots", slot, num_slots),
+            SlotTableError::NodeIndexOutOfRange { slot, index } => write!(f, "slot {} refers to unknown node index {}", slot, index),
+            SlotTableError::TooManyNodes => write!(f, "a slot table can hold at most 65536 nodes"),
+            SlotTableError::Empty => write!(f, "a slot table needs at least one slot and one node"),
+        }
+    }
+}
+
+impl Error for SlotTableError {}
+
+
+///Fixed-slot (Redis-cluster style) placement: a key belongs to slot
+///`hash_key(key) % num_slots` and every slot has an explicit owner that can be
+///reassigned, e.g. by data migration tooling.
+///
+///Built with `ConsistentHashing::to_slot_table`, which assigns slot `s` to the ring
+///node owning position `s * 2^32 / num_slots`, so the initial split follows the
+///ring's (weight-proportional) ownership.
+pub struct SlotTable<T: RingNode + Hash + Clone + WithWeightInfo> {
+    nodes: Vec<T>,
+    slots: Vec<u16>,
+}
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo> SlotTable<T> {
+    ///Builds a table from a node list and the node index of every slot, the
+    ///inverse of `nodes` + `to_indices`.
+    pub fn from_indices(nodes: &[T], indices: &[u16]) -> Result<SlotTable<T>, SlotTableError> {
+        if nodes.len() > u16::MAX as usize + 1 {
+            return Err(SlotTableError::TooManyNodes);
+        }
+        if nodes.is_empty() || indices.is_empty() {
+            return Err(SlotTableError::Empty);
+        }
+        for (slot, &index) in indices.iter().enumerate() {
+            if index as usize >= nodes.len() {
+                return Err(SlotTableError::NodeIndexOutOfRange { slot, index });
+            }
+        }
+        Ok(SlotTable {
+            nodes: nodes.to_vec(),
+            slots: indices.to_vec(),
+        })
+    }
+
+    pub fn num_slots(&self) -> usize {
+        self.slots.len()
+    }
+
+    ///The nodes the serialized indices refer to, in index order.
+    pub fn nodes(&self) -> &[T] {
+        &self.nodes
+    }
+
+    ///Serializes slot ownership as one node index per slot, see `nodes`.
+    pub fn to_indices(&self) -> Vec<u16> {
+        self.slots.clone()
+    }
+
+    pub fn slot_of(&self, key: &str) -> usize {
+        (hash_key(key) as usize) % self.slots.len()
+    }
+
+    pub fn owner_of(&self, slot: usize) -> Option<&T> {
+        self.slots.get(slot).map(|&index| &self.nodes[index as usize])
+    }
+
+    ///Given a string key the owner of its slot is returned.
+    pub fn get_node(&self, key: &str) -> T {
+        self.nodes[self.slots[self.slot_of(key)] as usize].clone()
+    }
+
+    ///Hands `slot` to `node`, which joins the node list if it isn't on it yet
+    ///(nodes are matched by name).
+    pub fn reassign_slot(&mut self, slot: usize, node: T) -> Result<(), SlotTableError> {
+        if slot >= self.slots.len() {
+            return Err(SlotTableError::SlotOutOfRange { slot, num_slots: self.slots.len() });
+        }
+        let index = match self.nodes.iter().position(|n| n.ring_id() == node.ring_id()) {
+            Some(index) => index,
+            None => {
+                if self.nodes.len() > u16::MAX as usize {
+                    return Err(SlotTableError::TooManyNodes);
+                }
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.slots[slot] = index as u16;
+        Ok(())
+    }
+
+    ///Lists the slots whose owner (by name) differs between the two tables. When
+    ///the slot counts differ, the slots only one of them has are listed too.
+    pub fn diff(&self, other: &SlotTable<T>) -> Vec<usize> {
+        let longest = self.slots.len().max(other.slots.len());
+        (0..longest)
+            .filter(|&slot| {
+                match (self.owner_of(slot), other.owner_of(slot)) {
+                    (Some(a), Some(b)) => a.ring_id() != b.ring_id(),
+                    _ => true,
+                }
+            })
+            .collect()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::hashing_ring::{ConsistentHashing, NodeInfoWithWeight};
+
+    fn ring() -> ConsistentHashing<NodeInfoWithWeight> {
+        let nodes = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 2),
+        ];
+        ConsistentHashing::new(&nodes, Some(40))
+    }
+
+    #[test]
+    fn test_initial_assignment_follows_ring() {
+        let ring = ring();
+        let table = ring.to_slot_table(16384).unwrap();
+        assert_eq!(table.num_slots(), 16384);
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for slot in 0..table.num_slots() {
+            *counts.entry(table.owner_of(slot).unwrap().to_string()).or_insert(0) += 1;
+        }
+        for (name, fraction) in ring.percent_owned() {
+            let share = counts[&name] as f64 / 16384.0;
+            assert!((share - fraction).abs() < 0.01, "{}: {} vs {}", name, share, fraction);
+        }
+
+        let key = "my_key";
+        assert_eq!(table.slot_of(key), hash_key(key) as usize % 16384);
+        assert_eq!(table.get_node(key).to_string(), table.owner_of(table.slot_of(key)).unwrap().to_string());
+    }
+
+    #[test]
+    fn test_reassign_and_diff() {
+        let table = ring().to_slot_table(1024).unwrap();
+        let mut moved = ring().to_slot_table(1024).unwrap();
+        assert!(table.diff(&moved).is_empty());
+
+        let newcomer = NodeInfoWithWeight::new("192.168.0.104:11212", 1);
+        moved.reassign_slot(7, newcomer.clone()).unwrap();
+        moved.reassign_slot(9, newcomer.clone()).unwrap();
+        assert_eq!(moved.nodes().len(), 4);
+        assert_eq!(moved.owner_of(7).unwrap().node_name, "192.168.0.104:11212");
+        assert_eq!(table.diff(&moved), vec![7, 9]);
+
+        assert_eq!(
+            moved.reassign_slot(1024, newcomer).err(),
+            Some(SlotTableError::SlotOutOfRange { slot: 1024, num_slots: 1024 })
+        );
+    }
+
+    #[test]
+    fn test_indices_round_trip() {
+        let table = ring().to_slot_table(4096).unwrap();
+        let indices = table.to_indices();
+        assert_eq!(indices.len(), 4096);
+
+        let restored = SlotTable::from_indices(table.nodes(), &indices).unwrap();
+        assert!(table.diff(&restored).is_empty());
+        assert_eq!(restored.to_indices(), indices);
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(table.get_node(&key).node_name, restored.get_node(&key).node_name);
+        }
+
+        let mut corrupt = indices.clone();
+        corrupt[5] = 3;
+        assert_eq!(
+            SlotTable::from_indices(table.nodes(), &corrupt).err(),
+            Some(SlotTableError::NodeIndexOutOfRange { slot: 5, index: 3 })
+        );
+        assert_eq!(SlotTable::from_indices(table.nodes(), &[]).err(), Some(SlotTableError::Empty));
+    }
+
+    #[test]
+    fn test_empty_ring() {
+        let ring: ConsistentHashing<NodeInfoWithWeight> = ConsistentHashing::new(&vec![], None);
+        assert!(ring.to_slot_table(16384).is_none());
+        assert!(self::ring().to_slot_table(0).is_none());
+    }
+}
@@ -0,0 +1,158 @@
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::net::{AddrParseError, SocketAddr, ToSocketAddrs};
+use crate::hashing_ring::{RingNode, WithMutableWeight, WithWeightInfo};
+
+
+///A weighted `host:port` node, e.g. a memcached server.
+///
+///Its `ring_id` is the canonical `host:port` string: for IP addresses it's the
+///address as `SocketAddr` prints it (so `[0:0::1]:11211` and `[::1]:11211` are
+///the same node), for hostnames it's the hostname lowercased. Hostnames aren't
+///looked up when parsing, only when `resolve` is called.
+#[derive(Clone, Debug)]
+pub struct SocketNode {
+    host_port: String,
+    //None for hostnames
+    addr: Option<SocketAddr>,
+    pub weight: usize,
+}
+
+impl SocketNode {
+    ///Parses `"ip:port"`, `"[ipv6]:port"` or `"hostname:port"`. The port must be a
+    ///number below 65536 and IPv6 addresses need their brackets.
+    pub fn parse(host_port: &str, weight: usize) -> Result<SocketNode, AddrParseError> {
+        match host_port.parse::<SocketAddr>() {
+            Ok(addr) => Ok(SocketNode { host_port: addr.to_string(), addr: Some(addr), weight }),
+            Err(err) => match split_hostname(host_port) {
+                Some((host, port)) => Ok(SocketNode { host_port: format!("{}:{}", host.to_ascii_lowercase(), port), addr: None, weight }),
+                None => Err(err),
+            },
+        }
+    }
+
+    ///The address, if the node was given as an IP address rather than a hostname.
+    pub fn addr(&self) -> Option<SocketAddr> {
+        self.addr
+    }
+
+    ///The canonical `host:port` string, same as `ring_id`.
+    pub fn host_port(&self) -> &str {
+        &self.host_port
+    }
+
+    ///Looks the node up through `ToSocketAddrs`, which for a hostname asks the
+    ///system resolver and may block.
+    pub fn resolve(&self) -> io::Result<Vec<SocketAddr>> {
+        match self.addr {
+            Some(addr) => Ok(vec![addr]),
+            None => Ok(self.host_port.to_socket_addrs()?.collect()),
+        }
+    }
+}
+
+//"host:port" with a DNS-style host
+fn split_hostname(host_port: &str) -> Option<(&str, u16)> {
+    let (host, port) = host_port.rsplit_once(':')?;
+    let port: u16 = port.parse().ok()?;
+    let valid = !host.is_empty()
+        && !host.starts_with('-')
+        && host.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_');
+    if valid {
+        Some((host, port))
+    } else {
+        None
+    }
+}
+
+impl fmt::Display for SocketNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.host_port)
+    }
+}
+
+impl Hash for SocketNode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.host_port.hash(state);
+    }
+}
+
+impl WithWeightInfo for SocketNode {
+    fn get_weight(&self) -> usize {
+        self.weight
+    }
+}
+
+impl WithMutableWeight for SocketNode {
+    fn set_weight(&mut self, weight: usize) {
+        self.weight = weight;
+    }
+}
+
+impl RingNode for SocketNode {
+    fn ring_id(&self) -> &str {
+        &self.host_port
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+    use crate::hashing_ring::{ConsistentHashing, NodeInfoWithWeight};
+
+    #[test]
+    fn test_parse_ip() {
+        let node = SocketNode::parse("10.0.0.5:11211", 2).unwrap();
+        assert_eq!(node.ring_id(), "10.0.0.5:11211");
+        assert_eq!(node.addr(), Some(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5)), 11211)));
+        assert_eq!(node.get_weight(), 2);
+        assert_eq!(node.resolve().unwrap(), vec![node.addr().unwrap()]);
+    }
+
+    #[test]
+    fn test_parse_ipv6() {
+        let node = SocketNode::parse("[0:0:0:0:0:0:0:1]:11211", 1).unwrap();
+        assert_eq!(node.ring_id(), "[::1]:11211");
+        assert_eq!(node.addr(), Some(SocketAddr::new(IpAddr::V6(Ipv6Addr::LOCALHOST), 11211)));
+        assert_eq!(SocketNode::parse("[fe80::1]:80", 1).unwrap().to_string(), "[fe80::1]:80");
+    }
+
+    #[test]
+    fn test_parse_hostname() {
+        let node = SocketNode::parse("Cache-1.Example.com:11211", 1).unwrap();
+        assert_eq!(node.ring_id(), "cache-1.example.com:11211");
+        assert!(node.addr().is_none());
+
+        let localhost = SocketNode::parse("localhost:11211", 1).unwrap();
+        assert!(localhost.resolve().unwrap().iter().all(|addr| addr.port() == 11211 && addr.ip().is_loopback()));
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for input in &["", "10.0.0.5", "10.0.0.5:", ":11211", "10.0.0.5:65536", "10.0.0.5:port",
+                       "::1:11211", "[::1]", "[::1:11211", "bad host:11211", "-cache:11211"] {
+            assert!(SocketNode::parse(input, 1).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_same_placement_as_named_node() {
+        let nodes = vec![
+            SocketNode::parse("192.168.0.101:11212", 1).unwrap(),
+            SocketNode::parse("192.168.0.102:11212", 1).unwrap(),
+        ];
+        let named = vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 1),
+        ];
+        let consistent_hasing_ring = ConsistentHashing::new(&nodes, None);
+        let named_ring = ConsistentHashing::new(&named, None);
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(consistent_hasing_ring.get_node(&key).unwrap().ring_id(), named_ring.get_node(&key).unwrap().ring_id());
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use crate::hashing_ring::RingNode;
+
+
+///FNV-1a over every point and the index of its node in `nodes`, both as little
+///endian u32s, for pinning a port's whole continuum in one number.
+pub(crate) fn checksum<N: RingNode>(points: &[(u32, &str)], nodes: &[N]) -> u64 {
+    let mut checksum: u64 = 0xcbf29ce484222325;
+    for (point, node) in points {
+        let index = nodes.iter().position(|n| n.ring_id() == *node).unwrap() as u32;
+        for byte in point.to_le_bytes().iter().chain(index.to_le_bytes().iter()) {
+            checksum ^= *byte as u64;
+            checksum = checksum.wrapping_mul(0x100000001b3);
+        }
+    }
+    checksum
+}
+
+///Checks a vector of lookups: `expected` has a digit per key, the index in `nodes`
+///of the node `get_node` has to map the key to.
+pub(crate) fn assert_keys<N, K, F>(nodes: &[N], keys: K, expected: &str, get_node: F)
+    where N: RingNode, K: ExactSizeIterator<Item = String>, F: Fn(&str) -> Option<N> {
+    assert_eq!(expected.len(), keys.len());
+    for (key, index) in keys.zip(expected.bytes()) {
+        assert_eq!(get_node(&key).unwrap().ring_id(), nodes[(index - b'0') as usize].ring_id(), "{}", key);
+    }
+}
+
+///xorshift64* from `seed`, which mustn't be 0: each call draws a number below
+///`bound`, the same ones for the same seed on every run and platform.
+pub(crate) fn xorshift(seed: u64) -> impl FnMut(u64) -> u64 {
+    let mut state = seed;
+    move |bound| {
+        state ^= state >> 12;
+        state ^= state << 25;
+        state ^= state >> 27;
+        state.wrapping_mul(0x2545_f491_4f6c_dd1d) % bound
+    }
+}
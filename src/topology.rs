@@ -0,0 +1,239 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use crate::hashing_ring::{hash_pair64, ConsistentHashing, RingNode, WithWeightInfo};
+use crate::rendezvous::weighted_score;
+
+
+///Nodes that know where they live, as a `/`-separated path from the widest
+///failure domain down to the node itself, e.g. `dc1/rackA/host3`. Level 0 of that
+///path is the datacenter, level 1 the rack, and so on.
+pub trait WithLocation {
+    fn location(&self) -> &str;
+}
+
+
+///A weighted node with a location path.
+#[derive(Clone, Debug)]
+pub struct LocatedNode {
+    pub node_name: String,
+    pub location: String,
+    pub weight: usize,
+}
+
+impl fmt::Display for LocatedNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.node_name)
+    }
+}
+
+impl Hash for LocatedNode {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.node_name.hash(state);
+    }
+}
+
+impl WithWeightInfo for LocatedNode {
+    fn get_weight(&self) -> usize {
+        self.weight
+    }
+}
+
+impl RingNode for LocatedNode {
+    fn ring_id(&self) -> &str {
+        &self.node_name
+    }
+}
+
+impl WithLocation for LocatedNode {
+    fn location(&self) -> &str {
+        &self.location
+    }
+}
+
+
+///Reports that `get_nodes_spread` couldn't keep every node in its own failure
+///domain: only `available` domains exist at `level` for the `requested` nodes,
+///so the remaining nodes share domains with earlier picks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Relaxation {
+    pub level: usize,
+    pub available: usize,
+    pub requested: usize,
+}
+
+///The nodes picked by `get_nodes_spread`, in preference order.
+#[derive(Debug, Clone)]
+pub struct SpreadResult<T> {
+    pub nodes: Vec<T>,
+    pub relaxed: Option<Relaxation>,
+}
+
+
+///CRUSH-like hierarchical placement.
+///
+///For a key and a level, the failure domains at that level (e.g. racks) are
+///ranked with weighted rendezvous hashing (a domain weighs as much as its nodes
+///together), and within each domain a consistent hashing ring over its nodes
+///picks the node. Losing a domain therefore only moves the keys that had a
+///replica in it, and only that replica.
+pub struct Topology<T: RingNode + Hash + Clone + WithWeightInfo + WithLocation> {
+    //per level: domain path -> (domain weight, ring over the domain's nodes)
+    levels: Vec<HashMap<String, (usize, ConsistentHashing<T>)>>,
+    nodes_num: usize,
+}
+
+impl<T: RingNode + Hash + Clone + WithWeightInfo + WithLocation> Topology<T> {
+    pub fn new(nodes: &[T]) -> Topology<T> {
+        let depth = nodes.iter().map(|node| node.location().split('/').count()).max().unwrap_or(0);
+        let levels = (0..depth)
+            .map(|level| {
+                let mut domains: HashMap<String, Vec<T>> = HashMap::new();
+                for node in nodes {
+                    domains.entry(ancestor(node.location(), level)).or_default().push(node.clone());
+                }
+                domains.into_iter()
+                    .map(|(domain, members)| {
+                        let weight = members.iter().map(|node| node.get_weight()).sum();
+                        (domain, (weight, ConsistentHashing::new(&members, None)))
+                    })
+                    .collect()
+            })
+            .collect();
+        Topology {
+            levels,
+            nodes_num: nodes.len(),
+        }
+    }
+
+    ///Picks up to `n` distinct nodes for the key, no two of which share an ancestor
+    ///at `level` (0 = first path component). Levels deeper than any path are
+    ///treated as the deepest one.
+    ///
+    ///When there are fewer domains than `n`, every domain gets one node first and
+    ///the rest are filled from the domains again in the same order; `relaxed` then
+    ///reports the shortfall.
+    pub fn get_nodes_spread(&self, key: &str, n: usize, level: usize) -> SpreadResult<T> {
+        let domains = match self.levels.get(level).or_else(|| self.levels.last()) {
+            Some(domains) => domains,
+            None => return SpreadResult { nodes: Vec::new(), relaxed: None },
+        };
+        let level = level.min(self.levels.len() - 1);
+
+        let mut ranked: Vec<(f64, &String, &ConsistentHashing<T>)> = domains.iter()
+            .filter(|(_, (weight, _))| *weight > 0)
+            .map(|(domain, (weight, ring))| (weighted_score(hash_pair64(domain, key), *weight), domain, ring))
+            .collect();
+        ranked.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal).then_with(|| a.1.cmp(b.1)));
+
+        let wanted = n.min(self.nodes_num);
+        let candidates: Vec<Vec<T>> = ranked.iter().map(|(_, _, ring)| ring.get_nodes(key, wanted)).collect();
+        let mut nodes: Vec<T> = Vec::new();
+        let rounds = candidates.iter().map(|domain_nodes| domain_nodes.len()).max().unwrap_or(0);
+        for round in 0..rounds {
+            for domain_nodes in &candidates {
+                if nodes.len() < wanted {
+                    if let Some(node) = domain_nodes.get(round) {
+                        nodes.push(node.clone());
+                    }
+                }
+            }
+        }
+
+        let relaxed = if n > ranked.len() && !ranked.is_empty() {
+            Some(Relaxation { level, available: ranked.len(), requested: n })
+        } else {
+            None
+        };
+        SpreadResult { nodes, relaxed }
+    }
+}
+
+///The first `level + 1` components of a location path.
+fn ancestor(location: &str, level: usize) -> String {
+    location.split('/').take(level + 1).collect::<Vec<&str>>().join("/")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //2 datacenters, 2 racks each, 3 hosts per rack
+    fn nodes() -> Vec<LocatedNode> {
+        let mut nodes = Vec::new();
+        for dc in &["dc1", "dc2"] {
+            for rack in &["rackA", "rackB"] {
+                for host in 0..3 {
+                    nodes.push(LocatedNode {
+                        node_name: format!("{}-{}-host{}", dc, rack, host),
+                        location: format!("{}/{}/host{}", dc, rack, host),
+                        weight: 1,
+                    });
+                }
+            }
+        }
+        nodes
+    }
+
+    fn domain_of(node: &LocatedNode, level: usize) -> String {
+        ancestor(&node.location, level)
+    }
+
+    #[test]
+    fn test_spread_across_racks() {
+        let topology = Topology::new(&nodes());
+        for i in 0..200 {
+            let spread = topology.get_nodes_spread(&format!("key-{}", i), 3, 1);
+            assert!(spread.relaxed.is_none());
+            assert_eq!(spread.nodes.len(), 3);
+            let mut racks: Vec<String> = spread.nodes.iter().map(|node| domain_of(node, 1)).collect();
+            racks.sort();
+            racks.dedup();
+            assert_eq!(racks.len(), 3);
+        }
+    }
+
+    #[test]
+    fn test_spread_across_datacenters_relaxes() {
+        let topology = Topology::new(&nodes());
+        let spread = topology.get_nodes_spread("my_key", 3, 0);
+        assert_eq!(spread.nodes.len(), 3);
+        assert_ne!(domain_of(&spread.nodes[0], 0), domain_of(&spread.nodes[1], 0));
+        assert_eq!(spread.relaxed, Some(Relaxation { level: 0, available: 2, requested: 3 }));
+
+        let mut names: Vec<&str> = spread.nodes.iter().map(|node| node.node_name.as_str()).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), 3);
+
+        //asking for more nodes than there are gives every node once
+        assert_eq!(topology.get_nodes_spread("my_key", 20, 1).nodes.len(), 12);
+    }
+
+    #[test]
+    fn test_losing_a_rack_only_moves_its_replicas() {
+        let all = nodes();
+        let before = Topology::new(&all);
+        let survivors: Vec<LocatedNode> = all.iter().filter(|node| !node.location.starts_with("dc1/rackB/")).cloned().collect();
+        let after = Topology::new(&survivors);
+
+        for i in 0..200 {
+            let key = format!("key-{}", i);
+            let old = before.get_nodes_spread(&key, 2, 1).nodes;
+            let new = after.get_nodes_spread(&key, 2, 1).nodes;
+            for node in old.iter().filter(|node| !node.location.starts_with("dc1/rackB/")) {
+                assert!(new.iter().any(|n| n.node_name == node.node_name), "{} lost {}", key, node.node_name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_empty() {
+        let topology: Topology<LocatedNode> = Topology::new(&[]);
+        let spread = topology.get_nodes_spread("my_key", 3, 1);
+        assert!(spread.nodes.is_empty());
+        assert!(spread.relaxed.is_none());
+    }
+}
@@ -0,0 +1,114 @@
+use serde::Deserialize;
+use wasm_bindgen::prelude::*;
+use crate::hashing_ring::{ConsistentHashing, NodeInfoWithWeight};
+
+
+//a node as JS passes it: a bare name, weighted 1, or `{ name, weight }`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsNode {
+    Name(String),
+    Weighted { name: String, weight: usize },
+}
+
+///`ConsistentHashing<NodeInfoWithWeight>` for JavaScript, behind the
+///`wasm-bindgen` feature. Placement is the same as on native targets, so a
+///dashboard or a worker built for `wasm32-unknown-unknown` maps keys to the
+///nodes the Rust services do.
+#[wasm_bindgen]
+pub struct JsRing {
+    ring: ConsistentHashing<NodeInfoWithWeight>,
+}
+
+#[wasm_bindgen]
+impl JsRing {
+    ///`new JsRing(["10.0.0.1:11211", { name: "10.0.0.2:11211", weight: 2 }])`; a
+    ///bare name has weight 1. Throws if `nodes` isn't such an array.
+    #[wasm_bindgen(constructor)]
+    pub fn new(nodes: JsValue) -> Result<JsRing, JsError> {
+        let nodes: Vec<JsNode> = serde_wasm_bindgen::from_value(nodes)
+            .map_err(|err| JsError::new(&format!("nodes must be names or {{ name, weight }} objects: {}", err)))?;
+        let nodes: Vec<NodeInfoWithWeight> = nodes
+            .into_iter()
+            .map(|node| match node {
+                JsNode::Name(name) => NodeInfoWithWeight::new(name, 1),
+                JsNode::Weighted { name, weight } => NodeInfoWithWeight::new(name, weight),
+            })
+            .collect();
+        Ok(JsRing { ring: ConsistentHashing::new(&nodes, None) })
+    }
+
+    ///The name of the node the key maps to, `undefined` on an empty ring.
+    #[wasm_bindgen(js_name = getNode)]
+    pub fn get_node(&self, key: String) -> Option<String> {
+        self.ring.get_node(&key).map(|node| node.node_name)
+    }
+
+    ///Adds a node, returning false if one with the same name is already there.
+    #[wasm_bindgen(js_name = addNode)]
+    pub fn add_node(&mut self, name: String, weight: usize) -> bool {
+        self.ring.add_node(NodeInfoWithWeight::new(name, weight)).is_some()
+    }
+
+    ///Removes a node, returning whether it was on the ring.
+    #[wasm_bindgen(js_name = removeNode)]
+    pub fn remove_node(&mut self, name: String) -> bool {
+        if !self.ring.contains_node(&name) {
+            return false;
+        }
+        self.ring.remove_node_by_name(&name);
+        true
+    }
+}
+
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod tests {
+    use super::*;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    fn nodes() -> Vec<NodeInfoWithWeight> {
+        vec![
+            NodeInfoWithWeight::new("192.168.0.101:11212", 1),
+            NodeInfoWithWeight::new("192.168.0.102:11212", 2),
+            NodeInfoWithWeight::new("192.168.0.103:11212", 1),
+        ]
+    }
+
+    fn js_nodes() -> JsValue {
+        use serde::Serialize;
+        serde_json::json!([
+            "192.168.0.101:11212",
+            { "name": "192.168.0.102:11212", "weight": 2 },
+            { "name": "192.168.0.103:11212", "weight": 1 },
+        ]).serialize(&serde_wasm_bindgen::Serializer::json_compatible()).unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn test_matches_ring() {
+        let mut js_ring = JsRing::new(js_nodes()).unwrap();
+        let mut consistent_hasing_ring = ConsistentHashing::new(&nodes(), None);
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(js_ring.get_node(key.clone()), consistent_hasing_ring.get_node(&key).map(|node| node.node_name));
+        }
+
+        assert!(js_ring.add_node("192.168.0.104:11212".to_string(), 3));
+        assert!(!js_ring.add_node("192.168.0.104:11212".to_string(), 3));
+        assert!(js_ring.remove_node("192.168.0.101:11212".to_string()));
+        assert!(!js_ring.remove_node("192.168.0.101:11212".to_string()));
+        consistent_hasing_ring.add_node(NodeInfoWithWeight::new("192.168.0.104:11212", 3));
+        consistent_hasing_ring.remove_node_by_name("192.168.0.101:11212");
+        for i in 0..100 {
+            let key = format!("key-{}", i);
+            assert_eq!(js_ring.get_node(key.clone()), consistent_hasing_ring.get_node(&key).map(|node| node.node_name));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_empty_and_invalid() {
+        let empty = JsRing::new(serde_wasm_bindgen::to_value(&Vec::<String>::new()).unwrap()).unwrap();
+        assert_eq!(empty.get_node("my_key".to_string()), None);
+        assert!(JsRing::new(JsValue::from_f64(3.0)).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! Checks that building a 1000-node ring allocates once per node, for the `Arc`
+//! the node is shared behind, plus a constant for the ring's own tables, and
+//! nothing per virtual point. Without a test harness, so nothing else allocates
+//! while it counts.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use consistent_hashing_ring::hashing_ring::{ConsistentHashing, NodeInfoWithWeight, RingNode, WithWeightInfo};
+
+const NODES: usize = 1000;
+//the points, owners and hash tables growing, each a few dozen times at most
+const TABLES: usize = 100;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+//a node whose copies allocate nothing, so only the ring's allocations count
+#[derive(Clone, Hash)]
+struct StaticNode(&'static str);
+
+impl RingNode for StaticNode {
+    fn ring_id(&self) -> &str {
+        self.0
+    }
+}
+
+impl WithWeightInfo for StaticNode {
+    fn get_weight(&self) -> usize {
+        1
+    }
+}
+
+fn name(i: usize) -> String {
+    format!("10.0.{}.{}:11211", i / 256, i % 256)
+}
+
+//allocations made by `build`, and what it built
+fn counted<R>(build: impl FnOnce() -> R) -> (usize, R) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    let built = build();
+    (ALLOCATIONS.load(Ordering::Relaxed) - before, built)
+}
+
+fn main() {
+    //the parallel build collects every node's points before putting them on the ring
+    if cfg!(feature = "rayon") {
+        return;
+    }
+    let nodes: Vec<StaticNode> = (0..NODES).map(|i| StaticNode(Box::leak(name(i).into_boxed_str()))).collect();
+    let (allocations, ring) = counted(|| ConsistentHashing::new(&nodes, None));
+    assert_eq!(ring.virtual_node_count(), 119999);
+    assert!(allocations <= NODES + TABLES, "{} allocations for {} nodes", allocations, NODES);
+
+    //the ring keeps its own copy of each node, which here owns its name
+    let nodes: Vec<NodeInfoWithWeight> = (0..NODES).map(|i| NodeInfoWithWeight::new(name(i), 1)).collect();
+    let (allocations, ring) = counted(|| ConsistentHashing::new(&nodes, None));
+    assert_eq!(ring.virtual_node_count(), 119999);
+    assert!(allocations <= 2 * NODES + TABLES, "{} allocations for {} nodes", allocations, NODES);
+}